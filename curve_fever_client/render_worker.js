@@ -0,0 +1,8 @@
+// Draws the main canvas off the main thread, see `src/worker.rs`.
+import init, { start_render_worker } from './pkg/curve_fever_wasm.js';
+
+async function run() {
+    await init();
+    start_render_worker();
+}
+run();
@@ -0,0 +1,236 @@
+//! JavaScript API to build an own interface on top of the game's connection.
+//!
+//! The bundled interface only starts on its own on pages with a `div#content`, other pages can
+//! `mount` it into an element of their choice. Pages with an own interface create a
+//! `CurveFeverClient` instead:
+//!
+//! ```js
+//! import init, { CurveFeverClient } from './pkg/curve_fever_wasm.js';
+//!
+//! await init();
+//! const client = new CurveFeverClient();
+//! client.onEvent(event => console.log(event));
+//! client.connect();
+//! client.createRoom('alice');
+//! ```
+//!
+//! Events are the messages of the server in their serde JSON form, e.g.
+//! `{ "JoinSuccess": { "room_name": "...", ... } }` or `"RoundPaused"`, and the connection events
+//! `"Open"` and `{ "Close": <code> }`. Messages are queued until the connection is open.
+
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+use curve_fever_common::{ClientMessage, Direction, ServerMessage};
+
+use crate::{build_cb, server_url, JsClosure, JsError, OptionJsValue};
+
+/// Shared with the callbacks of the WebSocket
+#[derive(Default)]
+struct Connection {
+    on_event: Option<js_sys::Function>,
+    open: bool,
+    queued: Vec<ClientMessage>, // sent before the connection was open
+}
+
+/// Not borrowed during the callback, it may well send a message right away.
+fn emit(connection: &RefCell<Connection>, event: &JsValue) -> JsError {
+    let on_event = connection.borrow().on_event.clone();
+    if let Some(on_event) = on_event {
+        on_event.call1(&JsValue::NULL, event)?;
+    }
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub struct CurveFeverClient {
+    ws: Option<WebSocket>,
+    connection: Rc<RefCell<Connection>>,
+    callbacks: Vec<JsClosure<JsValue>>, // of `ws`
+}
+
+#[wasm_bindgen]
+impl CurveFeverClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            ws: None,
+            connection: Rc::new(RefCell::new(Connection::default())),
+            callbacks: vec![],
+        }
+    }
+
+    /// Sets the function that receives every event.
+    #[wasm_bindgen(js_name = onEvent)]
+    pub fn on_event(&mut self, callback: js_sys::Function) {
+        self.connection.borrow_mut().on_event = Some(callback);
+    }
+
+    /// Connects to `url`, by default to the server the bundled interface would use.
+    pub fn connect(&mut self, url: Option<String>) -> JsError {
+        self.close();
+        let url = match url {
+            Some(url) => url,
+            None => {
+                let window = web_sys::window().to_js_err("no global window exists")?;
+                server_url(&window.document().to_js_err("no document on window")?)?
+            }
+        };
+        let ws = WebSocket::new(&url)?;
+        ws.set_binary_type(BinaryType::Arraybuffer);
+
+        let connection = self.connection.clone();
+        let socket = ws.clone();
+        self.listen(&ws, "open", move |_| {
+            let queued = {
+                let mut connection = connection.borrow_mut();
+                connection.open = true;
+                std::mem::take(&mut connection.queued)
+            };
+            for msg in queued {
+                send(&socket, &msg)?;
+            }
+            emit(&connection, &"Open".into())
+        })?;
+
+        let connection = self.connection.clone();
+        let socket = ws.clone();
+        self.listen(&ws, "message", move |e| {
+            let data = e.dyn_into::<MessageEvent>()?.data();
+            let data = js_sys::Uint8Array::new(&data).to_vec();
+            let msg: ServerMessage = bincode::deserialize(&data)
+                .map_err(|e| JsValue::from_str(&format!("Failed to deserialize: {}", e)))?;
+            // latency measurements of the server are answered right away
+            if let ServerMessage::Ping(seq) = msg {
+                send(&socket, &ClientMessage::Pong(seq))?;
+            }
+            let json = serde_json::to_string(&msg)
+                .map_err(|e| JsValue::from_str(&format!("Failed to serialize: {}", e)))?;
+            emit(&connection, &js_sys::JSON::parse(&json)?)
+        })?;
+
+        let connection = self.connection.clone();
+        self.listen(&ws, "close", move |e| {
+            let code = e.dyn_into::<CloseEvent>()?.code();
+            connection.borrow_mut().open = false;
+            let event = js_sys::Object::new();
+            js_sys::Reflect::set(&event, &"Close".into(), &code.into())?;
+            emit(&connection, &event)
+        })?;
+
+        self.ws = Some(ws);
+        Ok(())
+    }
+
+    /// Closes the connection, no more events are emitted for it.
+    pub fn close(&mut self) {
+        if let Some(ws) = self.ws.take() {
+            for (name, cb) in ["open", "message", "close"].iter().zip(&self.callbacks) {
+                let _ = ws.remove_event_listener_with_callback(name, cb.as_ref().unchecked_ref());
+            }
+            let _ = ws.close();
+        }
+        self.callbacks.clear();
+        let mut connection = self.connection.borrow_mut();
+        connection.open = false;
+        connection.queued.clear();
+    }
+
+    /// Sends a message in the serde JSON form of `ClientMessage`, e.g. `"PauseGame"` or
+    /// `{ "Emote": "Wave" }`.
+    pub fn send(&self, message: JsValue) -> JsError {
+        let json: String = js_sys::JSON::stringify(&message)?.into();
+        let msg = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid message: {}", e)))?;
+        self.send_message(msg)
+    }
+
+    #[wasm_bindgen(js_name = createRoom)]
+    pub fn create_room(&self, name: String) -> JsError {
+        self.send_message(ClientMessage::CreateRoom(name))
+    }
+
+    #[wasm_bindgen(js_name = joinRoom)]
+    pub fn join_room(&self, name: String, room: String) -> JsError {
+        self.send_message(ClientMessage::JoinRoom(name, room))
+    }
+
+    #[wasm_bindgen(js_name = quickPlay)]
+    pub fn quick_play(&self, name: String) -> JsError {
+        self.send_message(ClientMessage::QuickPlay(name))
+    }
+
+    pub fn spectate(&self, room: String) -> JsError {
+        self.send_message(ClientMessage::Spectate(room))
+    }
+
+    #[wasm_bindgen(js_name = startGame)]
+    pub fn start_game(&self) -> JsError {
+        self.send_message(ClientMessage::StartGame)
+    }
+
+    /// `direction` is one of `left`, `right` and `straight`.
+    pub fn steer(&self, direction: &str) -> JsError {
+        let direction = match direction {
+            "left" => Direction::Left,
+            "right" => Direction::Right,
+            "straight" => Direction::Unchanged,
+            _ => return Err(format!("Unknown direction: {}", direction).into()),
+        };
+        self.send_message(ClientMessage::Move(direction))
+    }
+
+    #[wasm_bindgen(js_name = useItem)]
+    pub fn use_item(&self) -> JsError {
+        self.send_message(ClientMessage::UseItem)
+    }
+
+    pub fn chat(&self, text: String) -> JsError {
+        self.send_message(ClientMessage::Chat(text))
+    }
+}
+
+impl CurveFeverClient {
+    fn listen<F>(&mut self, ws: &WebSocket, name: &str, f: F) -> JsError
+    where
+        F: FnMut(JsValue) -> JsError + 'static,
+    {
+        let cb = build_cb(f);
+        ws.add_event_listener_with_callback(name, cb.as_ref().unchecked_ref())?;
+        self.callbacks.push(cb);
+        Ok(())
+    }
+
+    fn send_message(&self, msg: ClientMessage) -> JsError {
+        let ws = self
+            .ws
+            .as_ref()
+            .to_js_err("Not connected, call connect first")?;
+        let mut connection = self.connection.borrow_mut();
+        if connection.open {
+            send(ws, &msg)
+        } else {
+            connection.queued.push(msg);
+            Ok(())
+        }
+    }
+}
+
+impl Default for CurveFeverClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CurveFeverClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn send(ws: &WebSocket, msg: &ClientMessage) -> JsError {
+    let encoded = bincode::serialize(msg)
+        .map_err(|e| JsValue::from_str(&format!("Could not encode: {}", e)))?;
+    ws.send_with_u8_array(&encoded[..])
+}
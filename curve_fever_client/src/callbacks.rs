@@ -0,0 +1,51 @@
+//! Event listeners that last as long as whatever owns them, usually one of the states.
+
+use wasm_bindgen::{convert::FromWasmAbi, JsCast, JsValue};
+use web_sys::EventTarget;
+
+use crate::{build_cb, JsError};
+
+struct Listener {
+    target: EventTarget,
+    name: &'static str,
+    callback: Box<dyn AsRef<JsValue>>, // the closure, freed along with the listener
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let callback: &JsValue = (*self.callback).as_ref();
+        let _ = self
+            .target
+            .remove_event_listener_with_callback(self.name, callback.unchecked_ref());
+    }
+}
+
+/// Holds the callbacks of a state and removes them from their targets once dropped, a state
+/// that was left hears of no more events.
+///
+/// States are replaced from within their own callbacks. Dropping a callback while it runs is
+/// fine, wasm-bindgen frees it once it returned.
+#[derive(Default)]
+pub struct CallbackRegistry {
+    listeners: Vec<Listener>,
+}
+
+impl CallbackRegistry {
+    /// Calls `f` on every `name` event of `target` until the registry is dropped.
+    pub fn add<E, F, T>(&mut self, target: &E, name: &'static str, f: F) -> JsError
+    where
+        E: AsRef<EventTarget>,
+        F: FnMut(T) -> JsError + 'static,
+        T: FromWasmAbi + 'static,
+    {
+        let target = target.as_ref().clone();
+        let cb = build_cb(f);
+        target.add_event_listener_with_callback(name, cb.as_ref().unchecked_ref())?;
+        self.listeners.push(Listener {
+            target,
+            name,
+            callback: Box::new(cb),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Render and network statistics for the debug overlay, toggled with F3.
+//!
+//! The counters are fed from the WebSocket and the drawing code, which don't share a state, so
+//! they live in their own global next to `HANDLE`.
+
+use instant::Instant;
+use std::{sync::Mutex, time::Duration};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{Document, Window};
+
+/// Counters are turned into rates over windows of this length
+const WINDOW: Duration = Duration::from_secs(1);
+const REFRESH_MS: i32 = 250;
+const OVERLAY_ID: &str = "debug_overlay";
+
+#[derive(Default)]
+struct Counts {
+    frames: u32,
+    states: u32,
+    bytes_up: usize,
+    bytes_down: usize,
+}
+
+struct Diagnostics {
+    enabled: bool,
+    window_start: Instant,
+    current: Counts, // of the running window
+    last: Counts,    // of the last complete window
+    last_state: Option<Instant>,
+    missed: u32,    // states that never arrived
+    outdated: u32,  // states that arrived after a newer one
+    pending: usize, // inputs of the local player the server hasn't applied yet
+}
+
+lazy_static::lazy_static! {
+    static ref DIAGNOSTICS: Mutex<Diagnostics> = Mutex::new(Diagnostics {
+        enabled: false,
+        window_start: Instant::now(),
+        current: Counts::default(),
+        last: Counts::default(),
+        last_state: None,
+        missed: 0,
+        outdated: 0,
+        pending: 0,
+    });
+}
+
+fn with<T>(f: impl FnOnce(&mut Diagnostics) -> T) -> T {
+    f(&mut DIAGNOSTICS.lock().unwrap())
+}
+
+pub fn frame() {
+    with(|d| d.current.frames += 1);
+}
+
+/// A `GameState` was applied, `missed` states were skipped since the last one.
+pub fn state_received(missed: u32) {
+    with(|d| {
+        d.current.states += 1;
+        d.missed += missed;
+        d.last_state = Some(Instant::now());
+    });
+}
+
+pub fn state_outdated() {
+    with(|d| d.outdated += 1);
+}
+
+pub fn inputs_pending(count: usize) {
+    with(|d| d.pending = count);
+}
+
+pub fn sent(bytes: usize) {
+    with(|d| d.current.bytes_up += bytes);
+}
+
+pub fn received(bytes: usize) {
+    with(|d| d.current.bytes_down += bytes);
+}
+
+pub fn toggle(doc: &Document) {
+    let enabled = with(|d| {
+        d.enabled = !d.enabled;
+        d.enabled
+    });
+    if let Some(overlay) = doc.get_element_by_id(OVERLAY_ID) {
+        overlay.set_class_name(if enabled { "visible" } else { "hidden" });
+    }
+    refresh(doc);
+}
+
+/// Keeps the overlay up to date while it is shown.
+pub fn start(window: &Window, doc: Document) -> Result<(), JsValue> {
+    let cb = Closure::wrap(Box::new(move || refresh(&doc)) as Box<dyn FnMut()>);
+    window.set_interval_with_callback_and_timeout_and_arguments_0(
+        cb.as_ref().unchecked_ref(),
+        REFRESH_MS,
+    )?;
+    cb.forget();
+    Ok(())
+}
+
+fn refresh(doc: &Document) {
+    let text = with(|d| {
+        if d.window_start.elapsed() >= WINDOW {
+            d.last = std::mem::take(&mut d.current);
+            d.window_start = Instant::now();
+        }
+        if !d.enabled {
+            return None;
+        }
+        let age = match d.last_state {
+            Some(time) => format!("{} ms", time.elapsed().as_millis()),
+            None => "-".into(),
+        };
+        Some(format!(
+            "FPS: {}\nTick rate: {}/s\nUp: {:.1} kB/s\nDown: {:.1} kB/s\nLast state: {}\nMissed states: {}\nOutdated states: {}\nPending inputs: {}",
+            d.last.frames,
+            d.last.states,
+            d.last.bytes_up as f64 / 1000.,
+            d.last.bytes_down as f64 / 1000.,
+            age,
+            d.missed,
+            d.outdated,
+            d.pending,
+        ))
+    });
+    if let (Some(text), Some(overlay)) = (text, doc.get_element_by_id(OVERLAY_ID)) {
+        overlay.set_text_content(Some(&text));
+    }
+}
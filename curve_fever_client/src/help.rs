@@ -0,0 +1,105 @@
+//! Overlay listing the controls, toggled with `?` or the help button next to the room name.
+//!
+//! The list is built from the stored key bindings every time it is shown, so it stays right after
+//! keys were rebound on the start screen.
+
+use wasm_bindgen::JsValue;
+use web_sys::Document;
+
+use curve_fever_common::EmoteId;
+
+use crate::{
+    i18n::{tr, Text},
+    preferences::{Action, Preferences},
+};
+
+const OVERLAY_ID: &str = "help_overlay";
+
+pub fn toggle(doc: &Document) -> Result<(), JsValue> {
+    let overlay = match doc.get_element_by_id(OVERLAY_ID) {
+        Some(overlay) => overlay,
+        None => return Ok(()),
+    };
+    if overlay.class_name() == "visible" {
+        overlay.set_class_name("hidden");
+        return Ok(());
+    }
+
+    overlay.set_inner_html("");
+    let title = doc.create_element("p")?;
+    title.set_text_content(Some(tr(Text::Controls)));
+    overlay.append_child(&title)?;
+    let table = doc.create_element("table")?;
+    for (keys, text) in controls() {
+        let row = doc.create_element("tr")?;
+        let keys_cell = doc.create_element("td")?;
+        for key in keys {
+            let kbd = doc.create_element("kbd")?;
+            kbd.set_text_content(Some(&key));
+            keys_cell.append_child(&kbd)?;
+        }
+        row.append_child(&keys_cell)?;
+        let text_cell = doc.create_element("td")?;
+        text_cell.set_text_content(Some(tr(text)));
+        row.append_child(&text_cell)?;
+        table.append_child(&row)?;
+    }
+    overlay.append_child(&table)?;
+    overlay.set_class_name("visible");
+    Ok(())
+}
+
+/// Keys, as shown to the player, and what they do
+fn controls() -> Vec<(Vec<String>, Text)> {
+    let bindings = Preferences::load().keys;
+    let mut controls: Vec<_> = Action::ALL
+        .iter()
+        .map(|&action| {
+            let mut keys = vec![key_name(bindings.key(action))];
+            for key in action.fixed_keys().iter().map(|key| key_name(key)) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+            (keys, action_text(action))
+        })
+        .collect();
+    let emotes = format!("1-{}", EmoteId::ALL.len());
+    let fixed = [
+        (" ", Text::HelpGhost),
+        (" ", Text::HelpStart),
+        ("Escape", Text::HelpCancelAutoStart),
+        ("p", Text::HelpPause),
+        (&emotes, Text::HelpEmotes),
+        ("Tab", Text::HelpWatchNext),
+        ("c", Text::HelpFollow),
+        ("g", Text::HelpRenderer),
+        ("F3", Text::HelpDebug),
+        ("?", Text::HelpToggle),
+    ];
+    controls.extend(fixed.iter().map(|(key, text)| (vec![key_name(key)], *text)));
+    controls
+}
+
+fn action_text(action: Action) -> Text {
+    match action {
+        Action::Left => Text::KeyLeft,
+        Action::Right => Text::KeyRight,
+        Action::Boost => Text::KeyBoost,
+        Action::Brake => Text::KeyBrake,
+        Action::UseItem => Text::KeyItem,
+    }
+}
+
+/// `KeyboardEvent.key` values in a readable form
+fn key_name(key: &str) -> String {
+    match key {
+        " " => tr(Text::SpaceKey).into(),
+        "ArrowLeft" => "←".into(),
+        "ArrowRight" => "→".into(),
+        "ArrowUp" => "↑".into(),
+        "ArrowDown" => "↓".into(),
+        key if key.chars().count() == 1 => key.to_uppercase(),
+        key => key.into(),
+    }
+}
@@ -0,0 +1,400 @@
+//! Translations of the user interface.
+//!
+//! Every text shown to the player is a `Text` with one translation per `Language`. Texts with
+//! `{}` in them are filled in with `trf`. Static texts of the page carry their key in a
+//! `data-i18n` attribute (`data-i18n-placeholder` for placeholders) and are replaced by
+//! `translate_page`.
+//!
+//! Messages coming from the server, e.g. why joining a room failed, are shown as they are.
+
+use std::{fmt::Display, sync::Mutex};
+use wasm_bindgen::JsCast;
+use web_sys::Element;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Language {
+    English,
+    German,
+    French,
+}
+
+impl Language {
+    pub const ALL: [Language; 3] = [Language::English, Language::German, Language::French];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::German => "de",
+            Language::French => "fr",
+        }
+    }
+
+    /// Name of the language in itself, for the language picker
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "Deutsch",
+            Language::French => "Français",
+        }
+    }
+
+    /// Accepts language tags with a region, like `de-AT`
+    pub fn from_code(code: &str) -> Option<Language> {
+        let primary = code.split('-').next()?.to_lowercase();
+        Language::ALL
+            .iter()
+            .copied()
+            .find(|language| language.code() == primary)
+    }
+
+    /// The language of the browser, English if there is no translation for it
+    pub fn detect() -> Language {
+        web_sys::window()
+            .and_then(|window| window.navigator().language())
+            .and_then(|code| Language::from_code(&code))
+            .unwrap_or(Language::English)
+    }
+}
+
+static LANGUAGE: Mutex<Language> = Mutex::new(Language::English);
+
+pub fn language() -> Language {
+    *LANGUAGE.lock().unwrap()
+}
+
+pub fn set_language(language: Language) {
+    *LANGUAGE.lock().unwrap() = language;
+}
+
+macro_rules! texts {
+    ($($name:ident => [$en:literal, $de:literal, $fr:literal],)*) => {
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub enum Text {
+            $($name,)*
+        }
+
+        impl Text {
+            const ALL: &'static [Text] = &[$(Text::$name,)*];
+
+            /// Key in the `data-i18n` attributes of the page
+            fn key(&self) -> &'static str {
+                match self {
+                    $(Text::$name => stringify!($name),)*
+                }
+            }
+
+            /// In the order of `Language::ALL`
+            fn translations(&self) -> [&'static str; 3] {
+                match self {
+                    $(Text::$name => [$en, $de, $fr],)*
+                }
+            }
+        }
+    };
+}
+
+texts! {
+    // start screen
+    NameLabel => ["Name:", "Name:", "Nom :"],
+    RoomLabel => ["Room:", "Raum:", "Salle :"],
+    CreateRoom => ["Create new room", "Neuen Raum erstellen", "Créer une salle"],
+    JoinRoom => ["Join existing room", "Raum beitreten", "Rejoindre la salle"],
+    WatchRoom => ["Watch room", "Raum zuschauen", "Regarder la salle"],
+    QuickPlay => ["Quick play", "Schnelles Spiel", "Partie rapide"],
+    Practice => ["Practice", "Üben", "Entraînement"],
+    PracticeBots => ["Bots:", "Bots:", "Bots :"],
+    LeaveRoom => ["Leave", "Verlassen", "Quitter"],
+    RegisterName => ["Register name", "Namen registrieren", "Enregistrer le nom"],
+    RoomTitleLabel => ["Room name:", "Raumname:", "Nom de la salle :"],
+    InvalidRoomCode => [
+        "Room codes are 7 letters and digits, without 0, O, 1 or I",
+        "Raumcodes bestehen aus 7 Buchstaben und Ziffern, ohne 0, O, 1 und I",
+        "Les codes de salle ont 7 lettres et chiffres, sans 0, O, 1 ni I"
+    ],
+    EnterRoomToWatch => [
+        "Enter the room to watch",
+        "Gib den Raum zum Zuschauen ein",
+        "Entrez la salle à regarder"
+    ],
+    LoggedIn => [
+        "Logged in as {} (rating {}, {} of {} rounds won)",
+        "Angemeldet als {} (Wertung {}, {} von {} Runden gewonnen)",
+        "Connecté en tant que {} (classement {}, {} manches gagnées sur {})"
+    ],
+    KeyLeft => ["Left", "Links", "Gauche"],
+    KeyRight => ["Right", "Rechts", "Droite"],
+    KeyBoost => ["Boost", "Boost", "Accélérer"],
+    KeyBrake => ["Brake", "Bremsen", "Freiner"],
+    KeyItem => ["Item", "Item", "Objet"],
+    Controls => ["Controls", "Steuerung", "Commandes"],
+    SpaceKey => ["Space", "Leertaste", "Espace"],
+    HelpGhost => [
+        "Ghost, during a round",
+        "Geist, während einer Runde",
+        "Fantôme, pendant une manche"
+    ],
+    HelpStart => [
+        "Start the round, as host",
+        "Runde starten, als Gastgeber",
+        "Lancer la manche, en tant qu'hôte"
+    ],
+    HelpCancelAutoStart => [
+        "Cancel the automatic start",
+        "Automatischen Start abbrechen",
+        "Annuler le lancement automatique"
+    ],
+    HelpPause => [
+        "Pause or resume, as host",
+        "Pausieren oder fortsetzen, als Gastgeber",
+        "Pause ou reprise, en tant qu'hôte"
+    ],
+    HelpEmotes => ["Emotes", "Emotes", "Émotes"],
+    HelpWatchNext => [
+        "Watch the next survivor once out",
+        "Nach dem Ausscheiden den nächsten Überlebenden ansehen",
+        "Suivre le survivant suivant une fois éliminé"
+    ],
+    HelpFollow => [
+        "Camera follows you",
+        "Kamera folgt dir",
+        "La caméra vous suit"
+    ],
+    HelpRenderer => [
+        "Switch between 2D, WebGL and WebGL in a worker",
+        "Zwischen 2D, WebGL und WebGL in einem Worker wechseln",
+        "Basculer entre 2D, WebGL et WebGL dans un worker"
+    ],
+    HelpDebug => ["Debug overlay", "Debug-Anzeige", "Affichage de débogage"],
+    HelpToggle => [
+        "Show or hide this help",
+        "Diese Hilfe ein- oder ausblenden",
+        "Afficher ou masquer cette aide"
+    ],
+    PatternedTrails => [
+        "Patterned trails (color-blind mode)",
+        "Gemusterte Spuren (Modus für Farbenblinde)",
+        "Traces à motifs (mode daltonien)"
+    ],
+    LanguageLabel => ["Language:", "Sprache:", "Langue :"],
+
+    // connection
+    ReconnectNow => ["Reconnect now", "Jetzt neu verbinden", "Se reconnecter"],
+    ReconnectingIn => [
+        "Reconnecting in {} seconds...",
+        "Neue Verbindung in {} Sekunden...",
+        "Reconnexion dans {} secondes..."
+    ],
+    Reconnecting => ["Reconnecting...", "Verbinde neu...", "Reconnexion..."],
+    ServerWasShutDown => [
+        "The server was shut down",
+        "Der Server wurde heruntergefahren",
+        "Le serveur a été arrêté"
+    ],
+    ServerUnreachable => [
+        "Could not reach the server",
+        "Der Server ist nicht erreichbar",
+        "Impossible de joindre le serveur"
+    ],
+    ConnectionLost => [
+        "Connection lost (code {})",
+        "Verbindung verloren (Code {})",
+        "Connexion perdue (code {})"
+    ],
+    ReplayNotFound => ["Replay not found", "Wiederholung nicht gefunden", "Replay introuvable"],
+    Replay => ["Replay", "Wiederholung", "Replay"],
+    PracticeRoom => ["Practice", "Übungsraum", "Entraînement"],
+    PracticeName => ["Player", "Spieler", "Joueur"],
+
+    // canvas
+    Paused => ["PAUSED", "PAUSIERT", "EN PAUSE"],
+    SuddenDeath => ["SUDDEN DEATH", "SUDDEN DEATH", "MORT SUBITE"],
+    RoundOver => ["ROUND OVER", "RUNDE VORBEI", "MANCHE TERMINÉE"],
+    NewBestRun => ["NEW BEST RUN", "NEUE BESTZEIT", "NOUVEAU RECORD"],
+    WinsTheRound => ["{} wins the round!", "{} gewinnt die Runde!", "{} remporte la manche !"],
+    PointsGained => [" +{} points", " +{} Punkte", " +{} points"],
+    NextRoundIn => ["NEXT ROUND IN {}s", "NÄCHSTE RUNDE IN {}s", "PROCHAINE MANCHE DANS {}s"],
+    EscToCancel => [" (ESC TO CANCEL)", " (ESC ZUM ABBRECHEN)", " (ÉCHAP POUR ANNULER)"],
+    ServerRestartIn => [
+        "SERVER RESTARTS IN {}s",
+        "SERVER STARTET IN {}s NEU",
+        "REDÉMARRAGE DU SERVEUR DANS {}s"
+    ],
+    ServerShutdownIn => [
+        "SERVER SHUTDOWN IN {}s",
+        "SERVER STOPPT IN {}s",
+        "ARRÊT DU SERVEUR DANS {}s"
+    ],
+    JoinNextRound => [
+        "You join with the next round",
+        "Du spielst ab der nächsten Runde mit",
+        "Vous jouez à partir de la prochaine manche"
+    ],
+    AfkWarning => [
+        "You will be removed for inactivity in {}s",
+        "Du wirst in {}s wegen Inaktivität entfernt",
+        "Vous serez retiré pour inactivité dans {}s"
+    ],
+    CutOff => ["{} cut off {}", "{} schneidet {} ab", "{} a coupé {}"],
+    Watching => ["Watching {} (Tab)", "Du schaust {} zu (Tab)", "Vous regardez {} (Tab)"],
+    NoItem => ["No item", "Kein Item", "Aucun objet"],
+    Eraser => ["Eraser", "Radierer", "Gomme"],
+    Confusion => ["Confusion", "Verwirrung", "Confusion"],
+    GapAhead => ["Gap ahead", "Lücke kommt", "Trou imminent"],
+    ControlsInverted => [
+        "Controls inverted!",
+        "Steuerung vertauscht!",
+        "Commandes inversées !"
+    ],
+    SaveImage => ["Save image", "Bild speichern", "Enregistrer l'image"],
+    SaveVideo => ["Save video", "Video speichern", "Enregistrer la vidéo"],
+    ShareReplay => ["Share replay", "Wiederholung teilen", "Partager le replay"],
+    MatchFinished => ["Match finished", "Spiel beendet", "Match terminé"],
+    Points => ["Points", "Punkte", "Points"],
+    Distance => ["Distance", "Strecke", "Distance"],
+    DistanceScore => ["{} px", "{} px", "{} px"],
+    RoomStandings => ["Room standings", "Wertung des Raums", "Classement du salon"],
+    Matches => ["Matches", "Spiele", "Matchs"],
+    MatchesWon => ["Won", "Gewonnen", "Gagnés"],
+    RoundsWon => ["Rounds won", "Runden gewonnen", "Manches gagnées"],
+
+    // right column
+    SpeedLabel => ["Speed:", "Tempo:", "Vitesse :"],
+    ThemeLabel => ["Theme:", "Farben:", "Thème :"],
+    ScoringLabel => ["Scoring:", "Wertung:", "Score :"],
+    SurvivalScoring => ["Survival", "Überleben", "Survie"],
+    DistanceScoring => ["Distance", "Strecke", "Distance"],
+    MapLabel => ["Map:", "Karte:", "Carte :"],
+    OpenMap => ["Open", "Offen", "Ouverte"],
+    RampsMap => ["Ramps", "Rampen", "Rampes"],
+    SwampMap => ["Swamp", "Sumpf", "Marais"],
+    PortalsMap => ["Portals", "Portale", "Portails"],
+    HitboxLabel => ["Hitbox:", "Trefferzone:", "Zone de collision :"],
+    BandHitbox => ["Full line", "Ganze Linie", "Ligne entière"],
+    SquareHitbox => ["Square head", "Eckiger Kopf", "Tête carrée"],
+    CircleHitbox => ["Round head", "Runder Kopf", "Tête ronde"],
+    GridLabel => ["Grid:", "Feld:", "Grille :"],
+    MaxPlayersLabel => ["Max players:", "Max. Spieler:", "Joueurs max :"],
+    TargetScoreLabel => ["Target score:", "Zielpunktzahl:", "Score cible :"],
+    BestOfLabel => ["Best of (rounds):", "Best of (Runden):", "Au meilleur de (manches) :"],
+    TrailFadeLabel => ["Trail fade (s):", "Spuren verblassen (s):", "Effacement des traces (s) :"],
+    AutoStartLabel => ["Auto start (players):", "Autostart (Spieler):", "Départ auto (joueurs) :"],
+    TickRateLabel => ["Tick rate (per s):", "Tickrate (pro s):", "Fréquence (par s) :"],
+    EqualDelayLabel => [
+        "Equal input delay:",
+        "Gleiche Eingabeverzögerung:",
+        "Délai d'entrée égal :"
+    ],
+    HazardsLabel => ["Hazards:", "Hindernisse:", "Obstacles :"],
+    GrowingTrailsLabel => ["Growing trails:", "Wachsende Spuren:", "Traces croissantes :"],
+    GapIntervalLabel => ["Gap interval:", "Lückenabstand:", "Intervalle des trous :"],
+    GapLengthLabel => ["Gap length:", "Lückenlänge:", "Longueur des trous :"],
+    GapVariationLabel => ["Gap variation:", "Lückenstreuung:", "Variation des trous :"],
+    GapValues => [
+        "Gaps every {} widths, {} long, ±{}%",
+        "Lücken alle {} Breiten, {} lang, ±{}%",
+        "Trous toutes les {} largeurs, longs de {}, ±{}%"
+    ],
+    ItemsLabel => ["Items:", "Items:", "Objets :"],
+    KeepItemsLabel => ["Keep items:", "Items behalten:", "Garder les objets :"],
+    RoomTraffic => [
+        "Upload: {} kB/s, {} messages/s to {} connections",
+        "Upload: {} kB/s, {} Nachrichten/s an {} Verbindungen",
+        "Envoi : {} ko/s, {} messages/s vers {} connexions"
+    ],
+    PlaceholderNone => ["none", "keine", "aucun"],
+    PlaceholderOff => ["off", "aus", "non"],
+    ChatPlaceholder => [
+        "Chat (Enter to send)",
+        "Chat (Enter zum Senden)",
+        "Chat (Entrée pour envoyer)"
+    ],
+    NextRound => ["next round", "nächste Runde", "prochaine manche"],
+    You => [" (You)", " (Du)", " (Vous)"],
+    KillsThisMatch => ["Kills this match", "Abschüsse in diesem Spiel", "Éliminations de ce match"],
+    RoundWinsThisMatch => [
+        "Rounds won this match",
+        "Gewonnene Runden in diesem Spiel",
+        "Manches gagnées dans ce match"
+    ],
+    MatchWinsInRoom => [
+        "Matches won in this room",
+        "Gewonnene Spiele in diesem Raum",
+        "Matchs gagnés dans ce salon"
+    ],
+    RoundOf => ["Round {}/{}", "Runde {}/{}", "Manche {}/{}"],
+    FirstTo => ["First to {}", "Wer zuerst {} hat", "Premier à {}"],
+    MatchWonBy => ["{} wins the match!", "{} gewinnt das Spiel!", "{} remporte le match !"],
+    SpectatorsWatching => ["{} watching", "{} schauen zu", "{} spectateurs"],
+    RoundNumber => ["Round {}", "Runde {}", "Manche {}"],
+    JoinYourMatch => ["Join your match", "Zu deinem Spiel", "Rejoindre votre match"],
+    Champion => ["Champion: {}", "Sieger: {}", "Champion : {}"],
+    EventJoined => ["{} joined", "{} ist beigetreten", "{} a rejoint"],
+    EventLeft => ["{} left", "{} hat den Raum verlassen", "{} est parti"],
+    EventHostChanged => [
+        "{} is the host now",
+        "{} ist jetzt Gastgeber",
+        "{} est maintenant l'hôte"
+    ],
+    EventRoundStarted => ["Round {} started", "Runde {} gestartet", "La manche {} a commencé"],
+    EventCrashed => ["{} crashed", "{} ist gecrasht", "{} s'est écrasé"],
+    EventCrashedInto => [
+        "{} crashed into {}",
+        "{} ist in {} gecrasht",
+        "{} s'est écrasé contre {}"
+    ],
+    EventRoundWon => ["{} won the round", "{} hat die Runde gewonnen", "{} a gagné la manche"],
+    MostAggressive => [
+        "Most aggressive driver: {} ({} near-misses)",
+        "Aggressivste Fahrweise: {} ({} knappe Ausweichmanöver)",
+        "Pilote le plus agressif : {} ({} frôlements)"
+    ],
+    BusiestHands => [
+        "Busiest hands: {} ({} turns)",
+        "Fleißigste Finger: {} ({} Kurven)",
+        "Mains les plus actives : {} ({} virages)"
+    ],
+    StraightShooter => [
+        "Straight shooter: {} ({}% straight ahead)",
+        "Geradeausfahrer: {} ({}% geradeaus)",
+        "Tout droit : {} ({}% en ligne droite)"
+    ],
+}
+
+/// The text in the current language
+pub fn tr(text: Text) -> &'static str {
+    text.translations()[language() as usize]
+}
+
+/// The text in the current language with its `{}` replaced by `args` in order
+pub fn trf(text: Text, args: &[&dyn Display]) -> String {
+    let mut out = String::new();
+    for (i, part) in tr(text).split("{}").enumerate() {
+        if let Some(arg) = i.checked_sub(1).and_then(|i| args.get(i)) {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// Replaces the static texts inside of `root` with the ones of the current language.
+pub fn translate_page(root: &Element) {
+    let elements = |attribute: &str, text: &Text| -> Vec<Element> {
+        let selector = format!("[{}={}]", attribute, text.key());
+        let list = match root.query_selector_all(&selector) {
+            Ok(list) => list,
+            Err(_) => return vec![],
+        };
+        (0..list.length())
+            .filter_map(|i| list.item(i)?.dyn_into::<Element>().ok())
+            .collect()
+    };
+    for text in Text::ALL {
+        for element in elements("data-i18n", text) {
+            element.set_text_content(Some(tr(*text)));
+        }
+        for element in elements("data-i18n-placeholder", text) {
+            let _ = element.set_attribute("placeholder", tr(*text));
+        }
+    }
+}
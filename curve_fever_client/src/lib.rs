@@ -1,18 +1,51 @@
 use arrayvec::ArrayString;
 use lazy_static;
-use std::{collections::HashMap, ops::Deref, ops::DerefMut, rc::Rc, sync::Mutex};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Deref,
+    ops::DerefMut,
+    rc::Rc,
+    sync::Mutex,
+};
 use wasm_bindgen::convert::FromWasmAbi;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    Blob, CanvasRenderingContext2d, Document, Element, Event, EventTarget, FileReader,
-    HtmlButtonElement, HtmlCanvasElement, HtmlElement, HtmlInputElement, InputEvent, KeyboardEvent,
-    MessageEvent, ProgressEvent, Text, TouchEvent, WebSocket, Window,
+    Blob, BlobEvent, CanvasRenderingContext2d, CloseEvent, Document, Element, Event, EventTarget,
+    FileReader, HtmlButtonElement, HtmlCanvasElement, HtmlElement, HtmlInputElement,
+    HtmlSelectElement, InputEvent, KeyboardEvent, MediaRecorder, MessageEvent, ProgressEvent,
+    TouchEvent, Url, WebSocket, Window, XmlHttpRequest, XmlHttpRequestResponseType,
 };
 
-use curve_fever_common::{ClientMessage, Direction, GridInfo, Player, PlayerState, ServerMessage};
+use curve_fever_common::{
+    Account, ChatMessage, ClientMessage, Death, DeathCause, Direction, EmoteId, GapConfig,
+    GhostRun, GridInfo, Hazard, Hitbox, Input, InputStats, Item, ItemKind, MapPreset, Player,
+    PlayerState, PortalPair, Replay, RoomConfig, RoomEvent, RoomSnapshot, RoundResult, ScoringMode,
+    ServerMessage, SessionStats, SpeedChange, SpeedPreset, SpeedZone, Teleport, Theme,
+    TournamentState, TrafficStats, TrailPattern, TrailSync, HAZARD_WIDTH, ITEM_RADIUS,
+    PORTAL_RADIUS, ROOM_CODE_LENGTH, SLOW_MOTION_FACTOR, SLOW_MOTION_SECONDS, TICK_RATE_LIMITS,
+};
+use instant::Instant;
 use uuid::Uuid;
 
+mod api;
+mod callbacks;
+mod diagnostics;
+mod help;
+mod i18n;
+mod practice;
+mod preferences;
+mod render;
+mod worker;
+
+use callbacks::CallbackRegistry;
+use i18n::{tr, trf, Language, Text};
+use practice::Practice;
+use preferences::{Action, Backend, KeyBindings, Preferences};
+use render::{Canvas2d, Rect, Renderer, WebGl};
+use worker::WorkerRenderer;
+
 type JsResult<T> = Result<T, JsValue>;
 type JsError = Result<(), JsValue>;
 type JsClosure<T> = Closure<dyn FnMut(T) -> JsError>;
@@ -36,78 +69,824 @@ struct Line {
     to: (f64, f64),
     linewidth: f64,
     color: ArrayString<7>,
+    dashes: &'static [f64],      // dash pattern, see `TrailPattern::dashes`
+    offset: f64,                 // distance along the trail at `from`, continues the pattern
+    control: Option<(f64, f64)>, // control point if the line is a quadratic curve
+}
+
+impl Line {
+    fn midpoint(&self) -> (f64, f64) {
+        (
+            (self.from.0 + self.to.0) / 2.,
+            (self.from.1 + self.to.1) / 2.,
+        )
+    }
+
+    fn length(&self) -> f64 {
+        (self.to.0 - self.from.0).hypot(self.to.1 - self.from.1)
+    }
+}
+
+/// Rounds off the corners between consecutive segments of a trail.
+///
+/// Each joint becomes a quadratic curve from the midpoint of the segment before it to the midpoint
+/// of the segment after it, with the joint as control point. Where the trail ends or breaks, the
+/// segment is finished with a straight line.
+fn smooth(lines: &[Line]) -> Vec<Line> {
+    let joined = |a: &Line, b: &Line| a.to == b.from;
+    let mut smoothed = Vec::with_capacity(lines.len() + 1);
+    for (i, line) in lines.iter().enumerate() {
+        let prev = i
+            .checked_sub(1)
+            .map(|j| &lines[j])
+            .filter(|prev| joined(prev, line));
+        smoothed.push(match prev {
+            Some(prev) => Line {
+                from: prev.midpoint(),
+                to: line.midpoint(),
+                offset: prev.offset + prev.length() / 2.,
+                control: Some(line.from),
+                ..*line
+            },
+            None => Line {
+                to: line.midpoint(),
+                ..*line
+            },
+        });
+        if !lines.get(i + 1).is_some_and(|next| joined(line, next)) {
+            smoothed.push(Line {
+                from: line.midpoint(),
+                offset: line.offset + line.length() / 2.,
+                ..*line
+            });
+        }
+    }
+    smoothed
+}
+
+/// Retained trail segments of every player, so the board can be replayed at any time.
+#[derive(Default)]
+struct Trails {
+    segments: HashMap<Uuid, Vec<(u32, Line)>>, // with the tick each segment was drawn in
+}
+
+impl Trails {
+    fn push(&mut self, id: Uuid, tick: u32, line: Line) {
+        self.segments.entry(id).or_default().push((tick, line));
+    }
+
+    /// Drops the segments drawn before `tick` and returns them.
+    fn expire(&mut self, tick: u32) -> Vec<Line> {
+        let mut expired = vec![];
+        for lines in self.segments.values_mut() {
+            // segments are pushed in order, the old ones are at the front
+            let n = lines.partition_point(|(t, _)| *t < tick);
+            expired.extend(lines.drain(..n).map(|(_, line)| line));
+        }
+        expired
+    }
+
+    fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    /// Drops the segments touching a circle.
+    fn erase(&mut self, (x, y): (f64, f64), radius: f64) {
+        let inside = |(px, py): (f64, f64)| (px - x).hypot(py - y) <= radius;
+        for lines in self.segments.values_mut() {
+            lines.retain(|(_, line)| !inside(line.from) && !inside(line.to));
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &(u32, Line)> {
+        self.segments.values().flatten()
+    }
+
+    /// The smoothed trails of all players, only with the segments of ticks that are `shown`
+    fn smoothed(&self, shown: impl Fn(u32) -> bool) -> Vec<Line> {
+        self.segments
+            .values()
+            .flat_map(|segments| {
+                let lines: Vec<Line> = segments
+                    .iter()
+                    .filter(|(tick, _)| shown(*tick))
+                    .map(|(_, line)| *line)
+                    .collect();
+                smooth(&lines)
+            })
+            .collect()
+    }
+
+    /// What the latest segment of a player adds to the smoothed trail: the curve up to its
+    /// midpoint, and the rest of the segment before if the trail broke in between. The rest of
+    /// the latest segment follows along with the next one.
+    fn smoothed_latest(&self, id: &Uuid) -> Vec<Line> {
+        let segments = match self.segments.get(id) {
+            Some(segments) => segments,
+            None => return vec![],
+        };
+        let n = segments.len();
+        let lines: Vec<Line> = segments[n.saturating_sub(2)..]
+            .iter()
+            .map(|(_, line)| *line)
+            .collect();
+        let mut smoothed = smooth(&lines);
+        smoothed.pop();
+        if n >= 2 {
+            // already drawn with the segment before
+            smoothed.remove(0);
+        }
+        smoothed
+    }
+}
+
+const CRASH_ANIMATION_FRAMES: u32 = 15;
+/// Progress to the next gap from which on the local player sees it coming, in percent
+const GAP_HINT_PROGRESS: u8 = 85;
+const FLASH_ANIMATION_FRAMES: u32 = 12;
+const CAMERA_ZOOM: f64 = 2.5;
+/// Opacity of the best run of the local player, drawn under the trails
+const BEST_RUN_ALPHA: f64 = 0.3;
+/// Steps of the best run longer than this are teleports, which are left out
+const BEST_RUN_MAX_STEP: f64 = 50.;
+
+#[derive(Copy, Clone)]
+struct Crash {
+    pos: (f64, f64),
+    size: f64,
+    color: ArrayString<7>,
+    frame: u32,
+    tick: u32, // when the player crashed, to replay it
+}
+
+/// Shown where a player went into a portal and where it came out
+struct Flash {
+    pos: (f64, f64),
+    color: ArrayString<7>,
+    frame: u32,
 }
 
 struct Canvas {
     canvas: HtmlCanvasElement,
-    context: CanvasRenderingContext2d,
+    renderer: Box<dyn Renderer>,
     width: u32,
     height: u32,
-    lines: Vec<Line>,
+    trails: Trails,
+    crashes: Vec<Crash>,
+    wall_margin: u32,
+    camera: Option<(f64, f64)>, // center of the view when following a player
+    tick: u32,                  // ticks of the round so far, to age the trails
+    items: HashMap<u32, Item>,  // powerups on the field by id
+    patterns: bool,             // draw the dash patterns of the trails
+    replay: Option<u32>,        // during a replay only what happened up to this tick is shown
+    theme: Theme,
+    hazards: Vec<Hazard>,
+    zones: Vec<SpeedZone>, // of the map, drawn under the trails
+    portals: Vec<PortalPair>,
+    flashes: Vec<Flash>,  // of teleports, removed once their animation is over
+    dirty: Option<Rect>,  // to redraw with the next frame, only used without the camera
+    gap_heads: Vec<Line>, // heads of players in a gap, drawn on top of the board every frame
+    best_run: Vec<Line>,  // to drive against, the segment at `i` is shown from tick `i + 1` on
+}
+
+/// Creates the renderer of the main canvas, the worker falls back to WebGL and WebGL to the 2D
+/// context if unavailable.
+fn create_renderer(canvas: &HtmlCanvasElement, backend: Backend) -> JsResult<Box<dyn Renderer>> {
+    if backend == Backend::Worker {
+        if worker::supported() {
+            return Ok(Box::new(WorkerRenderer::new(canvas)?));
+        }
+        console_log!("OffscreenCanvas is not supported, drawing on the main thread");
+    }
+    if backend != Backend::Canvas2d {
+        match WebGl::new(canvas.clone(), true)? {
+            Some(renderer) => return Ok(Box::new(renderer)),
+            None => console_log!("WebGL 2 is not supported, using the 2D canvas"),
+        }
+    }
+    Ok(Box::new(Canvas2d::new(canvas.clone())?))
+}
+
+/// Replaces the main canvas by a fresh copy of it.
+///
+/// A canvas keeps the type of its first context and one handed to a worker can't be resized or
+/// drawn on from the page again.
+fn fresh_canvas(canvas: &HtmlCanvasElement) -> JsResult<HtmlCanvasElement> {
+    let fresh = canvas.clone_node()?.dyn_into::<HtmlCanvasElement>()?;
+    canvas.replace_with_with_node_1(&fresh)?;
+    Ok(fresh)
 }
 
 impl Canvas {
-    fn new(base: Rc<Base>, width: u32, height: u32) -> JsResult<Canvas> {
-        let canvas: HtmlCanvasElement = base
-            .get_element_by_id("main_canvas")?
-            .dyn_into::<HtmlCanvasElement>()?;
+    fn new(base: Rc<Base>, width: u32, height: u32, theme: Theme) -> JsResult<Canvas> {
+        let canvas = fresh_canvas(
+            &base
+                .get_element_by_id("main_canvas")?
+                .dyn_into::<HtmlCanvasElement>()?,
+        )?;
         canvas.set_width(width);
         canvas.set_height(height);
-
-        let context = canvas
-            .get_context("2d")?
-            .unwrap()
-            .dyn_into::<CanvasRenderingContext2d>()?;
-
-        context.set_line_cap("round");
+        let preferences = Preferences::load();
+        let renderer = create_renderer(&canvas, preferences.backend)?;
 
         Ok(Canvas {
             canvas,
-            context,
+            renderer,
             width,
             height,
-            lines: Vec::new(),
+            trails: Trails::default(),
+            crashes: Vec::new(),
+            wall_margin: 0,
+            camera: None,
+            tick: 0,
+            items: HashMap::new(),
+            patterns: preferences.patterns,
+            replay: None,
+            theme,
+            hazards: vec![],
+            zones: vec![],
+            portals: vec![],
+            flashes: vec![],
+            dirty: None,
+            gap_heads: vec![],
+            best_run: vec![],
         })
     }
 
-    fn draw_line(&self, line: &Line) {
-        self.context.set_line_width(line.linewidth);
-        self.context
-            .set_stroke_style(&line.color.to_string().into());
-        self.context.set_fill_style(&line.color.to_string().into());
+    /// Switches the backend and redraws the board with it.
+    fn set_backend(&mut self, backend: Backend) -> JsError {
+        let canvas = fresh_canvas(&self.canvas)?;
+        canvas.focus()?;
+        self.renderer = create_renderer(&canvas, backend)?;
+        self.canvas = canvas;
+        self.redraw_all();
+        Ok(())
+    }
 
-        self.context.begin_path();
-        let from_x = line.from.0;
-        let from_y = line.from.1;
-        self.context.move_to(from_x, from_y);
+    fn draw_line(&self, line: &Line) {
+        self.stroke_trails(vec![*line]);
+    }
 
-        let to_x = line.to.0;
-        let to_y = line.to.1;
-        self.context.line_to(to_x, to_y);
-        self.context.stroke();
+    /// Strokes trail segments, solid unless patterns are enabled.
+    fn stroke_trails(&self, mut lines: Vec<Line>) {
+        if !self.patterns {
+            lines.iter_mut().for_each(|line| line.dashes = &[]);
+        }
+        self.renderer.stroke_lines(&lines);
     }
 
-    fn draw(&mut self, line: Line, invisible: bool) {
+    fn draw(&mut self, id: Uuid, line: Line, invisible: bool) {
         //console_log!("Drawing Canvas... {}: from ({}-{}) to ({}-{})", color, from.0, from.1, to.0, to.1);
         let mut line = line;
         if invisible {
-            self.redraw_all();
+            if self.camera.is_some() {
+                // the whole view is redrawn after all players are updated
+                return;
+            }
             line.from = line.to;
+            self.mark_dirty(Rect::of_line(&line));
+            self.gap_heads.push(line);
         } else {
-            self.lines.push(line);
+            self.trails.push(id, self.tick, line);
+            if self.camera.is_none() {
+                self.stroke_trails(self.trails.smoothed_latest(&id));
+            }
+        }
+    }
+
+    fn set_camera(&mut self, camera: Option<(f64, f64)>) {
+        self.camera = camera;
+    }
+
+    /// Renders the whole board, also when following a player, as PNG data URL.
+    fn png_url(&mut self) -> JsResult<String> {
+        let camera = self.camera.take();
+        self.redraw_all();
+        let url = self.canvas.to_data_url();
+        self.set_camera(camera);
+        self.redraw_all();
+        url
+    }
+
+    /// Whether a line is (partially) inside the zoomed view.
+    fn in_view(&self, line: &Line) -> bool {
+        match self.camera {
+            Some((cx, cy)) => {
+                let half_w = self.width as f64 / 2. / CAMERA_ZOOM + line.linewidth;
+                let half_h = self.height as f64 / 2. / CAMERA_ZOOM + line.linewidth;
+                let inside =
+                    |(x, y): (f64, f64)| (x - cx).abs() < half_w && (y - cy).abs() < half_h;
+                inside(line.from) || inside(line.to)
+            }
+            None => true,
+        }
+    }
+
+    /// Position of a point of the arena on the page, relative to the canvas' wrapper
+    fn to_screen(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (x, y) = match self.camera {
+            Some((cx, cy)) => (
+                (x - cx) * CAMERA_ZOOM + self.width as f64 / 2.,
+                (y - cy) * CAMERA_ZOOM + self.height as f64 / 2.,
+            ),
+            None => (x, y),
+        };
+        // the canvas may be shown smaller than its resolution
+        let scale = self.canvas.client_width() as f64 / self.width as f64;
+        (
+            self.canvas.offset_left() as f64 + x * scale,
+            self.canvas.offset_top() as f64 + y * scale,
+        )
+    }
+
+    fn apply_view(&self) {
+        self.renderer
+            .set_view(self.camera.map(|camera| (camera, CAMERA_ZOOM)));
+    }
+
+    fn draw_arrow(&self, pos: (f64, f64), rotation: f64, size: f64, color: &ArrayString<7>) {
+        // same heading convention as the server: x follows sin, y follows cos
+        let (dx, dy) = (rotation.to_radians().sin(), rotation.to_radians().cos());
+        let base = (pos.0 + dx * size * 1.5, pos.1 + dy * size * 1.5);
+        let tip = (pos.0 + dx * size * 3.5, pos.1 + dy * size * 3.5);
+
+        self.renderer.fill_triangle(
+            [
+                tip,
+                (base.0 + dy * size, base.1 - dx * size),
+                (base.0 - dy * size, base.1 + dx * size),
+            ],
+            color,
+        );
+    }
+
+    fn draw_crash(&self, crash: &Crash) {
+        let (x, y) = crash.pos;
+        if crash.frame < CRASH_ANIMATION_FRAMES {
+            // expanding burst, fading out
+            let progress = crash.frame as f64 / CRASH_ANIMATION_FRAMES as f64;
+            self.renderer.fill_circle(
+                crash.pos,
+                crash.size * (1. + 3. * progress),
+                &crash.color,
+                1. - progress,
+            );
+        }
+
+        // the X stays until the next round
+        let arm = crash.size * 1.5;
+        let stroke = |from, to| Line {
+            from,
+            to,
+            linewidth: crash.size / 2.,
+            color: ArrayString::from(self.theme.foreground()).unwrap(),
+            dashes: &[],
+            offset: 0.,
+            control: None,
+        };
+        self.renderer.stroke_lines(&[
+            stroke((x - arm, y - arm), (x + arm, y + arm)),
+            stroke((x + arm, y - arm), (x - arm, y + arm)),
+        ]);
+    }
+
+    fn draw_item(&self, item: &Item) {
+        let color = match item.kind {
+            ItemKind::Eraser => self.theme.foreground(),
+            ItemKind::Confusion => self.theme.accent(),
+        };
+        self.renderer
+            .fill_circle((item.x, item.y), ITEM_RADIUS, color, 1.);
+    }
+
+    fn draw_hazards(&self) {
+        let lines: Vec<Line> = self
+            .hazards
+            .iter()
+            .map(|hazard| Line {
+                color: ArrayString::from(self.theme.foreground()).unwrap(),
+                ..hazard_line(hazard)
+            })
+            .collect();
+        self.renderer.stroke_lines(&lines);
+    }
+
+    fn draw_zones(&self) {
+        for zone in &self.zones {
+            self.renderer.fill_rect(
+                (zone.x, zone.y),
+                (zone.width, zone.height),
+                self.theme.zone(zone.speeds_up()),
+            );
+        }
+    }
+
+    fn set_zones(&mut self, zones: Vec<SpeedZone>) {
+        self.zones = zones;
+        self.redraw_all();
+    }
+
+    /// Rings of the accent color, both ends of a pair look the same
+    fn draw_portals(&self) {
+        let ends = self.portals.iter().flat_map(|pair| vec![pair.a, pair.b]);
+        for center in ends {
+            self.renderer
+                .fill_circle(center, PORTAL_RADIUS, self.theme.accent(), 0.8);
+            self.renderer
+                .fill_circle(center, PORTAL_RADIUS * 0.6, self.theme.background(), 1.);
+        }
+    }
+
+    fn set_portals(&mut self, portals: Vec<PortalPair>) {
+        self.portals = portals;
+        self.redraw_all();
+    }
+
+    fn draw_flash(&self, flash: &Flash) {
+        let progress = flash.frame as f64 / FLASH_ANIMATION_FRAMES as f64;
+        self.renderer.fill_circle(
+            flash.pos,
+            PORTAL_RADIUS * (1. + progress),
+            &flash.color,
+            0.7 * (1. - progress),
+        );
+    }
+
+    fn add_flash(&mut self, flash: Flash) {
+        self.draw_flash(&flash);
+        self.flashes.push(flash);
+    }
+
+    /// Moves the hazards, where they were and where they are now is redrawn.
+    fn set_hazards(&mut self, hazards: Vec<Hazard>) {
+        let old = std::mem::replace(&mut self.hazards, hazards);
+        if self.camera.is_none() {
+            // with the camera the whole view is redrawn after the players moved anyway
+            let areas: Vec<Rect> = old
+                .iter()
+                .chain(&self.hazards)
+                .map(|hazard| Rect::of_line(&hazard_line(hazard)))
+                .collect();
+            areas.into_iter().for_each(|area| self.mark_dirty(area));
+            self.redraw_dirty();
+        }
+    }
+
+    fn add_crash(&mut self, crash: Crash) {
+        self.draw_crash(&crash);
+        self.crashes.push(crash);
+    }
+
+    /// Advances running crash and teleport animations, the area they cover is redrawn with the
+    /// frame.
+    fn animate_effects(&mut self) {
+        // the largest a flash or burst gets, which also covers its last frame
+        let mut areas: Vec<Rect> = self
+            .flashes
+            .iter()
+            .map(|flash| Rect::around(flash.pos, PORTAL_RADIUS * 2.))
+            .collect();
+        self.flashes.iter_mut().for_each(|flash| flash.frame += 1);
+        self.flashes
+            .retain(|flash| flash.frame < FLASH_ANIMATION_FRAMES);
+        for crash in self
+            .crashes
+            .iter_mut()
+            .filter(|c| c.frame < CRASH_ANIMATION_FRAMES)
+        {
+            areas.push(Rect::around(crash.pos, crash.size * 4.));
+            crash.frame += 1;
+        }
+        areas.into_iter().for_each(|area| self.mark_dirty(area));
+    }
+
+    /// Adds an area to redraw with the next frame.
+    fn mark_dirty(&mut self, rect: Rect) {
+        self.dirty = Some(self.dirty.map_or(rect, |dirty| dirty.union(&rect)));
+    }
+
+    /// Redraws what changed since the last frame and the heads of players in a gap.
+    ///
+    /// Only the dirty area is cleared and drawn again, which keeps long trails cheap. The heads
+    /// are drawn on top and erased with the next frame.
+    fn redraw_dirty(&mut self) {
+        let dirty = match self.dirty.take() {
+            Some(dirty) if self.camera.is_none() => dirty,
+            _ => return,
+        };
+        self.renderer.set_clip(Some(dirty));
+        self.redraw(Some(&dirty));
+        for line in std::mem::take(&mut self.gap_heads) {
+            self.draw_line(&line);
+            self.mark_dirty(Rect::of_line(&line));
         }
-        self.draw_line(&line);
+        self.renderer.set_clip(None);
     }
 
     fn redraw_all(&self) {
+        self.redraw(None);
+    }
+
+    /// Marks the segments of the best run shown since tick `from` to be drawn.
+    fn advance_best_run(&mut self, from: u32) {
+        let areas: Vec<Rect> = self
+            .best_run
+            .iter()
+            .take(self.tick as usize)
+            .skip(from as usize)
+            .map(Rect::of_line)
+            .collect();
+        areas.into_iter().for_each(|area| self.mark_dirty(area));
+    }
+
+    /// Draws the board, only what lies in `area` if given.
+    fn redraw(&self, area: Option<&Rect>) {
         self.clear();
-        self.lines.iter().for_each(|line| self.draw_line(&line));
+        let shown = |tick: u32| self.replay.is_none_or(|replay| tick <= replay);
+        let visible = |line: &Line| {
+            self.in_view(line) && area.is_none_or(|area| area.intersects(&Rect::of_line(line)))
+        };
+        let best_run: Vec<Line> = self
+            .best_run
+            .iter()
+            .take(self.tick as usize)
+            .enumerate()
+            .filter(|(i, line)| shown(*i as u32 + 1) && visible(line))
+            .map(|(_, line)| *line)
+            .collect();
+        self.renderer.stroke_lines(&best_run);
+        let mut lines = self.trails.smoothed(shown);
+        lines.retain(|line| visible(line));
+        self.stroke_trails(lines);
+        self.items.values().for_each(|item| self.draw_item(item));
+        self.draw_hazards();
+        self.crashes
+            .iter()
+            .filter(|crash| shown(crash.tick))
+            .for_each(|crash| self.draw_crash(crash));
+        self.flashes.iter().for_each(|flash| self.draw_flash(flash));
+    }
+
+    fn clear(&self) {
+        if self.camera.is_some() {
+            // outside of the arena
+            self.renderer.fill_surface(self.theme.outside());
+        }
+        self.apply_view();
+        self.renderer.fill_rect(
+            (0., 0.),
+            (self.width.into(), self.height.into()),
+            self.theme.background(),
+        );
+        self.draw_zones();
+        self.draw_portals();
+        self.draw_walls();
+    }
+
+    fn draw_walls(&self) {
+        if self.wall_margin == 0 {
+            return;
+        }
+        let (w, h, m) = (
+            self.width as f64,
+            self.height as f64,
+            self.wall_margin as f64,
+        );
+        let color = self.theme.accent();
+        self.renderer.fill_rect((0., 0.), (w, m), color);
+        self.renderer.fill_rect((0., h - m), (w, m), color);
+        self.renderer.fill_rect((0., 0.), (m, h), color);
+        self.renderer.fill_rect((w - m, 0.), (m, h), color);
+    }
+
+    fn set_wall_margin(&mut self, wall_margin: u32) {
+        self.wall_margin = wall_margin;
+        self.draw_walls();
+    }
+}
+
+/// `color` as if drawn over `background` with `alpha`, both `#RRGGBB`
+fn blend(color: &str, background: &str, alpha: f64) -> ArrayString<7> {
+    let channel = |color: &str, i: usize| {
+        color
+            .get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .unwrap_or(0) as f64
+    };
+    let mix = |i: usize| {
+        (alpha * channel(color, i) + (1. - alpha) * channel(background, i)).round() as u8
+    };
+    ArrayString::from(&format!("#{:02X}{:02X}{:02X}", mix(1), mix(3), mix(5))).unwrap()
+}
+
+/// Whether runs recorded with one config can be compared to rounds with the other
+fn same_track(recorded: &RoomConfig, config: &RoomConfig) -> bool {
+    let track = |c: &RoomConfig| (c.width, c.height, c.preset, c.map, c.tick_rate);
+    track(recorded) == track(config)
+}
+
+/// The line a hazard is drawn as, without its color
+fn hazard_line(hazard: &Hazard) -> Line {
+    Line {
+        from: hazard.ends().0,
+        to: hazard.ends().1,
+        linewidth: HAZARD_WIDTH,
+        color: ArrayString::new(),
+        dashes: &[],
+        offset: 0.,
+        control: None,
+    }
+}
+
+fn stroke_line(context: &CanvasRenderingContext2d, line: &Line) {
+    context.set_line_width(line.linewidth);
+    context.set_stroke_style(&line.color.to_string().into());
+    context.set_fill_style(&line.color.to_string().into());
+
+    context.begin_path();
+    let from_x = line.from.0;
+    let from_y = line.from.1;
+    context.move_to(from_x, from_y);
+
+    let to_x = line.to.0;
+    let to_y = line.to.1;
+    match line.control {
+        Some((cx, cy)) => context.quadratic_curve_to(cx, cy, to_x, to_y),
+        None => context.line_to(to_x, to_y),
+    }
+    context.stroke();
+}
+
+const MINIMAP_WIDTH: u32 = 250;
+
+/// Downscaled view of the whole arena, drawn from the same segments as the main canvas.
+struct Minimap {
+    context: CanvasRenderingContext2d,
+    head: HtmlElement,
+    width: u32,
+    height: u32,
+    scale: f64,
+    theme: Theme,
+}
+
+impl Minimap {
+    fn new(base: &Base, width: u32, height: u32, theme: Theme) -> JsResult<Minimap> {
+        let canvas: HtmlCanvasElement = base
+            .get_element_by_id("minimap_canvas")?
+            .dyn_into::<HtmlCanvasElement>()?;
+        let scale = MINIMAP_WIDTH as f64 / width as f64;
+        canvas.set_width(MINIMAP_WIDTH);
+        canvas.set_height((height as f64 * scale) as u32);
+
+        let context = canvas
+            .get_context("2d")?
+            .unwrap()
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        context.set_line_cap("round");
+        // draw in arena coordinates
+        context.scale(scale, scale)?;
+
+        let head = base
+            .get_element_by_id("minimap_head")?
+            .dyn_into::<HtmlElement>()?;
+
+        Ok(Minimap {
+            context,
+            head,
+            width,
+            height,
+            scale,
+            theme,
+        })
+    }
+
+    fn draw_line(&self, line: &Line) {
+        stroke_line(&self.context, line);
+    }
+
+    /// Clears `area` and draws the parts of `lines` within it again.
+    fn redraw<'a>(&self, area: &Rect, lines: impl Iterator<Item = &'a Line>) {
+        let (w, h) = area.size();
+        self.context.save();
+        self.context.begin_path();
+        self.context.rect(area.min.0, area.min.1, w, h);
+        self.context.clip();
+        self.fill_background(area.min, (w, h));
+        lines
+            .filter(|line| area.intersects(&Rect::of_line(line)))
+            .for_each(|line| self.draw_line(line));
+        self.context.restore();
+    }
+
+    fn set_head(&self, pos: Option<(f64, f64)>) -> JsError {
+        match pos {
+            Some((x, y)) => {
+                let style = self.head.style();
+                style.set_property("left", &format!("{}px", x * self.scale))?;
+                style.set_property("top", &format!("{}px", y * self.scale))?;
+                self.head.set_class_name("visible");
+            }
+            None => self.head.set_class_name("hidden"),
+        }
+        Ok(())
+    }
+
+    fn fill_background(&self, (x, y): (f64, f64), (w, h): (f64, f64)) {
+        self.context.set_fill_style(&self.theme.background().into());
+        self.context.fill_rect(x, y, w, h);
     }
 
     fn clear(&self) {
-        self.context.set_fill_style(&"#263238".into());
-        self.context
-            .fill_rect(0., 0., self.width.into(), self.height.into());
+        self.fill_background((0., 0.), (self.width.into(), self.height.into()));
+    }
+}
+
+/// Bar in the HUD showing a percentage
+struct Meter {
+    div: HtmlElement,
+    fill: HtmlElement,
+}
+
+impl Meter {
+    fn new(base: &Base, id: &str) -> JsResult<Meter> {
+        let div = base.get_element_by_id(id)?.dyn_into::<HtmlElement>()?;
+        let fill = div
+            .first_element_child()
+            .to_js_err("Meter has no fill")?
+            .dyn_into::<HtmlElement>()?;
+        Ok(Meter { div, fill })
+    }
+
+    fn set(&self, percent: u8) -> JsError {
+        self.fill
+            .style()
+            .set_property("width", &format!("{}%", percent))
+    }
+
+    fn show(&self, visible: bool) {
+        self.div.set_class_name(match visible {
+            true => "meter",
+            false => "meter hidden",
+        });
+    }
+}
+
+/// The inventory of the local player, shown when collected items are kept until used
+struct ItemSlot {
+    div: HtmlElement,
+    held: Option<ItemKind>,
+}
+
+impl ItemSlot {
+    fn new(base: &Base, id: &str) -> JsResult<ItemSlot> {
+        let div = base.get_element_by_id(id)?.dyn_into::<HtmlElement>()?;
+        let slot = ItemSlot { div, held: None };
+        slot.show_held();
+        Ok(slot)
+    }
+
+    fn set(&mut self, item: Option<ItemKind>) {
+        if item != self.held {
+            self.held = item;
+            self.show_held();
+        }
+    }
+
+    fn show_held(&self) {
+        let text = match self.held {
+            Some(ItemKind::Eraser) => Text::Eraser,
+            Some(ItemKind::Confusion) => Text::Confusion,
+            None => Text::NoItem,
+        };
+        self.div.set_text_content(Some(tr(text)));
+    }
+
+    fn show(&self, visible: bool) {
+        self.div.set_class_name(match (visible, self.held) {
+            (false, _) => "hidden",
+            (true, Some(_)) => "held",
+            (true, None) => "",
+        });
+    }
+}
+
+/// Live status of a player, shown in the players panel during rounds
+#[derive(Copy, Clone, PartialEq)]
+struct Status {
+    speed: u16,
+    speed_change: SpeedChange,
+    ghost_active: bool,
+    ghost_ready: u8, // rounded to tens, so the panel isn't rebuilt every tick
+}
+
+impl From<&PlayerState> for Status {
+    fn from(s: &PlayerState) -> Self {
+        Status {
+            speed: s.speed,
+            speed_change: s.speed_change,
+            ghost_active: s.ghost_active,
+            ghost_ready: s.ghost_ready / 10 * 10,
+        }
     }
 }
 
@@ -116,21 +895,34 @@ struct MyPlayer {
     player: Player,
     x_prev: f64,
     y_prev: f64,
+    distance: f64, // travelled this round, to continue the dash pattern
+    alive: bool,
+    status: Status,
 }
 
 impl MyPlayer {
-    fn update_pos(&mut self, x: f64, y: f64, invisible: bool) {
+    fn update_pos(&mut self, x: f64, y: f64, rotation: f64, invisible: bool, alive: bool) {
+        self.alive = alive;
+        if !alive {
+            // keep the last position, there is nothing left to draw
+            return;
+        }
         self.x_prev = self.x;
         self.y_prev = self.y;
+        self.distance += (x - self.x).hypot(y - self.y);
         self.x = x;
         self.y = y;
+        self.rotation = rotation;
         self.invisible = invisible;
     }
-    fn init_pos(&mut self, x: f64, y: f64) {
+    fn init_pos(&mut self, x: f64, y: f64, rotation: f64) {
+        self.alive = true;
+        self.distance = 0.;
         self.x_prev = x;
         self.x = x;
         self.y_prev = y;
         self.y = y;
+        self.rotation = rotation;
     }
 }
 
@@ -154,24 +946,51 @@ impl From<Player> for MyPlayer {
             player,
             x_prev: player.x,
             y_prev: player.y,
+            distance: 0.,
+            alive: true,
+            status: Status {
+                speed: player.current_speed().round() as u16,
+                speed_change: SpeedChange::Normal,
+                ghost_active: false,
+                ghost_ready: 100,
+            },
         }
     }
 }
 
 trait PlayerDraw {
+    fn line(&self) -> Line;
     fn draw(&self, canvas: &mut Canvas);
+    fn draw_heading(&self, canvas: &Canvas);
 }
 
 impl PlayerDraw for MyPlayer {
+    fn line(&self) -> Line {
+        let length = (self.x - self.x_prev).hypot(self.y - self.y_prev);
+        Line {
+            from: (self.x_prev, self.y_prev),
+            to: (self.x, self.y),
+            linewidth: self.line_width as f64,
+            color: self.color,
+            dashes: self.pattern.dashes(),
+            offset: self.distance - length,
+            control: None,
+        }
+    }
+
     fn draw(&self, canvas: &mut Canvas) {
-        canvas.draw(
-            Line {
-                from: (self.x_prev, self.y_prev),
-                to: (self.x, self.y),
-                linewidth: self.line_width as f64,
-                color: self.color,
-            },
-            self.invisible,
+        if !self.alive {
+            return;
+        }
+        canvas.draw(self.uuid, self.line(), self.invisible);
+    }
+
+    fn draw_heading(&self, canvas: &Canvas) {
+        canvas.draw_arrow(
+            (self.x, self.y),
+            self.rotation,
+            self.line_width as f64,
+            &self.color,
         );
     }
 }
@@ -179,13 +998,54 @@ impl PlayerDraw for MyPlayer {
 struct Game {
     base: Rc<Base>,
     canvas: Canvas,
+    minimap: Minimap,
+    energy_meter: Meter, // boost/brake energy of the local player
+    ghost_meter: Meter,  // cooldown of the ghost ability of the local player
+    item_slot: ItemSlot,
+    confused_div: HtmlElement, // warns the local player of inverted controls
+    gap_hint: HtmlElement,     // fades in right before the local player's next gap
+    head_marker: HtmlElement,  // ring around the head of the local player during rounds
+    uuid: Uuid,                // the local player
     players: HashMap<Uuid, MyPlayer>,
     running: bool,
+    frozen: bool, // countdown before a round, turning is allowed but nobody moves
+    paused: bool, // the host paused the round
+    headings_shown: bool,
+    follow: bool,          // camera follows the local player
+    watched: Option<Uuid>, // survivor the camera follows once the local player is out
+    keys: KeyBindings,
+    last_seq: Option<u32>,       // sequence number of the last applied state
+    sync_requested: bool,        // waiting for a `FullTrailSync` after missed states
+    trail_lifetime: Option<u32>, // ticks until a trail fades, as in the room config
+    replay: Option<(Instant, u32, f64)>, // start, first tick and ticks per second of a replay
+    input_seq: u32,              // number of the last input sent
+    pending_inputs: VecDeque<(u32, Input)>, // sent but not applied by the server yet, for prediction
 }
 
 impl Game {
-    fn new(base: Rc<Base>, x_max: u32, y_max: u32, players: Vec<MyPlayer>) -> JsResult<Game> {
-        let canvas = Canvas::new(base.clone(), x_max, y_max)?;
+    fn new(
+        base: Rc<Base>,
+        x_max: u32,
+        y_max: u32,
+        players: Vec<MyPlayer>,
+        uuid: Uuid,
+        theme: Theme,
+    ) -> JsResult<Game> {
+        let canvas = Canvas::new(base.clone(), x_max, y_max, theme)?;
+        let minimap = Minimap::new(&base, x_max, y_max, theme)?;
+        let energy_meter = Meter::new(&base, "energy")?;
+        let ghost_meter = Meter::new(&base, "ghost")?;
+        let item_slot = ItemSlot::new(&base, "item_slot")?;
+        let confused_div = base
+            .get_element_by_id("confused")?
+            .dyn_into::<HtmlElement>()?;
+        let gap_hint = base
+            .get_element_by_id("gap_hint")?
+            .dyn_into::<HtmlElement>()?;
+        let head_marker = base
+            .get_element_by_id("head_marker")?
+            .dyn_into::<HtmlElement>()?;
+        head_marker.set_class_name("hidden");
         let players = {
             let mut map = HashMap::new();
             players.iter().for_each(|player| {
@@ -194,46 +1054,207 @@ impl Game {
             map
         };
         canvas.clear();
+        minimap.clear();
+        let preferences = Preferences::load();
 
         Ok(Game {
             base,
             canvas,
+            minimap,
+            energy_meter,
+            ghost_meter,
+            item_slot,
+            confused_div,
+            gap_hint,
+            head_marker,
+            uuid,
             players,
             running: false,
+            frozen: false,
+            paused: false,
+            headings_shown: false,
+            follow: preferences.follow,
+            watched: None,
+            keys: preferences.keys,
+            last_seq: None,
+            sync_requested: false,
+            trail_lifetime: None,
+            replay: None,
+            input_seq: 0,
+            pending_inputs: VecDeque::new(),
         })
     }
 
+    /// Sends an input of the local player, numbered so that the server can acknowledge it.
+    fn send_input(&mut self, input: Input) -> JsError {
+        self.input_seq += 1;
+        self.pending_inputs.push_back((self.input_seq, input));
+        self.base.send(ClientMessage::Input(self.input_seq, input))
+    }
+
+    /// Forgets the inputs the server applied up to `ack`, the rest is still on its way.
+    fn acknowledge_inputs(&mut self, ack: u32) {
+        while self
+            .pending_inputs
+            .front()
+            .is_some_and(|(seq, _)| *seq <= ack)
+        {
+            self.pending_inputs.pop_front();
+        }
+        diagnostics::inputs_pending(self.pending_inputs.len());
+    }
+
     fn on_keydown(&mut self, event: KeyboardEvent) -> JsError {
         //console_log!("Key pressed - {}", event.key().as_str());
-        if self.running {
-            match event.key().as_str() {
-                "ArrowLeft" | "h" | "a" => self.base.send(ClientMessage::Move(Direction::Left))?,
-                "ArrowRight" | "l" | "d" => {
-                    self.base.send(ClientMessage::Move(Direction::Right))?
-                }
-                _ => (),
-            }
-        } else {
-            match event.key().as_str() {
-                " " => self.base.send(ClientMessage::StartGame)?,
-                _ => (),
+        if event.key() == "Tab" && self.running && !self.is_alive(&self.uuid) {
+            event.prevent_default();
+            return self.watch_next();
+        }
+        if self.view_keydown(&event)? {
+            return Ok(());
+        }
+        if event.key().as_str() == "p" && (self.running || self.frozen) {
+            if self.players.get(&self.uuid).is_some_and(|p| p.host) {
+                return self.base.send(match self.paused {
+                    true => ClientMessage::ResumeGame,
+                    false => ClientMessage::PauseGame,
+                });
             }
+            return Ok(());
+        }
+        match self.keys.action(&event.key()) {
+            Some(Action::Left) => self.send_input(Input::Move(Direction::Left))?,
+            Some(Action::Right) => self.send_input(Input::Move(Direction::Right))?,
+            Some(Action::Boost) => self.send_input(Input::Speed(SpeedChange::Boost))?,
+            Some(Action::Brake) => self.send_input(Input::Speed(SpeedChange::Brake))?,
+            Some(Action::UseItem) if self.running => self.send_input(Input::UseItem)?,
+            Some(Action::UseItem) => (),
+            None if event.key() == " " && self.running => self.send_input(Input::UseGhost)?,
+            None => (),
         }
         Ok(())
     }
 
+    /// Keys that change the view, in and between rounds. Returns whether the key was one of them.
+    fn view_keydown(&mut self, event: &KeyboardEvent) -> JsResult<bool> {
+        match event.key().as_str() {
+            "c" => self.toggle_follow()?,
+            "g" => self.toggle_renderer()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
     fn on_keyup(&mut self, event: KeyboardEvent) -> JsError {
-        if self.running {
-            match event.key().as_str() {
-                "ArrowLeft" | "h" | "a" => {
-                    self.base.send(ClientMessage::Move(Direction::Unchanged))?
-                }
-                "ArrowRight" | "l" | "d" => {
-                    self.base.send(ClientMessage::Move(Direction::Unchanged))?
-                }
-                _ => (),
+        match self.keys.action(&event.key()) {
+            Some(Action::Left) | Some(Action::Right) => {
+                self.send_input(Input::Move(Direction::Unchanged))?
+            }
+            Some(Action::Boost) | Some(Action::Brake) => {
+                self.send_input(Input::Speed(SpeedChange::Normal))?
+            }
+            Some(Action::UseItem) | None => (),
+        }
+        Ok(())
+    }
+
+    fn toggle_follow(&mut self) -> JsError {
+        self.follow = !self.follow;
+        let mut preferences = Preferences::load();
+        preferences.follow = self.follow;
+        preferences.save()?;
+        if !self.follow {
+            self.canvas.set_camera(None);
+        }
+        self.update_camera();
+        self.canvas.redraw_all();
+        if !self.running {
+            self.draw_headings();
+        }
+        Ok(())
+    }
+
+    /// Switches to the next renderer, from the 2D context to WebGL and WebGL in a worker.
+    fn toggle_renderer(&mut self) -> JsError {
+        let mut preferences = Preferences::load();
+        preferences.backend = preferences.backend.next();
+        preferences.save()?;
+        self.canvas.set_backend(preferences.backend)?;
+        if !self.running {
+            self.draw_headings();
+        }
+        Ok(())
+    }
+
+    fn is_alive(&self, id: &Uuid) -> bool {
+        self.players.get(id).is_some_and(|p| p.alive)
+    }
+
+    /// Follows the next survivor in the order of their ids, after the last one the view goes back
+    /// to normal.
+    fn watch_next(&mut self) -> JsError {
+        let mut alive: Vec<Uuid> = self
+            .players
+            .values()
+            .filter(|p| p.alive && p.uuid != self.uuid)
+            .map(|p| p.uuid)
+            .collect();
+        alive.sort();
+        let next = match self.watched {
+            Some(current) => alive.iter().find(|id| **id > current),
+            None => alive.first(),
+        };
+        self.set_watched(next.copied())
+    }
+
+    fn set_watched(&mut self, watched: Option<Uuid>) -> JsError {
+        self.watched = watched;
+        let label = self.base.get_element_by_id("watching")?;
+        match watched.and_then(|id| self.players.get(&id)) {
+            Some(player) => {
+                label.set_text_content(Some(&trf(Text::Watching, &[&player.name])));
+                label.set_class_name("visible");
             }
+            None => label.set_class_name("hidden"),
+        }
+        if watched.is_none() && !self.follow {
+            self.canvas.set_camera(None);
+        }
+        self.update_camera();
+        self.canvas.redraw_all();
+        Ok(())
+    }
+
+    /// The player the camera is centered on, if any
+    fn camera_target(&self) -> Option<&MyPlayer> {
+        let watched = self.watched.and_then(|id| self.players.get(&id));
+        let own = self.players.get(&self.uuid).filter(|_| self.follow);
+        watched.or(own).filter(|p| p.alive)
+    }
+
+    fn update_camera(&mut self) {
+        if let Some((x, y)) = self.camera_target().map(|p| (p.x, p.y)) {
+            self.canvas.set_camera(Some((x, y)));
+            return;
+        }
+        if !self.follow {
+            return;
+        }
+        if self.canvas.camera.is_none() {
+            self.canvas.set_camera(Some((
+                self.canvas.width as f64 / 2.,
+                self.canvas.height as f64 / 2.,
+            )));
         }
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> JsError {
+        let theme = self.canvas.theme;
+        self.canvas = Canvas::new(self.base.clone(), width, height, theme)?;
+        self.minimap = Minimap::new(&self.base, width, height, theme)?;
+        self.update_camera();
+        // the new canvases start out empty, the retained trails are drawn again
+        self.redraw_trails();
         Ok(())
     }
 
@@ -254,26 +1275,212 @@ impl Game {
         Ok(())
     }
 
-    fn game_update(&mut self, game_state: Vec<PlayerState>) -> JsError {
+    /// Whether a state is newer than the last one, requesting a full sync if states were missed.
+    fn check_seq(&mut self, seq: u32) -> JsResult<bool> {
+        if let Some(last) = self.last_seq {
+            if seq.wrapping_sub(last) as i32 <= 0 {
+                console_log!("Dropping outdated state {} (last was {})", seq, last);
+                diagnostics::state_outdated();
+                return Ok(false);
+            }
+            if seq != last.wrapping_add(1) && !self.sync_requested {
+                console_log!("Missed states before {}, requesting a full sync", seq);
+                self.base.send(ClientMessage::RequestFullSync)?;
+                self.sync_requested = true;
+            }
+        }
+        let missed = self.last_seq.map_or(0, |last| seq.wrapping_sub(last) - 1);
+        diagnostics::state_received(missed);
+        self.last_seq = Some(seq);
+        Ok(true)
+    }
+
+    /// Replaces the retained trails with the ones of the server.
+    fn full_trail_sync(&mut self, seq: u32, sync: TrailSync) -> JsError {
+        self.sync_requested = false;
+        if self
+            .last_seq
+            .is_none_or(|last| seq.wrapping_sub(last) as i32 > 0)
+        {
+            self.last_seq = Some(seq);
+        }
+        self.canvas.trails.clear();
+        for (id, x, y, len) in sync.spans() {
+            if let Some(player) = self.players.get(&id) {
+                // one pixel high strokes, the round caps stick out by half a pixel
+                let line = Line {
+                    from: (x as f64 + 0.5, y as f64 + 0.5),
+                    to: ((x + len) as f64 - 0.5, y as f64 + 0.5),
+                    linewidth: 1.,
+                    color: player.color,
+                    dashes: &[],
+                    offset: 0.,
+                    control: None,
+                };
+                // the age of synced trails is unknown, they fade as if drawn just now
+                self.canvas.trails.push(id, self.canvas.tick, line);
+            }
+        }
+        self.redraw_trails();
+        Ok(())
+    }
+
+    fn game_update(&mut self, seq: u32, game_state: Vec<PlayerState>) -> JsError {
+        let last_seq = self.last_seq;
+        if !self.check_seq(seq)? {
+            return Ok(());
+        }
+        if self.running && !self.paused {
+            // one state per tick, missed states still count
+            let ticks = last_seq.map_or(1, |last| seq.wrapping_sub(last));
+            let from = self.canvas.tick;
+            self.canvas.tick = self.canvas.tick.wrapping_add(ticks);
+            self.canvas.advance_best_run(from);
+            self.expire_trails();
+        }
+        if let Some(s) = game_state.iter().find(|s| s.id == self.uuid) {
+            self.acknowledge_inputs(s.input_ack);
+            self.energy_meter.set(s.energy)?;
+            self.ghost_meter.set(s.ghost_ready)?;
+            if s.item != self.item_slot.held {
+                self.item_slot.set(s.item);
+                self.item_slot.show(true);
+            }
+            self.confused_div
+                .set_class_name(match s.confused && s.alive {
+                    true => "visible",
+                    false => "hidden",
+                });
+            let hint = match s.alive && s.gap_progress >= GAP_HINT_PROGRESS {
+                true => {
+                    (s.gap_progress - GAP_HINT_PROGRESS) as f64 / (100 - GAP_HINT_PROGRESS) as f64
+                }
+                false => 0.,
+            };
+            self.gap_hint
+                .style()
+                .set_property("opacity", &hint.to_string())?;
+        }
         if self.running {
+            if self.headings_shown {
+                // first running tick, get rid of the starting arrows
+                self.canvas.redraw_all();
+                self.headings_shown = false;
+            }
             game_state.iter().for_each(|s| {
-                self.players
-                    .get_mut(&s.id)
-                    .unwrap()
-                    .update_pos(s.x, s.y, s.invisible);
+                let (x, y) = s.pos();
+                let player = self.players.get_mut(&s.id).unwrap();
+                player.update_pos(x, y, s.rotation, s.invisible, s.alive);
+                player.status = Status::from(s);
             });
         } else {
             // initializing
+            if self.watched.is_some() {
+                self.set_watched(None)?;
+            }
+            self.canvas.wall_margin = 0;
+            self.canvas.tick = 0;
+            self.canvas.items.clear();
             self.canvas.clear();
-            self.canvas.lines.clear();
+            self.canvas.trails.clear();
+            self.canvas.crashes.clear();
+            self.minimap.clear();
             game_state.iter().for_each(|s| {
-                self.players.get_mut(&s.id).unwrap().init_pos(s.x, s.y);
+                let (x, y) = s.pos();
+                self.players
+                    .get_mut(&s.id)
+                    .unwrap()
+                    .init_pos(x, y, s.rotation);
             });
         };
         self.draw()?;
+        if !self.running {
+            self.draw_headings();
+        }
         Ok(())
     }
 
+    /// Removes trails older than the trail lifetime of the room from both canvases, only where
+    /// they were is drawn again.
+    fn expire_trails(&mut self) {
+        let lifetime = match self.trail_lifetime {
+            Some(lifetime) => lifetime,
+            None => return,
+        };
+        let expired = self
+            .canvas
+            .trails
+            .expire(self.canvas.tick.saturating_sub(lifetime) + 1);
+        for line in &expired {
+            let area = Rect::of_line(line);
+            self.canvas.mark_dirty(area);
+            self.minimap
+                .redraw(&area, self.canvas.trails.iter().map(|(_, line)| line));
+        }
+    }
+
+    /// Switches to the background of another theme, the new player colors follow with
+    /// `ServerMessage::PlayerColors`.
+    fn set_theme(&mut self, theme: Theme) {
+        self.canvas.theme = theme;
+        self.minimap.theme = theme;
+        self.redraw_trails();
+    }
+
+    /// Gives the players the colors and patterns the server picked from a new palette, trails
+    /// and crashes included.
+    fn set_colors(&mut self, colors: Vec<(Uuid, ArrayString<7>, TrailPattern)>) {
+        let mut changed = HashMap::new();
+        for (id, color, pattern) in colors {
+            if let Some(player) = self.players.get_mut(&id) {
+                changed.insert(player.player.color, (color, pattern));
+                player.player.color = color;
+                player.player.pattern = pattern;
+            }
+        }
+        for lines in self.canvas.trails.segments.values_mut() {
+            for (_, line) in lines.iter_mut() {
+                if let Some((color, pattern)) = changed.get(&line.color) {
+                    line.color = *color;
+                    line.dashes = pattern.dashes();
+                }
+            }
+        }
+        for crash in self.canvas.crashes.iter_mut() {
+            if let Some((color, _)) = changed.get(&crash.color) {
+                crash.color = *color;
+            }
+        }
+        self.redraw_trails();
+    }
+
+    /// Redraws both canvases from the retained trails, after trails were removed or the canvases
+    /// were lost.
+    fn redraw_trails(&mut self) {
+        self.canvas.redraw_all();
+        self.minimap.clear();
+        self.canvas
+            .trails
+            .iter()
+            .for_each(|(_, line)| self.minimap.draw_line(line));
+    }
+
+    fn item_spawned(&mut self, item: Item) {
+        self.canvas.draw_item(&item);
+        self.canvas.items.insert(item.id, item);
+    }
+
+    fn item_collected(&mut self, id: u32) {
+        if self.canvas.items.remove(&id).is_some() {
+            self.canvas.redraw_all();
+        }
+    }
+
+    fn area_cleared(&mut self, center: (f64, f64), radius: f64) {
+        self.canvas.trails.erase(center, radius);
+        self.redraw_trails();
+    }
+
     fn game_tick(&mut self) -> JsError {
         //self.players
         //.iter_mut()
@@ -281,33 +1488,573 @@ impl Game {
         self.draw()
     }
 
+    /// Replays the round from `first` on with `rate` ticks per second.
+    fn start_replay(&mut self, first: u32, rate: f64) {
+        self.replay = Some((Instant::now(), first, rate));
+        self.canvas.crashes.iter_mut().for_each(|crash| {
+            // crashes of the replayed moments animate once more
+            if crash.tick > first {
+                crash.frame = 0;
+            }
+        });
+    }
+
+    /// Draws the next frame of the replay, returns whether it continues.
+    fn replay_frame(&mut self) -> bool {
+        let (start, first, rate) = match self.replay {
+            Some(replay) => replay,
+            None => return false,
+        };
+        let tick = first + (start.elapsed().as_secs_f64() * rate) as u32;
+        if tick >= self.canvas.tick {
+            self.stop_replay();
+            return false;
+        }
+        self.canvas.replay = Some(tick);
+        self.canvas.crashes.iter_mut().for_each(|crash| {
+            if crash.tick <= tick && crash.frame < CRASH_ANIMATION_FRAMES {
+                crash.frame += 1;
+            }
+        });
+        self.canvas.redraw_all();
+        diagnostics::frame();
+        true
+    }
+
+    fn stop_replay(&mut self) {
+        if self.replay.take().is_some() {
+            self.canvas.replay = None;
+            self.canvas
+                .crashes
+                .iter_mut()
+                .for_each(|crash| crash.frame = CRASH_ANIMATION_FRAMES);
+            self.canvas.redraw_all();
+        }
+    }
+
+    /// Flashes at both ends of the portal, the trail breaks on its own while the player is
+    /// immune.
+    fn teleported(&mut self, teleport: &Teleport) {
+        let color = match self.players.get(&teleport.id) {
+            Some(player) => player.color,
+            None => return,
+        };
+        for pos in [teleport.from, teleport.to] {
+            self.canvas.add_flash(Flash {
+                pos,
+                color,
+                frame: 0,
+            });
+        }
+    }
+
+    fn player_died(&mut self, death: &Death) -> JsError {
+        let player = self
+            .players
+            .get_mut(&death.id)
+            .ok_or_else(|| format!("Player with uuid `{}` not found", death.id))?;
+        player.alive = false;
+        let crash = Crash {
+            pos: (death.x, death.y),
+            size: player.line_width as f64,
+            color: player.color,
+            frame: 0,
+            tick: self.canvas.tick,
+        };
+        self.canvas.add_crash(crash);
+        if self.watched == Some(death.id) {
+            self.watch_next()?;
+        }
+        Ok(())
+    }
+
     fn draw(&mut self) -> JsError {
+        diagnostics::frame();
+        self.canvas.animate_effects();
         for (_id, player) in &self.players {
             player.draw(&mut self.canvas);
+            if player.alive && !player.invisible {
+                self.minimap.draw_line(&player.line());
+            }
+        }
+        self.canvas.redraw_dirty();
+        if self.follow || self.watched.is_some() {
+            self.update_camera();
+            self.canvas.redraw_all();
+            // heads of players in a gap aren't part of the retained trails
+            self.players
+                .values()
+                .filter(|player| player.alive && player.invisible)
+                .for_each(|player| {
+                    let mut line = player.line();
+                    line.from = line.to;
+                    self.canvas.draw_line(&line);
+                });
+        }
+        let head = match self.watched {
+            Some(id) => self.players.get(&id),
+            None => self.players.get(&self.uuid),
+        };
+        self.minimap.set_head(
+            head.filter(|player| player.alive)
+                .map(|player| (player.x, player.y)),
+        )?;
+        self.mark_head()
+    }
+
+    /// Keeps the marker on the local player's head, so it is found right away on a crowded board.
+    fn mark_head(&self) -> JsError {
+        let own = self
+            .players
+            .get(&self.uuid)
+            .filter(|player| player.alive && (self.running || self.frozen));
+        match own {
+            Some(player) => {
+                let (x, y) = self.canvas.to_screen((player.x, player.y));
+                let style = self.head_marker.style();
+                style.set_property("left", &format!("{}px", x))?;
+                style.set_property("top", &format!("{}px", y))?;
+                style.set_property("color", &player.color)?;
+                self.head_marker.set_class_name("visible");
+            }
+            None => self.head_marker.set_class_name("hidden"),
         }
         Ok(())
     }
+
+    /// Everything the players panel shows during a round
+    fn hud_state(&self) -> Vec<(Uuid, bool, Status)> {
+        self.players
+            .values()
+            .map(|player| (player.uuid, player.alive, player.status))
+            .collect()
+    }
+
+    fn draw_headings(&mut self) {
+        for player in self.players.values() {
+            player.draw_heading(&self.canvas);
+        }
+        self.headings_shown = true;
+    }
 }
 
-#[derive(Clone)]
 struct Base {
     doc: Document,
+    root: Element, // the interface is mounted in, see `mount`
     ws: WebSocket,
+    _ws_callbacks: CallbackRegistry, // of `ws`
     touch: bool,
+    local: RefCell<Option<Vec<ClientMessage>>>, // for a practice room, `None` sends to the server
 }
 
 impl Base {
     fn send(&self, msg: ClientMessage) -> JsError {
+        if let Some(local) = self.local.borrow_mut().as_mut() {
+            local.push(msg);
+            return Ok(());
+        }
         let encoded = bincode::serialize(&msg)
             .map_err(|e| JsValue::from_str(&format!("Could not encode: {}", e)))?;
+        diagnostics::sent(encoded.len());
         self.ws.send_with_u8_array(&encoded[..])
     }
 
     fn get_element_by_id(&self, id: &str) -> JsResult<Element> {
-        Ok(self
-            .doc
-            .get_element_by_id(id)
-            .to_js_err(&format!("Could not find id: {}", id))?)
+        find_element(&self.root, id)
+    }
+}
+
+/// The element with `id` inside of `root`, the ids only have to be unique within the mount point.
+fn find_element(root: &Element, id: &str) -> JsResult<Element> {
+    root.query_selector(&format!("#{}", id))?
+        .to_js_err(&format!("Could not find id: {}", id))
+}
+
+/// What is needed to get back into a room after the connection was lost
+#[derive(Clone)]
+struct Session {
+    room_name: String,
+    token: Uuid,
+}
+
+/// How long an emote is shown next to the name of its sender
+const EMOTE_DURATION_MS: i32 = 3000;
+/// How long a kill stays in the kill feed
+const KILL_FEED_DURATION_MS: i32 = 4000;
+/// How long the winner of a round or match is announced
+const WINNER_DURATION_MS: i32 = 4000;
+/// Chat messages kept in the chat panel
+const CHAT_LINES: u32 = 100;
+/// Room events kept in the event log above the chat
+const EVENT_LINES: u32 = 50;
+/// Speed of the exported video relative to the round
+const VIDEO_SPEED: f64 = 2.;
+
+/// Size of a head hitbox until the host enters one
+const HITBOX_SIZE: u32 = 2;
+
+const SETTINGS_INPUTS: [&str; 18] = [
+    "room_title",
+    "grid_width",
+    "grid_height",
+    "max_players",
+    "target_score",
+    "best_of",
+    "trail_fade",
+    "auto_start",
+    "tick_rate",
+    "equalize_latency",
+    "hazards",
+    "growing_trails",
+    "items",
+    "item_inventory",
+    "hitbox_size",
+    "gap_interval",
+    "gap_length",
+    "gap_variation",
+];
+
+/// The room settings panel, editable by the host between rounds
+struct Settings {
+    title_input: HtmlInputElement, // name of the room, empty shows the code
+    preset_select: HtmlSelectElement,
+    theme_select: HtmlSelectElement,
+    scoring_select: HtmlSelectElement,
+    map_select: HtmlSelectElement,
+    hitbox_select: HtmlSelectElement,
+    hitbox_size_input: HtmlInputElement, // pixels, only for head hitboxes
+    width_input: HtmlInputElement,
+    height_input: HtmlInputElement,
+    max_players_input: HtmlInputElement,
+    target_score_input: HtmlInputElement,
+    best_of_input: HtmlInputElement,
+    trail_fade_input: HtmlInputElement, // seconds until trails fade
+    auto_start_input: HtmlInputElement,
+    tick_rate_input: HtmlInputElement,        // ticks per second
+    equalize_latency_input: HtmlInputElement, // checkbox
+    hazards_input: HtmlInputElement,          // checkbox
+    growing_trails_input: HtmlInputElement,   // checkbox
+    items_input: HtmlInputElement,            // checkbox
+    item_inventory_input: HtmlInputElement,   // checkbox
+    gap_interval_input: HtmlInputElement,     // slider
+    gap_length_input: HtmlInputElement,       // slider
+    gap_variation_input: HtmlInputElement,    // slider
+    gap_values: HtmlElement,                  // the values of the sliders
+    error_p: HtmlElement,
+    traffic_p: HtmlElement, // what the room sends, only the host is told
+}
+
+impl Settings {
+    fn new(base: &Base) -> JsResult<Settings> {
+        let preset_select = base
+            .get_element_by_id("preset_select")?
+            .dyn_into::<HtmlSelectElement>()?;
+        preset_select.set_inner_html("");
+        for preset in SpeedPreset::ALL.iter() {
+            let option = base.doc.create_element("option")?;
+            option.set_attribute("value", preset.name())?;
+            option.set_text_content(Some(preset.name()));
+            preset_select.append_child(&option)?;
+        }
+        let theme_select = base
+            .get_element_by_id("theme_select")?
+            .dyn_into::<HtmlSelectElement>()?;
+        theme_select.set_inner_html("");
+        for theme in Theme::ALL.iter() {
+            let option = base.doc.create_element("option")?;
+            option.set_attribute("value", theme.name())?;
+            option.set_text_content(Some(theme.name()));
+            theme_select.append_child(&option)?;
+        }
+        let scoring_select = base
+            .get_element_by_id("scoring_select")?
+            .dyn_into::<HtmlSelectElement>()?;
+        scoring_select.set_inner_html("");
+        for scoring in ScoringMode::ALL.iter() {
+            let option = base.doc.create_element("option")?;
+            option.set_attribute("value", scoring.name())?;
+            option.set_text_content(Some(scoring_name(*scoring)));
+            scoring_select.append_child(&option)?;
+        }
+        let map_select = base
+            .get_element_by_id("map_select")?
+            .dyn_into::<HtmlSelectElement>()?;
+        map_select.set_inner_html("");
+        for map in MapPreset::ALL.iter() {
+            let option = base.doc.create_element("option")?;
+            option.set_attribute("value", map.name())?;
+            option.set_text_content(Some(map_name(*map)));
+            map_select.append_child(&option)?;
+        }
+        let hitbox_select = base
+            .get_element_by_id("hitbox_select")?
+            .dyn_into::<HtmlSelectElement>()?;
+        hitbox_select.set_inner_html("");
+        for hitbox in [Hitbox::Band, Hitbox::Square(0), Hitbox::Circle(0)].iter() {
+            let option = base.doc.create_element("option")?;
+            option.set_attribute("value", hitbox.name())?;
+            option.set_text_content(Some(hitbox_name(*hitbox)));
+            hitbox_select.append_child(&option)?;
+        }
+        let input = |id: &str| -> JsResult<HtmlInputElement> {
+            Ok(base.get_element_by_id(id)?.dyn_into::<HtmlInputElement>()?)
+        };
+
+        Ok(Settings {
+            title_input: input("room_title")?,
+            preset_select,
+            theme_select,
+            scoring_select,
+            map_select,
+            hitbox_select,
+            hitbox_size_input: input("hitbox_size")?,
+            width_input: input("grid_width")?,
+            height_input: input("grid_height")?,
+            max_players_input: input("max_players")?,
+            target_score_input: input("target_score")?,
+            best_of_input: input("best_of")?,
+            trail_fade_input: input("trail_fade")?,
+            auto_start_input: input("auto_start")?,
+            tick_rate_input: input("tick_rate")?,
+            equalize_latency_input: input("equalize_latency")?,
+            hazards_input: input("hazards")?,
+            growing_trails_input: input("growing_trails")?,
+            items_input: input("items")?,
+            item_inventory_input: input("item_inventory")?,
+            gap_interval_input: input("gap_interval")?,
+            gap_length_input: input("gap_length")?,
+            gap_variation_input: input("gap_variation")?,
+            gap_values: base
+                .get_element_by_id("gap_values")?
+                .dyn_into::<HtmlElement>()?,
+            error_p: base
+                .get_element_by_id("settings_error")?
+                .dyn_into::<HtmlElement>()?,
+            traffic_p: base
+                .get_element_by_id("room_traffic")?
+                .dyn_into::<HtmlElement>()?,
+        })
+    }
+
+    fn show(&self, config: &RoomConfig, editable: bool) {
+        self.title_input
+            .set_value(config.title.as_deref().unwrap_or_default());
+        self.preset_select.set_value(config.preset.name());
+        self.theme_select.set_value(config.theme.name());
+        self.scoring_select.set_value(config.scoring.name());
+        self.map_select.set_value(config.map.name());
+        self.hitbox_select.set_value(config.hitbox.name());
+        self.hitbox_size_input.set_value(
+            &config
+                .hitbox
+                .size()
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+        );
+        self.width_input.set_value(&config.width.to_string());
+        self.height_input.set_value(&config.height.to_string());
+        self.max_players_input
+            .set_value(&config.max_players.to_string());
+        self.target_score_input.set_value(
+            &config
+                .target_score
+                .map(|score| score.to_string())
+                .unwrap_or_default(),
+        );
+        self.best_of_input.set_value(
+            &config
+                .best_of
+                .map(|rounds| rounds.to_string())
+                .unwrap_or_default(),
+        );
+        self.trail_fade_input.set_value(
+            &config
+                .trail_lifetime
+                .map(|ticks| (ticks / config.tick_rate).to_string())
+                .unwrap_or_default(),
+        );
+        self.auto_start_input.set_value(
+            &config
+                .auto_start
+                .map(|players| players.to_string())
+                .unwrap_or_default(),
+        );
+        self.tick_rate_input
+            .set_value(&config.tick_rate.to_string());
+        self.equalize_latency_input
+            .set_checked(config.equalize_latency);
+        self.hazards_input.set_checked(config.hazards);
+        self.growing_trails_input.set_checked(config.growing_trails);
+        self.items_input.set_checked(config.items);
+        self.item_inventory_input.set_checked(config.item_inventory);
+        let gaps = config.gaps;
+        self.gap_interval_input
+            .set_value(&gaps.interval.to_string());
+        self.gap_length_input.set_value(&gaps.length.to_string());
+        self.gap_variation_input
+            .set_value(&gaps.variation.to_string());
+        self.gap_values.set_text_content(Some(&trf(
+            Text::GapValues,
+            &[&gaps.interval, &gaps.length, &gaps.variation],
+        )));
+
+        self.preset_select.set_disabled(!editable);
+        self.theme_select.set_disabled(!editable);
+        self.scoring_select.set_disabled(!editable);
+        self.map_select.set_disabled(!editable);
+        self.hitbox_select.set_disabled(!editable);
+        for input in &[
+            &self.title_input,
+            &self.width_input,
+            &self.height_input,
+            &self.max_players_input,
+            &self.target_score_input,
+            &self.best_of_input,
+            &self.trail_fade_input,
+            &self.auto_start_input,
+            &self.tick_rate_input,
+            &self.equalize_latency_input,
+            &self.hazards_input,
+            &self.growing_trails_input,
+            &self.items_input,
+            &self.item_inventory_input,
+            &self.hitbox_size_input,
+            &self.gap_interval_input,
+            &self.gap_length_input,
+            &self.gap_variation_input,
+        ] {
+            input.set_disabled(!editable);
+        }
+    }
+
+    /// Builds the config from the inputs, fields that can't be parsed stay as in `config`
+    fn read(&self, config: &RoomConfig) -> RoomConfig {
+        let parse = |input: &HtmlInputElement, current: usize| {
+            input.value().trim().parse::<usize>().unwrap_or(current)
+        };
+        let value = self.preset_select.value();
+        let theme = self.theme_select.value();
+        let scoring = self.scoring_select.value();
+        let map = self.map_select.value();
+        let size = self
+            .hitbox_size_input
+            .value()
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .or(config.hitbox.size())
+            .unwrap_or(HITBOX_SIZE);
+        // the fading is entered in seconds, with the new rate if it changes as well
+        let tick_rate = parse(&self.tick_rate_input, config.tick_rate as usize) as u32;
+        RoomConfig {
+            preset: *SpeedPreset::ALL
+                .iter()
+                .find(|p| p.name() == value)
+                .unwrap_or(&config.preset),
+            theme: *Theme::ALL
+                .iter()
+                .find(|t| t.name() == theme)
+                .unwrap_or(&config.theme),
+            scoring: *ScoringMode::ALL
+                .iter()
+                .find(|s| s.name() == scoring)
+                .unwrap_or(&config.scoring),
+            map: *MapPreset::ALL
+                .iter()
+                .find(|m| m.name() == map)
+                .unwrap_or(&config.map),
+            hitbox: match self.hitbox_select.value().as_str() {
+                "Band" => Hitbox::Band,
+                "Square" => Hitbox::Square(size),
+                "Circle" => Hitbox::Circle(size),
+                _ => config.hitbox,
+            },
+            width: parse(&self.width_input, config.width),
+            height: parse(&self.height_input, config.height),
+            max_players: parse(&self.max_players_input, config.max_players),
+            target_score: match self.target_score_input.value().trim() {
+                "" => None,
+                score => score.parse::<usize>().ok().or(config.target_score),
+            },
+            best_of: match self.best_of_input.value().trim() {
+                "" => None,
+                rounds => rounds.parse::<u32>().ok().or(config.best_of),
+            },
+            trail_lifetime: match self.trail_fade_input.value().trim() {
+                "" => None,
+                seconds => seconds
+                    .parse::<u32>()
+                    .ok()
+                    .map(|seconds| seconds.saturating_mul(tick_rate))
+                    .or(config.trail_lifetime),
+            },
+            auto_start: match self.auto_start_input.value().trim() {
+                "" => None,
+                players => players.parse::<usize>().ok().or(config.auto_start),
+            },
+            tick_rate,
+            equalize_latency: self.equalize_latency_input.checked(),
+            hazards: self.hazards_input.checked(),
+            growing_trails: self.growing_trails_input.checked(),
+            items: self.items_input.checked(),
+            item_inventory: self.item_inventory_input.checked(),
+            title: Some(self.title_input.value().trim().to_string())
+                .filter(|title| !title.is_empty()),
+            gaps: GapConfig {
+                interval: parse(&self.gap_interval_input, config.gaps.interval as usize) as u32,
+                length: parse(&self.gap_length_input, config.gaps.length as usize) as u32,
+                variation: parse(&self.gap_variation_input, config.gaps.variation as usize) as u32,
+            },
+            ..config.clone()
+        }
+    }
+
+    fn show_error(&self, text: Option<&str>) {
+        self.error_p.set_text_content(text);
+    }
+
+    fn show_traffic(&self, stats: Option<TrafficStats>) {
+        let stats = match stats {
+            Some(stats) => stats,
+            None => return self.traffic_p.set_class_name("hidden"),
+        };
+        let kilobytes = format!("{:.1}", stats.bytes_per_second as f64 / 1000.);
+        self.traffic_p.set_text_content(Some(&trf(
+            Text::RoomTraffic,
+            &[&kilobytes, &stats.messages_per_second, &stats.connections],
+        )));
+        self.traffic_p.set_class_name("visible");
+    }
+}
+
+/// Messages of a loaded replay, handed out tick by tick
+struct Playback {
+    window: Rc<Window>,
+    messages: VecDeque<(u32, ServerMessage)>,
+    tick: u32,
+    interval: i32,
+    _callback: Closure<dyn FnMut()>, // of `interval`
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.window.clear_interval_with_handle(self.interval);
+    }
+}
+
+/// A practice room played in the browser, fed with what would be sent to the server
+struct PracticeRun {
+    base: Rc<Base>,
+    window: Rc<Window>,
+    practice: Practice,
+    interval: i32,
+    _callback: Closure<dyn FnMut()>, // of `interval`
+}
+
+impl Drop for PracticeRun {
+    fn drop(&mut self) {
+        self.window.clear_interval_with_handle(self.interval);
+        *self.base.local.borrow_mut() = None;
     }
 }
 
@@ -317,417 +2064,2689 @@ struct Playing {
     game: Game,
 
     uuid: Uuid,
+    session: Session,
     players_div: HtmlElement,
     chat_div: HtmlElement,
+    chat_log: HtmlElement,
+    event_log: HtmlElement,
+    chat_input: HtmlInputElement,
+    countdown_div: HtmlElement,
+    banner_div: HtmlElement,
+    winner_div: HtmlElement, // announces the winner in its color
+    winner_serial: u32,      // of the timeout hiding `winner_div`
+    paused_div: HtmlElement,
+    auto_start_div: HtmlElement,
+    summary_div: HtmlElement,      // results of all rounds after a match
+    room_stats: Vec<SessionStats>, // of the matches finished in the room
+    match_round_div: HtmlElement,
+    match_round: u32, // of a best of match
+    kill_feed_div: HtmlElement,
+    export_div: HtmlElement,         // download buttons after a round
+    recorder: Option<MediaRecorder>, // records the replay of the round as video
+    replay_link: HtmlElement,        // shares the replay of the last round
+    playback: Option<Playback>,      // watching a replay instead of a room
+    practice: Option<PracticeRun>,   // playing in the browser instead of a room
+    tournament_div: HtmlElement,
+    settings: Settings,
+    config: RoomConfig,
+    shutting_down: bool,
+    emotes: HashMap<Uuid, (EmoteId, u32)>, // shown emotes with the serial of their timeout
+    emote_serial: u32,
+    spectators: u32,
+    queued: HashSet<Uuid>, // players waiting for the next round
+    afk: HashSet<Uuid>,
+    winner: Option<Uuid>, // of the last round
+    winner_points: usize, // points the winner got for the last round
     handle_id: i32,
+    _callbacks: CallbackRegistry, // removed along with the state
 }
 
 impl Playing {
+    /// Listens to the room settings and controls for as long as the state lasts.
+    fn register_callbacks(base: &Base) -> JsResult<CallbackRegistry> {
+        let mut callbacks = CallbackRegistry::default();
+        for id in &[
+            "preset_select",
+            "theme_select",
+            "scoring_select",
+            "map_select",
+            "hitbox_select",
+        ] {
+            callbacks.add(&base.get_element_by_id(id)?, "change", move |_: Event| {
+                HANDLE.lock().unwrap().on_settings_changed()
+            })?;
+        }
+        for id in SETTINGS_INPUTS.iter() {
+            callbacks.add(&base.get_element_by_id(id)?, "change", move |_: Event| {
+                HANDLE.lock().unwrap().on_settings_changed()
+            })?;
+        }
+        callbacks.add(
+            &base.get_element_by_id("chat_input")?,
+            "keydown",
+            move |event: KeyboardEvent| HANDLE.lock().unwrap().on_chat_keydown(event),
+        )?;
+        callbacks.add(
+            &base.get_element_by_id("export_png")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_export_png(),
+        )?;
+        callbacks.add(
+            &base.get_element_by_id("export_video")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_export_video(),
+        )?;
+        callbacks.add(
+            &base.get_element_by_id("leave_button")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_leave_room(),
+        )?;
+        Ok(callbacks)
+    }
+
     fn new(
         base: Rc<Base>,
         window: Rc<Window>,
         game: Game,
         room_name: String,
         uuid: Uuid,
+        token: Uuid,
+        config: RoomConfig,
     ) -> JsResult<Playing> {
         // show game
         base.get_element_by_id("game")?
             .set_attribute("class", "visible")?;
 
-        base.get_element_by_id("room_name")?
-            .set_inner_html(&room_name);
+        base.get_element_by_id("countdown")?
+            .set_attribute("class", "hidden")?;
+        base.get_element_by_id("banner")?
+            .set_attribute("class", "hidden")?;
 
         let players_div = base
             .get_element_by_id("players")?
             .dyn_into::<HtmlElement>()?;
         let chat_div = base.get_element_by_id("chat")?.dyn_into::<HtmlElement>()?;
+        let chat_log = base
+            .get_element_by_id("chat_log")?
+            .dyn_into::<HtmlElement>()?;
+        chat_log.set_inner_html("");
+        let event_log = base
+            .get_element_by_id("event_log")?
+            .dyn_into::<HtmlElement>()?;
+        event_log.set_inner_html("");
+        let chat_input = base
+            .get_element_by_id("chat_input")?
+            .dyn_into::<HtmlInputElement>()?;
+        // spectators can only read
+        chat_input.set_disabled(uuid.is_nil());
+        let countdown_div = base
+            .get_element_by_id("countdown")?
+            .dyn_into::<HtmlElement>()?;
+        let banner_div = base
+            .get_element_by_id("banner")?
+            .dyn_into::<HtmlElement>()?;
+        let winner_div = base
+            .get_element_by_id("winner")?
+            .dyn_into::<HtmlElement>()?;
+        winner_div.set_class_name("hidden");
+        let paused_div = base
+            .get_element_by_id("paused")?
+            .dyn_into::<HtmlElement>()?;
+        paused_div.set_class_name("hidden");
+        let auto_start_div = base
+            .get_element_by_id("auto_start")?
+            .dyn_into::<HtmlElement>()?;
+        auto_start_div.set_class_name("hidden");
+        let summary_div = base
+            .get_element_by_id("match_summary")?
+            .dyn_into::<HtmlElement>()?;
+        summary_div.set_class_name("hidden");
+        let match_round_div = base
+            .get_element_by_id("match_round")?
+            .dyn_into::<HtmlElement>()?;
+        match_round_div.set_class_name("hidden");
+        let kill_feed_div = base
+            .get_element_by_id("kill_feed")?
+            .dyn_into::<HtmlElement>()?;
+        kill_feed_div.set_inner_html("");
+        let export_div = base
+            .get_element_by_id("round_export")?
+            .dyn_into::<HtmlElement>()?;
+        export_div.set_class_name("hidden");
+        let replay_link = base
+            .get_element_by_id("replay_link")?
+            .dyn_into::<HtmlElement>()?;
+        replay_link.set_class_name("hidden");
+        let tournament_div = base
+            .get_element_by_id("tournament")?
+            .dyn_into::<HtmlElement>()?;
+        tournament_div.set_class_name("hidden");
 
-        Ok(Playing {
+        let settings = Settings::new(&base)?;
+        settings.show_error(None);
+        apply_accent(&base.doc, config.theme)?;
+
+        let callbacks = Self::register_callbacks(&base)?;
+        let mut playing = Playing {
             base,
             window,
             game,
             uuid,
+            session: Session { room_name, token },
             players_div,
             chat_div,
+            chat_log,
+            event_log,
+            chat_input,
+            countdown_div,
+            banner_div,
+            winner_div,
+            winner_serial: 0,
+            paused_div,
+            auto_start_div,
+            summary_div,
+            room_stats: vec![],
+            match_round_div,
+            match_round: 1,
+            kill_feed_div,
+            export_div,
+            recorder: None,
+            replay_link,
+            playback: None,
+            practice: None,
+            tournament_div,
+            settings,
+            config,
+            shutting_down: false,
+            emotes: HashMap::new(),
+            emote_serial: 0,
+            spectators: 0,
+            queued: HashSet::new(),
+            afk: HashSet::new(),
+            winner: None,
+            winner_points: 0,
             handle_id: 0,
-        })
+            _callbacks: callbacks,
+        };
+        playing.update_settings();
+        playing
+            .game
+            .ghost_meter
+            .show(playing.config.ghost.is_some());
+        playing
+            .game
+            .item_slot
+            .show(playing.config.items && playing.config.item_inventory);
+        playing.game.trail_lifetime = playing.config.trail_lifetime;
+        playing.show_match_round();
+        playing.show_map();
+        playing.show_room_name()?;
+        Ok(playing)
     }
 
-    fn on_keydown(&mut self, event: KeyboardEvent) -> JsError {
-        self.game.on_keydown(event)
+    /// Shows the name the host gave the room, with the code to join next to it.
+    fn show_room_name(&self) -> JsError {
+        let code = &self.session.room_name;
+        let (name, code) = match &self.config.title {
+            Some(title) => (title.as_str(), format!("({})", code)),
+            None => (code.as_str(), String::new()),
+        };
+        self.base
+            .get_element_by_id("room_name")?
+            .set_text_content(Some(name));
+        self.base
+            .get_element_by_id("room_code")?
+            .set_text_content(Some(&code));
+        Ok(())
     }
 
-    fn on_keyup(&mut self, event: KeyboardEvent) -> JsError {
-        self.game.on_keyup(event)
+    /// Reflects the room config, only the host may change it and only between rounds.
+    fn update_settings(&self) {
+        let is_host = self.game.players.get(&self.uuid).is_some_and(|p| p.host);
+        self.settings.show(
+            &self.config,
+            is_host && !self.game.running && !self.game.frozen,
+        );
+        if !is_host {
+            self.settings.show_traffic(None);
+        }
     }
 
-    fn add_player(&mut self, player: Player) -> JsError {
-        self.game.add_player(player.into())?;
-        self.draw_player()?;
+    fn settings_changed(&mut self) -> JsError {
+        let config = self.settings.read(&self.config);
+        self.base.send(ClientMessage::UpdateConfig(config))?;
         Ok(())
     }
 
-    fn remove_player(&mut self, uuid: Uuid, uuid_host: Uuid) -> JsError {
-        self.game.remove_player(uuid, uuid_host)?;
-        self.draw_player()?;
+    fn config_changed(&mut self, config: RoomConfig) -> JsError {
+        if config.theme != self.config.theme {
+            self.game.set_theme(config.theme);
+            apply_accent(&self.base.doc, config.theme)?;
+            self.draw_player()?;
+        }
+        self.game.ghost_meter.show(config.ghost.is_some());
+        self.game
+            .item_slot
+            .show(config.items && config.item_inventory);
+        self.game.trail_lifetime = config.trail_lifetime;
+        let map_changed = config.map != self.config.map;
+        let target_changed = config.target_score != self.config.target_score;
+        let title_changed = config.title != self.config.title;
+        self.config = config;
+        if title_changed {
+            self.show_room_name()?;
+        }
+        if map_changed {
+            self.show_map();
+        }
+        if target_changed {
+            // progress bars on the scoreboard
+            self.draw_player()?;
+        }
+        self.settings.show_error(None);
+        self.update_settings();
+        self.show_match_round();
         Ok(())
     }
 
-    fn game_update(&mut self, game_state: Vec<PlayerState>) -> JsError {
-        self.game.game_update(game_state)?;
-        Ok(())
+    fn match_round(&mut self, round: u32) {
+        self.match_round = round;
+        self.show_match_round();
     }
 
-    fn round_started(&mut self) -> JsError {
-        // TODO: start tick?
-        // game ticks
-        //let cb = Closure::wrap(Box::new(move || {
-        //HANDLE
-        //.lock()
-        //.unwrap()
-        //.game_tick()
-        //.expect("Could not update game");
-        //}) as Box<dyn Fn()>);
+    /// The win condition of the match, e.g. "First to 30" or "Round 3/7"
+    fn show_match_round(&self) {
+        let mut parts = vec![];
+        if let Some(target) = self.config.target_score {
+            parts.push(trf(Text::FirstTo, &[&self.score_text(target)]));
+        }
+        if let Some(rounds) = self.config.best_of {
+            parts.push(trf(Text::RoundOf, &[&self.match_round, &rounds]));
+        }
+        match parts.is_empty() {
+            true => self.match_round_div.set_class_name("hidden"),
+            false => {
+                self.match_round_div
+                    .set_text_content(Some(&parts.join(" \u{00B7} ")));
+                self.match_round_div.set_class_name("visible");
+            }
+        }
+    }
 
-        //self.handle_id = self
-        //.window
-        //.set_interval_with_callback_and_timeout_and_arguments_0(
-        //cb.as_ref().unchecked_ref(),
-        //15,
-        //)?;
-        //cb.forget();
+    fn game_over(&mut self, winner: Uuid) -> JsError {
+        match self.game.players.get(&winner) {
+            Some(player) => {
+                let text = trf(Text::MatchWonBy, &[&player.name]);
+                let color = player.color;
+                self.show_winner_text(&text, &color)
+            }
+            None => Ok(()),
+        }
+    }
 
-        self.game.running = true;
+    fn config_rejected(&mut self, reason: &str) -> JsError {
+        self.settings.show_error(Some(reason));
+        // go back to the values the server knows about
+        self.update_settings();
         Ok(())
     }
 
-    fn round_ended(&mut self, winner: Uuid, points: Vec<(Uuid, usize)>) -> JsError {
-        self.game.running = false;
-        // TODO: show that someone has won
-        // update points
-        points.iter().for_each(|(id, points)| {
-            let player = self.game.players.get_mut(id).unwrap();
-            player.points = *points;
+    fn grid_changed(&mut self, grid_info: GridInfo) -> JsError {
+        self.game.resize(grid_info.width, grid_info.height)?;
+        self.show_map();
+        Ok(())
+    }
+
+    /// Draws the speed zones of the map on the current grid, as the server places them.
+    fn show_map(&mut self) {
+        let canvas = &mut self.game.canvas;
+        let zones = self
+            .config
+            .map
+            .zones(canvas.width as usize, canvas.height as usize);
+        canvas.set_zones(zones);
+        let portals = self
+            .config
+            .map
+            .portals(canvas.width as usize, canvas.height as usize);
+        canvas.set_portals(portals);
+    }
+
+    fn on_keydown(&mut self, event: KeyboardEvent) -> JsError {
+        match emote_key(&event) {
+            Some(emote) => self.base.send(ClientMessage::Emote(emote)),
+            None => self.game.on_keydown(event),
+        }
+    }
+
+    /// Keys between rounds, steering keys do nothing here.
+    fn lobby_keydown(&mut self, event: KeyboardEvent) -> JsError {
+        if let Some(emote) = emote_key(&event) {
+            return self.base.send(ClientMessage::Emote(emote));
+        }
+        if self.game.view_keydown(&event)? {
+            return Ok(());
+        }
+        match event.key().as_str() {
+            " " => self.base.send(ClientMessage::StartGame),
+            "Escape" => self.base.send(ClientMessage::CancelAutoStart),
+            _ => Ok(()),
+        }
+    }
+
+    fn emote(&mut self, id: Uuid, emote: EmoteId) -> JsError {
+        self.emote_serial += 1;
+        let serial = self.emote_serial;
+        self.emotes.insert(id, (emote, serial));
+        let cb = Closure::once_into_js(move || {
+            if let Err(e) = HANDLE.lock().unwrap().on_emote_expired(id, serial) {
+                console_log!("Could not hide emote: {:?}", e);
+            }
         });
+        self.window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(
+                cb.unchecked_ref(),
+                EMOTE_DURATION_MS,
+            )?;
+        self.draw_player()
+    }
+
+    fn emote_expired(&mut self, id: Uuid, serial: u32) -> JsError {
+        // a newer emote of the same player has its own timeout
+        if self.emotes.get(&id).is_some_and(|(_, s)| *s == serial) {
+            self.emotes.remove(&id);
+            self.draw_player()?;
+        }
+        Ok(())
+    }
+
+    fn on_keyup(&mut self, event: KeyboardEvent) -> JsError {
+        self.game.on_keyup(event)
+    }
+
+    fn add_player(&mut self, player: Player) -> JsError {
+        self.game.add_player(player.into())?;
         self.draw_player()?;
         Ok(())
     }
 
-    fn draw_player(&self) -> JsError {
-        self.players_div.set_inner_html("");
-        for (id, player) in &self.game.players {
-            let p = self.base.doc.create_element("p")?;
-            p.set_class_name("player_entry_wrapper");
-            let span = self.base.doc.create_element("span")?;
-            span.set_class_name("player_entry");
-            span.set_attribute("style", &format!("color: {}", player.color.as_str()))?;
-            span.set_text_content(Some(player.name.as_str()));
-            if player.host {
-                let host = self.base.doc.create_element("span")?;
-                host.set_class_name("host");
-                host.set_text_content(Some("*"));
-                span.append_child(&host)?;
-            }
-            if *id == self.uuid {
-                let you = self.base.doc.create_element("span")?;
-                you.set_class_name("you");
-                you.set_text_content(Some(" (You)"));
-                span.append_child(&you)?;
+    fn remove_player(&mut self, uuid: Uuid, uuid_host: Uuid) -> JsError {
+        self.game.remove_player(uuid, uuid_host)?;
+        self.draw_player()?;
+        self.update_settings();
+        Ok(())
+    }
+
+    fn game_update(&mut self, seq: u32, game_state: Vec<PlayerState>) -> JsError {
+        let hud_before = self.game.hud_state();
+        self.game.game_update(seq, game_state)?;
+        if self.game.hud_state() != hud_before {
+            self.draw_player()?;
+        }
+        Ok(())
+    }
+
+    fn show_banner(&self, text: &str) {
+        self.banner_div.set_text_content(Some(text));
+        self.banner_div.set_class_name("visible");
+    }
+
+    fn hide_banner(&self) {
+        self.banner_div.set_class_name("hidden");
+    }
+
+    fn set_paused(&mut self, paused: bool) -> JsError {
+        self.game.paused = paused;
+        self.paused_div
+            .set_class_name(if paused { "visible" } else { "hidden" });
+        Ok(())
+    }
+
+    fn sudden_death(&mut self) -> JsError {
+        self.show_banner(tr(Text::SuddenDeath));
+        Ok(())
+    }
+
+    fn announcement(&mut self, text: &str) -> JsError {
+        self.show_banner(text);
+        self.banner_div.class_list().add_1("announcement")?;
+        Ok(())
+    }
+
+    fn auto_start_countdown(&mut self, seconds: Option<u32>) -> JsError {
+        match seconds {
+            Some(seconds) => {
+                let host = self
+                    .game
+                    .players
+                    .get(&self.uuid)
+                    .is_some_and(|player| player.host);
+                let hint = if host { tr(Text::EscToCancel) } else { "" };
+                self.auto_start_div.set_text_content(Some(&format!(
+                    "{}{}",
+                    trf(Text::NextRoundIn, &[&seconds]),
+                    hint
+                )));
+                self.auto_start_div.set_class_name("visible");
             }
-            p.append_child(&span)?;
+            None => self.auto_start_div.set_class_name("hidden"),
+        }
+        Ok(())
+    }
 
-            let score = self.base.doc.create_element("span")?;
-            score.set_class_name("player_score");
-            score.set_text_content(Some(&player.points.to_string()));
-            p.append_child(&score)?;
+    fn server_shutdown(&mut self, seconds: u32, resumable: bool) -> JsError {
+        // after a restart the session is resumed like after a lost connection
+        self.shutting_down = !resumable;
+        let text = match resumable {
+            true => Text::ServerRestartIn,
+            false => Text::ServerShutdownIn,
+        };
+        self.show_banner(&trf(text, &[&seconds]));
+        Ok(())
+    }
 
-            self.players_div.append_child(&p)?;
+    fn arena_shrunk(&mut self, wall_margin: u32) -> JsError {
+        self.game.canvas.set_wall_margin(wall_margin);
+        Ok(())
+    }
+
+    /// Trails drawn from now on use `line_width`, the ones already drawn keep theirs.
+    fn set_line_width(&mut self, line_width: u32) {
+        self.game
+            .players
+            .values_mut()
+            .for_each(|player| player.line_width = line_width);
+    }
+
+    fn countdown(&mut self, seconds_left: u8) -> JsError {
+        self.hide_banner();
+        self.winner_div.set_class_name("hidden");
+        self.auto_start_div.set_class_name("hidden");
+        self.summary_div.set_class_name("hidden");
+        self.export_div.set_class_name("hidden");
+        self.replay_link.set_class_name("hidden");
+        self.cancel_recording()?;
+        if seconds_left > 0 {
+            self.game.frozen = true;
+            self.countdown_div
+                .set_text_content(Some(&seconds_left.to_string()));
+            self.countdown_div.set_class_name("visible");
+        } else {
+            self.game.frozen = false;
+            self.countdown_div.set_class_name("hidden");
         }
+        self.draw_player()?;
+        self.update_settings();
         Ok(())
     }
-}
 
-struct MyHtmlInputElement {
-    element: HtmlInputElement,
-    prev_value: String,
-    max_len: u32,
-}
+    fn round_started(&mut self) -> JsError {
+        // TODO: start tick?
+        // game ticks
+        //let cb = Closure::wrap(Box::new(move || {
+        //HANDLE
+        //.lock()
+        //.unwrap()
+        //.game_tick()
+        //.expect("Could not update game");
+        //}) as Box<dyn Fn()>);
 
-impl MyHtmlInputElement {
-    fn new(element: HtmlInputElement, max_len: u32) -> Self {
-        Self {
-            element: element.clone(),
-            prev_value: element.value(),
-            max_len,
+        //self.handle_id = self
+        //.window
+        //.set_interval_with_callback_and_timeout_and_arguments_0(
+        //cb.as_ref().unchecked_ref(),
+        //15,
+        //)?;
+        //cb.forget();
+
+        self.game.running = true;
+        self.game.frozen = false;
+        // trails grown in the previous round start over
+        self.set_line_width(self.config.line_width);
+        self.show_best_run();
+        self.draw_player()?;
+        self.update_settings();
+        Ok(())
+    }
+
+    /// Drives the best run of the local player along in rounds played alone on the same track.
+    fn show_best_run(&mut self) {
+        let solo = self.game.players.len() == 1;
+        self.game.canvas.best_run =
+            match (preferences::best_run(), self.game.players.get(&self.uuid)) {
+                (Some(run), Some(player)) if solo && same_track(&run.config, &self.config) => {
+                    let color = blend(
+                        &player.color,
+                        self.config.theme.background(),
+                        BEST_RUN_ALPHA,
+                    );
+                    let linewidth = self.config.line_width as f64;
+                    run.trace()
+                        .windows(2)
+                        .map(|step| {
+                            let (from, to) = (step[0], step[1]);
+                            let teleported =
+                                (to.0 - from.0).hypot(to.1 - from.1) > BEST_RUN_MAX_STEP;
+                            Line {
+                                from: if teleported { to } else { from },
+                                to,
+                                linewidth,
+                                color,
+                                dashes: &[],
+                                offset: 0.,
+                                control: None,
+                            }
+                        })
+                        .collect()
+                }
+                _ => vec![],
+            };
+    }
+
+    /// Keeps a round the local player survived alone if it lasted longer than the best run on the
+    /// same track. With others around a run depends on how they played, those rounds don't count.
+    fn ghost_recorded(&mut self, run: GhostRun) -> JsError {
+        let participants = run
+            .players
+            .iter()
+            .filter(|player| !run.afk.contains(&player.uuid))
+            .count();
+        if run.runner != self.uuid || participants != 1 {
+            return Ok(());
+        }
+        let best = preferences::best_run().filter(|best| same_track(&best.config, &run.config));
+        if best.is_some_and(|best| best.ticks >= run.ticks) {
+            return Ok(());
         }
+        preferences::set_best_run(&run)?;
+        self.show_banner(tr(Text::NewBestRun));
+        Ok(())
     }
 
-    fn value(&self) -> String {
-        self.element.value()
+    fn player_died(&mut self, death: Death) -> JsError {
+        self.game.player_died(&death)?;
+        if let DeathCause::Player(killer) = death.cause {
+            if let Some(killer) = self.game.players.get_mut(&killer) {
+                killer.kills += 1;
+            }
+            self.kill_toast(&killer, &death.id)?;
+        }
+        self.draw_player()?;
+        Ok(())
     }
 
-    fn check_name(&self, name: &str) -> bool {
-        if name.len() == 0 {
-            true
-        } else if name.len() as u32 > self.max_len {
-            false
-        } else if name.contains("<") || name.contains(">") {
-            false
-        } else {
-            true
+    /// Sends the typed message on Enter.
+    fn chat_keydown(&self, event: KeyboardEvent) -> JsError {
+        if event.key() != "Enter" {
+            return Ok(());
         }
+        let text = self.chat_input.value();
+        if !text.trim().is_empty() {
+            self.base.send(ClientMessage::Chat(text))?;
+        }
+        self.chat_input.set_value("");
+        Ok(())
     }
 
-    fn set_value(&mut self, val: &str) {
-        if self.check_name(val) {
-            // accept
-            self.element.set_value(val);
-            self.prev_value = self.element.value();
+    fn chat(&self, msg: &ChatMessage) -> JsError {
+        let line = self.base.doc.create_element("p")?;
+        let name = self.base.doc.create_element("span")?;
+        name.set_text_content(Some(&msg.name));
+        if let Some(player) = self.game.players.get(&msg.sender) {
+            name.set_attribute("style", &format!("color: {}", player.color))?;
+        }
+        line.append_child(&name)?;
+        line.append_child(&self.base.doc.create_text_node(&format!(": {}", msg.text)))?;
+        self.chat_log.append_child(&line)?;
+        while self.chat_log.child_element_count() > CHAT_LINES {
+            if let Some(first) = self.chat_log.first_element_child() {
+                first.remove();
+            }
+        }
+        self.chat_log.set_scroll_top(self.chat_log.scroll_height());
+        Ok(())
+    }
+
+    fn room_event(&self, event: &RoomEvent) -> JsError {
+        let text = match event {
+            RoomEvent::PlayerJoined(name) => trf(Text::EventJoined, &[name]),
+            RoomEvent::PlayerLeft(name) => trf(Text::EventLeft, &[name]),
+            RoomEvent::HostChanged(name) => trf(Text::EventHostChanged, &[name]),
+            RoomEvent::RoundStarted(round) => trf(Text::EventRoundStarted, &[round]),
+            RoomEvent::PlayerDied(name, Some(killer)) if killer != name => {
+                trf(Text::EventCrashedInto, &[name, killer])
+            }
+            RoomEvent::PlayerDied(name, _) => trf(Text::EventCrashed, &[name]),
+            RoomEvent::RoundEnded(winner) => trf(Text::EventRoundWon, &[winner]),
+        };
+        let line = self.base.doc.create_element("p")?;
+        line.set_text_content(Some(&text));
+        self.event_log.append_child(&line)?;
+        while self.event_log.child_element_count() > EVENT_LINES {
+            if let Some(first) = self.event_log.first_element_child() {
+                first.remove();
+            }
+        }
+        self.event_log
+            .set_scroll_top(self.event_log.scroll_height());
+        Ok(())
+    }
+
+    /// Shows "killer cut off victim" for a few seconds in the corner of the canvas.
+    fn kill_toast(&self, killer: &Uuid, victim: &Uuid) -> JsError {
+        let name = |id| self.game.players.get(id).map(|player| player.name);
+        let (killer, victim) = match (name(killer), name(victim)) {
+            (Some(killer), Some(victim)) => (killer, victim),
+            _ => return Ok(()),
+        };
+        let toast = self.base.doc.create_element("p")?;
+        toast.set_text_content(Some(&trf(Text::CutOff, &[&killer, &victim])));
+        self.kill_feed_div.append_child(&toast)?;
+        let cb = Closure::once_into_js(move || toast.remove());
+        self.window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(
+                cb.unchecked_ref(),
+                KILL_FEED_DURATION_MS,
+            )?;
+        Ok(())
+    }
+
+    fn player_queued(&mut self, id: Uuid) -> JsError {
+        self.queued.insert(id);
+        if id == self.uuid {
+            self.announcement(tr(Text::JoinNextRound))?;
+        }
+        self.draw_player()
+    }
+
+    fn player_afk(&mut self, id: Uuid, afk: bool) -> JsError {
+        if afk {
+            self.afk.insert(id);
         } else {
-            // decline
-            self.element.set_value(&self.prev_value);
+            self.afk.remove(&id);
+            if id == self.uuid {
+                self.hide_banner();
+            }
         }
+        self.draw_player()
     }
-}
 
-struct Join {
-    base: Rc<Base>,
-    window: Rc<Window>,
+    fn afk_warning(&mut self, seconds: u32) -> JsError {
+        self.announcement(&trf(Text::AfkWarning, &[&seconds]))
+    }
 
-    input_name: MyHtmlInputElement,
-    input_room: MyHtmlInputElement,
-    join_button: HtmlButtonElement,
-    err_div: HtmlElement,
+    fn round_ending(&mut self, winner: Uuid) -> JsError {
+        self.winner = Some(winner);
+        let ticks = (SLOW_MOTION_SECONDS * self.config.tick_rate as f64) as u32;
+        let first = self.game.canvas.tick.saturating_sub(ticks);
+        let rate = self.config.tick_rate as f64 * SLOW_MOTION_FACTOR;
+        self.game.start_replay(first, rate);
+        self.request_replay_frame()
+    }
+
+    fn request_replay_frame(&self) -> JsError {
+        let cb = Closure::once_into_js(move || {
+            if let Err(e) = HANDLE.lock().unwrap().on_replay_frame() {
+                console_log!("Could not draw the replay: {:?}", e);
+            }
+        });
+        self.window.request_animation_frame(cb.unchecked_ref())?;
+        Ok(())
+    }
+
+    fn replay_frame(&mut self) -> JsError {
+        if self.game.replay_frame() {
+            return self.request_replay_frame();
+        }
+        if let Some(recorder) = self.recorder.take() {
+            recorder.stop()?;
+            self.game.update_camera();
+            self.game.canvas.redraw_all();
+            self.export_div.set_class_name("visible");
+        }
+        self.show_winner()
+    }
+
+    /// Downloads the final board as image.
+    fn export_png(&mut self) -> JsError {
+        let url = self.game.canvas.png_url()?;
+        download(&self.base.doc, &url, "curve-fever-round.png")
+    }
+
+    /// Replays the whole round while recording the canvas, the video is downloaded when done.
+    fn export_video(&mut self) -> JsError {
+        if self.recorder.is_some() || self.game.running {
+            return Ok(());
+        }
+        let stream = self.game.canvas.canvas.capture_stream()?;
+        let recorder = MediaRecorder::new_with_media_stream(&stream)?;
+        let doc = self.base.doc.clone();
+        let window = self.window.clone();
+        let cb = Closure::once_into_js(move |event: BlobEvent| {
+            if let Err(e) = save_recording(&doc, &window, event) {
+                console_log!("Could not save the recording: {:?}", e);
+            }
+        });
+        recorder.set_ondataavailable(Some(cb.unchecked_ref()));
+        recorder.start()?;
+        self.recorder = Some(recorder);
+
+        self.export_div.set_class_name("hidden");
+        self.hide_banner();
+        self.game.canvas.set_camera(None);
+        self.game
+            .start_replay(0, self.config.tick_rate as f64 * VIDEO_SPEED);
+        self.request_replay_frame()
+    }
+
+    fn replay_saved(&mut self, id: &str) -> JsError {
+        let location = self
+            .base
+            .doc
+            .location()
+            .to_js_err("Could not get doc location")?;
+        let url = format!(
+            "{}{}#replay={}",
+            location.origin()?,
+            location.pathname()?,
+            id
+        );
+        self.replay_link.set_attribute("href", &url)?;
+        self.replay_link.set_class_name("visible");
+        Ok(())
+    }
+
+    /// Plays the messages of a replay back at the tick rate of its room.
+    fn start_playback(&mut self, messages: Vec<(u32, ServerMessage)>) -> JsError {
+        let cb = Closure::wrap(Box::new(move || {
+            // the handlers lock `HANDLE` themselves
+            let messages = HANDLE.lock().unwrap().on_playback_tick();
+            for msg in messages {
+                if let Err(e) = on_message(msg) {
+                    console_log!("Could not play the replay: {:?}", e);
+                }
+            }
+        }) as Box<dyn FnMut()>);
+        let interval = self
+            .window
+            .set_interval_with_callback_and_timeout_and_arguments_0(
+                cb.as_ref().unchecked_ref(),
+                1000 / self.config.tick_rate as i32,
+            )?;
+        self.playback = Some(Playback {
+            window: self.window.clone(),
+            messages: messages.into(),
+            tick: 0,
+            interval,
+            _callback: cb,
+        });
+        Ok(())
+    }
+
+    /// Runs the room of `practice` in the browser, the first round starts right away.
+    fn start_practice(&mut self, practice: Practice) -> JsError {
+        let cb = Closure::wrap(Box::new(move || {
+            // the handlers lock `HANDLE` themselves
+            let messages = HANDLE.lock().unwrap().on_practice_tick();
+            for msg in messages {
+                if let Err(e) = on_message(msg) {
+                    console_log!("Could not play the practice round: {:?}", e);
+                }
+            }
+        }) as Box<dyn FnMut()>);
+        // often enough for any tick rate, `Practice` keeps the pace of its config
+        let interval = self
+            .window
+            .set_interval_with_callback_and_timeout_and_arguments_0(
+                cb.as_ref().unchecked_ref(),
+                1000 / TICK_RATE_LIMITS.1 as i32,
+            )?;
+        *self.base.local.borrow_mut() = Some(vec![]);
+        self.practice = Some(PracticeRun {
+            base: self.base.clone(),
+            window: self.window.clone(),
+            practice,
+            interval,
+            _callback: cb,
+        });
+        self.base.send(ClientMessage::StartGame)
+    }
+
+    /// Answers to what was sent since the last tick, along with the messages of the ticks played.
+    fn practice_tick(&mut self) -> Vec<ServerMessage> {
+        let run = match &mut self.practice {
+            Some(run) => run,
+            None => return vec![],
+        };
+        let sent = self
+            .base
+            .local
+            .borrow_mut()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        let mut messages: Vec<ServerMessage> = sent
+            .into_iter()
+            .flat_map(|msg| run.practice.apply(msg))
+            .collect();
+        messages.extend(run.practice.advance());
+        messages
+    }
+
+    /// Messages of the replay that are due in this tick.
+    fn playback_tick(&mut self) -> Vec<ServerMessage> {
+        let playback = match &mut self.playback {
+            Some(playback) => playback,
+            None => return vec![],
+        };
+        let mut due = vec![];
+        while let Some((tick, _)) = playback.messages.front() {
+            if *tick > playback.tick {
+                break;
+            }
+            due.extend(playback.messages.pop_front().map(|(_, msg)| msg));
+        }
+        playback.tick += 1;
+        if playback.messages.is_empty() {
+            self.playback = None;
+        }
+        due
+    }
+
+    /// Discards a recording in progress, e.g. when the next round starts.
+    fn cancel_recording(&mut self) -> JsError {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.set_ondataavailable(None);
+            recorder.stop()?;
+            self.game.stop_replay();
+            self.game.update_camera();
+        }
+        Ok(())
+    }
+
+    /// Announces the winner of the last round along with the points it got.
+    fn show_winner(&mut self) -> JsError {
+        let player = match self
+            .winner
+            .and_then(|winner| self.game.players.get(&winner))
+        {
+            Some(player) => player,
+            None => return Ok(()),
+        };
+        if self.game.players.len() == 1 {
+            self.show_banner(tr(Text::RoundOver));
+            return Ok(());
+        }
+        let mut text = trf(Text::WinsTheRound, &[&player.name]);
+        if self.winner_points > 0 {
+            text.push_str(&trf(Text::PointsGained, &[&self.winner_points]));
+        }
+        let color = player.color;
+        self.show_winner_text(&text, &color)
+    }
+
+    /// Shows `text` in the middle of the canvas in the color of the winner, until it times out.
+    fn show_winner_text(&mut self, text: &str, color: &str) -> JsError {
+        self.winner_div.set_text_content(Some(text));
+        let style = self.winner_div.style();
+        style.set_property("color", color)?;
+        style.set_property("border-color", color)?;
+        self.winner_div.set_class_name("visible");
+        self.winner_serial += 1;
+        let serial = self.winner_serial;
+        let cb = Closure::once_into_js(move || {
+            if let Err(e) = HANDLE.lock().unwrap().on_winner_expired(serial) {
+                console_log!("Could not hide the winner: {:?}", e);
+            }
+        });
+        self.window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(
+                cb.unchecked_ref(),
+                WINNER_DURATION_MS,
+            )?;
+        Ok(())
+    }
+
+    fn winner_expired(&mut self, serial: u32) {
+        // a newer announcement has its own timeout
+        if serial == self.winner_serial {
+            self.winner_div.set_class_name("hidden");
+        }
+    }
+
+    fn round_ended(&mut self, winner: Uuid, points: Vec<(Uuid, usize, usize)>) -> JsError {
+        self.game.running = false;
+        self.queued.clear();
+        self.set_paused(false)?;
+        self.hide_banner();
+        self.game.stop_replay();
+        self.winner = Some(winner);
+        self.export_div.set_class_name("visible");
+        // update points
+        let before = self
+            .game
+            .players
+            .get(&winner)
+            .map_or(0, |player| player.points);
+        points.iter().for_each(|(id, points, kills)| {
+            let player = self.game.players.get_mut(id).unwrap();
+            player.points = *points;
+            player.kills = *kills;
+        });
+        if let Some(player) = self.game.players.get_mut(&winner) {
+            player.round_wins += 1;
+            self.winner_points = player.points.saturating_sub(before);
+        }
+        self.show_winner()?;
+        self.draw_player()?;
+        self.update_settings();
+        Ok(())
+    }
+
+    /// Catches up with the room after joining, resuming a session or starting to watch.
+    fn room_snapshot(&mut self, snapshot: RoomSnapshot) -> JsError {
+        for player in &snapshot.players {
+            if let Some(known) = self.game.players.get_mut(&player.uuid) {
+                known.points = player.points;
+            }
+        }
+        self.afk = snapshot.afk.into_iter().collect();
+        self.spectators = snapshot.spectators;
+        self.room_stats = snapshot.session;
+        self.match_round(snapshot.round);
+        if let Some(tournament) = snapshot.tournament {
+            self.tournament_state(tournament)?;
+        }
+        // trails drawn before joining, later ones arrive with the states
+        if let Some((seq, trails)) = snapshot.trails {
+            self.game.full_trail_sync(seq, trails)?;
+        }
+        self.draw_player()
+    }
+
+    fn spectators_changed(&mut self, spectators: u32) -> JsError {
+        self.spectators = spectators;
+        self.draw_player()
+    }
+
+    /// Shows the bracket of the tournament this room is part of, with a link to the room of the
+    /// next match of the local player.
+    fn tournament_state(&mut self, state: TournamentState) -> JsError {
+        let doc = &self.base.doc;
+        let add = |parent: &Element, tag: &str, text: &str| -> JsResult<Element> {
+            let element = doc.create_element(tag)?;
+            element.set_text_content(Some(text));
+            parent.append_child(&element)?;
+            Ok(element)
+        };
+        let own_name = self
+            .game
+            .players
+            .get(&self.uuid)
+            .map(|player| player.name.to_string());
+
+        self.tournament_div.set_inner_html("");
+        self.tournament_div.set_class_name("flex-item");
+        add(&self.tournament_div, "p", &state.name)?;
+        for (i, round) in state.rounds.iter().enumerate() {
+            let column = add(&self.tournament_div, "div", "")?;
+            column.set_class_name("bracket_round");
+            add(&column, "small", &trf(Text::RoundNumber, &[&(i + 1)]))?;
+            for bracket_match in round {
+                let div = add(&column, "div", "")?;
+                div.set_class_name("bracket_match");
+                if bracket_match.players.is_empty() {
+                    add(&div, "span", &bracket_match.room)?;
+                }
+                for name in &bracket_match.players {
+                    let span = add(&div, "span", name)?;
+                    if bracket_match.winner.as_ref() == Some(name) {
+                        span.set_class_name("bracket_winner");
+                    }
+                }
+                let next_match = bracket_match.winner.is_none()
+                    && bracket_match.room != self.session.room_name
+                    && own_name
+                        .iter()
+                        .any(|own| bracket_match.players.contains(own));
+                if next_match {
+                    let link = add(&div, "a", tr(Text::JoinYourMatch))?;
+                    link.set_attribute("href", &format!("?room={}", bracket_match.room))?;
+                }
+            }
+        }
+        let champion = state
+            .rounds
+            .last()
+            .filter(|round| round.len() == 1)
+            .and_then(|round| round[0].winner.as_ref());
+        if let Some(name) = champion {
+            add(&self.tournament_div, "p", &trf(Text::Champion, &[name]))?;
+        }
+        Ok(())
+    }
+
+    /// Shows a table with the placement of every player in every round of the finished match.
+    fn match_summary(&mut self, rounds: Vec<Vec<RoundResult>>) -> JsError {
+        let doc = &self.base.doc;
+        // appends a new element with the given text to `parent`
+        let add = |parent: &Element, tag: &str, text: &str| -> JsResult<Element> {
+            let element = doc.create_element(tag)?;
+            element.set_text_content(Some(text));
+            parent.append_child(&element)?;
+            Ok(element)
+        };
+
+        // everyone that took part, best final score first
+        let mut players: Vec<(Uuid, String)> = vec![];
+        for result in rounds.iter().flatten() {
+            if !players.iter().any(|(id, _)| *id == result.id) {
+                players.push((result.id, result.name.clone()));
+            }
+        }
+        let points = |id: &Uuid| self.game.players.get(id).map(|player| player.points);
+        players.sort_by_key(|(id, _)| std::cmp::Reverse(points(id)));
+
+        self.summary_div.set_inner_html("");
+        add(&self.summary_div, "p", tr(Text::MatchFinished))?;
+        let table = add(&self.summary_div, "table", "")?;
+        let header = add(&table, "tr", "")?;
+        add(&header, "th", "")?;
+        for i in 1..=rounds.len() {
+            add(&header, "th", &format!("R{}", i))?;
+        }
+        let by_distance = self.config.scoring == ScoringMode::Distance;
+        add(
+            &header,
+            "th",
+            tr(if by_distance {
+                Text::Distance
+            } else {
+                Text::Points
+            }),
+        )?;
+
+        for (id, name) in &players {
+            let row = add(&table, "tr", "")?;
+            add(&row, "td", name)?;
+            for round in &rounds {
+                match round.iter().find(|result| result.id == *id) {
+                    Some(result) => {
+                        let td = add(&row, "td", &format!("#{}", result.placement))?;
+                        let mut details = format!("{:.1}s", result.survival_ms as f64 / 1000.);
+                        if result.kills > 0 {
+                            details += &format!(", {} kills", result.kills);
+                        }
+                        add(&td, "small", &details)?;
+                    }
+                    None => {
+                        add(&row, "td", "-")?;
+                    }
+                }
+            }
+            let total = points(id).map(|p| self.score_text(p)).unwrap_or_default();
+            add(&row, "td", &total)?;
+        }
+
+        // fun titles from how everyone drove
+        let stats: Vec<(&str, InputStats)> = players
+            .iter()
+            .map(|(id, name)| {
+                let mut total = InputStats::default();
+                for result in rounds.iter().flatten().filter(|r| r.id == *id) {
+                    total.turns += result.stats.turns;
+                    total.turning += result.stats.turning;
+                    total.straight += result.stats.straight;
+                    total.near_misses += result.stats.near_misses;
+                }
+                (name.as_str(), total)
+            })
+            .collect();
+        let awards = add(&self.summary_div, "ul", "")?;
+        awards.set_class_name("awards");
+        let most_near_misses = stats.iter().max_by_key(|(_, s)| s.near_misses);
+        if let Some((name, s)) = most_near_misses.filter(|(_, s)| s.near_misses > 0) {
+            let text = trf(Text::MostAggressive, &[name, &s.near_misses]);
+            add(&awards, "li", &text)?;
+        }
+        let most_turns = stats.iter().max_by_key(|(_, s)| s.turns);
+        if let Some((name, s)) = most_turns.filter(|(_, s)| s.turns > 0) {
+            add(&awards, "li", &trf(Text::BusiestHands, &[name, &s.turns]))?;
+        }
+        let straightest = stats
+            .iter()
+            .filter(|(_, s)| s.turning + s.straight > 0.)
+            .min_by(|(_, a), (_, b)| a.turning_share().total_cmp(&b.turning_share()));
+        if let Some((name, s)) = straightest {
+            let share = ((1. - s.turning_share()) * 100.).round();
+            add(&awards, "li", &trf(Text::StraightShooter, &[name, &share]))?;
+        }
+        self.summary_div.set_class_name("visible");
+
+        // the next match starts from zero
+        self.game.players.values_mut().for_each(|player| {
+            player.points = 0;
+            player.kills = 0;
+            player.round_wins = 0;
+        });
+        self.draw_player()
+    }
+
+    /// Adds the results of all matches in the room to the summary of the one that just finished.
+    fn session_stats(&mut self, stats: Vec<SessionStats>) -> JsError {
+        let doc = &self.base.doc;
+        let add = |parent: &Element, tag: &str, text: &str| -> JsResult<Element> {
+            let element = doc.create_element(tag)?;
+            element.set_text_content(Some(text));
+            parent.append_child(&element)?;
+            Ok(element)
+        };
+
+        // nothing to compare after the first match
+        if stats.iter().any(|s| s.matches > 1) {
+            add(&self.summary_div, "p", tr(Text::RoomStandings))?;
+            let table = add(&self.summary_div, "table", "")?;
+            let header = add(&table, "tr", "")?;
+            add(&header, "th", "")?;
+            for text in [Text::Matches, Text::MatchesWon, Text::RoundsWon].iter() {
+                add(&header, "th", tr(*text))?;
+            }
+            for s in &stats {
+                let row = add(&table, "tr", "")?;
+                add(&row, "td", &s.name)?;
+                for value in [s.matches, s.match_wins, s.round_wins].iter() {
+                    add(&row, "td", &value.to_string())?;
+                }
+            }
+        }
+        self.room_stats = stats;
+        self.draw_player()
+    }
+
+    /// Points as shown on the scoreboard, with distance scoring they are pixels travelled
+    fn score_text(&self, points: usize) -> String {
+        match self.config.scoring {
+            ScoringMode::Survival => points.to_string(),
+            ScoringMode::Distance => trf(Text::DistanceScore, &[&points]),
+        }
+    }
+
+    fn colors_changed(&mut self, colors: Vec<(Uuid, ArrayString<7>, TrailPattern)>) -> JsError {
+        self.game.set_colors(colors);
+        self.draw_player()
+    }
+
+    fn ratings_changed(&mut self, ratings: Vec<(Uuid, u32)>) -> JsError {
+        for (id, rating) in ratings {
+            if let Some(player) = self.game.players.get_mut(&id) {
+                player.rating = Some(rating);
+            }
+        }
+        self.draw_player()
+    }
+
+    /// Speed and abilities of a player during a round
+    fn status_span(&self, status: &Status) -> JsResult<Element> {
+        let span = self.base.doc.create_element("span")?;
+        span.set_class_name("player_status");
+        let icon = |class: &str| -> JsResult<Element> {
+            let i = self.base.doc.create_element("i")?;
+            i.set_class_name(class);
+            Ok(i)
+        };
+        let speed_icon = match status.speed_change {
+            SpeedChange::Boost => Some("fas fa-angle-double-up"),
+            SpeedChange::Brake => Some("fas fa-angle-double-down"),
+            SpeedChange::Normal => None,
+        };
+        if let Some(class) = speed_icon {
+            let i = icon(class)?;
+            span.append_child(&i)?;
+        }
+        span.append_child(
+            &self
+                .base
+                .doc
+                .create_text_node(&format!(" {} px/s ", status.speed)),
+        )?;
+        if self.config.ghost.is_some() {
+            let ghost = icon("fas fa-ghost")?;
+            if status.ghost_active {
+                ghost.class_list().add_1("active")?;
+            } else if status.ghost_ready < 100 {
+                ghost.class_list().add_1("cooldown")?;
+                span.append_child(
+                    &self
+                        .base
+                        .doc
+                        .create_text_node(&format!("{}% ", status.ghost_ready)),
+                )?;
+            }
+            span.append_child(&ghost)?;
+        }
+        Ok(span)
+    }
+
+    fn draw_player(&self) -> JsError {
+        self.players_div.set_inner_html("");
+        for (id, player) in &self.game.players {
+            let p = self.base.doc.create_element("p")?;
+            p.set_class_name("player_entry_wrapper");
+            let queued = self.queued.contains(id);
+            if !player.alive || queued {
+                p.class_list().add_1("dead")?;
+            }
+            if *id == self.uuid {
+                p.class_list().add_1("own")?;
+            }
+            let span = self.base.doc.create_element("span")?;
+            span.set_class_name("player_entry");
+            span.set_attribute("style", &format!("color: {}", player.color.as_str()))?;
+            span.set_text_content(Some(player.name.as_str()));
+            if player.host {
+                let host = self.base.doc.create_element("span")?;
+                host.set_class_name("host");
+                host.set_text_content(Some("*"));
+                span.append_child(&host)?;
+            }
+            if queued {
+                let next = self.base.doc.create_element("span")?;
+                next.set_class_name("queued");
+                next.set_text_content(Some(tr(Text::NextRound)));
+                span.append_child(&next)?;
+            } else if self.afk.contains(id) {
+                let afk = self.base.doc.create_element("span")?;
+                afk.set_class_name("queued");
+                afk.set_text_content(Some("AFK"));
+                span.append_child(&afk)?;
+            } else if !player.alive {
+                let skull = self.base.doc.create_element("i")?;
+                skull.set_class_name("fas fa-skull skull");
+                span.append_child(&skull)?;
+            }
+            if let Some(rating) = player.rating {
+                let rating_span = self.base.doc.create_element("span")?;
+                rating_span.set_class_name("rating");
+                rating_span.set_text_content(Some(&rating.to_string()));
+                span.append_child(&rating_span)?;
+            }
+            if *id == self.uuid {
+                let you = self.base.doc.create_element("span")?;
+                you.set_class_name("you");
+                you.set_text_content(Some(tr(Text::You)));
+                span.append_child(&you)?;
+            }
+            if let Some((emote, _)) = self.emotes.get(id) {
+                let bubble = self.base.doc.create_element("span")?;
+                bubble.set_class_name("emote");
+                bubble.set_text_content(Some(emote.symbol()));
+                span.append_child(&bubble)?;
+            }
+            p.append_child(&span)?;
+
+            if player.alive && (self.game.running || self.game.frozen) {
+                let status = self.status_span(&player.status)?;
+                p.append_child(&status)?;
+            }
+
+            if self.config.best_of.is_some() && player.round_wins > 0 {
+                let wins = self.base.doc.create_element("span")?;
+                wins.set_class_name("player_wins");
+                wins.set_attribute("title", tr(Text::RoundWinsThisMatch))?;
+                wins.set_text_content(Some(&format!("{} \u{1F3C6}", player.round_wins)));
+                p.append_child(&wins)?;
+            }
+
+            let match_wins = self
+                .room_stats
+                .iter()
+                .find(|s| s.name == player.name.as_str())
+                .map_or(0, |s| s.match_wins);
+            if match_wins > 0 {
+                let wins = self.base.doc.create_element("span")?;
+                wins.set_class_name("player_wins");
+                wins.set_attribute("title", tr(Text::MatchWinsInRoom))?;
+                wins.set_text_content(Some(&format!("{} \u{1F451}", match_wins)));
+                p.append_child(&wins)?;
+            }
+
+            if player.kills > 0 {
+                let kills = self.base.doc.create_element("span")?;
+                kills.set_class_name("player_kills");
+                kills.set_attribute("title", tr(Text::KillsThisMatch))?;
+                kills.set_text_content(Some(&format!("{} \u{2694}", player.kills)));
+                p.append_child(&kills)?;
+            }
+
+            let score = self.base.doc.create_element("span")?;
+            score.set_class_name("player_score");
+            score.set_text_content(Some(&self.score_text(player.points)));
+            p.append_child(&score)?;
+
+            if let Some(target) = self.config.target_score {
+                // how close the player is to winning the match
+                let bar = self.base.doc.create_element("span")?;
+                bar.set_class_name("score_progress");
+                let fill = self.base.doc.create_element("span")?;
+                let percent = (player.points as f64 / target as f64 * 100.).min(100.);
+                fill.set_attribute(
+                    "style",
+                    &format!("width: {}%; background-color: {}", percent, player.color),
+                )?;
+                bar.append_child(&fill)?;
+                p.append_child(&bar)?;
+            }
+
+            self.players_div.append_child(&p)?;
+        }
+        if self.spectators > 0 {
+            let p = self.base.doc.create_element("p")?;
+            p.set_class_name("spectators");
+            p.set_text_content(Some(&trf(Text::SpectatorsWatching, &[&self.spectators])));
+            self.players_div.append_child(&p)?;
+        }
+        Ok(())
+    }
+}
+
+struct MyHtmlInputElement {
+    element: HtmlInputElement,
+    prev_value: String,
+    max_len: u32,
+}
+
+impl MyHtmlInputElement {
+    fn new(element: HtmlInputElement, max_len: u32) -> Self {
+        Self {
+            element: element.clone(),
+            prev_value: element.value(),
+            max_len,
+        }
+    }
+
+    fn value(&self) -> String {
+        self.element.value()
+    }
+
+    fn check_name(&self, name: &str) -> bool {
+        if name.len() == 0 {
+            true
+        } else if name.len() as u32 > self.max_len {
+            false
+        } else if name.contains("<") || name.contains(">") {
+            false
+        } else {
+            true
+        }
+    }
+
+    fn set_value(&mut self, val: &str) {
+        if self.check_name(val) {
+            // accept
+            self.element.set_value(val);
+            self.prev_value = self.element.value();
+        } else {
+            // decline
+            self.element.set_value(&self.prev_value);
+        }
+    }
+}
+
+struct Join {
+    base: Rc<Base>,
+    window: Rc<Window>,
+
+    input_name: MyHtmlInputElement,
+    input_room: MyHtmlInputElement,
+    join_button: HtmlButtonElement,
+    register_button: HtmlButtonElement,
+    err_div: HtmlElement,
+    account_div: HtmlElement,
+    patterns_input: HtmlInputElement,
+    language_select: HtmlSelectElement,
+
+    create: bool,
+    logging_in: bool,             // a stored token was sent and is not answered yet
+    _callbacks: CallbackRegistry, // removed along with the state
+}
+
+impl Drop for Playing {
+    fn drop(&mut self) {
+        self.base
+            .get_element_by_id("game")
+            .unwrap()
+            .set_attribute("class", "hidden")
+            .unwrap();
+    }
+}
+
+impl Drop for Join {
+    fn drop(&mut self) {
+        self.base
+            .get_element_by_id("start")
+            .unwrap()
+            .set_attribute("class", "hidden")
+            .unwrap();
+    }
+}
+
+impl Join {
+    /// Listens to the join form for as long as the state lasts.
+    fn register_callbacks(base: &Base) -> JsResult<CallbackRegistry> {
+        let mut callbacks = CallbackRegistry::default();
+        callbacks.add(
+            &base.get_element_by_id("join_name")?,
+            "input",
+            move |event: InputEvent| HANDLE.lock().unwrap().on_input_name(event),
+        )?;
+
+        callbacks.add(
+            &base.get_element_by_id("join_room")?,
+            "input",
+            move |event: InputEvent| HANDLE.lock().unwrap().on_input_room(event),
+        )?;
+
+        for &action in Action::ALL.iter() {
+            callbacks.add(
+                &base.get_element_by_id(action.input_id())?,
+                "keydown",
+                move |event: KeyboardEvent| HANDLE.lock().unwrap().on_bind_key(action, event),
+            )?;
+        }
+
+        callbacks.add(
+            &base.get_element_by_id("patterns")?,
+            "change",
+            move |_: Event| HANDLE.lock().unwrap().on_patterns_changed(),
+        )?;
+
+        callbacks.add(
+            &base.get_element_by_id("language_select")?,
+            "change",
+            move |_: Event| HANDLE.lock().unwrap().on_language_changed(),
+        )?;
+
+        callbacks.add(
+            &base.get_element_by_id("spectate")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_spectate(),
+        )?;
+
+        callbacks.add(
+            &base.get_element_by_id("quick_play")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_quick_play(),
+        )?;
+
+        callbacks.add(
+            &base.get_element_by_id("practice")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_practice(),
+        )?;
+
+        callbacks.add(
+            &base.get_element_by_id("register")?,
+            "click",
+            move |_: Event| HANDLE.lock().unwrap().on_register(),
+        )?;
+
+        let form = base.get_element_by_id("join_form")?;
+        callbacks.add(&form, "submit", move |e: Event| {
+            e.prevent_default();
+            HANDLE.lock().unwrap().on_create_or_join()
+        })?;
+        Ok(callbacks)
+    }
+
+    fn new(base: Rc<Base>, window: Rc<Window>) -> JsResult<Self> {
+        base.get_element_by_id("start")?
+            .set_attribute("class", "visible")?;
+
+        // input fields
+        let input_name = MyHtmlInputElement::new(
+            base.get_element_by_id("join_name")?
+                .dyn_into::<HtmlInputElement>()?,
+            20,
+        );
+        let input_room = MyHtmlInputElement::new(
+            base.get_element_by_id("join_room")?
+                .dyn_into::<HtmlInputElement>()?,
+            ROOM_CODE_LENGTH as u32,
+        );
+
+        // error div
+        let err_div = base
+            .get_element_by_id("join_error")?
+            .dyn_into::<HtmlElement>()?;
+        err_div.set_inner_html("");
+
+        // click for create or join button
+        let join_button = base
+            .get_element_by_id("create_or_join")?
+            .dyn_into::<HtmlButtonElement>()?;
+        let register_button = base
+            .get_element_by_id("register")?
+            .dyn_into::<HtmlButtonElement>()?;
+        let account_div = base
+            .get_element_by_id("account_status")?
+            .dyn_into::<HtmlElement>()?;
+        let patterns_input = base
+            .get_element_by_id("patterns")?
+            .dyn_into::<HtmlInputElement>()?;
+        let language_select = base
+            .get_element_by_id("language_select")?
+            .dyn_into::<HtmlSelectElement>()?;
+        language_select.set_inner_html("");
+        for language in Language::ALL.iter() {
+            let option = base.doc.create_element("option")?;
+            option.set_attribute("value", language.code())?;
+            option.set_text_content(Some(language.name()));
+            language_select.append_child(&option)?;
+        }
+        language_select.set_value(i18n::language().code());
+
+        let callbacks = Self::register_callbacks(&base)?;
+        let mut join = Self {
+            base,
+            window,
+            input_name,
+            input_room,
+            join_button,
+            register_button,
+            err_div,
+            account_div,
+            patterns_input,
+            language_select,
+            create: true,
+            logging_in: false,
+            _callbacks: callbacks,
+        };
+        // the form keeps its values after a reconnect
+        let preferences = Preferences::load();
+        if join.input_name.value().is_empty() {
+            join.input_name.set_value(&preferences.name);
+        }
+        for &action in Action::ALL.iter() {
+            join.key_input(action)?
+                .set_value(&key_label(preferences.keys.key(action)));
+        }
+        join.patterns_input.set_checked(preferences.patterns);
+        // links to tournament matches name the room
+        let search = join.window.location().search()?;
+        if let Some(room) = search.strip_prefix("?room=") {
+            join.input_room.set_value(room);
+        }
+        join.input_room_changed()?;
+        Ok(join)
+    }
+
+    fn language_changed(&mut self) -> JsError {
+        let language = match Language::from_code(&self.language_select.value()) {
+            Some(language) => language,
+            None => return Ok(()),
+        };
+        i18n::set_language(language);
+        i18n::translate_page(&self.base.root);
+        self.input_room_changed()?;
+        let mut preferences = Preferences::load();
+        preferences.language = Some(language);
+        preferences.save()
+    }
+
+    fn patterns_changed(&self) -> JsError {
+        let mut preferences = Preferences::load();
+        preferences.patterns = self.patterns_input.checked();
+        preferences.save()
+    }
+
+    fn key_input(&self, action: Action) -> JsResult<HtmlInputElement> {
+        self.base
+            .get_element_by_id(action.input_id())?
+            .dyn_into::<HtmlInputElement>()
+            .map_err(JsValue::from)
+    }
+
+    fn bind_key(&self, action: Action, event: KeyboardEvent) -> JsError {
+        let key = event.key();
+        if key == "Tab" || key == "Escape" || key == " " {
+            // keep navigating the form, space is taken by the ghost ability
+            return Ok(());
+        }
+        event.prevent_default();
+        let mut preferences = Preferences::load();
+        preferences.keys.bind(action, &key);
+        preferences.save()?;
+        self.key_input(action)?.set_value(&key_label(&key));
+        Ok(())
+    }
+
+    fn input_room_changed(&mut self) -> JsError {
+        self.input_room
+            .set_value(&curve_fever_common::normalize_room_code(
+                &self.input_room.value(),
+            ));
+        if self.input_room.value().is_empty() {
+            self.join_button
+                .set_text_content(Some(tr(Text::CreateRoom)));
+            self.create = true;
+        } else {
+            self.join_button.set_text_content(Some(tr(Text::JoinRoom)));
+            self.create = false;
+        }
+        Ok(())
+    }
+
+    fn input_name_changed(&mut self) -> JsError {
+        self.input_name.set_value(&self.input_name.value());
+        if self.input_name.value().chars().all(|c| c == ' ') {
+            self.input_name.set_value("");
+        }
+        Ok(())
+    }
+
+    /// Whether the typed room code can be sent, shows why not otherwise.
+    fn check_room_code(&self) -> bool {
+        let valid = curve_fever_common::is_room_code(&self.input_room.value());
+        if !valid {
+            self.err_div
+                .set_text_content(Some(tr(Text::InvalidRoomCode)));
+        }
+        valid
+    }
+
+    fn create_or_join_clicked(&self) -> JsError {
+        if !self.create && !self.check_room_code() {
+            return Ok(());
+        }
+        if !self.input_name.value().is_empty() {
+            self.err_div.set_inner_html("");
+            let msg = match self.create {
+                true => ClientMessage::CreateRoom(self.input_name.value()),
+                false => ClientMessage::JoinRoom(self.input_name.value(), self.input_room.value()),
+            };
+            self.base.send(msg)?;
+            let mut preferences = Preferences::load();
+            preferences.name = self.input_name.value();
+            preferences.save()?;
+        }
+        Ok(())
+    }
+
+    fn join_failed(&self, err: &str) -> JsError {
+        self.err_div.set_inner_html(err);
+        Ok(())
+    }
+
+    /// Logs into the account registered in this browser, if there is one.
+    fn login(&mut self) -> JsError {
+        if let Some(token) = preferences::account_token() {
+            self.logging_in = true;
+            self.base.send(ClientMessage::Login(token))?;
+        }
+        Ok(())
+    }
+
+    /// Lets the server pick a room with players of about the same rating.
+    fn quick_play_clicked(&self) -> JsError {
+        if !self.input_name.value().is_empty() {
+            self.err_div.set_inner_html("");
+            self.base
+                .send(ClientMessage::QuickPlay(self.input_name.value()))?;
+            let mut preferences = Preferences::load();
+            preferences.name = self.input_name.value();
+            preferences.save()?;
+        }
+        Ok(())
+    }
+
+    fn spectate_clicked(&self) -> JsError {
+        if self.input_room.value().is_empty() {
+            self.err_div
+                .set_text_content(Some(tr(Text::EnterRoomToWatch)));
+        } else if self.check_room_code() {
+            self.err_div.set_inner_html("");
+            self.base
+                .send(ClientMessage::Spectate(self.input_room.value()))?;
+        }
+        Ok(())
+    }
+
+    fn register_clicked(&self) -> JsError {
+        if !self.input_name.value().is_empty() {
+            self.err_div.set_inner_html("");
+            self.base
+                .send(ClientMessage::Register(self.input_name.value()))?;
+        }
+        Ok(())
+    }
+
+    fn auth_result(&mut self, result: Result<(Uuid, Account), String>) -> JsError {
+        let logging_in = std::mem::replace(&mut self.logging_in, false);
+        match result {
+            Ok((token, account)) => {
+                preferences::set_account_token(Some(&token))?;
+                // the server uses the account name anyway
+                self.input_name.set_value(&account.name);
+                self.input_name.element.set_disabled(true);
+                self.register_button.set_class_name("hidden");
+                self.account_div.set_text_content(Some(&trf(
+                    Text::LoggedIn,
+                    &[
+                        &account.name,
+                        &account.stats.rating,
+                        &account.stats.rounds_won,
+                        &account.stats.rounds_played,
+                    ],
+                )));
+            }
+            Err(reason) => {
+                if logging_in {
+                    // the stored account does not exist anymore
+                    preferences::set_account_token(None)?;
+                }
+                self.err_div.set_inner_html(&reason);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn scoring_name(scoring: ScoringMode) -> &'static str {
+    tr(match scoring {
+        ScoringMode::Survival => Text::SurvivalScoring,
+        ScoringMode::Distance => Text::DistanceScoring,
+    })
+}
+
+fn map_name(map: MapPreset) -> &'static str {
+    tr(match map {
+        MapPreset::Open => Text::OpenMap,
+        MapPreset::Ramps => Text::RampsMap,
+        MapPreset::Swamp => Text::SwampMap,
+        MapPreset::Portals => Text::PortalsMap,
+    })
+}
+
+fn hitbox_name(hitbox: Hitbox) -> &'static str {
+    tr(match hitbox {
+        Hitbox::Band => Text::BandHitbox,
+        Hitbox::Square(_) => Text::SquareHitbox,
+        Hitbox::Circle(_) => Text::CircleHitbox,
+    })
+}
+
+/// How a bound key is shown on the start screen
+fn key_label(key: &str) -> String {
+    match key.strip_prefix("Arrow") {
+        Some(arrow) => arrow.to_string(),
+        None => key.to_uppercase(),
+    }
+}
+
+const RECONNECT_BASE_MS: i32 = 1000;
+const RECONNECT_MAX_MS: i32 = 30000;
+
+struct Disconnected {
+    doc: Document,
+    root: Element,
+    window: Rc<Window>,
+    session: Option<Session>,
+
+    status_div: HtmlElement,
+    ws: Option<(WebSocket, CallbackRegistry)>, // connection attempt in progress
+    attempt: u32,
+    timeout_id: Option<i32>,
+}
+
+impl Drop for Disconnected {
+    fn drop(&mut self) {
+        if let Some(id) = self.timeout_id {
+            self.window.clear_timeout_with_handle(id);
+        }
+        find_element(&self.root, "disconnected")
+            .unwrap()
+            .set_attribute("class", "hidden")
+            .unwrap();
+    }
+}
+
+impl Disconnected {
+    fn new(
+        doc: Document,
+        root: Element,
+        window: Rc<Window>,
+        session: Option<Session>,
+        reason: &str,
+    ) -> JsResult<Self> {
+        let get = |id: &str| find_element(&root, id);
+        get("disconnected")?.set_attribute("class", "visible")?;
+        get("disconnect_reason")?.set_text_content(Some(reason));
+        let status_div = get("reconnect_status")?.dyn_into::<HtmlElement>()?;
+
+        let mut disconnected = Self {
+            doc,
+            root,
+            window,
+            session,
+            status_div,
+            ws: None,
+            attempt: 0,
+            timeout_id: None,
+        };
+        disconnected.schedule_reconnect()?;
+        Ok(disconnected)
+    }
+
+    fn schedule_reconnect(&mut self) -> JsError {
+        let delay = RECONNECT_BASE_MS
+            .saturating_mul(1 << self.attempt.min(5))
+            .min(RECONNECT_MAX_MS);
+        self.status_div
+            .set_text_content(Some(&trf(Text::ReconnectingIn, &[&(delay / 1000)])));
+        let cb = Closure::once_into_js(move || {
+            if let Err(e) = HANDLE.lock().unwrap().on_reconnect() {
+                console_log!("Reconnect failed: {:?}", e);
+            }
+        });
+        self.timeout_id = Some(
+            self.window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(cb.unchecked_ref(), delay)?,
+        );
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> JsError {
+        if self.ws.is_some() {
+            // already trying
+            return Ok(());
+        }
+        if let Some(id) = self.timeout_id.take() {
+            self.window.clear_timeout_with_handle(id);
+        }
+        self.attempt += 1;
+        self.status_div
+            .set_text_content(Some(tr(Text::Reconnecting)));
+        self.ws = Some(connect(&self.doc)?);
+        Ok(())
+    }
+
+    fn connection_failed(&mut self) -> JsError {
+        if self.ws.take().is_some() {
+            self.schedule_reconnect()?;
+        }
+        Ok(())
+    }
+}
+
+/// `Lobby` and `InRound` share the room's screen, they only differ in what input does: rounds
+/// are started and the settings edited in the lobby, steering only works in a round.
+enum State {
+    Join(Join),
+    Lobby(Playing),
+    InRound(Playing),
+    Disconnected(Disconnected),
+    Empty,
+}
+
+/// The emote of a number key, 1 for the first one
+fn emote_key(event: &KeyboardEvent) -> Option<EmoteId> {
+    let n = event.key().parse::<usize>().ok()?;
+    EmoteId::ALL.get(n.checked_sub(1)?).copied()
+}
+
+/// The text field a key event was typed into, if any.
+fn text_input_target(event: &KeyboardEvent) -> Option<HtmlElement> {
+    let target = event.target()?.dyn_into::<HtmlElement>().ok()?;
+    if target.is_content_editable() || target.dyn_ref::<HtmlInputElement>().is_some() {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+impl State {
+    fn on_keydown(&mut self, event: KeyboardEvent) -> JsError {
+        if let Some(input) = text_input_target(&event) {
+            // typing, no game controls
+            if event.key() == "Escape" {
+                input.blur()?;
+                if let State::Lobby(s) | State::InRound(s) = self {
+                    s.game.canvas.canvas.focus()?;
+                }
+            }
+            return Ok(());
+        }
+        Ok(match self {
+            State::Lobby(s) => s.lobby_keydown(event)?,
+            State::InRound(s) => s.on_keydown(event)?,
+            _ => (),
+        })
+    }
+
+    fn on_keyup(&mut self, event: KeyboardEvent) -> JsError {
+        if text_input_target(&event).is_some() {
+            return Ok(());
+        }
+        Ok(match self {
+            State::InRound(s) => s.on_keyup(event)?,
+            _ => (),
+        })
+    }
+
+    /// Moves the room between `Lobby` and `InRound` as rounds start and end.
+    fn update_phase(&mut self) {
+        let in_round = match self {
+            State::Lobby(s) | State::InRound(s) => s.game.running || s.game.frozen,
+            _ => return,
+        };
+        *self = match std::mem::replace(self, State::Empty) {
+            State::Lobby(s) if in_round => State::InRound(s),
+            State::InRound(s) if !in_round => State::Lobby(s),
+            state => state,
+        };
+    }
+
+    fn on_closed(&mut self, reason: &str) -> JsError {
+        let (base, window, session, reason) = match self {
+            State::Join(s) => (s.base.clone(), s.window.clone(), None, reason),
+            // there is nothing to resume after a shutdown
+            State::Lobby(s) | State::InRound(s) if s.shutting_down => (
+                s.base.clone(),
+                s.window.clone(),
+                None,
+                tr(Text::ServerWasShutDown),
+            ),
+            // the practice room goes on without the server
+            State::Lobby(s) | State::InRound(s) if s.practice.is_some() => return Ok(()),
+            State::Lobby(s) | State::InRound(s) => (
+                s.base.clone(),
+                s.window.clone(),
+                // spectators have no session to resume
+                Some(s.session.clone()).filter(|_| !s.uuid.is_nil()),
+                reason,
+            ),
+            State::Disconnected(s) => return s.connection_failed(),
+            State::Empty => return Ok(()),
+        };
+        // drop the old state first, it hides its screen
+        *self = State::Empty;
+        *self = State::Disconnected(Disconnected::new(
+            base.doc.clone(),
+            base.root.clone(),
+            window,
+            session,
+            reason,
+        )?);
+        Ok(())
+    }
+
+    fn on_reconnect(&mut self) -> JsError {
+        Ok(match self {
+            State::Disconnected(s) => s.reconnect()?,
+            _ => (),
+        })
+    }
+
+    fn on_connected(&mut self) -> JsError {
+        let s = match self {
+            State::Join(s) => return s.login(),
+            State::Disconnected(s) => s,
+            _ => return Ok(()),
+        };
+        let (ws, ws_callbacks) = s.ws.take().to_js_err("No pending connection")?;
+        let base = Rc::new(Base {
+            doc: s.doc.clone(),
+            root: s.root.clone(),
+            ws,
+            _ws_callbacks: ws_callbacks,
+            touch: false,
+            local: RefCell::new(None),
+        });
+        let window = s.window.clone();
+        let session = s.session.take();
+
+        *self = State::Empty;
+        let mut join = Join::new(base.clone(), window)?;
+        join.login()?;
+        *self = State::Join(join);
+        if let Some(session) = session {
+            // get straight back into the room
+            base.send(ClientMessage::ResumeSession(
+                session.room_name,
+                session.token,
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Goes back to the join screen, the connection stays open for the next room.
+    fn on_leave_room(&mut self) -> JsError {
+        let (base, window, practice) = match self {
+            State::Lobby(s) | State::InRound(s) => {
+                (s.base.clone(), s.window.clone(), s.practice.is_some())
+            }
+            _ => return Ok(()),
+        };
+        // a practice room is unknown to the server
+        if !practice {
+            base.send(ClientMessage::LeaveRoom)?;
+        }
+        // drop the old state first, it hides its screen
+        *self = State::Empty;
+        if practice && base.ws.ready_state() != WebSocket::OPEN {
+            *self = State::Disconnected(Disconnected::new(
+                base.doc.clone(),
+                base.root.clone(),
+                window,
+                None,
+                tr(Text::ServerUnreachable),
+            )?);
+            return Ok(());
+        }
+        let mut join = Join::new(base, window)?;
+        join.login()?;
+        *self = State::Join(join);
+        Ok(())
+    }
+
+    fn on_input_room(&mut self, _event: InputEvent) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.input_room_changed()?,
+            _ => (),
+        })
+    }
+
+    fn on_input_name(&mut self, _event: InputEvent) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.input_name_changed()?,
+            _ => (),
+        })
+    }
+
+    fn on_create_or_join(&mut self) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.create_or_join_clicked()?,
+            _ => (),
+        })
+    }
+
+    fn on_bind_key(&mut self, action: Action, event: KeyboardEvent) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.bind_key(action, event)?,
+            _ => (),
+        })
+    }
+
+    fn on_language_changed(&mut self) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.language_changed()?,
+            _ => (),
+        })
+    }
+
+    fn on_patterns_changed(&mut self) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.patterns_changed()?,
+            _ => (),
+        })
+    }
+
+    fn on_spectate(&mut self) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.spectate_clicked()?,
+            _ => (),
+        })
+    }
+
+    fn on_quick_play(&mut self) -> JsError {
+        if let State::Join(s) = self {
+            s.quick_play_clicked()?;
+        }
+        Ok(())
+    }
+
+    fn on_spectators_changed(&mut self, spectators: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.spectators_changed(spectators)?,
+            _ => (),
+        })
+    }
+
+    fn on_register(&mut self) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.register_clicked()?,
+            _ => (),
+        })
+    }
+
+    fn on_auth_result(&mut self, result: Result<(Uuid, Account), String>) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.auth_result(result)?,
+            _ => (),
+        })
+    }
+
+    fn on_join_failed(&mut self, err_text: &str) -> JsError {
+        Ok(match self {
+            State::Join(s) => s.join_failed(err_text)?,
+            _ => (),
+        })
+    }
+
+    fn on_join_success(
+        &mut self,
+        room_name: String,
+        grid_info: GridInfo,
+        players: Vec<Player>,
+        uuid: Uuid,
+        token: Uuid,
+        config: RoomConfig,
+    ) -> JsError {
+        Ok(match self {
+            State::Join(s) => {
+                // switch state to `Lobby`, the first state of a running round moves it on
+                let game = Game::new(
+                    s.base.clone(),
+                    grid_info.width,
+                    grid_info.height,
+                    players
+                        .iter()
+                        .map(|v| (*v).into())
+                        .collect::<Vec<MyPlayer>>(),
+                    uuid,
+                    config.theme,
+                )?;
+                let s = std::mem::replace(self, State::Empty);
+                match s {
+                    State::Join(s) => {
+                        *self = State::Lobby(Playing::new(
+                            s.base.clone(),
+                            s.window.clone(),
+                            game,
+                            room_name,
+                            uuid,
+                            token,
+                            config,
+                        )?)
+                    }
+                    _ => panic!("Invalid state"),
+                }
+            }
+            _ => (),
+        })
+    }
+
+    fn on_new_player(&mut self, player: Player) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.add_player(player)?;
+            }
+            _ => (),
+        })
+    }
+
+    fn on_player_queued(&mut self, id: Uuid) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.player_queued(id)?,
+            _ => (),
+        })
+    }
+
+    fn on_player_afk(&mut self, id: Uuid, afk: bool) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.player_afk(id, afk)?,
+            _ => (),
+        })
+    }
+
+    fn on_afk_warning(&mut self, seconds: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.afk_warning(seconds)?,
+            _ => (),
+        })
+    }
+
+    fn on_game_over(&mut self, winner: Uuid) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.game_over(winner)?,
+            _ => (),
+        })
+    }
+
+    fn on_match_summary(&mut self, rounds: Vec<Vec<RoundResult>>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.match_summary(rounds)?,
+            _ => (),
+        })
+    }
+
+    fn on_session_stats(&mut self, stats: Vec<SessionStats>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.session_stats(stats)?,
+            _ => (),
+        })
+    }
+
+    fn on_item_spawned(&mut self, item: Item) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.game.item_spawned(item);
+            }
+            _ => (),
+        })
+    }
+
+    fn on_item_collected(&mut self, id: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.game.item_collected(id);
+            }
+            _ => (),
+        })
+    }
+
+    fn on_player_teleported(&mut self, teleport: Teleport) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.game.teleported(&teleport),
+            _ => (),
+        })
+    }
+
+    fn on_area_cleared(&mut self, center: (f64, f64), radius: f64) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.game.area_cleared(center, radius);
+            }
+            _ => (),
+        })
+    }
+
+    fn on_player_died(&mut self, death: Death) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.player_died(death)?;
+            }
+            _ => (),
+        })
+    }
+
+    fn on_player_disconnected(&mut self, uuid: Uuid, uuid_host: Uuid) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.remove_player(uuid, uuid_host)?;
+            }
+            _ => (),
+        })
+    }
+
+    fn on_countdown(&mut self, seconds_left: u8) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.countdown(seconds_left)?;
+            }
+            _ => (),
+        })
+    }
+
+    fn on_chat_keydown(&mut self, event: KeyboardEvent) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.chat_keydown(event)?,
+            _ => (),
+        })
+    }
+
+    fn on_chat(&mut self, msg: ChatMessage) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.chat(&msg)?,
+            _ => (),
+        })
+    }
+
+    fn on_room_event(&mut self, event: RoomEvent) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.room_event(&event)?,
+            _ => (),
+        })
+    }
+
+    fn on_chat_history(&mut self, history: Vec<ChatMessage>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                for msg in &history {
+                    s.chat(msg)?;
+                }
+            }
+            _ => (),
+        })
+    }
+
+    fn on_emote(&mut self, id: Uuid, emote: EmoteId) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.emote(id, emote)?,
+            _ => (),
+        })
+    }
+
+    fn on_round_ending(&mut self, winner: Uuid) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.round_ending(winner)?,
+            _ => (),
+        })
+    }
+
+    fn on_replay_frame(&mut self) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.replay_frame()?,
+            _ => (),
+        })
+    }
+
+    fn on_replay_saved(&mut self, id: &str) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.replay_saved(id)?,
+            _ => (),
+        })
+    }
+
+    fn on_ghost_recorded(&mut self, run: GhostRun) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.ghost_recorded(run)?,
+            _ => (),
+        })
+    }
+
+    /// Watches a downloaded replay like a spectator.
+    fn on_replay_loaded(&mut self, replay: Replay) -> JsError {
+        self.on_join_success(
+            tr(Text::Replay).into(),
+            replay.grid_info,
+            replay.players,
+            Uuid::nil(),
+            Uuid::nil(),
+            replay.config,
+        )?;
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.start_playback(replay.messages)?,
+            _ => (),
+        })
+    }
+
+    fn on_playback_tick(&mut self) -> Vec<ServerMessage> {
+        match self {
+            State::Lobby(s) | State::InRound(s) => s.playback_tick(),
+            _ => vec![],
+        }
+    }
+
+    /// Starts a practice room in the browser, from the join screen or while the server can't be
+    /// reached.
+    fn on_practice(&mut self) -> JsError {
+        let (base, window, name) = match self {
+            State::Join(s) => (s.base.clone(), s.window.clone(), s.input_name.value()),
+            State::Disconnected(s) => {
+                // the server is tried again, it might be back once the practice is over
+                let (ws, ws_callbacks) = match s.ws.take() {
+                    Some(pending) => pending,
+                    None => connect(&s.doc)?,
+                };
+                let base = Rc::new(Base {
+                    doc: s.doc.clone(),
+                    root: s.root.clone(),
+                    ws,
+                    _ws_callbacks: ws_callbacks,
+                    touch: false,
+                    local: RefCell::new(None),
+                });
+                (base, s.window.clone(), String::new())
+            }
+            _ => return Ok(()),
+        };
+        let name = Some(name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| Preferences::load().name);
+        let name = match name.is_empty() {
+            true => tr(Text::PracticeName).to_string(),
+            false => name,
+        };
+        let bots = base
+            .get_element_by_id("practice_bots")?
+            .dyn_into::<HtmlInputElement>()?
+            .value()
+            .trim()
+            .parse::<usize>()
+            .unwrap_or(0);
+        let practice = Practice::new(&name, bots);
+
+        if let State::Disconnected(_) = self {
+            // drop the old state first, it hides its screen
+            *self = State::Empty;
+            *self = State::Join(Join::new(base, window)?);
+        }
+        self.on_join_success(
+            tr(Text::PracticeRoom).into(),
+            practice.grid_info(),
+            practice.players(),
+            practice.uuid(),
+            Uuid::nil(),
+            practice.config().clone(),
+        )?;
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.start_practice(practice)?,
+            _ => (),
+        })
+    }
+
+    fn on_practice_tick(&mut self) -> Vec<ServerMessage> {
+        match self {
+            State::Lobby(s) | State::InRound(s) => s.practice_tick(),
+            _ => vec![],
+        }
+    }
+
+    fn on_export_png(&mut self) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.export_png()?,
+            _ => (),
+        })
+    }
 
-    create: bool,
-}
+    fn on_export_video(&mut self) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.export_video()?,
+            _ => (),
+        })
+    }
 
-impl Drop for Join {
-    fn drop(&mut self) {
-        self.base
-            .get_element_by_id("start")
-            .unwrap()
-            .set_attribute("class", "hidden")
-            .unwrap();
+    fn on_winner_expired(&mut self, serial: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.winner_expired(serial),
+            _ => (),
+        })
     }
-}
 
-impl Join {
-    fn new(base: Rc<Base>, window: Rc<Window>) -> JsResult<Self> {
-        // input fields
-        let input_name = MyHtmlInputElement::new(
-            base.get_element_by_id("join_name")?
-                .dyn_into::<HtmlInputElement>()?,
-            20,
-        );
-        set_event_cb(&input_name.element, "input", move |event: InputEvent| {
-            HANDLE.lock().unwrap().on_input_name(event)
+    fn on_emote_expired(&mut self, id: Uuid, serial: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.emote_expired(id, serial)?,
+            _ => (),
         })
-        .forget();
+    }
 
-        let input_room = MyHtmlInputElement::new(
-            base.get_element_by_id("join_room")?
-                .dyn_into::<HtmlInputElement>()?,
-            7,
-        );
-        set_event_cb(&input_room.element, "input", move |event: InputEvent| {
-            HANDLE.lock().unwrap().on_input_room(event)
+    fn on_ping(&mut self, seq: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.base.send(ClientMessage::Pong(seq))?,
+            _ => (),
         })
-        .forget();
+    }
 
-        // error div
-        let err_div = base
-            .get_element_by_id("join_error")?
-            .dyn_into::<HtmlElement>()?;
-        err_div.set_inner_html("");
+    fn on_tournament_state(&mut self, tournament: TournamentState) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.tournament_state(tournament)?,
+            _ => (),
+        })
+    }
 
-        // click for create or join button
-        let join_button = base
-            .get_element_by_id("create_or_join")?
-            .dyn_into::<HtmlButtonElement>()?;
+    fn on_auto_start_countdown(&mut self, seconds: Option<u32>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.auto_start_countdown(seconds)?,
+            _ => (),
+        })
+    }
 
-        let form = base.get_element_by_id("join_form")?;
-        set_event_cb(&form, "submit", move |e: Event| {
-            e.prevent_default();
-            HANDLE.lock().unwrap().on_create_or_join()
+    fn on_announcement(&mut self, text: &str) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.announcement(text)?,
+            _ => (),
         })
-        .forget();
+    }
 
-        Ok(Self {
-            base,
-            window,
-            input_name,
-            input_room,
-            join_button,
-            err_div,
-            create: true,
+    fn on_server_shutdown(&mut self, seconds: u32, resumable: bool) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.server_shutdown(seconds, resumable)?,
+            _ => (),
         })
     }
 
-    fn input_room_changed(&mut self) -> JsError {
-        self.input_room.set_value(&self.input_room.value());
-        if self.input_room.value().is_empty() {
-            self.join_button.set_inner_html("Create new room");
-            self.create = true;
-        } else {
-            self.join_button.set_inner_html("Join existing room");
-            self.create = false;
+    /// Draws the board again, a resized window or a tab coming back may have cleared it.
+    fn on_redraw(&mut self) -> JsError {
+        if let State::Lobby(s) | State::InRound(s) = self {
+            s.game.redraw_trails();
         }
         Ok(())
     }
 
-    fn input_name_changed(&mut self) -> JsError {
-        self.input_name.set_value(&self.input_name.value());
-        if self.input_name.value().chars().all(|c| c == ' ') {
-            self.input_name.set_value("");
-        }
-        Ok(())
+    fn on_sudden_death(&mut self) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.sudden_death()?;
+            }
+            _ => (),
+        })
     }
 
-    fn create_or_join_clicked(&self) -> JsError {
-        if !self.input_name.value().is_empty() {
-            self.err_div.set_inner_html("");
-            let msg = match self.create {
-                true => ClientMessage::CreateRoom(self.input_name.value()),
-                false => ClientMessage::JoinRoom(self.input_name.value(), self.input_room.value()),
-            };
-            self.base.send(msg)?;
-        }
-        Ok(())
+    fn on_hazards_moved(&mut self, hazards: Vec<Hazard>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.game.canvas.set_hazards(hazards),
+            _ => (),
+        })
     }
 
-    fn join_failed(&self, err: &str) -> JsError {
-        self.err_div.set_inner_html(err);
-        Ok(())
+    fn on_trails_grew(&mut self, line_width: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.set_line_width(line_width),
+            _ => (),
+        })
     }
-}
 
-enum State {
-    Join(Join),
-    Playing(Playing),
-    Empty,
-}
+    fn on_arena_shrunk(&mut self, wall_margin: u32) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.arena_shrunk(wall_margin)?;
+            }
+            _ => (),
+        })
+    }
 
-impl State {
-    fn on_keydown(&mut self, event: KeyboardEvent) -> JsError {
+    fn on_round_paused(&mut self, paused: bool) -> JsError {
         Ok(match self {
-            State::Playing(s) => s.on_keydown(event)?,
+            State::Lobby(s) | State::InRound(s) => s.set_paused(paused)?,
             _ => (),
         })
     }
 
-    fn on_keyup(&mut self, event: KeyboardEvent) -> JsError {
+    fn on_room_snapshot(&mut self, snapshot: RoomSnapshot) -> JsError {
         Ok(match self {
-            State::Playing(s) => s.on_keyup(event)?,
+            State::Lobby(s) | State::InRound(s) => s.room_snapshot(snapshot)?,
             _ => (),
         })
     }
 
-    fn on_input_room(&mut self, _event: InputEvent) -> JsError {
+    fn on_match_round(&mut self, round: u32) -> JsError {
         Ok(match self {
-            State::Join(s) => s.input_room_changed()?,
+            State::Lobby(s) | State::InRound(s) => s.match_round(round),
             _ => (),
         })
     }
 
-    fn on_input_name(&mut self, _event: InputEvent) -> JsError {
+    fn on_round_started(&mut self) -> JsError {
         Ok(match self {
-            State::Join(s) => s.input_name_changed()?,
+            State::Lobby(s) | State::InRound(s) => {
+                s.round_started()?;
+            }
             _ => (),
         })
     }
 
-    fn on_create_or_join(&mut self) -> JsError {
+    fn on_settings_changed(&mut self) -> JsError {
         Ok(match self {
-            State::Join(s) => s.create_or_join_clicked()?,
+            State::Lobby(s) => s.settings_changed()?,
             _ => (),
         })
     }
 
-    fn on_join_failed(&mut self, err_text: &str) -> JsError {
+    fn on_traffic_stats(&mut self, stats: TrafficStats) -> JsError {
         Ok(match self {
-            State::Join(s) => s.join_failed(err_text)?,
+            State::Lobby(s) | State::InRound(s) => s.settings.show_traffic(Some(stats)),
             _ => (),
         })
     }
 
-    fn on_join_success(
-        &mut self,
-        room_name: String,
-        grid_info: GridInfo,
-        players: Vec<Player>,
-        uuid: Uuid,
-    ) -> JsError {
+    fn on_config_rejected(&mut self, reason: &str) -> JsError {
         Ok(match self {
-            State::Join(s) => {
-                // switch state to `Playing`
-                let game = Game::new(
-                    s.base.clone(),
-                    grid_info.width,
-                    grid_info.height,
-                    players
-                        .iter()
-                        .map(|v| (*v).into())
-                        .collect::<Vec<MyPlayer>>(),
-                )?;
-                let s = std::mem::replace(self, State::Empty);
-                match s {
-                    State::Join(s) => {
-                        *self = State::Playing(Playing::new(
-                            s.base.clone(),
-                            s.window.clone(),
-                            game,
-                            room_name,
-                            uuid,
-                        )?)
-                    }
-                    _ => panic!("Invalid state"),
-                }
-            }
+            State::Lobby(s) | State::InRound(s) => s.config_rejected(reason)?,
             _ => (),
         })
     }
 
-    fn on_new_player(&mut self, player: Player) -> JsError {
+    fn on_grid_changed(&mut self, grid_info: GridInfo) -> JsError {
         Ok(match self {
-            State::Playing(s) => {
-                s.add_player(player)?;
-            }
+            State::Lobby(s) | State::InRound(s) => s.grid_changed(grid_info)?,
             _ => (),
         })
     }
 
-    fn on_player_disconnected(&mut self, uuid: Uuid, uuid_host: Uuid) -> JsError {
+    fn on_config_changed(&mut self, config: RoomConfig) -> JsError {
         Ok(match self {
-            State::Playing(s) => {
-                s.remove_player(uuid, uuid_host)?;
-            }
+            State::Lobby(s) | State::InRound(s) => s.config_changed(config)?,
             _ => (),
         })
     }
 
-    fn on_round_started(&mut self) -> JsError {
+    fn on_round_ended(&mut self, winner: Uuid, points: Vec<(Uuid, usize, usize)>) -> JsError {
         Ok(match self {
-            State::Playing(s) => {
-                s.round_started()?;
+            State::Lobby(s) | State::InRound(s) => {
+                s.round_ended(winner, points)?;
             }
             _ => (),
         })
     }
 
-    fn on_round_ended(&mut self, winner: Uuid, points: Vec<(Uuid, usize)>) -> JsError {
+    fn on_player_colors(&mut self, colors: Vec<(Uuid, ArrayString<7>, TrailPattern)>) -> JsError {
         Ok(match self {
-            State::Playing(s) => {
-                s.round_ended(winner, points)?;
+            State::Lobby(s) | State::InRound(s) => s.colors_changed(colors)?,
+            _ => (),
+        })
+    }
+
+    fn on_ratings_changed(&mut self, ratings: Vec<(Uuid, u32)>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => s.ratings_changed(ratings)?,
+            _ => (),
+        })
+    }
+
+    fn game_update(&mut self, seq: u32, game_state: Vec<PlayerState>) -> JsError {
+        Ok(match self {
+            State::Lobby(s) | State::InRound(s) => {
+                s.game_update(seq, game_state)?;
             }
             _ => (),
         })
     }
 
-    fn game_update(&mut self, game_state: Vec<PlayerState>) -> JsError {
+    fn on_full_trail_sync(&mut self, seq: u32, sync: TrailSync) -> JsError {
         Ok(match self {
-            State::Playing(s) => {
-                s.game_update(game_state)?;
+            State::Lobby(s) | State::InRound(s) => {
+                s.game.full_trail_sync(seq, sync)?;
             }
             _ => (),
         })
@@ -735,7 +4754,7 @@ impl State {
 
     fn game_tick(&mut self) -> JsError {
         Ok(match self {
-            State::Playing(s) => {
+            State::Lobby(s) | State::InRound(s) => {
                 s.game.game_tick()?;
             }
             _ => (),
@@ -762,6 +4781,30 @@ where
     Closure::wrap(Box::new(f) as Box<dyn FnMut(T) -> JsError>)
 }
 
+/// Lets the browser download `href` as file.
+fn download(doc: &Document, href: &str, filename: &str) -> JsError {
+    let link = doc.create_element("a")?.dyn_into::<HtmlElement>()?;
+    link.set_attribute("href", href)?;
+    link.set_attribute("download", filename)?;
+    link.click();
+    Ok(())
+}
+
+/// Downloads a finished recording, its object URL is released once the download started.
+fn save_recording(doc: &Document, window: &Window, event: BlobEvent) -> JsError {
+    let blob = match event.data() {
+        Some(blob) => blob,
+        None => return Ok(()),
+    };
+    let url = Url::create_object_url_with_blob(&blob)?;
+    download(doc, &url, "curve-fever-round.webm")?;
+    let revoke = Closure::once_into_js(move || {
+        let _ = Url::revoke_object_url(&url);
+    });
+    window.set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), 1000)?;
+    Ok(())
+}
+
 #[must_use]
 fn set_event_cb<E, F, T>(obj: &E, name: &str, f: F) -> JsClosure<T>
 where
@@ -784,32 +4827,136 @@ fn on_message(msg: ServerMessage) -> JsError {
     //console_log!("Received Message: {:?}", msg);
     let mut state = HANDLE.lock().unwrap();
     match msg {
-        ServerMessage::GameState(game_state) => state.game_update(game_state)?,
+        ServerMessage::GameState(seq, game_state) => state.game_update(seq, game_state)?,
+        ServerMessage::FullTrailSync(seq, sync) => state.on_full_trail_sync(seq, sync)?,
         ServerMessage::JoinFailed(err_text) => state.on_join_failed(&err_text)?,
+        ServerMessage::AuthResult(result) => state.on_auth_result(result)?,
+        ServerMessage::RatingsChanged(ratings) => state.on_ratings_changed(ratings)?,
+        ServerMessage::ReplaySaved(id) => state.on_replay_saved(&id)?,
+        ServerMessage::GhostRecorded(run) => state.on_ghost_recorded(run)?,
+        ServerMessage::SpectatorsChanged(spectators) => state.on_spectators_changed(spectators)?,
+        ServerMessage::ItemSpawned(item) => state.on_item_spawned(item)?,
+        ServerMessage::ItemCollected(id, _) => state.on_item_collected(id)?,
+        ServerMessage::AreaCleared(center, radius) => state.on_area_cleared(center, radius)?,
+        ServerMessage::MatchSummary(rounds) => state.on_match_summary(rounds)?,
+        ServerMessage::GameOver(winner) => state.on_game_over(winner)?,
+        ServerMessage::SessionStats(stats) => state.on_session_stats(stats)?,
         ServerMessage::JoinSuccess {
             room_name,
             grid_info,
             players,
             uuid,
-        } => state.on_join_success(room_name, grid_info, players, uuid)?,
+            token,
+            config,
+        } => state.on_join_success(room_name, grid_info, players, uuid, token, config)?,
         ServerMessage::NewPlayer(player) => state.on_new_player(player)?,
+        ServerMessage::PlayerQueued(id) => state.on_player_queued(id)?,
+        ServerMessage::PlayerAfk(id, afk) => state.on_player_afk(id, afk)?,
+        ServerMessage::AfkWarning(seconds) => state.on_afk_warning(seconds)?,
+        ServerMessage::ConfigChanged(config) => state.on_config_changed(config)?,
+        ServerMessage::PlayerColors(colors) => state.on_player_colors(colors)?,
+        ServerMessage::ConfigRejected(reason) => state.on_config_rejected(&reason)?,
+        ServerMessage::TrafficStats(stats) => state.on_traffic_stats(stats)?,
+        ServerMessage::GridChanged(grid_info) => state.on_grid_changed(grid_info)?,
+        ServerMessage::PlayerDied(death) => state.on_player_died(death)?,
         ServerMessage::PlayerDisconnected(uuid, uuid_host) => {
             state.on_player_disconnected(uuid, uuid_host)?
         }
-        ServerMessage::RoundStarted => state.on_round_started()?,
+        ServerMessage::Countdown(seconds_left) => state.on_countdown(seconds_left)?,
+        ServerMessage::RoundStarted(_) => state.on_round_started()?,
+        ServerMessage::MatchRound(round) => state.on_match_round(round)?,
+        ServerMessage::RoomSnapshot(snapshot) => state.on_room_snapshot(snapshot)?,
+        ServerMessage::PlayerTeleported(teleport) => state.on_player_teleported(teleport)?,
+        ServerMessage::RoundEnding(winner) => state.on_round_ending(winner)?,
+        ServerMessage::RoundPaused => state.on_round_paused(true)?,
+        ServerMessage::RoundResumed => state.on_round_paused(false)?,
+        ServerMessage::SuddenDeath => state.on_sudden_death()?,
+        ServerMessage::ServerShutdown(seconds, resumable) => {
+            state.on_server_shutdown(seconds, resumable)?
+        }
+        ServerMessage::Announcement(text) => state.on_announcement(&text)?,
+        ServerMessage::AutoStartCountdown(seconds) => state.on_auto_start_countdown(seconds)?,
+        ServerMessage::Ping(seq) => state.on_ping(seq)?,
+        ServerMessage::TournamentState(tournament) => state.on_tournament_state(tournament)?,
+        ServerMessage::Emote(id, emote) => state.on_emote(id, emote)?,
+        ServerMessage::Chat(msg) => state.on_chat(msg)?,
+        ServerMessage::ChatHistory(history) => state.on_chat_history(history)?,
+        ServerMessage::RoomEvent(event) => state.on_room_event(event)?,
+        ServerMessage::ArenaShrunk(wall_margin) => state.on_arena_shrunk(wall_margin)?,
+        ServerMessage::TrailsGrew(line_width) => state.on_trails_grew(line_width)?,
+        ServerMessage::HazardsMoved(hazards) => state.on_hazards_moved(hazards)?,
         ServerMessage::RoundEnded((winner, points)) => state.on_round_ended(winner, points)?,
     };
+    state.update_phase();
     Ok(())
 }
 
-#[wasm_bindgen(start)]
-pub fn main() -> JsError {
-    console_log!("Started main!");
-    let window = web_sys::window().to_js_err("no global window exists")?;
+/// Colors the borders of the UI like the walls of the arena.
+fn apply_accent(doc: &Document, theme: Theme) -> JsError {
+    doc.document_element()
+        .to_js_err("Document has no root element")?
+        .dyn_into::<HtmlElement>()?
+        .style()
+        .set_property("--accent", theme.accent())
+}
 
-    let doc = window
-        .document()
-        .to_js_err("should have a document on window")?;
+/// The path of the WebSocket if the page is served by the game server itself, see `http` of the
+/// server.
+fn websocket_path(doc: &Document) -> Option<String> {
+    doc.query_selector("meta[name=websocket-path]")
+        .ok()??
+        .get_attribute("content")
+}
+
+/// Fetches the replay with the short `id` from the replay server and starts watching it.
+fn load_replay(doc: &Document, id: &str) -> JsError {
+    let location = doc.location().to_js_err("Could not get doc location")?;
+    let (protocol, port) = if location.protocol()? == "https:" {
+        ("https", 8098)
+    } else {
+        ("http", 8097)
+    };
+    let url = if websocket_path(doc).is_some() {
+        format!("/replays/{}", id)
+    } else {
+        format!(
+            "{}://{}:{}/replays/{}",
+            protocol,
+            location.hostname()?,
+            port,
+            id
+        )
+    };
+
+    let request = XmlHttpRequest::new()?;
+    request.open("GET", &url)?;
+    request.set_response_type(XmlHttpRequestResponseType::Arraybuffer);
+    let cb = Closure::once_into_js({
+        let request = request.clone();
+        move || {
+            if let Err(e) = on_replay_response(&request) {
+                console_log!("Could not load the replay: {:?}", e);
+            }
+        }
+    });
+    request.set_onloadend(Some(cb.unchecked_ref()));
+    request.send()?;
+    Ok(())
+}
+
+fn on_replay_response(request: &XmlHttpRequest) -> JsError {
+    let mut state = HANDLE.lock().unwrap();
+    if request.status()? != 200 {
+        return state.on_join_failed(tr(Text::ReplayNotFound));
+    }
+    let data = js_sys::Uint8Array::new(&request.response()?).to_vec();
+    let replay = bincode::deserialize(&data)
+        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize: {}", e)))?;
+    state.on_replay_loaded(replay)
+}
+
+/// URL of the game server for the page in `doc`
+fn server_url(doc: &Document) -> JsResult<String> {
     let location = doc.location().to_js_err("Could not get doc location")?;
     let hostname = location.hostname()?;
     let (ws_protocol, ws_port) = if location.protocol()? == "https:" {
@@ -817,9 +4964,18 @@ pub fn main() -> JsError {
     } else {
         ("ws", 8095)
     };
-    let hostname = format!("{}://{}:{}", ws_protocol, hostname, ws_port);
+    Ok(match websocket_path(doc) {
+        // same origin, e.g. behind a reverse proxy
+        Some(path) => format!("{}://{}{}", ws_protocol, location.host()?, path),
+        None => format!("{}://{}:{}", ws_protocol, hostname, ws_port),
+    })
+}
 
-    let ws = WebSocket::new(&hostname)?;
+/// Opens the WebSocket to the server and routes its events into `HANDLE` for as long as the
+/// returned callbacks are kept.
+fn connect(doc: &Document) -> JsResult<(WebSocket, CallbackRegistry)> {
+    let ws = WebSocket::new(&server_url(doc)?)?;
+    let mut callbacks = CallbackRegistry::default();
 
     // callback when message received
     let on_decoded_cb = Closure::wrap(Box::new(move |e: ProgressEvent| {
@@ -829,6 +4985,7 @@ pub fn main() -> JsError {
         let buf = js_sys::Uint8Array::new(&result);
         let mut data = vec![0; buf.length() as usize];
         buf.copy_to(&mut data[..]);
+        diagnostics::received(data.len());
         let msg = bincode::deserialize(&data[..])
             .map_err(|e| JsValue::from_str(&format!("Failed to deserialize: {}", e)))
             .expect("Could not decode message");
@@ -836,31 +4993,155 @@ pub fn main() -> JsError {
     }) as Box<dyn FnMut(ProgressEvent)>);
 
     // register callback
-    set_event_cb(&ws, "message", move |e: MessageEvent| {
+    callbacks.add(&ws, "message", move |e: MessageEvent| {
         let blob = e.data().dyn_into::<Blob>()?;
         let fr = FileReader::new()?;
         fr.add_event_listener_with_callback("load", &on_decoded_cb.as_ref().unchecked_ref())?;
         fr.read_as_array_buffer(&blob)?;
         Ok(())
-    })
-    .forget();
+    })?;
+
+    callbacks.add(&ws, "open", move |_: Event| {
+        HANDLE.lock().unwrap().on_connected()
+    })?;
+
+    callbacks.add(&ws, "error", move |_: Event| {
+        HANDLE
+            .lock()
+            .unwrap()
+            .on_closed(tr(Text::ServerUnreachable))
+    })?;
+
+    callbacks.add(&ws, "close", move |e: CloseEvent| {
+        let reason = if e.reason().is_empty() {
+            trf(Text::ConnectionLost, &[&e.code()])
+        } else {
+            e.reason()
+        };
+        HANDLE.lock().unwrap().on_closed(&reason)
+    })?;
+
+    Ok((ws, callbacks))
+}
+
+#[wasm_bindgen(start)]
+pub fn main() -> JsError {
+    let window = match web_sys::window() {
+        Some(window) => window,
+        // the render worker, see `worker::start_render_worker`
+        None => return Ok(()),
+    };
+    console_log!("Started main!");
+
+    let doc = window
+        .document()
+        .to_js_err("should have a document on window")?;
+    // other pages call `mount` themselves or use `api::CurveFeverClient`
+    match doc.get_element_by_id("content") {
+        Some(root) => mount(root),
+        None => Ok(()),
+    }
+}
+
+/// Starts the bundled interface in `root`, which has to contain the markup of `index.html`.
+///
+/// The elements are looked up inside of `root` only, so the page around it may use the same ids.
+/// There is a single game per page, it can be mounted once.
+#[wasm_bindgen]
+pub fn mount(root: Element) -> JsError {
+    if !matches!(*HANDLE.lock().unwrap(), State::Empty) {
+        return Err("The game is already mounted".into());
+    }
+    let window = web_sys::window().to_js_err("no global window exists")?;
+    let doc = root
+        .owner_document()
+        .to_js_err("the root element has no document")?;
+
+    let (ws, ws_callbacks) = connect(&doc)?;
 
     let base = Base {
         doc,
+        root,
         ws,
+        _ws_callbacks: ws_callbacks,
         touch: false,
+        local: RefCell::new(None),
     };
 
+    // listeners of the page outside of any state, the game stays mounted for good
+    let doc = base.doc.clone();
     set_event_cb(&base.doc, "keydown", move |event: KeyboardEvent| {
+        if event.key() == "F3" {
+            event.prevent_default();
+            diagnostics::toggle(&doc);
+            return Ok(());
+        }
+        if event.key() == "?" && text_input_target(&event).is_none() {
+            return help::toggle(&doc);
+        }
         HANDLE.lock().unwrap().on_keydown(event)
     })
     .forget();
+    diagnostics::start(&window, base.doc.clone())?;
 
     set_event_cb(&base.doc, "keyup", move |event: KeyboardEvent| {
         HANDLE.lock().unwrap().on_keyup(event)
     })
     .forget();
 
-    *HANDLE.lock().unwrap() = State::Join(Join::new(Rc::new(base), Rc::new(window))?);
+    set_event_cb(&window, "resize", move |_: Event| {
+        HANDLE.lock().unwrap().on_redraw()
+    })
+    .forget();
+
+    let doc = base.doc.clone();
+    set_event_cb(&base.doc, "visibilitychange", move |_: Event| {
+        match doc.hidden() {
+            true => Ok(()),
+            false => HANDLE.lock().unwrap().on_redraw(),
+        }
+    })
+    .forget();
+
+    let doc = base.doc.clone();
+    set_event_cb(
+        &base.get_element_by_id("help_button")?,
+        "click",
+        move |_: Event| help::toggle(&doc),
+    )
+    .forget();
+
+    set_event_cb(
+        &base.get_element_by_id("reconnect_button")?,
+        "click",
+        move |_: Event| HANDLE.lock().unwrap().on_reconnect(),
+    )
+    .forget();
+
+    set_event_cb(
+        &base.get_element_by_id("practice_offline")?,
+        "click",
+        move |_: Event| HANDLE.lock().unwrap().on_practice(),
+    )
+    .forget();
+
+    i18n::set_language(
+        Preferences::load()
+            .language
+            .unwrap_or_else(Language::detect),
+    );
+    i18n::translate_page(&base.root);
+
+    let base = Rc::new(base);
+    *HANDLE.lock().unwrap() = State::Join(Join::new(base.clone(), Rc::new(window))?);
+
+    // shared replay links point to `#replay=<id>`
+    let hash = base
+        .doc
+        .location()
+        .map_or(Ok(String::new()), |location| location.hash())?;
+    if let Some(id) = hash.strip_prefix("#replay=") {
+        load_replay(&base.doc, id)?;
+    }
     Ok(())
 }
@@ -0,0 +1,339 @@
+//! Practice rounds without a server, the room runs in the browser.
+//!
+//! `Practice` stands in for a room on the server: it takes the messages the client would send and
+//! answers with the messages a room would, which go through the usual handlers. A practice round
+//! looks and plays like any other, but nothing goes over the network, so it also works while the
+//! server is unreachable. Bots take the other places if wanted.
+
+use instant::Instant;
+use uuid::Uuid;
+
+use curve_fever_common::{
+    ClientMessage, Game, GhostRun, GridInfo, Input, ItemEvent, Player, RoomConfig, ServerMessage,
+    TrailPattern, MAX_PLAYERS, SLOW_MOTION_FACTOR, SLOW_MOTION_SECONDS,
+};
+
+/// Bots besides the local player, at most
+pub const MAX_BOTS: usize = MAX_PLAYERS - 1;
+/// As in the rooms of the server
+const COUNTDOWN_SECONDS: u8 = 3;
+/// Ticks caught up at once, after the tab was in the background the round rather goes on slower
+const MAX_CATCH_UP_TICKS: u32 = 10;
+
+pub struct Practice {
+    config: RoomConfig,
+    game: Game,
+    uuid: Uuid,                 // the local player
+    names: Vec<(Uuid, String)>, // of all players in the order they joined, the local one first
+    seq: u32,                   // sequence number of the last `GameState`
+    input_ack: u32,
+    freeze_ticks: u32, // remaining ticks of the countdown before a round
+    round_ticks: u32,
+    ending: Option<(Uuid, u32)>, // winner and remaining ticks of the slow motion replay
+    paused: bool,
+    seed: u64,               // of the current round
+    ghost: Option<GhostRun>, // of the current round, only recorded without bots
+    clock: (Instant, u32),   // since when and how many ticks were played
+}
+
+impl Practice {
+    pub fn new(name: &str, bots: usize) -> Self {
+        let uuid = Uuid::new_v4();
+        let mut names = vec![(uuid, name.to_string())];
+        names.extend((1..=bots.min(MAX_BOTS)).map(|i| (Uuid::new_v4(), format!("Bot {}", i))));
+        let config = RoomConfig::default();
+        let mut practice = Self {
+            game: Game::from_config(&config),
+            config,
+            uuid,
+            names,
+            seq: 0,
+            input_ack: 0,
+            freeze_ticks: 0,
+            round_ticks: 0,
+            ending: None,
+            paused: false,
+            seed: 0,
+            ghost: None,
+            clock: (Instant::now(), 0),
+        };
+        practice.add_players();
+        practice
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    pub fn config(&self) -> &RoomConfig {
+        &self.config
+    }
+
+    pub fn grid_info(&self) -> GridInfo {
+        self.game.grid_info()
+    }
+
+    pub fn players(&self) -> Vec<Player> {
+        self.game.players.values().copied().collect()
+    }
+
+    fn bots(&self) -> Vec<Uuid> {
+        self.names.iter().skip(1).map(|(id, _)| *id).collect()
+    }
+
+    /// Puts everybody into a game set up with the current config, the points stay.
+    fn add_players(&mut self) {
+        let old = std::mem::replace(&mut self.game, Game::from_config(&self.config));
+        let palette = self.config.theme.palette(self.config.max_players);
+        for (slot, (id, name)) in self.names.iter().enumerate() {
+            let mut player = Player::new(
+                *id,
+                name,
+                palette[slot],
+                self.config.width as u32,
+                self.config.height as u32,
+                self.config.line_width,
+                self.config.preset.turn_rate(),
+            );
+            player.pattern = TrailPattern::ALL[slot % TrailPattern::ALL.len()];
+            player.host = *id == self.uuid;
+            player.set_handling(self.game.speed, self.game.turn_rate);
+            player.set_ghost(self.game.ghost);
+            player.set_gaps(self.game.gaps);
+            if let Some(before) = old.players.get(id) {
+                player.points = before.points;
+                player.kills = before.kills;
+                player.round_wins = before.round_wins;
+            }
+            self.game.add_player(player);
+        }
+    }
+
+    fn round_in_progress(&self) -> bool {
+        self.freeze_ticks > 0 || self.game.running() || self.ending.is_some()
+    }
+
+    /// Answers a message of the local player like a room would.
+    pub fn apply(&mut self, msg: ClientMessage) -> Vec<ServerMessage> {
+        let mut out = vec![];
+        let msg = match msg {
+            ClientMessage::Input(seq, input) => {
+                self.input_ack = seq;
+                input.into()
+            }
+            msg => msg,
+        };
+        let input = match msg {
+            ClientMessage::Move(direction) => Input::Move(direction),
+            ClientMessage::Speed(change) => Input::Speed(change),
+            ClientMessage::UseGhost => Input::UseGhost,
+            ClientMessage::UseItem => Input::UseItem,
+            ClientMessage::StartGame if !self.round_in_progress() => {
+                self.start_round(&mut out);
+                return out;
+            }
+            ClientMessage::PauseGame if self.round_in_progress() && !self.paused => {
+                self.paused = true;
+                return vec![ServerMessage::RoundPaused];
+            }
+            ClientMessage::ResumeGame if self.paused => {
+                self.paused = false;
+                return vec![ServerMessage::RoundResumed];
+            }
+            ClientMessage::UpdateConfig(config) => {
+                self.update_config(config, &mut out);
+                return out;
+            }
+            ClientMessage::RequestFullSync => {
+                return vec![ServerMessage::FullTrailSync(
+                    self.seq,
+                    self.game.trail_sync(),
+                )];
+            }
+            // chat, emotes and the like have nobody to reach
+            _ => return out,
+        };
+        if self.paused {
+            return out;
+        }
+        if let Some(ghost) = &mut self.ghost {
+            // counted from the start of the countdown
+            let tick = ghost.countdown - self.freeze_ticks + self.round_ticks;
+            ghost.inputs.push((tick, self.uuid, input));
+        }
+        // inputs of a dead player are ignored like on the server
+        let _ = self.game.on_input(&self.uuid, input);
+        out
+    }
+
+    fn update_config(&mut self, config: RoomConfig, out: &mut Vec<ServerMessage>) {
+        let checked = match self.round_in_progress() {
+            true => Err("The config can't be changed during a round".to_string()),
+            false => config.validate(),
+        };
+        let checked = checked.and_then(|_| match config.max_players < self.names.len() {
+            true => Err(format!("There are {} players", self.names.len())),
+            false => Ok(()),
+        });
+        if let Err(reason) = checked {
+            out.push(ServerMessage::ConfigRejected(reason));
+            return;
+        }
+        let resized = (config.width, config.height) != (self.config.width, self.config.height);
+        self.config = config;
+        self.add_players();
+        if resized {
+            out.push(ServerMessage::GridChanged(self.game.grid_info()));
+        }
+        out.push(ServerMessage::ConfigChanged(self.config.clone()));
+        out.push(ServerMessage::PlayerColors(
+            self.game
+                .players
+                .values()
+                .map(|player| (player.uuid, player.color, player.pattern))
+                .collect(),
+        ));
+        // another tick rate counts from now on
+        self.clock = (Instant::now(), 0);
+    }
+
+    fn start_round(&mut self, out: &mut Vec<ServerMessage>) {
+        self.seed = rand::random();
+        self.freeze_ticks = COUNTDOWN_SECONDS as u32 * self.config.tick_rate;
+        // runs against bots depend on how the bots drove, they are not worth keeping
+        self.ghost = Some(GhostRun {
+            runner: self.uuid,
+            config: self.config.clone(),
+            players: self.players(),
+            afk: vec![],
+            seed: self.seed,
+            countdown: self.freeze_ticks,
+            inputs: vec![],
+            ticks: 0,
+        })
+        .filter(|_| self.names.len() == 1);
+        self.game.seed(self.seed);
+        self.game.initialize();
+        self.round_ticks = 0;
+        self.ending = None;
+        self.paused = false;
+        self.broadcast_state(out);
+        out.push(ServerMessage::HazardsMoved(self.game.hazards().to_vec()));
+        out.push(ServerMessage::Countdown(COUNTDOWN_SECONDS));
+    }
+
+    /// Plays the ticks that are due by now.
+    pub fn advance(&mut self) -> Vec<ServerMessage> {
+        let (start, played) = self.clock;
+        let due = (start.elapsed().as_secs_f64() * self.config.tick_rate as f64) as u32;
+        let mut ticks = due.saturating_sub(played);
+        if ticks > MAX_CATCH_UP_TICKS {
+            ticks = MAX_CATCH_UP_TICKS;
+            self.clock = (Instant::now(), 0);
+        } else {
+            self.clock.1 = due;
+        }
+        let mut out = vec![];
+        for _ in 0..ticks {
+            self.step(&mut out);
+        }
+        out
+    }
+
+    fn step(&mut self, out: &mut Vec<ServerMessage>) {
+        if self.paused {
+            return;
+        }
+        if self.freeze_ticks > 0 {
+            self.do_freeze_tick(out);
+        } else if self.game.running() {
+            self.do_tick(out);
+        } else if let Some((winner, ticks)) = self.ending {
+            match ticks {
+                0 => self.finish_round(winner, out),
+                _ => self.ending = Some((winner, ticks - 1)),
+            }
+        }
+    }
+
+    fn broadcast_state(&mut self, out: &mut Vec<ServerMessage>) {
+        self.seq = self.seq.wrapping_add(1);
+        let mut state = self.game.state();
+        for s in state.iter_mut().filter(|s| s.id == self.uuid) {
+            s.input_ack = self.input_ack;
+        }
+        out.push(ServerMessage::GameState(self.seq, state));
+    }
+
+    fn do_freeze_tick(&mut self, out: &mut Vec<ServerMessage>) {
+        self.game.tick_frozen(1. / self.config.tick_rate as f64);
+        self.broadcast_state(out);
+        self.freeze_ticks -= 1;
+        if self.freeze_ticks.is_multiple_of(self.config.tick_rate) {
+            let seconds_left = (self.freeze_ticks / self.config.tick_rate) as u8;
+            out.push(ServerMessage::Countdown(seconds_left));
+            if seconds_left == 0 {
+                out.push(ServerMessage::RoundStarted(self.seed));
+            }
+        }
+    }
+
+    fn do_tick(&mut self, out: &mut Vec<ServerMessage>) {
+        self.round_ticks += 1;
+        if let Some(limit) = self.config.round_time_limit {
+            if self.round_ticks == limit * self.config.tick_rate {
+                self.game.start_sudden_death();
+                out.push(ServerMessage::SuddenDeath);
+            }
+        }
+        for bot in self.bots() {
+            let direction = self.game.bot_direction(&bot);
+            let _ = self.game.on_move(&bot, direction);
+        }
+
+        let wall_margin = self.game.wall_margin();
+        let trail_width = self.game.trail_width();
+        for death in self.game.tick(1. / self.config.tick_rate as f64) {
+            out.push(ServerMessage::PlayerDied(death));
+        }
+        for event in self.game.take_item_events() {
+            out.push(match event {
+                ItemEvent::Spawned(item) => ServerMessage::ItemSpawned(item),
+                ItemEvent::Collected(item, id) => ServerMessage::ItemCollected(item, id),
+                ItemEvent::AreaCleared(center, radius) => {
+                    ServerMessage::AreaCleared(center, radius)
+                }
+            });
+        }
+        for teleport in self.game.take_teleports() {
+            out.push(ServerMessage::PlayerTeleported(teleport));
+        }
+        if self.game.wall_margin() != wall_margin {
+            out.push(ServerMessage::ArenaShrunk(self.game.wall_margin()));
+        }
+        if self.game.trail_width() != trail_width {
+            out.push(ServerMessage::TrailsGrew(self.game.trail_width()));
+        }
+        if !self.game.hazards().is_empty() {
+            out.push(ServerMessage::HazardsMoved(self.game.hazards().to_vec()));
+        }
+        self.broadcast_state(out);
+        if let Some(winner) = self.game.get_winner() {
+            let seconds = SLOW_MOTION_SECONDS / SLOW_MOTION_FACTOR;
+            let ticks = (seconds * self.config.tick_rate as f64).round() as u32;
+            self.ending = Some((winner, ticks));
+            if let Some(ghost) = &mut self.ghost {
+                ghost.ticks = self.round_ticks;
+            }
+            out.push(ServerMessage::RoundEnding(winner));
+        }
+    }
+
+    fn finish_round(&mut self, winner: Uuid, out: &mut Vec<ServerMessage>) {
+        self.ending = None;
+        out.push(ServerMessage::RoundEnded((winner, self.game.state_ended())));
+        if let Some(ghost) = self.ghost.take() {
+            out.push(ServerMessage::GhostRecorded(ghost));
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Preferences of the local player, kept in `window.localStorage` across visits.
+
+use std::convert::TryInto;
+
+use crate::i18n::Language;
+use curve_fever_common::GhostRun;
+use uuid::Uuid;
+use wasm_bindgen::JsValue;
+use web_sys::Storage;
+
+const NAME_KEY: &str = "name";
+const FOLLOW_KEY: &str = "follow_camera";
+const KEYS_KEY: &str = "key_bindings";
+const RENDERER_KEY: &str = "renderer";
+const PATTERNS_KEY: &str = "trail_patterns";
+const ACCOUNT_TOKEN_KEY: &str = "account_token";
+const LANGUAGE_KEY: &str = "language";
+const BEST_RUN_KEY: &str = "best_run";
+
+fn storage() -> Option<Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+fn get(key: &str) -> Option<String> {
+    storage()?.get_item(key).ok()?
+}
+
+fn set(key: &str, value: Option<&str>) -> Result<(), JsValue> {
+    let storage = storage().ok_or_else(|| JsValue::from_str("Local storage is not available"))?;
+    match value {
+        Some(value) => storage.set_item(key, value),
+        None => storage.remove_item(key),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    Left,
+    Right,
+    Boost,
+    Brake,
+    UseItem,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] = [
+        Action::Left,
+        Action::Right,
+        Action::Boost,
+        Action::Brake,
+        Action::UseItem,
+    ];
+
+    /// Id of the input on the start screen that rebinds the action
+    pub fn input_id(&self) -> &'static str {
+        match self {
+            Action::Left => "key_left",
+            Action::Right => "key_right",
+            Action::Boost => "key_boost",
+            Action::Brake => "key_brake",
+            Action::UseItem => "key_item",
+        }
+    }
+
+    /// Arrow and vim keys, which always trigger the action next to the configurable key
+    pub fn fixed_keys(&self) -> [&'static str; 2] {
+        match self {
+            Action::Left => ["ArrowLeft", "h"],
+            Action::Right => ["ArrowRight", "l"],
+            Action::Boost => ["ArrowUp", "k"],
+            Action::Brake => ["ArrowDown", "j"],
+            Action::UseItem => ["Enter", "Shift"],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    keys: [String; 5], // in the order of `Action::ALL`
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: ["a", "d", "w", "s", "e"].map(String::from),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: Action) -> &str {
+        &self.keys[action as usize]
+    }
+
+    pub fn bind(&mut self, action: Action, key: &str) {
+        self.keys[action as usize] = key.to_string();
+    }
+
+    /// The action a pressed key stands for
+    pub fn action(&self, key: &str) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|&action| self.key(action) == key || action.fixed_keys().contains(&key))
+    }
+}
+
+/// How the board is drawn
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    Canvas2d,
+    WebGl,
+    Worker, // WebGL on an `OffscreenCanvas` in a Web Worker
+}
+
+impl Backend {
+    fn code(self) -> &'static str {
+        match self {
+            Backend::Canvas2d => "2d",
+            Backend::WebGl => "webgl",
+            Backend::Worker => "worker",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        [Backend::Canvas2d, Backend::WebGl, Backend::Worker]
+            .iter()
+            .copied()
+            .find(|backend| backend.code() == code)
+    }
+
+    /// The backend the renderer key switches to
+    pub fn next(self) -> Self {
+        match self {
+            Backend::Canvas2d => Backend::WebGl,
+            Backend::WebGl => Backend::Worker,
+            Backend::Worker => Backend::Canvas2d,
+        }
+    }
+}
+
+pub struct Preferences {
+    pub name: String,
+    pub follow: bool, // camera follows the local player
+    pub keys: KeyBindings,
+    pub backend: Backend,
+    pub patterns: bool, // dash patterns on the trails for color-blind players
+    pub language: Option<Language>, // `None` follows the browser
+}
+
+impl Preferences {
+    /// Reads the stored preferences, anything missing or invalid is left at its default.
+    pub fn load() -> Self {
+        let keys = get(KEYS_KEY)
+            .map(|keys| keys.split('\n').map(String::from).collect::<Vec<_>>())
+            .map(|mut keys| {
+                // bindings stored before an action was added keep the default for it
+                let defaults = KeyBindings::default().keys;
+                keys.extend(defaults.iter().skip(keys.len()).cloned());
+                keys
+            })
+            .and_then(|keys| keys.try_into().ok())
+            .map(|keys| KeyBindings { keys })
+            .unwrap_or_default();
+        Self {
+            name: get(NAME_KEY).unwrap_or_default(),
+            follow: get(FOLLOW_KEY).as_deref() == Some("true"),
+            keys,
+            backend: get(RENDERER_KEY)
+                .and_then(|code| Backend::from_code(&code))
+                .unwrap_or(Backend::Canvas2d),
+            patterns: get(PATTERNS_KEY).as_deref() == Some("true"),
+            language: get(LANGUAGE_KEY).and_then(|code| Language::from_code(&code)),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), JsValue> {
+        set(NAME_KEY, Some(&self.name))?;
+        set(FOLLOW_KEY, Some(&self.follow.to_string()))?;
+        set(KEYS_KEY, Some(&self.keys.keys.join("\n")))?;
+        set(RENDERER_KEY, Some(self.backend.code()))?;
+        set(PATTERNS_KEY, Some(&self.patterns.to_string()))?;
+        set(LANGUAGE_KEY, self.language.map(|language| language.code()))
+    }
+}
+
+/// The token of the account registered in this browser
+pub fn account_token() -> Option<Uuid> {
+    Uuid::parse_str(&get(ACCOUNT_TOKEN_KEY)?).ok()
+}
+
+pub fn set_account_token(token: Option<&Uuid>) -> Result<(), JsValue> {
+    set(ACCOUNT_TOKEN_KEY, token.map(Uuid::to_string).as_deref())
+}
+
+/// The longest round the local player survived alone, driven along in later rounds
+pub fn best_run() -> Option<GhostRun> {
+    serde_json::from_str(&get(BEST_RUN_KEY)?).ok()
+}
+
+pub fn set_best_run(run: &GhostRun) -> Result<(), JsValue> {
+    let json = serde_json::to_string(run).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    set(BEST_RUN_KEY, Some(&json))
+}
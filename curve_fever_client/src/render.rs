@@ -0,0 +1,590 @@
+//! Backends drawing the board of the main canvas.
+//!
+//! `Canvas` keeps what is on the board and decides what to draw, a `Renderer` only knows how to
+//! put shapes on the screen. The 2D context works everywhere, the WebGL backend draws the trails
+//! in a single call per frame and adds a glow around them.
+//!
+//! Without the camera the board is retained between frames: new trail segments are added on top
+//! and only the parts that change otherwise, like the heads of players in a gap or running
+//! animations, are redrawn, clipped to a `Rect`.
+
+use js_sys::{Float32Array, Object, Reflect};
+use std::{cell::Cell, f64::consts::PI};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{
+    CanvasRenderingContext2d, HtmlCanvasElement, OffscreenCanvas, WebGl2RenderingContext as Gl,
+    WebGlProgram, WebGlShader, WebGlUniformLocation,
+};
+
+use crate::{stroke_line, JsResult, Line};
+
+/// Drawing primitives of the main canvas, coordinates are in grid pixels.
+pub trait Renderer {
+    /// Applies the camera to the following calls: the view is zoomed by `zoom` and centered on
+    /// `center`, `None` shows the whole grid.
+    fn set_view(&self, view: Option<((f64, f64), f64)>);
+    /// Fills the whole canvas, including what lies outside of the view.
+    fn fill_surface(&self, color: &str);
+    fn fill_rect(&self, pos: (f64, f64), size: (f64, f64), color: &str);
+    fn fill_circle(&self, center: (f64, f64), radius: f64, color: &str, alpha: f64);
+    fn fill_triangle(&self, points: [(f64, f64); 3], color: &str);
+    /// Strokes lines with round caps.
+    fn stroke_lines(&self, lines: &[Line]);
+    /// Restricts the following calls to a part of the grid, `None` draws on the whole canvas
+    /// again. Only used without the camera, where grid and canvas pixels are the same.
+    fn set_clip(&self, clip: Option<Rect>);
+}
+
+/// Area of the grid, in grid pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+}
+
+impl Rect {
+    /// Square around a circle, with a pixel for antialiasing
+    pub fn around((x, y): (f64, f64), radius: f64) -> Self {
+        let r = radius + 1.;
+        Self {
+            min: (x - r, y - r),
+            max: (x + r, y + r),
+        }
+    }
+
+    /// Everything a line may touch, including its caps and glow
+    pub fn of_line(line: &Line) -> Self {
+        let points = [line.from, line.to, line.control.unwrap_or(line.from)];
+        let r = line.linewidth * GLOW_WIDTH / 2. + 1.;
+        Self {
+            min: (
+                points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min) - r,
+                points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min) - r,
+            ),
+            max: (
+                points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max) + r,
+                points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max) + r,
+            ),
+        }
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min.0 < other.max.0
+            && other.min.0 < self.max.0
+            && self.min.1 < other.max.1
+            && other.min.1 < self.max.1
+    }
+
+    /// Smallest rectangle containing both
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    pub fn size(&self) -> (f64, f64) {
+        (self.max.0 - self.min.0, self.max.1 - self.min.1)
+    }
+}
+
+pub struct Canvas2d {
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+    clipped: Cell<bool>, // a clip was saved on the context's stack
+}
+
+impl Canvas2d {
+    pub fn new(canvas: HtmlCanvasElement) -> JsResult<Self> {
+        let context = canvas
+            .get_context("2d")?
+            .ok_or("The canvas has no 2D context")?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        context.set_line_cap("round");
+        Ok(Self {
+            canvas,
+            context,
+            clipped: Cell::new(false),
+        })
+    }
+}
+
+impl Renderer for Canvas2d {
+    fn set_view(&self, view: Option<((f64, f64), f64)>) {
+        let _ = match view {
+            Some(((x, y), zoom)) => self.context.set_transform(
+                zoom,
+                0.,
+                0.,
+                zoom,
+                self.canvas.width() as f64 / 2. - x * zoom,
+                self.canvas.height() as f64 / 2. - y * zoom,
+            ),
+            None => self.context.set_transform(1., 0., 0., 1., 0., 0.),
+        };
+    }
+
+    fn fill_surface(&self, color: &str) {
+        let _ = self.context.set_transform(1., 0., 0., 1., 0., 0.);
+        self.context.set_fill_style(&color.into());
+        self.context.fill_rect(
+            0.,
+            0.,
+            self.canvas.width().into(),
+            self.canvas.height().into(),
+        );
+    }
+
+    fn fill_rect(&self, (x, y): (f64, f64), (w, h): (f64, f64), color: &str) {
+        self.context.set_fill_style(&color.into());
+        self.context.fill_rect(x, y, w, h);
+    }
+
+    fn fill_circle(&self, (x, y): (f64, f64), radius: f64, color: &str, alpha: f64) {
+        self.context.set_global_alpha(alpha);
+        self.context.set_fill_style(&color.into());
+        self.context.begin_path();
+        let _ = self.context.arc(x, y, radius, 0., PI * 2.);
+        self.context.fill();
+        self.context.set_global_alpha(1.);
+    }
+
+    fn fill_triangle(&self, [a, b, c]: [(f64, f64); 3], color: &str) {
+        self.context.set_fill_style(&color.into());
+        self.context.begin_path();
+        self.context.move_to(a.0, a.1);
+        self.context.line_to(b.0, b.1);
+        self.context.line_to(c.0, c.1);
+        self.context.close_path();
+        self.context.fill();
+    }
+
+    fn stroke_lines(&self, lines: &[Line]) {
+        lines
+            .iter()
+            .flat_map(dash_parts)
+            .for_each(|line| stroke_line(&self.context, &line));
+    }
+
+    fn set_clip(&self, clip: Option<Rect>) {
+        if self.clipped.replace(false) {
+            self.context.restore();
+        }
+        if let Some(rect) = clip {
+            self.context.save();
+            let _ = self.context.set_transform(1., 0., 0., 1., 0., 0.);
+            self.context.begin_path();
+            let (w, h) = rect.size();
+            self.context.rect(rect.min.0, rect.min.1, w, h);
+            self.context.clip();
+            self.clipped.set(true);
+        }
+    }
+}
+
+const VERTEX_SHADER: &str = r#"#version 300 es
+in vec2 a_pos;
+in vec4 a_color;
+in float a_edge;
+uniform vec3 u_view; // offset and zoom
+uniform vec2 u_size;
+out vec4 v_color;
+out float v_edge;
+void main() {
+    vec2 pos = (a_pos * u_view.z + u_view.xy) / u_size * 2.0 - 1.0;
+    gl_Position = vec4(pos.x, -pos.y, 0.0, 1.0);
+    v_color = a_color;
+    v_edge = a_edge;
+}
+"#;
+
+// `v_edge` is 0 in the middle of a shape and 1 at its border, which gives smooth edges or, for
+// the glow, a soft falloff
+const FRAGMENT_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec4 v_color;
+in float v_edge;
+uniform float u_glow;
+out vec4 color;
+void main() {
+    float d = abs(v_edge);
+    float alpha = mix(1.0 - smoothstep(0.8, 1.0, d), pow(1.0 - d, 2.0), u_glow);
+    color = vec4(v_color.rgb, v_color.a * alpha);
+}
+"#;
+
+/// Floats per vertex: position, color and distance from the middle
+const VERTEX_SIZE: i32 = 7;
+/// Triangles of a full circle, caps use half of them
+const CIRCLE_SEGMENTS: usize = 16;
+/// Width of the glow around a trail, relative to its width
+const GLOW_WIDTH: f64 = 3.;
+const GLOW_ALPHA: f64 = 0.35;
+/// Straight lines per curved trail segment, segments are only a few pixels long
+const CURVE_STEPS: usize = 4;
+
+/// `#RRGGBB` as floats between 0 and 1
+fn parse_color(color: &str) -> [f32; 3] {
+    let channel = |i: usize| {
+        color
+            .get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .unwrap_or(0) as f32
+            / 255.
+    };
+    [channel(1), channel(3), channel(5)]
+}
+
+/// Straight lines approximating a curve, straight lines stay as they are.
+fn flatten(line: &Line) -> Vec<Line> {
+    let (cx, cy) = match line.control {
+        Some(control) => control,
+        None => return vec![*line],
+    };
+    let at = |t: f64| {
+        let (a, b, c) = ((1. - t) * (1. - t), 2. * (1. - t) * t, t * t);
+        (
+            a * line.from.0 + b * cx + c * line.to.0,
+            a * line.from.1 + b * cy + c * line.to.1,
+        )
+    };
+    let mut offset = line.offset;
+    (0..CURVE_STEPS)
+        .map(|i| {
+            let from = at(i as f64 / CURVE_STEPS as f64);
+            let to = at((i + 1) as f64 / CURVE_STEPS as f64);
+            let part = Line {
+                from,
+                to,
+                offset,
+                control: None,
+                ..*line
+            };
+            offset += (to.0 - from.0).hypot(to.1 - from.1);
+            part
+        })
+        .collect()
+}
+
+/// Splits a line into the dashes of its pattern, lines without a pattern stay whole.
+fn dash_parts(line: &Line) -> Vec<Line> {
+    if line.dashes.is_empty() {
+        return vec![*line];
+    }
+    if line.control.is_some() {
+        return flatten(line).iter().flat_map(dash_parts).collect();
+    }
+    let scale = line.linewidth.max(1.);
+    let period: f64 = line.dashes.iter().sum::<f64>() * scale;
+    let (dx, dy) = (line.to.0 - line.from.0, line.to.1 - line.from.1);
+    let length = dx.hypot(dy);
+    let at = |t: f64| match length > 0. {
+        true => (line.from.0 + dx * t / length, line.from.1 + dy * t / length),
+        false => line.from,
+    };
+
+    // find the element of the pattern the line starts in
+    let mut i = 0;
+    let mut t = -line.offset.rem_euclid(period); // start of element `i` along the line
+    while t + line.dashes[i] * scale <= 0. {
+        t += line.dashes[i] * scale;
+        i = (i + 1) % line.dashes.len();
+    }
+
+    let mut parts = vec![];
+    while t <= length {
+        let end = t + line.dashes[i] * scale;
+        if i % 2 == 0 {
+            parts.push(Line {
+                from: at(t.max(0.)),
+                to: at(end.min(length)),
+                ..*line
+            });
+        }
+        t = end;
+        i = (i + 1) % line.dashes.len();
+    }
+    parts
+}
+
+/// Collects triangles for a single draw call.
+#[derive(Default)]
+struct Vertices(Vec<f32>);
+
+impl Vertices {
+    fn push(&mut self, (x, y): (f64, f64), [r, g, b]: [f32; 3], alpha: f64, edge: f64) {
+        self.0
+            .extend_from_slice(&[x as f32, y as f32, r, g, b, alpha as f32, edge as f32]);
+    }
+
+    /// A fan of triangles around `center`, from angle `start` over `angle` radians.
+    fn fan(
+        &mut self,
+        center: (f64, f64),
+        radius: f64,
+        (start, angle): (f64, f64),
+        color: [f32; 3],
+        alpha: f64,
+    ) {
+        let steps = ((CIRCLE_SEGMENTS as f64 * angle / (2. * PI)).ceil() as usize).max(1);
+        let point = |i: usize| {
+            let a = start + angle * i as f64 / steps as f64;
+            (center.0 + radius * a.cos(), center.1 + radius * a.sin())
+        };
+        for i in 0..steps {
+            self.push(center, color, alpha, 0.);
+            self.push(point(i), color, alpha, 1.);
+            self.push(point(i + 1), color, alpha, 1.);
+        }
+    }
+
+    /// A line as a quad with half circles at both ends.
+    fn line(&mut self, line: &Line, width: f64, alpha: f64) {
+        let color = parse_color(&line.color);
+        let radius = width / 2.;
+        let (dx, dy) = (line.to.0 - line.from.0, line.to.1 - line.from.1);
+        let length = dx.hypot(dy);
+        let angle = dy.atan2(dx);
+        if length > 0. {
+            // normal of the line, scaled to half the width
+            let (nx, ny) = (-dy / length * radius, dx / length * radius);
+            let corners = [
+                ((line.from.0 + nx, line.from.1 + ny), -1.),
+                ((line.from.0 - nx, line.from.1 - ny), 1.),
+                ((line.to.0 + nx, line.to.1 + ny), -1.),
+                ((line.to.0 - nx, line.to.1 - ny), 1.),
+            ];
+            for &i in &[0, 1, 2, 2, 1, 3] {
+                let (pos, edge) = corners[i];
+                self.push(pos, color, alpha, edge);
+            }
+        }
+        self.fan(line.from, radius, (angle + PI / 2., PI), color, alpha);
+        self.fan(line.to, radius, (angle - PI / 2., PI), color, alpha);
+    }
+}
+
+pub struct WebGl {
+    size: (u32, u32), // of the canvas
+    gl: Gl,
+    view: Cell<((f64, f64), f64)>, // offset and zoom
+    u_view: WebGlUniformLocation,
+    u_glow: WebGlUniformLocation,
+    glow: bool,
+}
+
+impl WebGl {
+    /// Sets up a WebGL 2 context, `None` if the browser does not support it.
+    pub fn new(canvas: HtmlCanvasElement, glow: bool) -> JsResult<Option<Self>> {
+        match canvas.get_context_with_context_options("webgl2", &context_options()?)? {
+            Some(gl) => Self::with_context(gl.dyn_into()?, (canvas.width(), canvas.height()), glow),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets up a WebGL 2 context on a canvas without element, like the one of the render worker.
+    pub fn offscreen(canvas: &OffscreenCanvas, glow: bool) -> JsResult<Option<Self>> {
+        match canvas.get_context_with_context_options("webgl2", &context_options()?)? {
+            Some(gl) => Self::with_context(gl.dyn_into()?, (canvas.width(), canvas.height()), glow),
+            None => Ok(None),
+        }
+    }
+
+    fn with_context(gl: Gl, (width, height): (u32, u32), glow: bool) -> JsResult<Option<Self>> {
+        let program = link_program(&gl)?;
+        gl.use_program(Some(&program));
+        let uniform = |name: &str| {
+            gl.get_uniform_location(&program, name)
+                .ok_or_else(|| JsValue::from_str(&format!("Missing uniform `{}`", name)))
+        };
+        let u_view = uniform("u_view")?;
+        let u_glow = uniform("u_glow")?;
+        gl.uniform2f(Some(&uniform("u_size")?), width as f32, height as f32);
+
+        let vao = gl
+            .create_vertex_array()
+            .ok_or("Could not create a vertex array")?;
+        gl.bind_vertex_array(Some(&vao));
+        let buffer = gl.create_buffer().ok_or("Could not create a buffer")?;
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&buffer));
+        let stride = VERTEX_SIZE * 4;
+        for (name, size, offset) in &[("a_pos", 2, 0), ("a_color", 4, 2), ("a_edge", 1, 6)] {
+            let location = gl.get_attrib_location(&program, name);
+            if location < 0 {
+                return Err(format!("Missing attribute `{}`", name).into());
+            }
+            gl.enable_vertex_attrib_array(location as u32);
+            gl.vertex_attrib_pointer_with_i32(
+                location as u32,
+                *size,
+                Gl::FLOAT,
+                false,
+                stride,
+                offset * 4,
+            );
+        }
+
+        gl.viewport(0, 0, width as i32, height as i32);
+        gl.enable(Gl::BLEND);
+        gl.blend_func(Gl::SRC_ALPHA, Gl::ONE_MINUS_SRC_ALPHA);
+
+        let renderer = Self {
+            size: (width, height),
+            gl,
+            view: Cell::new(((0., 0.), 1.)),
+            u_view,
+            u_glow,
+            glow,
+        };
+        renderer.set_view(None);
+        Ok(Some(renderer))
+    }
+
+    fn draw(&self, vertices: &Vertices, glow: bool) {
+        if vertices.0.is_empty() {
+            return;
+        }
+        let ((x, y), zoom) = self.view.get();
+        self.gl
+            .uniform3f(Some(&self.u_view), x as f32, y as f32, zoom as f32);
+        self.gl
+            .uniform1f(Some(&self.u_glow), if glow { 1. } else { 0. });
+        self.gl.buffer_data_with_array_buffer_view(
+            Gl::ARRAY_BUFFER,
+            &Float32Array::from(&vertices.0[..]),
+            Gl::STREAM_DRAW,
+        );
+        self.gl
+            .draw_arrays(Gl::TRIANGLES, 0, vertices.0.len() as i32 / VERTEX_SIZE);
+    }
+}
+
+fn context_options() -> JsResult<JsValue> {
+    // keep the drawn frame, trails are added to it instead of redrawing everything
+    let options = Object::new();
+    Reflect::set(&options, &"preserveDrawingBuffer".into(), &true.into())?;
+    Reflect::set(&options, &"antialias".into(), &true.into())?;
+    Ok(options.into())
+}
+
+fn compile_shader(gl: &Gl, kind: u32, source: &str) -> JsResult<WebGlShader> {
+    let shader = gl.create_shader(kind).ok_or("Could not create a shader")?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+    if gl
+        .get_shader_parameter(&shader, Gl::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(shader)
+    } else {
+        Err(gl.get_shader_info_log(&shader).unwrap_or_default().into())
+    }
+}
+
+fn link_program(gl: &Gl) -> JsResult<WebGlProgram> {
+    let program = gl.create_program().ok_or("Could not create a program")?;
+    gl.attach_shader(
+        &program,
+        &compile_shader(gl, Gl::VERTEX_SHADER, VERTEX_SHADER)?,
+    );
+    gl.attach_shader(
+        &program,
+        &compile_shader(gl, Gl::FRAGMENT_SHADER, FRAGMENT_SHADER)?,
+    );
+    gl.link_program(&program);
+    if gl
+        .get_program_parameter(&program, Gl::LINK_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(program)
+    } else {
+        Err(gl.get_program_info_log(&program).unwrap_or_default().into())
+    }
+}
+
+impl Renderer for WebGl {
+    fn set_view(&self, view: Option<((f64, f64), f64)>) {
+        self.view.set(match view {
+            Some(((x, y), zoom)) => (
+                (
+                    self.size.0 as f64 / 2. - x * zoom,
+                    self.size.1 as f64 / 2. - y * zoom,
+                ),
+                zoom,
+            ),
+            None => ((0., 0.), 1.),
+        });
+    }
+
+    fn fill_surface(&self, color: &str) {
+        let [r, g, b] = parse_color(color);
+        self.gl.clear_color(r, g, b, 1.);
+        self.gl.clear(Gl::COLOR_BUFFER_BIT);
+    }
+
+    fn fill_rect(&self, (x, y): (f64, f64), (w, h): (f64, f64), color: &str) {
+        let color = parse_color(color);
+        let mut vertices = Vertices::default();
+        for &corner in &[
+            (x, y),
+            (x + w, y),
+            (x, y + h),
+            (x, y + h),
+            (x + w, y),
+            (x + w, y + h),
+        ] {
+            vertices.push(corner, color, 1., 0.);
+        }
+        self.draw(&vertices, false);
+    }
+
+    fn fill_circle(&self, center: (f64, f64), radius: f64, color: &str, alpha: f64) {
+        let mut vertices = Vertices::default();
+        vertices.fan(center, radius, (0., 2. * PI), parse_color(color), alpha);
+        self.draw(&vertices, false);
+    }
+
+    fn fill_triangle(&self, points: [(f64, f64); 3], color: &str) {
+        let color = parse_color(color);
+        let mut vertices = Vertices::default();
+        points
+            .iter()
+            .for_each(|&point| vertices.push(point, color, 1., 0.));
+        self.draw(&vertices, false);
+    }
+
+    fn stroke_lines(&self, lines: &[Line]) {
+        if self.glow {
+            let mut glow = Vertices::default();
+            lines
+                .iter()
+                .flat_map(dash_parts)
+                .flat_map(|line| flatten(&line))
+                .for_each(|line| glow.line(&line, line.linewidth * GLOW_WIDTH, GLOW_ALPHA));
+            self.draw(&glow, true);
+        }
+        let mut vertices = Vertices::default();
+        lines
+            .iter()
+            .flat_map(dash_parts)
+            .flat_map(|line| flatten(&line))
+            .for_each(|line| vertices.line(&line, line.linewidth, 1.));
+        self.draw(&vertices, false);
+    }
+
+    fn set_clip(&self, clip: Option<Rect>) {
+        match clip {
+            Some(rect) => {
+                // the scissor box is in framebuffer pixels, with y going up
+                let (x, y) = (rect.min.0.floor(), rect.min.1.floor());
+                let (w, h) = (rect.max.0.ceil() - x, rect.max.1.ceil() - y);
+                let bottom = self.size.1 as f64 - y - h;
+                self.gl.enable(Gl::SCISSOR_TEST);
+                self.gl.scissor(x as i32, bottom as i32, w as i32, h as i32);
+            }
+            None => self.gl.disable(Gl::SCISSOR_TEST),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Drawing the board in a Web Worker, keeps the main thread free for input and server messages.
+//!
+//! The main canvas is handed to the worker as an `OffscreenCanvas`. `WorkerRenderer` forwards the
+//! calls of the `Renderer` trait as messages and the worker draws them with the WebGL backend, in
+//! the order they were made. The worker loads the same module as the page, see `render_worker.js`.
+//!
+//! Messages are arrays starting with the name of the call, trail segments are packed into a
+//! `Float64Array` of `LINE_FLOATS` numbers each.
+
+use arrayvec::ArrayString;
+use js_sys::{Array, Float64Array, Reflect};
+use std::cell::RefCell;
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{
+    DedicatedWorkerGlobalScope, HtmlCanvasElement, MessageEvent, OffscreenCanvas, Worker,
+    WorkerOptions, WorkerType,
+};
+
+use curve_fever_common::TrailPattern;
+
+use crate::{
+    render::{Rect, Renderer, WebGl},
+    JsResult, Line,
+};
+
+const SCRIPT: &str = "./render_worker.js";
+/// Numbers per trail segment: from, to, width, offset, control point, pattern and color
+const LINE_FLOATS: usize = 10;
+
+/// Whether the browser can draw with WebGL 2 on an `OffscreenCanvas`.
+///
+/// Checked on the main thread, browsers that have it there also have it in workers.
+pub fn supported() -> bool {
+    let global = js_sys::global();
+    let has = |object: &JsValue, name: &str| Reflect::has(object, &name.into()).unwrap_or(false);
+    let prototype = Reflect::get(&global, &"HTMLCanvasElement".into())
+        .and_then(|class| Reflect::get(&class, &"prototype".into()))
+        .unwrap_or(JsValue::UNDEFINED);
+    has(&global, "OffscreenCanvas")
+        && has(&global, "Worker")
+        && prototype.is_object()
+        && has(&prototype, "transferControlToOffscreen")
+        && OffscreenCanvas::new(1, 1)
+            .and_then(|canvas| canvas.get_context("webgl2"))
+            .is_ok_and(|context| context.is_some())
+}
+
+/// Sends the drawing calls to a worker owning the canvas.
+pub struct WorkerRenderer {
+    worker: Worker,
+}
+
+impl WorkerRenderer {
+    /// Starts a worker and hands it the canvas, which can't be drawn on from the page afterwards.
+    pub fn new(canvas: &HtmlCanvasElement) -> JsResult<Self> {
+        let offscreen = canvas.transfer_control_to_offscreen()?;
+        let options = WorkerOptions::new();
+        options.set_type(WorkerType::Module);
+        let worker = Worker::new_with_options(SCRIPT, &options)?;
+        let msg = Array::of3(&"init".into(), &offscreen, &true.into());
+        worker.post_message_with_transfer(&msg, &Array::of1(&offscreen))?;
+        Ok(Self { worker })
+    }
+
+    fn post(&self, args: &[JsValue]) {
+        let msg: Array = args.iter().collect();
+        if let Err(err) = self.worker.post_message(&msg) {
+            web_sys::console::error_2(&"Could not reach the render worker".into(), &err);
+        }
+    }
+}
+
+impl Drop for WorkerRenderer {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
+
+impl Renderer for WorkerRenderer {
+    fn set_view(&self, view: Option<((f64, f64), f64)>) {
+        match view {
+            Some(((x, y), zoom)) => self.post(&["view".into(), x.into(), y.into(), zoom.into()]),
+            None => self.post(&["view".into()]),
+        }
+    }
+
+    fn fill_surface(&self, color: &str) {
+        self.post(&["surface".into(), color.into()]);
+    }
+
+    fn fill_rect(&self, (x, y): (f64, f64), (w, h): (f64, f64), color: &str) {
+        self.post(&[
+            "rect".into(),
+            x.into(),
+            y.into(),
+            w.into(),
+            h.into(),
+            color.into(),
+        ]);
+    }
+
+    fn fill_circle(&self, (x, y): (f64, f64), radius: f64, color: &str, alpha: f64) {
+        self.post(&[
+            "circle".into(),
+            x.into(),
+            y.into(),
+            radius.into(),
+            color.into(),
+            alpha.into(),
+        ]);
+    }
+
+    fn fill_triangle(&self, [a, b, c]: [(f64, f64); 3], color: &str) {
+        self.post(&[
+            "triangle".into(),
+            a.0.into(),
+            a.1.into(),
+            b.0.into(),
+            b.1.into(),
+            c.0.into(),
+            c.1.into(),
+            color.into(),
+        ]);
+    }
+
+    fn stroke_lines(&self, lines: &[Line]) {
+        if lines.is_empty() {
+            return;
+        }
+        let floats: Vec<f64> = lines.iter().flat_map(pack_line).collect();
+        self.post(&["lines".into(), Float64Array::from(&floats[..]).into()]);
+    }
+
+    fn set_clip(&self, clip: Option<Rect>) {
+        match clip {
+            Some(rect) => self.post(&[
+                "clip".into(),
+                rect.min.0.into(),
+                rect.min.1.into(),
+                rect.max.0.into(),
+                rect.max.1.into(),
+            ]),
+            None => self.post(&["clip".into()]),
+        }
+    }
+}
+
+fn pack_line(line: &Line) -> [f64; LINE_FLOATS] {
+    let pattern = TrailPattern::ALL
+        .iter()
+        .position(|pattern| pattern.dashes() == line.dashes)
+        .unwrap_or(0);
+    let color = u32::from_str_radix(line.color.get(1..).unwrap_or(""), 16).unwrap_or(0);
+    let (cx, cy) = line.control.unwrap_or((f64::NAN, f64::NAN));
+    [
+        line.from.0,
+        line.from.1,
+        line.to.0,
+        line.to.1,
+        line.linewidth,
+        line.offset,
+        cx,
+        cy,
+        pattern as f64,
+        color as f64,
+    ]
+}
+
+fn unpack_line(floats: &[f64]) -> Line {
+    let color = format!("#{:06X}", floats[9] as u32);
+    Line {
+        from: (floats[0], floats[1]),
+        to: (floats[2], floats[3]),
+        linewidth: floats[4],
+        offset: floats[5],
+        control: Some((floats[6], floats[7])).filter(|(cx, _)| !cx.is_nan()),
+        dashes: TrailPattern::ALL
+            .get(floats[8] as usize)
+            .map_or(&[], |pattern| pattern.dashes()),
+        color: ArrayString::from(&color).unwrap_or_default(),
+    }
+}
+
+/// Entry point of the render worker, draws the messages of its `WorkerRenderer`.
+#[wasm_bindgen]
+pub fn start_render_worker() -> Result<(), JsValue> {
+    let scope = js_sys::global().dyn_into::<DedicatedWorkerGlobalScope>()?;
+    let renderer = RefCell::new(None);
+    let cb = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let msg = event.data().dyn_into::<Array>();
+        if let Err(err) = msg.and_then(|msg| on_message(&renderer, &msg)) {
+            web_sys::console::error_2(&"Render worker:".into(), &err);
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    scope.set_onmessage(Some(cb.as_ref().unchecked_ref()));
+    cb.forget();
+    Ok(())
+}
+
+fn on_message(renderer: &RefCell<Option<WebGl>>, msg: &Array) -> JsResult<()> {
+    let op = msg.get(0).as_string().unwrap_or_default();
+    let num = |i: u32| msg.get(i).as_f64().unwrap_or(0.);
+    let text = |i: u32| msg.get(i).as_string().unwrap_or_default();
+    if op == "init" {
+        let canvas = msg.get(1).dyn_into::<OffscreenCanvas>()?;
+        let webgl = WebGl::offscreen(&canvas, msg.get(2).is_truthy())?
+            .ok_or("WebGL 2 is not supported in the worker")?;
+        renderer.replace(Some(webgl));
+        return Ok(());
+    }
+    let renderer = renderer.borrow();
+    let renderer = renderer
+        .as_ref()
+        .ok_or("The render worker has no canvas yet")?;
+    match op.as_str() {
+        "view" if msg.length() > 1 => renderer.set_view(Some(((num(1), num(2)), num(3)))),
+        "view" => renderer.set_view(None),
+        "surface" => renderer.fill_surface(&text(1)),
+        "rect" => renderer.fill_rect((num(1), num(2)), (num(3), num(4)), &text(5)),
+        "circle" => renderer.fill_circle((num(1), num(2)), num(3), &text(4), num(5)),
+        "triangle" => renderer.fill_triangle(
+            [(num(1), num(2)), (num(3), num(4)), (num(5), num(6))],
+            &text(7),
+        ),
+        "lines" => {
+            let floats = msg.get(1).dyn_into::<Float64Array>()?.to_vec();
+            let lines: Vec<Line> = floats.chunks_exact(LINE_FLOATS).map(unpack_line).collect();
+            renderer.stroke_lines(&lines);
+        }
+        "clip" if msg.length() > 1 => renderer.set_clip(Some(Rect {
+            min: (num(1), num(2)),
+            max: (num(3), num(4)),
+        })),
+        "clip" => renderer.set_clip(None),
+        op => return Err(format!("Unknown message `{}`", op).into()),
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! Simple controllers to fill rooms.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use curve_fever_common::{ClientMessage, Direction, ServerMessage};
+
+use crate::{Controller, View};
+
+/// Distance to the border at which the wanderer turns around
+const WALL_DISTANCE: f64 = 60.;
+/// Chance per game state to pick a new random direction
+const TURN_CHANCE: f64 = 0.05;
+
+/// Drives around at random and stays away from the borders.
+///
+/// The host also starts the round once `players` are in the room.
+pub struct Wanderer {
+    join: Option<ClientMessage>, // `CreateRoom` or `JoinRoom`, sent on `start`
+    players: usize,              // only used by the host
+    direction: Direction,
+    rng: StdRng,
+}
+
+impl Wanderer {
+    pub fn new(join: ClientMessage, players: usize) -> Self {
+        Self {
+            join: Some(join),
+            players,
+            direction: Direction::Unchanged,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    fn steer(&mut self, view: &View) -> Option<Direction> {
+        let (me, grid) = (view.me()?, view.grid_info.as_ref()?);
+        if !me.alive {
+            return None;
+        }
+        let (x, y) = me.pos();
+        let near_wall = x < WALL_DISTANCE
+            || y < WALL_DISTANCE
+            || x > grid.width as f64 - WALL_DISTANCE
+            || y > grid.height as f64 - WALL_DISTANCE;
+        let direction = if near_wall {
+            // keep turning the same way until free again
+            match self.direction {
+                Direction::Unchanged => Direction::Left,
+                direction => direction,
+            }
+        } else if self.rng.gen_bool(TURN_CHANCE) {
+            match self.rng.gen_range(0..3) {
+                0 => Direction::Left,
+                1 => Direction::Right,
+                _ => Direction::Unchanged,
+            }
+        } else {
+            return None;
+        };
+        Some(direction).filter(|direction| *direction != self.direction)
+    }
+}
+
+impl Controller for Wanderer {
+    fn start(&mut self) -> Vec<ClientMessage> {
+        self.join.take().into_iter().collect()
+    }
+
+    fn on_message(&mut self, msg: &ServerMessage, view: &View) -> Vec<ClientMessage> {
+        match msg {
+            ServerMessage::GameState(..) => match self.steer(view) {
+                Some(direction) => {
+                    self.direction = direction;
+                    vec![ClientMessage::Move(direction)]
+                }
+                None => vec![],
+            },
+            ServerMessage::NewPlayer(_) => {
+                let host = view.players.iter().any(|p| p.uuid == view.uuid && p.host);
+                if host && !view.running && view.players.len() >= self.players {
+                    vec![ClientMessage::StartGame]
+                } else {
+                    vec![]
+                }
+            }
+            ServerMessage::RoundStarted(_) => {
+                self.direction = Direction::Unchanged;
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+}
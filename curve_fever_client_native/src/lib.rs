@@ -0,0 +1,128 @@
+//! Headless client for bots, integration tests and load generation.
+//!
+//! It speaks the same bincode protocol as the browser client, over async-tungstenite instead of
+//! web-sys. A `Controller` decides what to send, `run` connects it to a server and feeds it the
+//! messages of the room along with what is known about the room so far, the `View`.
+
+use anyhow::{Context, Result};
+use async_tungstenite::{tungstenite::Message, WebSocketStream};
+use futures::{sink::SinkExt, stream::StreamExt};
+use smol::Async;
+use std::net::{TcpStream, ToSocketAddrs};
+use uuid::Uuid;
+
+use curve_fever_common::{ClientMessage, GridInfo, Player, PlayerState, ServerMessage};
+
+pub mod bots;
+
+pub struct Connection {
+    stream: WebSocketStream<Async<TcpStream>>,
+}
+
+impl Connection {
+    /// `url` as the browser client uses it, e.g. `ws://localhost:8095` or `ws://example.com/ws`
+    pub async fn connect(url: &str) -> Result<Self> {
+        let host = url
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split('/').next())
+            .with_context(|| format!("Invalid server url `{}`", url))?;
+        let addr = host
+            .to_socket_addrs()?
+            .next()
+            .with_context(|| format!("Could not resolve `{}`", host))?;
+        let tcp = Async::<TcpStream>::connect(addr).await?;
+        let (stream, _) = async_tungstenite::client_async(url, tcp).await?;
+        Ok(Self { stream })
+    }
+
+    pub async fn send(&mut self, msg: &ClientMessage) -> Result<()> {
+        let bytes = bincode::serialize(msg)?;
+        self.stream.send(Message::Binary(bytes)).await?;
+        Ok(())
+    }
+
+    /// The next message of the server, `None` once the connection is closed.
+    pub async fn receive(&mut self) -> Result<Option<ServerMessage>> {
+        while let Some(msg) = self.stream.next().await {
+            if let Message::Binary(bytes) = msg? {
+                return Ok(Some(bincode::deserialize(&bytes)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// What the client knows about its room
+#[derive(Debug, Default)]
+pub struct View {
+    pub room_name: Option<String>,
+    pub uuid: Uuid, // nil until joined and for spectators
+    pub grid_info: Option<GridInfo>,
+    pub players: Vec<Player>,
+    pub states: Vec<PlayerState>, // of the latest `GameState`
+    pub running: bool,            // a round is being played
+}
+
+impl View {
+    /// The latest state of the own player
+    pub fn me(&self) -> Option<&PlayerState> {
+        self.states.iter().find(|s| s.id == self.uuid)
+    }
+
+    fn update(&mut self, msg: &ServerMessage) {
+        match msg {
+            ServerMessage::JoinSuccess {
+                room_name,
+                grid_info,
+                players,
+                uuid,
+                ..
+            } => {
+                self.room_name = Some(room_name.clone());
+                self.grid_info = Some(grid_info.clone());
+                self.players = players.clone();
+                self.uuid = *uuid;
+            }
+            ServerMessage::GridChanged(grid_info) => self.grid_info = Some(grid_info.clone()),
+            ServerMessage::NewPlayer(player) => self.players.push(*player),
+            ServerMessage::PlayerDisconnected(id, _) => self.players.retain(|p| p.uuid != *id),
+            ServerMessage::RoundStarted(_) => self.running = true,
+            ServerMessage::RoundEnding(_) | ServerMessage::RoundEnded(_) => self.running = false,
+            ServerMessage::GameState(_, states) => self.states = states.clone(),
+            _ => (),
+        }
+    }
+}
+
+pub trait Controller {
+    /// Messages sent right after connecting, usually to create or join a room
+    fn start(&mut self) -> Vec<ClientMessage>;
+
+    /// Answers a message of the server, `view` already contains it.
+    fn on_message(&mut self, msg: &ServerMessage, view: &View) -> Vec<ClientMessage>;
+}
+
+/// Plays with `controller` until the server closes the connection.
+pub async fn run<C: Controller>(url: &str, controller: &mut C) -> Result<View> {
+    let mut connection = Connection::connect(url).await?;
+    for msg in controller.start() {
+        connection.send(&msg).await?;
+    }
+
+    let mut view = View::default();
+    while let Some(msg) = connection.receive().await? {
+        // the server measures the latency with these
+        if let ServerMessage::Ping(seq) = msg {
+            connection.send(&ClientMessage::Pong(seq)).await?;
+        }
+        if let ServerMessage::JoinFailed(reason) = &msg {
+            anyhow::bail!("Could not join: {}", reason);
+        }
+        view.update(&msg);
+        for answer in controller.on_message(&msg, &view) {
+            connection.send(&answer).await?;
+        }
+    }
+    Ok(view)
+}
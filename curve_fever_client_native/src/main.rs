@@ -0,0 +1,82 @@
+//! Fills a room with bots, e.g. for load tests:
+//!
+//! ```text
+//! curve-fever-bots ws://localhost:8095 8        # new room, the first bot is the host
+//! curve-fever-bots ws://localhost:8095 3 abcd   # joins the existing room `abcd`
+//! ```
+
+use anyhow::Result;
+use futures::{
+    channel::oneshot,
+    future::{self, join_all},
+};
+use smol::Task;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+use curve_fever_client_native::{bots::Wanderer, run, Controller, View};
+use curve_fever_common::{ClientMessage, ServerMessage};
+
+/// Tells the other bots which room it created.
+struct Host {
+    wanderer: Wanderer,
+    room: Option<oneshot::Sender<String>>,
+}
+
+impl Controller for Host {
+    fn start(&mut self) -> Vec<ClientMessage> {
+        self.wanderer.start()
+    }
+
+    fn on_message(&mut self, msg: &ServerMessage, view: &View) -> Vec<ClientMessage> {
+        if let (Some(room), Some(name)) = (&view.room_name, self.room.take()) {
+            info!("Created room `{}`", room);
+            let _ = name.send(room.clone());
+        }
+        self.wanderer.on_message(msg, view)
+    }
+}
+
+async fn play(url: String, mut controller: impl Controller) {
+    if let Err(e) = run(&url, &mut controller).await {
+        error!("Bot stopped: {}", e);
+    }
+}
+
+fn main() -> Result<()> {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("curve_fever_bots=info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let mut args = std::env::args().skip(1);
+    let usage = "usage: curve-fever-bots <url> <bots> [room]";
+    let url = args.next().ok_or_else(|| anyhow::anyhow!(usage))?;
+    let bots: usize = args.next().ok_or_else(|| anyhow::anyhow!(usage))?.parse()?;
+    let room = args.next();
+
+    for _ in 0..num_cpus::get().max(2) {
+        std::thread::spawn(|| smol::run(future::pending::<()>()));
+    }
+
+    smol::block_on(async {
+        let mut tasks = vec![];
+        let room = match room {
+            Some(room) => room,
+            None => {
+                let (tx, rx) = oneshot::channel();
+                let host = Host {
+                    wanderer: Wanderer::new(ClientMessage::CreateRoom("bot0".into()), bots),
+                    room: Some(tx),
+                };
+                tasks.push(Task::spawn(play(url.clone(), host)));
+                rx.await?
+            }
+        };
+        for i in tasks.len()..bots {
+            let join = ClientMessage::JoinRoom(format!("bot{}", i), room.clone());
+            tasks.push(Task::spawn(play(url.clone(), Wanderer::new(join, bots))));
+        }
+        join_all(tasks).await;
+        Ok(())
+    })
+}
@@ -0,0 +1,84 @@
+use arrayvec::ArrayString;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use uuid::Uuid;
+
+use curve_fever_common::{Game, Grid, Player, RoomConfig, SpeedPreset};
+
+const WIDTH: usize = 1000;
+const HEIGHT: usize = 800;
+/// Ticks played before measuring, so every player drags a long trail behind
+const WARMUP_TICKS: usize = 200;
+/// Ticks measured per iteration, one second at the default tick rate
+const MEASURED_TICKS: usize = 40;
+
+/// A game where all players run side by side from left to right, so nobody dies while measuring.
+fn game_with_trails(n_players: usize, dt: f64) -> Game {
+    let config = RoomConfig::default();
+    let preset = SpeedPreset::Normal;
+    let mut game = Game::new(WIDTH, HEIGHT, config.line_width, preset.turn_rate());
+    for _ in 0..n_players {
+        let id = Uuid::new_v4();
+        let player = Player::new(
+            id,
+            "bench",
+            ArrayString::<7>::from("#FFFFFF").unwrap(),
+            WIDTH as u32,
+            HEIGHT as u32,
+            config.line_width,
+            preset.turn_rate(),
+        );
+        game.add_player(player);
+    }
+    game.set_handling(preset.speed(), preset.turn_rate());
+    game.initialize();
+
+    let lane_height = HEIGHT as f64 / (n_players + 1) as f64;
+    game.players
+        .values_mut()
+        .enumerate()
+        .for_each(|(i, player)| {
+            player.x = 20.;
+            player.y = lane_height * (i + 1) as f64;
+            player.rotation = 90.;
+        });
+
+    for _ in 0..WARMUP_TICKS {
+        game.tick(dt);
+    }
+    assert!(game.running(), "a player died while laying the trails");
+    game
+}
+
+fn bench_tick(c: &mut Criterion) {
+    let dt = 1. / RoomConfig::default().tick_rate as f64;
+    let mut group = c.benchmark_group("tick");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(MEASURED_TICKS as u64));
+    for n_players in [2, 4, 8, 16] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_players),
+            &n_players,
+            |b, &n_players| {
+                b.iter_batched(
+                    || game_with_trails(n_players, dt),
+                    |mut game| {
+                        for _ in 0..MEASURED_TICKS {
+                            game.tick(dt);
+                        }
+                        game
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_grid_clear(c: &mut Criterion) {
+    let mut grid = Grid::new(WIDTH, HEIGHT);
+    c.bench_function("grid_clear", |b| b.iter(|| grid.clear()));
+}
+
+criterion_group!(benches, bench_tick, bench_grid_clear);
+criterion_main!(benches);
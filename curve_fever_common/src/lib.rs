@@ -1,28 +1,515 @@
 use arrayvec::ArrayString;
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::TryInto,
     fmt,
-    ops::{Deref, DerefMut},
-    sync::{Arc, Mutex},
 };
 use uuid::Uuid;
 
-#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub mod palette;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Direction {
     Left,
     Right,
     Unchanged,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct PlayerState {
+impl Direction {
+    /// Left and right swapped, as with confused controls
+    pub fn inverted(&self) -> Self {
+        match self {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::Unchanged => Direction::Unchanged,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SpeedChange {
+    Boost,
+    Brake,
+    Normal,
+}
+
+impl SpeedChange {
+    fn factor(&self) -> f64 {
+        match self {
+            SpeedChange::Boost => BOOST_FACTOR,
+            SpeedChange::Brake => BRAKE_FACTOR,
+            SpeedChange::Normal => 1.,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum DeathCause {
+    Wall,
+    OwnTrail,
+    Player(Uuid), // uuid of the player whose trail was hit
+    Hazard,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Death {
     pub id: Uuid,
+    pub x: f64, // collision point
+    pub y: f64,
+    pub cause: DeathCause,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ItemKind {
+    Eraser,    // clears the trails around the player
+    Confusion, // swaps left and right of all other players for a while
+}
+
+impl ItemKind {
+    pub const ALL: [ItemKind; 2] = [ItemKind::Eraser, ItemKind::Confusion];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemKind::Eraser => "Eraser",
+            ItemKind::Confusion => "Confusion",
+        }
+    }
+}
+
+/// A powerup lying on the field until a player drives over it
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Item {
+    pub id: u32,
+    pub kind: ItemKind,
     pub x: f64,
     pub y: f64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ItemEvent {
+    Spawned(Item),
+    Collected(u32, Uuid),         // item id, player
+    AreaCleared((f64, f64), f64), // center, radius
+}
+
+pub const ITEM_RADIUS: f64 = 10.;
+/// Radius of the area cleared by the eraser
+pub const ERASER_RADIUS: f64 = 80.;
+/// Seconds the controls of players hit by a confusion stay inverted
+const CONFUSION_SECONDS: f64 = 4.;
+/// Seconds between two items appearing on the field
+const ITEM_SPAWN_SECONDS: f64 = 6.;
+const MAX_ITEMS: usize = 3;
+/// Items don't appear closer to the walls than this
+const ITEM_MARGIN: f64 = 40.;
+
+/// Thickness of a hazard bar, in pixels
+pub const HAZARD_WIDTH: f64 = 8.;
+/// Hazards placed at the start of a round
+const HAZARD_COUNT: usize = 2;
+/// Hazards never reach closer to the start of a player than this
+const HAZARD_CLEARANCE: f64 = 60.;
+/// Attempts to find a free spot for each hazard
+const HAZARD_ATTEMPTS: usize = 20;
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum HazardMotion {
+    Rotating(f64),                       // degrees per second around its center
+    Sliding { range: f64, period: f64 }, // pixels to both sides across the bar, seconds per swing
+}
+
+/// A bar moving on its own, deadly to touch
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Hazard {
+    pub x: f64, // center
+    pub y: f64,
+    pub length: f64,
+    pub angle: f64, // degrees
+    pub motion: HazardMotion,
+    origin: (f64, f64), // center that a sliding bar swings around
+    time: f64,          // seconds moved so far
+}
+
+impl Hazard {
+    fn random(rng: &mut impl Rng, width: f64, height: f64) -> Self {
+        let size = width.min(height);
+        let x = rng.gen_range(width * 0.2..width * 0.8);
+        let y = rng.gen_range(height * 0.2..height * 0.8);
+        let motion = match rng.gen_bool(0.5) {
+            true => {
+                let direction = if rng.gen_bool(0.5) { 1. } else { -1. };
+                HazardMotion::Rotating(direction * rng.gen_range(30.0..90.0))
+            }
+            false => HazardMotion::Sliding {
+                range: rng.gen_range(size * 0.05..size * 0.15),
+                period: rng.gen_range(3.0..8.0),
+            },
+        };
+        Self {
+            x,
+            y,
+            length: rng.gen_range(size * 0.15..size * 0.3),
+            angle: rng.gen_range(0.0..360.0),
+            motion,
+            origin: (x, y),
+            time: 0.,
+        }
+    }
+
+    /// Distance from the center the bar can sweep over
+    fn reach(&self) -> f64 {
+        match self.motion {
+            HazardMotion::Rotating(_) => self.length / 2.,
+            HazardMotion::Sliding { range, .. } => self.length / 2. + range,
+        }
+    }
+
+    fn tick(&mut self, dt: f64) {
+        self.time += dt;
+        match self.motion {
+            HazardMotion::Rotating(speed) => self.angle = (self.angle + speed * dt) % 360.,
+            HazardMotion::Sliding { range, period } => {
+                let offset = range * libm::sin(self.time / period * 2. * std::f64::consts::PI);
+                let normal = (self.angle + 90.).to_radians();
+                self.x = self.origin.0 + offset * libm::cos(normal);
+                self.y = self.origin.1 + offset * libm::sin(normal);
+            }
+        }
+    }
+
+    /// Both ends of the bar
+    pub fn ends(&self) -> ((f64, f64), (f64, f64)) {
+        let (dx, dy) = (
+            libm::cos(self.angle.to_radians()) * self.length / 2.,
+            libm::sin(self.angle.to_radians()) * self.length / 2.,
+        );
+        ((self.x - dx, self.y - dy), (self.x + dx, self.y + dy))
+    }
+}
+
+/// Seconds until trails grow by another pixel, with growing trails
+const TRAIL_GROWTH_SECONDS: f64 = 8.;
+/// Pixels trails grow at most during a round
+const MAX_TRAIL_GROWTH: u32 = 6;
+
+/// Seconds a speed zone keeps changing the speed after the player left it
+const ZONE_EFFECT_SECONDS: f64 = 1.5;
+
+/// Part of the arena that changes the speed of players driving through it
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct SpeedZone {
+    pub x: f64, // top left corner
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub factor: f64, // of the speed, above 1 for speed-up strips, below for slow zones
+}
+
+impl SpeedZone {
+    /// `x`, `y`, `width` and `height` relative to the arena
+    fn relative(arena: (f64, f64), rect: (f64, f64, f64, f64), factor: f64) -> Self {
+        Self {
+            x: rect.0 * arena.0,
+            y: rect.1 * arena.1,
+            width: rect.2 * arena.0,
+            height: rect.3 * arena.1,
+            factor,
+        }
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    pub fn speeds_up(&self) -> bool {
+        self.factor > 1.
+    }
+}
+
+pub const PORTAL_RADIUS: f64 = 18.;
+/// Seconds a player can't collide and leaves no trail after going through a portal
+const PORTAL_IMMUNITY_SECONDS: f64 = 0.4;
+
+/// Two portals leading to each other
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct PortalPair {
+    pub a: (f64, f64), // centers
+    pub b: (f64, f64),
+}
+
+impl PortalPair {
+    fn relative(arena: (f64, f64), a: (f64, f64), b: (f64, f64)) -> Self {
+        Self {
+            a: (a.0 * arena.0, a.1 * arena.1),
+            b: (b.0 * arena.0, b.1 * arena.1),
+        }
+    }
+}
+
+/// A player going through a portal
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Teleport {
+    pub id: Uuid,
+    pub from: (f64, f64), // center of the entered portal
+    pub to: (f64, f64),   // where the player comes out of the partner portal
+}
+
+/// Fixed features of the arena, picked by the host
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum MapPreset {
+    Open,
+    Ramps,   // speed-up strips across the arena
+    Swamp,   // slow zones around the center
+    Portals, // two portal pairs across the arena
+}
+
+impl MapPreset {
+    pub const ALL: [MapPreset; 4] = [
+        MapPreset::Open,
+        MapPreset::Ramps,
+        MapPreset::Swamp,
+        MapPreset::Portals,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapPreset::Open => "Open",
+            MapPreset::Ramps => "Ramps",
+            MapPreset::Swamp => "Swamp",
+            MapPreset::Portals => "Portals",
+        }
+    }
+
+    /// The speed zones on an arena of `width` x `height` pixels
+    pub fn zones(&self, width: usize, height: usize) -> Vec<SpeedZone> {
+        let arena = (width as f64, height as f64);
+        let zone = |rect, factor| SpeedZone::relative(arena, rect, factor);
+        match self {
+            MapPreset::Open => vec![],
+            MapPreset::Ramps => vec![
+                zone((0.2, 0.3, 0.6, 0.04), 1.6),
+                zone((0.2, 0.66, 0.6, 0.04), 1.6),
+                zone((0.1, 0.2, 0.04, 0.6), 1.6),
+                zone((0.86, 0.2, 0.04, 0.6), 1.6),
+            ],
+            MapPreset::Swamp => vec![
+                zone((0.25, 0.25, 0.15, 0.15), 0.5),
+                zone((0.6, 0.25, 0.15, 0.15), 0.5),
+                zone((0.25, 0.6, 0.15, 0.15), 0.5),
+                zone((0.6, 0.6, 0.15, 0.15), 0.5),
+            ],
+            MapPreset::Portals => vec![],
+        }
+    }
+
+    /// The portals on an arena of `width` x `height` pixels
+    pub fn portals(&self, width: usize, height: usize) -> Vec<PortalPair> {
+        let arena = (width as f64, height as f64);
+        match self {
+            MapPreset::Portals => vec![
+                PortalPair::relative(arena, (0.2, 0.5), (0.8, 0.5)),
+                PortalPair::relative(arena, (0.5, 0.2), (0.5, 0.8)),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+/// How a player did in a finished round
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RoundResult {
+    pub id: Uuid,
+    pub name: String,   // the player might have left before the match ends
+    pub placement: u32, // 1 for the winner
+    pub survival_ms: u32,
+    pub kills: u32, // players that died on this player's trail
+    #[serde(default)]
+    pub stats: InputStats,
+}
+
+/// What a player achieved over the finished matches of a room, kept after leaving it
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SessionStats {
+    pub name: String,
+    pub matches: u32, // finished matches the player took part in
+    pub match_wins: u32,
+    pub round_wins: u32,
+    pub kills: u32,
+}
+
+/// How a player drove during a round
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct InputStats {
+    pub turns: u32,       // presses of left or right
+    pub turning: f64,     // seconds spent turning
+    pub straight: f64,    // seconds spent driving straight
+    pub near_misses: u32, // times the head passed close by the trail of another player
+}
+
+impl InputStats {
+    /// Share of the time spent turning, between 0 and 1
+    pub fn turning_share(&self) -> f64 {
+        match self.turning + self.straight {
+            total if total > 0. => self.turning / total,
+            _ => 0.,
+        }
+    }
+}
+
+/// Steps per pixel of the coordinates on the wire, allows grids up to 2047 pixels
+pub const COORD_SCALE: f64 = 32.;
+
+/// Seconds at the end of a round that are replayed in slow motion
+pub const SLOW_MOTION_SECONDS: f64 = 1.;
+/// Speed of the slow motion replay
+pub const SLOW_MOTION_FACTOR: f64 = 0.25;
+
+pub fn quantize(value: f64) -> u16 {
+    (value * COORD_SCALE).round() as u16
+}
+
+pub fn dequantize(value: u16) -> f64 {
+    value as f64 / COORD_SCALE
+}
+
+/// Characters of room codes, without the easily confused 0/O and 1/I
+pub const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+pub const ROOM_CODE_LENGTH: usize = 7;
+
+/// A room code as typed by a player in the form the server hands out: upper case, without the
+/// spaces or dashes added to read it out.
+pub fn normalize_room_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// Whether a normalized code could belong to a room.
+pub fn is_room_code(code: &str) -> bool {
+    code.len() == ROOM_CODE_LENGTH && code.bytes().all(|c| ROOM_CODE_ALPHABET.contains(&c))
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlayerState {
+    pub id: Uuid,
+    pub x: u16, // quantized, see `COORD_SCALE`
+    pub y: u16,
+    pub rotation: f64,
     pub invisible: bool,
+    pub alive: bool,
+    pub energy: u8,      // percent of boost/brake energy left
+    pub ghost_ready: u8, // percent of the ghost cooldown that has passed
+    pub ghost_active: bool,
+    pub confused: bool,         // left and right are swapped
+    pub gap_progress: u8,       // percent of the way to the next gap
+    pub item: Option<ItemKind>, // held in the inventory
+    pub speed: u16,             // pixels per second, including boost/brake
+    pub speed_change: SpeedChange,
+    pub input_ack: u32, // number of the last `ClientMessage::Input` applied, 0 if none
+}
+
+impl PlayerState {
+    pub fn pos(&self) -> (f64, f64) {
+        (dequantize(self.x), dequantize(self.y))
+    }
+}
+
+/// Where a player is in the cycle of drawing and opening gaps, distances are in pixels.
+///
+/// Each cycle starts with a gap, which ends after its length, and the trail is drawn for the rest
+/// of the interval.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum GapState {
+    Drawing(f64), // travelled since the last gap started
+    Gap(f64),     // travelled since this gap started
+}
+
+impl GapState {
+    /// Drawing, `travelled` into the cycle
+    pub fn start(travelled: f64) -> Self {
+        GapState::Drawing(travelled)
+    }
+
+    pub fn travelled(&self) -> f64 {
+        match *self {
+            GapState::Drawing(travelled) | GapState::Gap(travelled) => travelled,
+        }
+    }
+
+    pub fn is_gap(&self) -> bool {
+        matches!(self, GapState::Gap(_))
+    }
+
+    /// Moves `distance` further. A new cycle starts after `interval`, with a gap only if `enabled`,
+    /// and a gap closes after `length`.
+    pub fn advance(self, distance: f64, interval: f64, length: f64, enabled: bool) -> Self {
+        let travelled = self.travelled() + distance;
+        match self {
+            _ if travelled >= interval => match enabled {
+                true => GapState::Gap(0.),
+                false => GapState::Drawing(0.),
+            },
+            GapState::Gap(_) if travelled >= length => GapState::Drawing(travelled),
+            GapState::Gap(_) => GapState::Gap(travelled),
+            GapState::Drawing(_) => GapState::Drawing(travelled),
+        }
+    }
+
+    /// Percent of the `interval` travelled, 100 right before the next gap opens
+    pub fn progress(&self, interval: f64) -> u8 {
+        (self.travelled() / interval * 100.).clamp(0., 100.) as u8
+    }
+}
+
+/// Speed factors while boosting and braking
+const BOOST_FACTOR: f64 = 1.6;
+const BRAKE_FACTOR: f64 = 0.5;
+/// Seconds a player can boost or brake with full energy
+const MAX_ENERGY: f64 = 2.;
+/// Energy regained per second while neither boosting nor braking
+const ENERGY_REGEN: f64 = 0.5;
+/// Seconds until energy regenerates again after it ran out
+const ENERGY_COOLDOWN: f64 = 1.5;
+
+/// Dash pattern of a trail, tells players apart without relying on their color
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum TrailPattern {
+    Solid,
+    Dashed,
+    Dotted,
+    DashDot,
+    LongDash,
+    DashDotDot,
+    ShortDash,
+}
+
+impl TrailPattern {
+    pub const ALL: [TrailPattern; 7] = [
+        TrailPattern::Solid,
+        TrailPattern::Dashed,
+        TrailPattern::Dotted,
+        TrailPattern::DashDot,
+        TrailPattern::LongDash,
+        TrailPattern::DashDotDot,
+        TrailPattern::ShortDash,
+    ];
+
+    /// Lengths of alternating dashes and gaps in multiples of the line width, empty if solid.
+    /// Dashes of length 0 are dots.
+    pub fn dashes(&self) -> &'static [f64] {
+        match self {
+            TrailPattern::Solid => &[],
+            TrailPattern::Dashed => &[3., 2.5],
+            TrailPattern::Dotted => &[0., 2.5],
+            TrailPattern::DashDot => &[3., 2., 0., 2.],
+            TrailPattern::LongDash => &[6., 2.5],
+            TrailPattern::DashDotDot => &[3., 2., 0., 2., 0., 2.],
+            TrailPattern::ShortDash => &[1.5, 2.],
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize)]
@@ -31,28 +518,42 @@ pub struct Player {
     pub host: bool,
     pub name: ArrayString<20>,
     pub color: ArrayString<7>,
+    pub pattern: TrailPattern, // assigned along with the color
 
     pub x: f64,
     pub y: f64,
     pub rotation: f64,
-    rotation_delta: f64,
+    turn_rate: f64, // degrees per second
     direction: Direction,
 
     pub x_max: u32,
     pub y_max: u32,
     pub line_width: u32,
-    speed: f64,
-    stop_count: f64,
+    speed: f64, // pixels per second
+    speed_change: SpeedChange,
+    energy: f64,   // seconds of boosting/braking left
+    cooldown: f64, // seconds until energy regenerates after running out
 
     pub invisible: bool,
-    invisible_max: usize,
-    invisible_count: usize,
-    invisible_length: usize,
+    gap: GapState,
+    gap_length: f64, // of the current or next gap, in line widths
+    gaps: GapConfig,
+    gaps_enabled: bool,
+    ghost: Option<GhostConfig>,
+    ghost_left: f64,          // seconds of invisibility left from the ghost ability
+    ghost_cooldown: f64,      // seconds until the ghost ability can be used again
+    item: Option<ItemKind>,   // collected but not used yet, only with the inventory
+    zone: Option<(f64, f64)>, // speed factor of the last speed zone and seconds it still lasts
+    portal_left: f64,         // seconds of immunity left after going through a portal
+    confused_left: f64,       // seconds left with left and right swapped
+    near_miss: bool,          // another trail was close by in the last tick
+    pub stats: InputStats,    // of the current round
 
     pub points: usize,
-
-    x_prev_range: (usize, usize),
-    y_prev_range: (usize, usize),
+    distance: f64, // pixels travelled during the match, the points with `ScoringMode::Distance`
+    pub kills: usize, // players that died on this player's trail during the match
+    pub round_wins: usize, // rounds this player was the last one standing in during the match
+    pub rating: Option<u32>, // only players with an account are rated
 }
 
 impl Player {
@@ -63,74 +564,163 @@ impl Player {
         x_max: u32,
         y_max: u32,
         line_width: u32,
-        rotation_delta: f64,
+        turn_rate: f64,
     ) -> Self {
         Self {
             uuid,
             host: false,
             name: ArrayString::<20>::from(name).unwrap(),
             color,
+            pattern: TrailPattern::Solid,
             x: 0.,
             y: 0.,
             rotation: 0.,
-            rotation_delta,
+            turn_rate,
             direction: Direction::Unchanged,
             x_max,
             y_max,
             line_width,
-            speed: 0.8,
-            stop_count: 0.,
+            speed: SpeedPreset::Normal.speed(),
+            speed_change: SpeedChange::Normal,
+            energy: MAX_ENERGY,
+            cooldown: 0.,
             invisible: false,
-            invisible_max: 100,
-            invisible_count: 0,
-            invisible_length: 3,
+            gap: GapState::start(0.),
+            gap_length: GapConfig::default().length as f64,
+            gaps: GapConfig::default(),
+            gaps_enabled: true,
+            ghost: None,
+            ghost_left: 0.,
+            ghost_cooldown: 0.,
+            item: None,
+            zone: None,
+            portal_left: 0.,
+            confused_left: 0.,
+            near_miss: false,
+            stats: InputStats::default(),
             points: 0,
-            x_prev_range: (0, 0),
-            y_prev_range: (0, 0),
+            distance: 0.,
+            kills: 0,
+            round_wins: 0,
+            rating: None,
         }
     }
 
-    fn initialize(&mut self) {
-        let mut rng = thread_rng();
+    fn initialize(&mut self, rng: &mut impl Rng) {
         self.direction = Direction::Unchanged;
-        self.invisible_count = self.invisible_max;
+        self.speed_change = SpeedChange::Normal;
+        self.energy = MAX_ENERGY;
+        self.cooldown = 0.;
+        self.invisible = false;
+        // players open their gaps at different times
+        self.gap = GapState::start(rng.gen_range(0.0..self.gap_interval()));
+        self.gap_length = self.gaps.length as f64;
+        self.gaps_enabled = true;
+        self.ghost_left = 0.;
+        self.ghost_cooldown = 0.;
+        self.item = None;
+        self.zone = None;
+        self.portal_left = 0.;
+        self.confused_left = 0.;
+        self.near_miss = false;
+        self.stats = InputStats::default();
         let x_limits = (self.x_max as f64 * 0.15) as u32;
         let y_limits = (self.y_max as f64 * 0.15) as u32;
         self.x = rng.gen_range(0 + x_limits..self.x_max - x_limits).into();
         self.y = rng.gen_range(0 + y_limits..self.y_max - y_limits).into();
-        self.rotation = self.rotation_delta
-            * rng.gen_range(0..(360 as f64 / self.rotation_delta as f64) as u32) as f64;
+        self.rotation = rng.gen_range(0..360).into();
     }
 
-    pub fn tick(&mut self) {
-        // don't move if in stop_count (handles speed by not updating)
-        self.stop_count -= 1.;
-        if self.stop_count > 0. {
-            return;
+    fn rotate(&mut self, dt: f64) {
+        match self.direction {
+            Direction::Left => self.rotation += self.turn_rate * dt,
+            Direction::Right => self.rotation -= self.turn_rate * dt,
+            Direction::Unchanged => (),
         }
-        self.stop_count = self.line_width as f64 - (self.line_width as f64 * self.speed);
+    }
 
-        // handle invisibility
-        self.invisible_count -= 1;
-        if self.invisible_count == 0 {
-            self.invisible = true;
-            self.invisible_count = self.invisible_max;
+    /// Tick while the round is frozen: the player may turn, but doesn't move.
+    pub fn tick_frozen(&mut self, dt: f64) {
+        self.rotate(dt);
+    }
+
+    /// Spends energy while boosting or braking, returns the current speed factor.
+    fn speed_factor(&mut self, dt: f64) -> f64 {
+        if self.speed_change == SpeedChange::Normal {
+            if self.cooldown > 0. {
+                self.cooldown = (self.cooldown - dt).max(0.);
+            } else {
+                self.energy = (self.energy + ENERGY_REGEN * dt).min(MAX_ENERGY);
+            }
+            return 1.;
         }
+        self.energy -= dt;
+        if self.energy <= 0. {
+            // ran out, no boosting or braking until the cooldown is over
+            self.energy = 0.;
+            self.cooldown = ENERGY_COOLDOWN;
+            self.speed_change = SpeedChange::Normal;
+        }
+        self.speed_change.factor()
+    }
+
+    /// Factor of the speed zone the player drove through last, wearing off after a while.
+    fn zone_factor(&mut self, dt: f64) -> f64 {
+        match &mut self.zone {
+            Some((factor, left)) => {
+                let factor = *factor;
+                *left -= dt;
+                if *left <= 0. {
+                    self.zone = None;
+                }
+                factor
+            }
+            None => 1.,
+        }
+    }
+
+    fn enter_zone(&mut self, zone: &SpeedZone) {
+        self.zone = Some((zone.factor, ZONE_EFFECT_SECONDS));
+    }
+
+    /// Pixels per second, including boost/brake and speed zones
+    pub fn current_speed(&self) -> f64 {
+        let zone = self.zone.map_or(1., |(factor, _)| factor);
+        self.speed * self.speed_change.factor() * zone
+    }
+
+    /// Moves the player by `dt` seconds.
+    pub fn tick(&mut self, dt: f64) {
+        let distance = self.speed * self.speed_factor(dt) * self.zone_factor(dt) * dt;
 
-        if self.invisible && self.invisible_count < self.invisible_max - self.invisible_length {
-            self.invisible = false;
+        // handle invisibility
+        let length = self.gap_length * self.line_width as f64;
+        self.gap = self
+            .gap
+            .advance(distance, self.gap_interval(), length, self.gaps_enabled);
+
+        self.ghost_left = (self.ghost_left - dt).max(0.);
+        self.ghost_cooldown = (self.ghost_cooldown - dt).max(0.);
+        self.portal_left = (self.portal_left - dt).max(0.);
+        if self.confused_left > 0. {
+            self.confused_left = (self.confused_left - dt).max(0.);
+            if self.confused_left == 0. {
+                // the held key turns the way it says again
+                self.direction = self.direction.inverted();
+            }
         }
+        self.invisible = self.gap.is_gap() || self.ghost_left > 0. || self.portal_left > 0.;
 
         // change rotation
+        self.rotate(dt);
         match self.direction {
-            Direction::Left => self.rotation += self.rotation_delta,
-            Direction::Right => self.rotation -= self.rotation_delta,
-            Direction::Unchanged => (),
+            Direction::Unchanged => self.stats.straight += dt,
+            _ => self.stats.turning += dt,
         }
 
-        // change position is relative to linewidth
-        let x_change = self.rotation.to_radians().sin() * (self.line_width as f64);
-        let y_change = self.rotation.to_radians().cos() * (self.line_width as f64);
+        // `libm` gives the same results on every target, unlike the platform's math library
+        let x_change = libm::sin(self.rotation.to_radians()) * distance;
+        let y_change = libm::cos(self.rotation.to_radians()) * distance;
 
         self.x += x_change;
         if self.x < 0. {
@@ -147,230 +737,1111 @@ impl Player {
         if self.y > self.y_max as f64 {
             self.y = self.y_max as f64;
         }
+
+        // stay on positions that can be sent exactly
+        self.x = dequantize(quantize(self.x));
+        self.y = dequantize(quantize(self.y));
+    }
+
+    /// Comes out of the portal at `exit`, keeping the heading. The player leaves no trail for a
+    /// moment, so the trail breaks between the portals.
+    fn teleport(&mut self, exit: (f64, f64)) -> (f64, f64) {
+        let clearance = PORTAL_RADIUS + self.line_width as f64;
+        let x = exit.0 + libm::sin(self.rotation.to_radians()) * clearance;
+        let y = exit.1 + libm::cos(self.rotation.to_radians()) * clearance;
+        self.x = dequantize(quantize(x.clamp(0., self.x_max as f64)));
+        self.y = dequantize(quantize(y.clamp(0., self.y_max as f64)));
+        self.portal_left = PORTAL_IMMUNITY_SECONDS;
+        self.invisible = true;
+        (self.x, self.y)
     }
 
     fn change_direction(&mut self, direction: Direction) {
+        let direction = match self.confused() {
+            true => direction.inverted(),
+            false => direction,
+        };
+        if direction != Direction::Unchanged && direction != self.direction {
+            self.stats.turns += 1;
+        }
         self.direction = direction;
     }
-}
-
-#[derive(Clone, Debug)]
-pub struct Grid {
-    data: Vec<Vec<Uuid>>,
-}
 
-impl Grid {
-    fn new(width: usize, height: usize) -> Self {
-        Self {
-            data: vec![vec![Uuid::default(); width]; height],
+    /// Counts a near-miss each time another trail comes close, not for every tick it stays close.
+    fn pass_by(&mut self, near: bool) {
+        if near && !self.near_miss {
+            self.stats.near_misses += 1;
         }
+        self.near_miss = near;
     }
 
-    fn clear(&mut self) {
-        self.data
-            .iter_mut()
-            .for_each(|row| row.iter_mut().for_each(|el| *el = Uuid::default()));
+    /// Inverts the controls, the held key turns the other way right away.
+    fn confuse(&mut self) {
+        if !self.confused() {
+            self.direction = self.direction.inverted();
+        }
+        self.confused_left = CONFUSION_SECONDS;
     }
-}
 
-impl Deref for Grid {
-    type Target = Vec<Vec<Uuid>>;
+    pub fn confused(&self) -> bool {
+        self.confused_left > 0.
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.data
+    fn change_speed(&mut self, change: SpeedChange) {
+        // boosting and braking need some energy to start
+        if change == SpeedChange::Normal || (self.cooldown == 0. && self.energy > 0.) {
+            self.speed_change = change;
+        }
     }
-}
 
-impl DerefMut for Grid {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.data
+    /// Turns invisible for a while, if the room allows it and the cooldown is over.
+    fn use_ghost(&mut self) {
+        match self.ghost {
+            Some(ghost) if self.gaps_enabled && self.ghost_cooldown == 0. => {
+                self.ghost_left = ghost.duration_ms as f64 / 1000.;
+                self.ghost_cooldown = ghost.cooldown_ms as f64 / 1000.;
+            }
+            _ => (),
+        }
     }
-}
 
-impl fmt::Display for Grid {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for row in self.iter() {
-            for el in row.iter() {
-                if *el == Uuid::default() {
-                    write!(f, " ")?;
-                } else {
-                    write!(f, "x")?;
-                }
+    /// Progress of the ghost cooldown, in percent
+    pub fn ghost_ready(&self) -> u8 {
+        match self.ghost {
+            Some(ghost) if ghost.cooldown_ms > 0 => {
+                let cooldown = ghost.cooldown_ms as f64 / 1000.;
+                ((1. - self.ghost_cooldown / cooldown) * 100.).round() as u8
             }
-            write!(f, "\n")?;
+            _ => 100,
         }
-        write!(f, "\n")
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct Game {
-    pub width: usize,  // pixel width
-    pub height: usize, // pixel height
-    pub line_width: u32,
-    pub rotation_delta: f64,
-    single_player: bool,
+    pub fn set_ghost(&mut self, ghost: Option<GhostConfig>) {
+        self.ghost = ghost;
+    }
+
+    pub fn set_gaps(&mut self, gaps: GapConfig) {
+        self.gaps = gaps;
+    }
+
+    /// Pixels from the start of one gap to the start of the next
+    fn gap_interval(&self) -> f64 {
+        self.gaps.interval as f64 * self.line_width as f64
+    }
+
+    /// Percent of the way to the next gap, 0 while in a gap or without gaps
+    pub fn gap_progress(&self) -> u8 {
+        match self.gap {
+            GapState::Drawing(_) if self.gaps_enabled => self.gap.progress(self.gap_interval()),
+            _ => 0,
+        }
+    }
+
+    /// Picks the length of a gap that just started, within the variation of the room.
+    fn vary_gap(&mut self, rng: &mut impl Rng) {
+        let length = self.gaps.length as f64;
+        let spread = length * self.gaps.variation as f64 / 100.;
+        self.gap_length = match spread > 0. {
+            true => rng.gen_range(length - spread..=length + spread),
+            false => length,
+        };
+    }
 
-    grid: Arc<Mutex<Grid>>, // grid with x and y pixels mapping to uuid of player
+    /// Boost/brake energy left, in percent
+    pub fn energy(&self) -> u8 {
+        (self.energy / MAX_ENERGY * 100.).round() as u8
+    }
 
-    pub players: HashMap<Uuid, Arc<Mutex<Player>>>,
-    active_players: HashMap<Uuid, Arc<Mutex<Player>>>,
+    pub fn set_handling(&mut self, speed: f64, turn_rate: f64) {
+        self.speed = speed;
+        self.turn_rate = turn_rate;
+    }
 }
 
-impl Game {
-    pub fn new(width: usize, height: usize, line_width: u32, rotation_delta: f64) -> Self {
-        let players = HashMap::new();
-        let active_players = HashMap::new();
-        let grid = Arc::new(Mutex::new(Grid::new(width, height)));
+/// Owner index of cells no player has written to
+const EMPTY: u8 = 0;
+/// Portal cell value of cells without a portal, others hold the portal's index + 1
+const NO_PORTAL: u8 = 0;
 
+/// Pixel grid of the trails, stored row by row.
+///
+/// Cells store a one byte owner index instead of the player's uuid, `owners` maps these indices
+/// back to uuids.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,    // owner index of each pixel, `EMPTY` if there is no trail
+    ticks: Vec<u32>,   // game tick each pixel was last written in
+    owners: Vec<Uuid>, // uuid of owner index `i + 1`
+    hazard: Vec<bool>, // pixels covered by a hazard right now
+    hazard_cells: Vec<usize>, // indices set in `hazard`, to clear them once the hazards move
+    portal: Vec<u8>,   // portal covering each pixel, `2 * pair + end + 1` or `NO_PORTAL`
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Self {
         Self {
             width,
             height,
-            line_width,
-            rotation_delta,
-            grid,
-            players,
-            active_players,
-            single_player: false,
+            cells: vec![EMPTY; width * height],
+            ticks: vec![0; width * height],
+            owners: vec![],
+            hazard: vec![false; width * height],
+            hazard_cells: vec![],
+            portal: vec![NO_PORTAL; width * height],
         }
     }
 
-    pub fn initialize(&mut self) {
-        if self.players.len() == 1 {
-            self.single_player = true;
-        } else {
-            self.single_player = false;
+    pub fn clear(&mut self) {
+        self.cells.fill(EMPTY);
+        self.ticks.fill(0);
+        self.owners.clear();
+        self.set_hazards(&[]);
+    }
+
+    /// Marks the pixels covered by the hazards in their current position.
+    pub fn set_hazards(&mut self, hazards: &[Hazard]) {
+        for &i in &self.hazard_cells {
+            self.hazard[i] = false;
+        }
+        self.hazard_cells.clear();
+        let half = (HAZARD_WIDTH / 2.) as i64;
+        for hazard in hazards {
+            let (from, to) = hazard.ends();
+            let points = line_points((from.0 as i64, from.1 as i64), (to.0 as i64, to.1 as i64));
+            for (px, py) in points {
+                for y in (py - half).max(0)..(py + half).min(self.height as i64) {
+                    for x in (px - half).max(0)..(px + half).min(self.width as i64) {
+                        let i = y as usize * self.width + x as usize;
+                        if !self.hazard[i] {
+                            self.hazard[i] = true;
+                            self.hazard_cells.push(i);
+                        }
+                    }
+                }
+            }
         }
-        self.grid.lock().unwrap().clear();
-        self.active_players = self.players.clone();
-        self.active_players
-            .iter_mut()
-            .map(|(_id, player)| player.lock().unwrap())
-            .for_each(|mut player| {
-                player.initialize();
-            });
     }
 
-    pub fn state(&self) -> Vec<PlayerState> {
-        self.active_players
-            .iter()
-            .map(|(id, player)| (id, player.lock().unwrap()))
-            .map(|(id, player)| PlayerState {
-                id: *id,
-                x: player.x,
-                y: player.y,
-                invisible: player.invisible,
-            })
-            .collect()
+    pub fn is_hazard(&self, x: usize, y: usize) -> bool {
+        self.hazard[y * self.width + x]
     }
 
-    pub fn state_ended(&self) -> Vec<(Uuid, usize)> {
-        self.players
+    /// Marks the pixels covered by the portals, they stay for every round on this grid.
+    pub fn set_portals(&mut self, portals: &[PortalPair]) {
+        self.portal.fill(NO_PORTAL);
+        let ends = portals.iter().flat_map(|pair| vec![pair.a, pair.b]);
+        for (i, (px, py)) in ends.enumerate() {
+            let y_range = (py - PORTAL_RADIUS).max(0.) as usize
+                ..((py + PORTAL_RADIUS) as usize + 1).min(self.height);
+            let x_range = (px - PORTAL_RADIUS).max(0.) as usize
+                ..((px + PORTAL_RADIUS) as usize + 1).min(self.width);
+            for y in y_range {
+                for x in x_range.clone() {
+                    if libm::hypot(x as f64 - px, y as f64 - py) <= PORTAL_RADIUS {
+                        self.portal[y * self.width + x] = i as u8 + 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Index of the portal end at a pixel, `2 * pair` or `2 * pair + 1`
+    pub fn portal(&self, x: usize, y: usize) -> Option<usize> {
+        match self.portal[y * self.width + x] {
+            NO_PORTAL => None,
+            i => Some(i as usize - 1),
+        }
+    }
+
+    /// Whether a trail of another owner than `owner` lies on the circle of `radius` around a
+    /// point, only trails of ticks for which `alive` holds count.
+    pub fn trail_near(
+        &self,
+        (x, y): (f64, f64),
+        radius: f64,
+        owner: u8,
+        alive: impl Fn(u32) -> bool,
+    ) -> bool {
+        (0..NEAR_MISS_SAMPLES).any(|i| {
+            let angle = i as f64 * std::f64::consts::TAU / NEAR_MISS_SAMPLES as f64;
+            let px = x + libm::cos(angle) * radius;
+            let py = y + libm::sin(angle) * radius;
+            if px < 0. || py < 0. || px >= self.width as f64 || py >= self.height as f64 {
+                return false;
+            }
+            matches!(self.get(px as usize, py as usize), Some((o, t)) if o != owner && alive(t))
+        })
+    }
+
+    /// Returns the owner index of a player, registering the player if necessary.
+    pub fn owner_index(&mut self, uuid: &Uuid) -> u8 {
+        match self.owners.iter().position(|owner| owner == uuid) {
+            Some(i) => i as u8 + 1,
+            None => {
+                self.owners.push(*uuid);
+                self.owners.len() as u8
+            }
+        }
+    }
+
+    /// Uuid of the player with the given owner index
+    pub fn owner_uuid(&self, index: u8) -> Option<Uuid> {
+        match index {
+            EMPTY => None,
+            i => self.owners.get(i as usize - 1).copied(),
+        }
+    }
+
+    /// Owner index and tick of the trail at a pixel, `None` if the pixel is empty.
+    pub fn get(&self, x: usize, y: usize) -> Option<(u8, u32)> {
+        let i = y * self.width + x;
+        match self.cells[i] {
+            EMPTY => None,
+            owner => Some((owner, self.ticks[i])),
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, owner: u8, tick: u32) {
+        let i = y * self.width + x;
+        self.cells[i] = owner;
+        self.ticks[i] = tick;
+    }
+
+    /// Removes all trails within `radius` pixels around a point.
+    pub fn clear_circle(&mut self, x: f64, y: f64, radius: f64) {
+        let x_range = (x - radius).max(0.) as usize..((x + radius) as usize + 1).min(self.width);
+        let y_range = (y - radius).max(0.) as usize..((y + radius) as usize + 1).min(self.height);
+        for cy in y_range {
+            for cx in x_range.clone() {
+                if libm::hypot(cx as f64 - x, cy as f64 - y) <= radius {
+                    self.cells[cy * self.width + cx] = EMPTY;
+                }
+            }
+        }
+    }
+
+    /// Run-length encoded trails, cells written before tick `since` count as empty.
+    pub fn trail_sync(&self, since: u32) -> TrailSync {
+        let mut runs: Vec<(u8, u32)> = vec![];
+        for (&owner, &tick) in self.cells.iter().zip(&self.ticks) {
+            let owner = if tick < since { EMPTY } else { owner };
+            match runs.last_mut() {
+                Some((last, len)) if *last == owner => *len += 1,
+                _ => runs.push((owner, 1)),
+            }
+        }
+        TrailSync {
+            width: self.width as u32,
+            owners: self.owners.clone(),
+            runs,
+        }
+    }
+}
+
+/// All trails of a grid, to redraw the board after missed updates.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrailSync {
+    pub width: u32,
+    pub owners: Vec<Uuid>,    // uuid of owner index `i + 1`
+    pub runs: Vec<(u8, u32)>, // (owner index, length) of the cells row by row, 0 if empty
+}
+
+impl TrailSync {
+    /// Horizontal pixel spans `(owner, x, y, length)` covered by trails.
+    pub fn spans(&self) -> Vec<(Uuid, u32, u32, u32)> {
+        let mut spans = vec![];
+        let mut pos = 0;
+        for &(owner, len) in &self.runs {
+            let end = pos + len;
+            if let Some(&uuid) = owner
+                .checked_sub(1)
+                .and_then(|i| self.owners.get(i as usize))
+            {
+                // runs may continue into the next row
+                while pos < end {
+                    let (x, y) = (pos % self.width, pos / self.width);
+                    let span = (end - pos).min(self.width - x);
+                    spans.push((uuid, x, y, span));
+                    pos += span;
+                }
+            }
+            pos = end;
+        }
+        spans
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.cells.chunks(self.width).take(self.height) {
+            for &el in row.iter() {
+                if el == EMPTY {
+                    write!(f, " ")?;
+                } else {
+                    write!(f, "x")?;
+                }
+            }
+            write!(f, "\n")?;
+        }
+        write!(f, "\n")
+    }
+}
+
+/// Pixels per second the walls move inwards during sudden death
+const SUDDEN_DEATH_SHRINK: f64 = 10.;
+/// A player doesn't collide with its own cells written within this many seconds
+const SELF_GRACE_SECONDS: f64 = 0.2;
+/// Pixels between the edge of a player's line and another trail that count as a near-miss
+const NEAR_MISS_DISTANCE: f64 = 4.;
+/// Points on the circle around the head that are checked for near-misses
+const NEAR_MISS_SAMPLES: usize = 8;
+/// Seconds a bot looks ahead on each way it could steer
+const BOT_LOOKAHEAD_SECONDS: f64 = 1.;
+/// Points a bot checks along each way
+const BOT_SAMPLES: usize = 20;
+
+/// The part of a player that collides with trails and hazards, the whole line is drawn either way
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum Hitbox {
+    #[default]
+    Band, // the full width of the line, along the whole move
+    Square(u32), // pixels from the center of the head to the edges of a square, like the classic
+    Circle(u32), // pixels from the center of the head
+}
+
+impl Hitbox {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hitbox::Band => "Band",
+            Hitbox::Square(_) => "Square",
+            Hitbox::Circle(_) => "Circle",
+        }
+    }
+
+    /// Pixels from the center of the head, `None` for the band
+    pub fn size(&self) -> Option<u32> {
+        match *self {
+            Hitbox::Band => None,
+            Hitbox::Square(size) | Hitbox::Circle(size) => Some(size),
+        }
+    }
+
+    /// Whether the cell `dx`, `dy` pixels away from the center of the head collides
+    pub fn covers(&self, dx: i64, dy: i64) -> bool {
+        match *self {
+            Hitbox::Band => true,
+            Hitbox::Square(size) => dx.abs().max(dy.abs()) <= size as i64,
+            Hitbox::Circle(size) => dx * dx + dy * dy <= size as i64 * size as i64,
+        }
+    }
+
+    /// Pixels from the center of the head to the edge of the hitbox
+    fn reach(&self, linewidth_half: f64) -> f64 {
+        match *self {
+            Hitbox::Band => linewidth_half,
+            Hitbox::Square(size) | Hitbox::Circle(size) => (size as f64).min(linewidth_half),
+        }
+    }
+}
+
+/// Pixels on the line between two pixels (Bresenham), including both ends
+fn line_points(from: (i64, i64), to: (i64, i64)) -> Vec<(i64, i64)> {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    let mut points = vec![(x, y)];
+    while (x, y) != to {
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+        points.push((x, y));
+    }
+    points
+}
+
+#[derive(Clone, Debug)]
+pub struct Game {
+    pub width: usize,  // pixel width
+    pub height: usize, // pixel height
+    pub line_width: u32,
+    pub speed: f64,     // pixels per second
+    pub turn_rate: f64, // degrees per second
+    pub ghost: Option<GhostConfig>,
+    pub gaps: GapConfig,
+    pub trail_lifetime: Option<u32>, // ticks until a trail disappears, `None` keeps trails
+    pub items_enabled: bool,
+    pub item_inventory: bool, // collected items are kept until their player uses them
+    pub hazards_enabled: bool,
+    pub growing_trails: bool, // the line width increases as the round goes on
+    pub hitbox: Hitbox,       // the part of each player that collides
+    growth: u32,              // pixels added to `line_width` so far this round
+    pub scoring: ScoringMode,
+    map: MapPreset,
+    zones: Vec<SpeedZone>, // of `map` on the current grid
+    portals: Vec<PortalPair>,
+    teleports: Vec<Teleport>, // since the last `take_teleports`
+    single_player: bool,
+    sudden_death: bool,
+    wall_margin: f64, // distance of the walls from the border of the grid
+    ticks: u32,
+
+    grid: Grid, // grid with x and y pixels mapping to the owning player
+
+    pub players: HashMap<Uuid, Player>,
+    active_players: HashSet<Uuid>, // players still alive in this round
+    participants: HashSet<Uuid>,   // players of this round, later joiners wait for the next one
+    afk: HashSet<Uuid>,            // inactive players, left out when a round starts
+
+    items: Vec<Item>,
+    next_item_id: u32,
+    item_timer: f64, // seconds until the next item appears
+    item_events: Vec<ItemEvent>,
+    hazards: Vec<Hazard>,
+    rng: StdRng, // start positions and items, seeded to replay a round
+}
+
+/// Input of a player, as kept in an input log
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum Input {
+    Move(Direction),
+    Speed(SpeedChange),
+    UseGhost,
+    UseItem,
+}
+
+impl From<Input> for ClientMessage {
+    fn from(input: Input) -> Self {
+        match input {
+            Input::Move(direction) => ClientMessage::Move(direction),
+            Input::Speed(change) => ClientMessage::Speed(change),
+            Input::UseGhost => ClientMessage::UseGhost,
+            Input::UseItem => ClientMessage::UseItem,
+        }
+    }
+}
+
+impl Game {
+    pub fn new(width: usize, height: usize, line_width: u32, turn_rate: f64) -> Self {
+        let players = HashMap::new();
+        let active_players = HashSet::new();
+        let participants = HashSet::new();
+        let afk = HashSet::new();
+        let grid = Grid::new(width, height);
+
+        Self {
+            width,
+            height,
+            line_width,
+            speed: SpeedPreset::Normal.speed(),
+            turn_rate,
+            ghost: None,
+            gaps: GapConfig::default(),
+            trail_lifetime: None,
+            items_enabled: false,
+            item_inventory: false,
+            grid,
+            players,
+            active_players,
+            participants,
+            afk,
+            single_player: false,
+            sudden_death: false,
+            wall_margin: 0.,
+            ticks: 0,
+            items: vec![],
+            next_item_id: 0,
+            item_timer: ITEM_SPAWN_SECONDS,
+            item_events: vec![],
+            hazards: vec![],
+            hazards_enabled: false,
+            growing_trails: false,
+            hitbox: Hitbox::Band,
+            growth: 0,
+            scoring: ScoringMode::Survival,
+            map: MapPreset::Open,
+            zones: vec![],
+            portals: vec![],
+            teleports: vec![],
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// A game set up the way `config` asks for.
+    pub fn from_config(config: &RoomConfig) -> Self {
+        let mut game = Game::new(
+            config.width,
+            config.height,
+            config.line_width,
+            config.preset.turn_rate(),
+        );
+        game.set_handling(config.preset.speed(), config.preset.turn_rate());
+        game.set_ghost(config.ghost);
+        game.set_gaps(config.gaps);
+        game.trail_lifetime = config.trail_lifetime;
+        game.items_enabled = config.items;
+        game.item_inventory = config.item_inventory;
+        game.hazards_enabled = config.hazards;
+        game.growing_trails = config.growing_trails;
+        game.hitbox = config.hitbox;
+        game.scoring = config.scoring;
+        game.set_map(config.map);
+        game
+    }
+
+    /// Makes the following rounds depend on `seed` only.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Ids of the players still alive, in the order they move
+    fn move_order(&self) -> Vec<Uuid> {
+        let mut order: Vec<Uuid> = self.active_players.iter().copied().collect();
+        order.sort();
+        order
+    }
+
+    /// Plays a round from `seed`, applying the inputs of `log` right before the tick they are
+    /// listed with. Returns the positions of all players after every tick, ordered by id.
+    ///
+    /// The same seed and log give the same positions on every target, which the client needs to
+    /// predict the server.
+    pub fn simulate(
+        &mut self,
+        seed: u64,
+        log: &[(u32, Uuid, Input)],
+        ticks: u32,
+        dt: f64,
+    ) -> Vec<Vec<(Uuid, f64, f64)>> {
+        self.seed(seed);
+        self.initialize();
+        (0..ticks)
+            .map(|tick| {
+                for (_, id, input) in log.iter().filter(|(t, _, _)| *t == tick) {
+                    // inputs of dead players are ignored like on the server
+                    let _ = self.on_input(id, *input);
+                }
+                self.tick(dt);
+                let mut positions: Vec<(Uuid, f64, f64)> = self
+                    .players
+                    .iter()
+                    .map(|(id, player)| (*id, player.x, player.y))
+                    .collect();
+                positions.sort_by_key(|(id, _, _)| *id);
+                positions
+            })
+            .collect()
+    }
+
+    pub fn initialize(&mut self) {
+        self.active_players = self
+            .players
+            .keys()
+            .filter(|id| !self.afk.contains(id))
+            .copied()
+            .collect();
+        self.participants = self.active_players.clone();
+        if self.participants.len() == 1 {
+            self.single_player = true;
+        } else {
+            self.single_player = false;
+        }
+        self.grid.clear();
+        self.sudden_death = false;
+        self.wall_margin = 0.;
+        self.ticks = 0;
+        self.items.clear();
+        self.item_timer = ITEM_SPAWN_SECONDS;
+        self.item_events.clear();
+        self.teleports.clear();
+        self.growth = 0;
+        let line_width = self.line_width;
+        self.players
+            .values_mut()
+            .for_each(|player| player.line_width = line_width);
+        // the random start positions are drawn in a fixed order
+        let mut ids: Vec<Uuid> = self.players.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            self.players.get_mut(&id).unwrap().initialize(&mut self.rng);
+        }
+        self.hazards.clear();
+        if self.hazards_enabled {
+            self.place_hazards();
+        }
+    }
+
+    /// Places the hazards of a round where they can't reach the start of any player.
+    fn place_hazards(&mut self) {
+        for _ in 0..HAZARD_COUNT {
+            for _ in 0..HAZARD_ATTEMPTS {
+                let hazard = Hazard::random(&mut self.rng, self.width as f64, self.height as f64);
+                let free = self.active_players.iter().all(|id| {
+                    let player = &self.players[id];
+                    libm::hypot(player.x - hazard.x, player.y - hazard.y)
+                        > hazard.reach() + HAZARD_CLEARANCE
+                });
+                if free {
+                    self.hazards.push(hazard);
+                    break;
+                }
+            }
+        }
+        self.grid.set_hazards(&self.hazards);
+    }
+
+    pub fn hazards(&self) -> &[Hazard] {
+        &self.hazards
+    }
+
+    pub fn set_map(&mut self, map: MapPreset) {
+        self.map = map;
+        self.zones = map.zones(self.width, self.height);
+        self.portals = map.portals(self.width, self.height);
+        self.grid.set_portals(&self.portals);
+    }
+
+    pub fn zones(&self) -> &[SpeedZone] {
+        &self.zones
+    }
+
+    pub fn portals(&self) -> &[PortalPair] {
+        &self.portals
+    }
+
+    pub fn take_teleports(&mut self) -> Vec<Teleport> {
+        std::mem::take(&mut self.teleports)
+    }
+
+    pub fn state(&self) -> Vec<PlayerState> {
+        self.players
             .iter()
-            .map(|(id, player)| (id, player.lock().unwrap()))
-            .map(|(id, player)| (*id, player.points))
+            .map(|(id, player)| PlayerState {
+                id: *id,
+                x: quantize(player.x),
+                y: quantize(player.y),
+                rotation: player.rotation,
+                invisible: player.invisible,
+                alive: self.active_players.contains(id),
+                energy: player.energy(),
+                ghost_ready: player.ghost_ready(),
+                ghost_active: player.ghost_left > 0.,
+                confused: player.confused(),
+                gap_progress: player.gap_progress(),
+                item: player.item,
+                speed: player.current_speed().round() as u16,
+                speed_change: player.speed_change,
+                input_ack: 0, // filled in by the server
+            })
             .collect()
     }
 
-    pub fn tick(&mut self) {
+    /// Points and kills of every player
+    pub fn state_ended(&self) -> Vec<(Uuid, usize, usize)> {
+        self.players
+            .iter()
+            .map(|(id, player)| (*id, player.points, player.kills))
+            .collect()
+    }
+
+    /// Advances the game by `dt` seconds.
+    pub fn tick(&mut self, dt: f64) -> Vec<Death> {
         // do a move for each player
-        let mut remove = vec![];
+        let mut deaths = vec![];
         let width = self.width;
         let height = self.height;
+        if self.sudden_death {
+            self.wall_margin += SUDDEN_DEATH_SHRINK * dt;
+        }
+        let wall_margin = self.wall_margin;
+        self.ticks += 1;
+        let tick = self.ticks;
+        if self.growing_trails {
+            self.grow_trails(tick as f64 * dt);
+        }
+        let grace_ticks = (SELF_GRACE_SECONDS / dt).round() as u32;
+        let hitbox = self.hitbox;
+        let trail_lifetime = self.trail_lifetime;
+        let by_distance = self.scoring == ScoringMode::Distance && !self.single_player;
+        let zones = &self.zones;
+        let portals = &self.portals;
+        if !self.hazards.is_empty() {
+            self.hazards.iter_mut().for_each(|hazard| hazard.tick(dt));
+            self.grid.set_hazards(&self.hazards);
+        }
         //let cpy = self.clone();
         {
-            let mut grid = self.grid.lock().unwrap();
-            self.active_players.iter_mut().for_each(|(uuid, player)| {
+            let order = self.move_order();
+            let grid = &mut self.grid;
+            let players = &mut self.players;
+            let teleports = &mut self.teleports;
+            let rng = &mut self.rng;
+            order.iter().for_each(|uuid| {
+                let player = players.get_mut(uuid).unwrap();
                 // move
-                player.lock().unwrap().tick();
-                let linewidth_half = player.lock().unwrap().line_width as f64 / 2.0;
+                let (from, was_invisible) = ((player.x, player.y), player.invisible);
+                let was_in_gap = player.gap.is_gap();
+                player.tick(dt);
+                if player.gap.is_gap() && !was_in_gap {
+                    player.vary_gap(rng);
+                }
+                if let Some(zone) = zones.iter().find(|zone| zone.contains(player.x, player.y)) {
+                    player.enter_zone(zone);
+                }
+                if by_distance {
+                    player.distance += libm::hypot(player.x - from.0, player.y - from.1);
+                    player.points = player.distance as usize;
+                }
+                // portals don't take players right back while they are still immune
+                let head = (
+                    (player.x as usize).min(width - 1),
+                    (player.y as usize).min(height - 1),
+                );
+                if let Some(i) = grid
+                    .portal(head.0, head.1)
+                    .filter(|_| player.portal_left == 0.)
+                {
+                    let pair = &portals[i / 2];
+                    let (entry, exit) = match i % 2 {
+                        0 => (pair.a, pair.b),
+                        _ => (pair.b, pair.a),
+                    };
+                    let to = player.teleport(exit);
+                    teleports.push(Teleport {
+                        id: *uuid,
+                        from: entry,
+                        to,
+                    });
+                }
+                let player = &*player;
+                let linewidth_half = player.line_width as f64 / 2.0;
 
                 // update the grid
                 // TODO: be better here. More discrete, no use of floats, ...
                 let pixel_range = |value: f64, max_value: usize| {
                     let lower = value - linewidth_half + 1.0;
-                    let lower: usize = match lower.is_sign_negative() {
+                    let lower: usize = match lower < wall_margin {
                         true => return None, // hit a wall
                         false => lower as usize,
                     };
                     let upper = (value + linewidth_half - 1.0) as usize;
-                    let upper = match upper > (max_value - 1).try_into().unwrap() {
+                    let upper = match upper as f64 > max_value as f64 - 1.0 - wall_margin {
                         true => return None, // hit a wall
-                        false => upper as usize,
+                        false => upper,
                     };
                     Some((lower, upper))
                 };
 
-                let check_pixels = &mut || -> Option<()> {
-                    let (x_prev_range, y_prev_range) = {
-                        let player = player.lock().unwrap();
-                        let (x_start, x_end) = pixel_range(player.x, width)?;
-                        let (y_start, y_end) = pixel_range(player.y, height)?;
-                        let (x_prev_start, x_prev_end) = player.x_prev_range;
-                        let (y_prev_start, y_prev_end) = player.y_prev_range;
-                        for x in x_start..x_end {
-                            for y in y_start..y_end {
-                                // don't check with your last move
-                                if (x < x_prev_start || x > x_prev_end)
-                                    || (y < y_prev_start || y > y_prev_end)
-                                {
-                                    // player is colliding with another player
-                                    if grid[y][x] != Uuid::default() {
-                                        println!("COLLISION WITH ANOTHER PLAYER: ({}-{})", x, y);
-                                        return None;
+                let check_pixels = &mut || -> Result<(), Death> {
+                    let index = grid.owner_index(uuid);
+                    let wall = Death {
+                        id: *uuid,
+                        x: player.x,
+                        y: player.y,
+                        cause: DeathCause::Wall,
+                    };
+                    pixel_range(player.x, width).ok_or(wall)?;
+                    pixel_range(player.y, height).ok_or(wall)?;
+
+                    // sweep the whole segment of this move, so nobody tunnels through a trail
+                    let from = match was_invisible {
+                        true => (player.x, player.y), // the trail starts here after a gap
+                        false => from,
+                    };
+                    let points = line_points(
+                        (from.0 as i64, from.1 as i64),
+                        (player.x as i64, player.y as i64),
+                    );
+                    for (px, py) in points {
+                        let (x_start, x_end) = pixel_range(px as f64, width).ok_or(wall)?;
+                        let (y_start, y_end) = pixel_range(py as f64, height).ok_or(wall)?;
+                        for y in y_start..y_end {
+                            for x in x_start..x_end {
+                                let hits = hitbox.covers(x as i64 - px, y as i64 - py);
+                                if hits && grid.is_hazard(x, y) {
+                                    return Err(Death {
+                                        id: *uuid,
+                                        x: x as f64,
+                                        y: y as f64,
+                                        cause: DeathCause::Hazard,
+                                    });
+                                }
+                                let cell = grid.get(x, y).filter(|&(_, cell_tick)| {
+                                    trail_lifetime.is_none_or(|n| tick - cell_tick < n)
+                                });
+                                if let Some((owner, cell_tick)) = cell {
+                                    // don't check with your most recent moves
+                                    let recent = owner == index && tick - cell_tick <= grace_ticks;
+                                    if !recent {
+                                        if !hits {
+                                            // outside the hitbox, the trail below stays as it is
+                                            continue;
+                                        }
+                                        // player is colliding with a trail
+                                        let cause = if owner == index {
+                                            DeathCause::OwnTrail
+                                        } else {
+                                            DeathCause::Player(grid.owner_uuid(owner).unwrap())
+                                        };
+                                        return Err(Death {
+                                            id: *uuid,
+                                            x: x as f64,
+                                            y: y as f64,
+                                            cause,
+                                        });
                                     }
                                 }
-                                // mark each cell with your player id
-                                grid[y][x] = *uuid;
+                                // mark each cell with your player index
+                                grid.set(x, y, index, tick);
                             }
                         }
-                        ((x_start, x_end), ((y_start, y_end)))
-                    };
-                    let mut player = player.lock().unwrap();
-                    player.x_prev_range = x_prev_range;
-                    player.y_prev_range = y_prev_range;
-                    Some(())
+                    }
+                    Ok(())
                 };
 
-                if !player.lock().unwrap().invisible {
-                    if let None = check_pixels() {
-                        // either inside a wall, or colliding with another player
+                if !player.invisible {
+                    if let Err(death) = check_pixels() {
+                        // either inside a wall, or colliding with a trail
                         //println!("{}", grid);
-                        remove.push(uuid.clone());
+                        deaths.push(death);
+                        return;
                     }
                 }
+                let index = grid.owner_index(uuid);
+                let near = grid.trail_near(
+                    (player.x, player.y),
+                    hitbox.reach(linewidth_half) + NEAR_MISS_DISTANCE,
+                    index,
+                    |cell_tick| trail_lifetime.is_none_or(|n| tick - cell_tick < n),
+                );
+                players.get_mut(uuid).unwrap().pass_by(near);
             });
         }
 
         // remove player from game
-        remove.iter().for_each(|uuid_remove| {
-            if !self.single_player {
+        deaths.iter().for_each(|death| {
+            // the owner of the trail gets the kill
+            if let DeathCause::Player(killer) = death.cause {
+                if let Some(killer) = self.players.get_mut(&killer) {
+                    killer.kills += 1;
+                }
+            }
+            if !self.single_player && self.scoring == ScoringMode::Survival {
                 // calculate points if not in single player
-                self.calculate_points(uuid_remove);
+                self.calculate_points(&death.id);
             }
-            self.active_players
-                .remove(uuid_remove)
-                .expect("Player to be removed was not found");
+            let removed = self.active_players.remove(&death.id);
+            assert!(removed, "Player to be removed was not found");
         });
 
-        if !self.single_player {
-            if self.active_players.len() == 1 {
-                // we have a winner
+        if !self.single_player && self.active_players.len() == 1 {
+            // we have a winner
+            let uuid = *self.active_players.iter().next().unwrap();
+            self.players.get_mut(&uuid).unwrap().round_wins += 1;
+            if self.scoring == ScoringMode::Survival {
                 println!("Calculate points of winner");
-                let uuid = *self.active_players.keys().next().unwrap();
                 self.calculate_points(&uuid);
             }
         }
+        if self.items_enabled {
+            self.tick_items(dt);
+        }
+        deaths
+    }
+
+    /// Spawns new items and hands out the ones players drove over.
+    fn tick_items(&mut self, dt: f64) {
+        self.item_timer -= dt;
+        if self.item_timer <= 0. {
+            self.item_timer = ITEM_SPAWN_SECONDS;
+            if self.items.len() < MAX_ITEMS {
+                let margin = self.wall_margin + ITEM_MARGIN;
+                let rng = &mut self.rng;
+                let item = Item {
+                    id: self.next_item_id,
+                    kind: ItemKind::ALL[rng.gen_range(0..ItemKind::ALL.len())],
+                    x: rng.gen_range(margin..(self.width as f64 - margin).max(margin + 1.)),
+                    y: rng.gen_range(margin..(self.height as f64 - margin).max(margin + 1.)),
+                };
+                self.next_item_id = self.next_item_id.wrapping_add(1);
+                self.items.push(item);
+                self.item_events.push(ItemEvent::Spawned(item));
+            }
+        }
+
+        let mut collected = vec![];
+        for id in &self.active_players {
+            let player = &self.players[id];
+            if self.item_inventory && player.item.is_some() {
+                // the slot is taken, the item stays on the field
+                continue;
+            }
+            let reach = ITEM_RADIUS + player.line_width as f64 / 2.;
+            let position = self
+                .items
+                .iter()
+                .position(|item| libm::hypot(item.x - player.x, item.y - player.y) < reach);
+            if let Some(i) = position {
+                collected.push((*id, self.items.remove(i)));
+            }
+        }
+        for (id, item) in collected {
+            self.item_events.push(ItemEvent::Collected(item.id, id));
+            if self.item_inventory {
+                self.players.get_mut(&id).unwrap().item = Some(item.kind);
+            } else {
+                self.activate_item(&id, item.kind);
+            }
+        }
+    }
+
+    fn activate_item(&mut self, id: &Uuid, kind: ItemKind) {
+        let player = &self.players[id];
+        match kind {
+            ItemKind::Eraser => {
+                self.grid.clear_circle(player.x, player.y, ERASER_RADIUS);
+                self.item_events
+                    .push(ItemEvent::AreaCleared((player.x, player.y), ERASER_RADIUS));
+            }
+            ItemKind::Confusion => {
+                for other in self.active_players.iter().filter(|other| *other != id) {
+                    self.players.get_mut(other).unwrap().confuse();
+                }
+            }
+        }
+    }
+
+    /// Starts a new match, all players start over without points and kills.
+    pub fn reset_points(&mut self) {
+        self.players.values_mut().for_each(|player| {
+            player.points = 0;
+            player.distance = 0.;
+            player.kills = 0;
+            player.round_wins = 0;
+        });
+    }
+
+    /// Item events since the last call
+    pub fn take_item_events(&mut self) -> Vec<ItemEvent> {
+        std::mem::take(&mut self.item_events)
+    }
+
+    /// Tick during the countdown before a round: positions stay frozen, only headings change.
+    pub fn tick_frozen(&mut self, dt: f64) {
+        let players = &mut self.players;
+        self.active_players
+            .iter()
+            .for_each(|id| players.get_mut(id).unwrap().tick_frozen(dt));
+    }
+
+    /// Stops new gaps from opening and starts moving the walls inwards.
+    pub fn start_sudden_death(&mut self) {
+        self.sudden_death = true;
+        let players = &mut self.players;
+        self.active_players
+            .iter()
+            .for_each(|id| players.get_mut(id).unwrap().gaps_enabled = false);
+    }
+
+    /// Changes speed and turning of all players, takes effect with the next tick.
+    pub fn set_handling(&mut self, speed: f64, turn_rate: f64) {
+        self.speed = speed;
+        self.turn_rate = turn_rate;
+        self.players
+            .values_mut()
+            .for_each(|player| player.set_handling(speed, turn_rate));
     }
 
-    pub fn remove_player(&mut self, uuid: &Uuid) {
+    pub fn set_ghost(&mut self, ghost: Option<GhostConfig>) {
+        self.ghost = ghost;
+        self.players
+            .values_mut()
+            .for_each(|player| player.set_ghost(ghost));
+    }
+
+    /// Changes when players open gaps, takes effect with the next round.
+    pub fn set_gaps(&mut self, gaps: GapConfig) {
+        self.gaps = gaps;
+        self.players
+            .values_mut()
+            .for_each(|player| player.set_gaps(gaps));
+    }
+
+    /// Re-creates the grid with new dimensions, only valid between rounds.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.grid = Grid::new(width, height);
+        self.players.values_mut().for_each(|player| {
+            player.x_max = width as u32;
+            player.y_max = height as u32;
+        });
+        self.set_map(self.map);
+    }
+
+    pub fn trail_sync(&self) -> TrailSync {
+        let since = match self.trail_lifetime {
+            Some(n) => (self.ticks + 1).saturating_sub(n),
+            None => 0,
+        };
+        self.grid.trail_sync(since)
+    }
+
+    pub fn grid_info(&self) -> GridInfo {
+        GridInfo {
+            width: self.width as u32,
+            height: self.height as u32,
+            line_width: self.line_width,
+        }
+    }
+
+    /// Widens the trails of all players according to the `seconds` the round lasts. The grid is
+    /// written with the new width from the next move on, older trails keep theirs.
+    fn grow_trails(&mut self, seconds: f64) {
+        let growth = ((seconds / TRAIL_GROWTH_SECONDS) as u32).min(MAX_TRAIL_GROWTH);
+        if growth != self.growth {
+            self.growth = growth;
+            let line_width = self.trail_width();
+            self.players
+                .values_mut()
+                .for_each(|player| player.line_width = line_width);
+        }
+    }
+
+    /// Current line width of all players, including the growth of the round
+    pub fn trail_width(&self) -> u32 {
+        self.line_width + self.growth
+    }
+
+    pub fn wall_margin(&self) -> u32 {
+        self.wall_margin as u32
+    }
+
+    pub fn add_player(&mut self, player: Player) {
+        self.players.insert(player.uuid, player);
+    }
+
+    /// Removes a player from the game, returning its last state.
+    pub fn remove_player(&mut self, uuid: &Uuid) -> Option<Player> {
         self.active_players.remove(uuid);
-        self.players.remove(uuid);
+        self.participants.remove(uuid);
+        self.afk.remove(uuid);
+        self.players.remove(uuid)
     }
 
     fn calculate_points(&mut self, uuid: &Uuid) {
-        let len_total = self.players.len();
-        let mut player = self.players.get_mut(uuid).unwrap().lock().unwrap();
+        let len_total = self.participants.len();
+        let player = self.players.get_mut(uuid).unwrap();
         player.points += 2_usize.pow((len_total - self.active_players.len()).try_into().unwrap());
     }
 
@@ -385,24 +1856,530 @@ impl Game {
     pub fn get_winner(&self) -> Option<Uuid> {
         if !self.running() {
             if self.single_player {
-                Some(*self.players.iter().next().unwrap().0)
+                self.participants.iter().next().copied()
             } else {
-                Some(*self.active_players.iter().next().unwrap().0)
+                Some(*self.active_players.iter().next().unwrap())
             }
         } else {
             None
         }
     }
 
+    /// Marks a player as inactive, which keeps them out of the following rounds.
+    pub fn set_afk(&mut self, id: &Uuid, afk: bool) {
+        if afk {
+            self.afk.insert(*id);
+        } else {
+            self.afk.remove(id);
+        }
+    }
+
+    pub fn is_alive(&self, id: &Uuid) -> bool {
+        self.active_players.contains(id)
+    }
+
+    /// Whether a point lies within the walls and on neither a trail nor a hazard.
+    pub fn is_free(&self, (x, y): (f64, f64)) -> bool {
+        let margin = self.wall_margin;
+        if x < margin || y < margin {
+            return false;
+        }
+        let (x, y) = (x as usize, y as usize);
+        if x as f64 >= self.width as f64 - margin || y as f64 >= self.height as f64 - margin {
+            return false;
+        }
+        let trail = self.grid.get(x, y).filter(|&(_, cell_tick)| {
+            self.trail_lifetime
+                .is_none_or(|n| self.ticks - cell_tick < n)
+        });
+        trail.is_none() && !self.grid.is_hazard(x, y)
+    }
+
+    /// Where a bot steers `id`: the way it gets farthest on before hitting anything, straight on
+    /// unless turning gets it farther.
+    pub fn bot_direction(&self, id: &Uuid) -> Direction {
+        let player = match self.players.get(id) {
+            Some(player) => player,
+            None => return Direction::Unchanged,
+        };
+        let step = BOT_LOOKAHEAD_SECONDS / BOT_SAMPLES as f64;
+        let distance = player.current_speed() * step;
+        let reach = |turn: f64| {
+            let (mut x, mut y, mut rotation) = (player.x, player.y, player.rotation);
+            let mut travelled = 0.;
+            for sample in 0..BOT_SAMPLES {
+                rotation += turn * step;
+                x += libm::sin(rotation.to_radians()) * distance;
+                y += libm::cos(rotation.to_radians()) * distance;
+                travelled += distance;
+                // the own head is no obstacle
+                if travelled > player.line_width as f64 && !self.is_free((x, y)) {
+                    return sample;
+                }
+            }
+            BOT_SAMPLES
+        };
+        let straight = reach(0.);
+        let left = reach(player.turn_rate);
+        let right = reach(-player.turn_rate);
+        if straight >= left && straight >= right {
+            Direction::Unchanged
+        } else if left >= right {
+            Direction::Left
+        } else {
+            Direction::Right
+        }
+    }
+
     pub fn on_move(&mut self, id: &Uuid, direction: Direction) -> Result<(), String> {
-        self.active_players
+        if !self.active_players.contains(id) {
+            return Err(format!("There is no player with uuid: {}", id));
+        }
+        self.players
             .get_mut(id)
-            .ok_or_else(|| format!("There is no player with uuid: {}", id))?
-            .lock()
             .unwrap()
             .change_direction(direction);
         Ok(())
     }
+
+    pub fn on_speed(&mut self, id: &Uuid, change: SpeedChange) -> Result<(), String> {
+        if !self.active_players.contains(id) {
+            return Err(format!("There is no player with uuid: {}", id));
+        }
+        self.players.get_mut(id).unwrap().change_speed(change);
+        Ok(())
+    }
+
+    pub fn on_input(&mut self, id: &Uuid, input: Input) -> Result<(), String> {
+        match input {
+            Input::Move(direction) => self.on_move(id, direction),
+            Input::Speed(change) => self.on_speed(id, change),
+            Input::UseGhost => self.on_use_ghost(id),
+            Input::UseItem => self.on_use_item(id),
+        }
+    }
+
+    /// Activates the item in the inventory of the player, if there is one.
+    pub fn on_use_item(&mut self, id: &Uuid) -> Result<(), String> {
+        if !self.active_players.contains(id) {
+            return Err(format!("There is no player with uuid: {}", id));
+        }
+        if let Some(kind) = self.players.get_mut(id).unwrap().item.take() {
+            self.activate_item(id, kind);
+        }
+        Ok(())
+    }
+
+    pub fn on_use_ghost(&mut self, id: &Uuid) -> Result<(), String> {
+        if !self.active_players.contains(id) {
+            return Err(format!("There is no player with uuid: {}", id));
+        }
+        self.players.get_mut(id).unwrap().use_ghost();
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SpeedPreset {
+    Slow,
+    Normal,
+    Fast,
+    Insane,
+}
+
+impl SpeedPreset {
+    pub const ALL: [SpeedPreset; 4] = [
+        SpeedPreset::Slow,
+        SpeedPreset::Normal,
+        SpeedPreset::Fast,
+        SpeedPreset::Insane,
+    ];
+
+    /// Pixels per second
+    pub fn speed(&self) -> f64 {
+        match self {
+            SpeedPreset::Slow => 80.,
+            SpeedPreset::Normal => 120.,
+            SpeedPreset::Fast => 240.,
+            SpeedPreset::Insane => 240.,
+        }
+    }
+
+    /// Degrees per second
+    pub fn turn_rate(&self) -> f64 {
+        match self {
+            SpeedPreset::Slow => 80.,
+            SpeedPreset::Normal => 160.,
+            SpeedPreset::Fast => 320.,
+            SpeedPreset::Insane => 480.,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedPreset::Slow => "Slow",
+            SpeedPreset::Normal => "Normal",
+            SpeedPreset::Fast => "Fast",
+            SpeedPreset::Insane => "Insane",
+        }
+    }
+}
+
+/// How the points of a round are given out
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ScoringMode {
+    Survival, // by the order of elimination, the last one standing gets the most
+    Distance, // one point per pixel travelled, accruing while alive
+}
+
+impl ScoringMode {
+    pub const ALL: [ScoringMode; 2] = [ScoringMode::Survival, ScoringMode::Distance];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScoringMode::Survival => "Survival",
+            ScoringMode::Distance => "Distance",
+        }
+    }
+}
+
+/// Colors of the arena and the trails, picked by the host for everyone in the room
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Theme {
+    Slate,
+    Midnight,
+    Paper,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Slate, Theme::Midnight, Theme::Paper];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Slate => "Slate",
+            Theme::Midnight => "Midnight",
+            Theme::Paper => "Paper",
+        }
+    }
+
+    /// The arena
+    pub fn background(&self) -> &'static str {
+        match self {
+            Theme::Slate => "#263238",
+            Theme::Midnight => "#0D1117",
+            Theme::Paper => "#FAFAFA",
+        }
+    }
+
+    /// Around the arena when the camera follows a player
+    pub fn outside(&self) -> &'static str {
+        match self {
+            Theme::Slate => "#212121",
+            Theme::Midnight => "#010409",
+            Theme::Paper => "#E0E0E0",
+        }
+    }
+
+    /// Walls of a shrinking arena and the borders of the UI
+    pub fn accent(&self) -> &'static str {
+        match self {
+            Theme::Slate => "#37474F",
+            Theme::Midnight => "#30363D",
+            Theme::Paper => "#90A4AE",
+        }
+    }
+
+    /// Speed zones on the arena, under the trails
+    pub fn zone(&self, speeds_up: bool) -> &'static str {
+        match (self, speeds_up) {
+            (Theme::Slate, true) => "#2E5D4B",
+            (Theme::Slate, false) => "#4E3B36",
+            (Theme::Midnight, true) => "#12362A",
+            (Theme::Midnight, false) => "#35231F",
+            (Theme::Paper, true) => "#C8E6C9",
+            (Theme::Paper, false) => "#D7CCC8",
+        }
+    }
+
+    /// Crash marks and items, readable on the background
+    pub fn foreground(&self) -> &'static str {
+        match self {
+            Theme::Slate => "#CFD8DC",
+            Theme::Midnight => "#E6EDF3",
+            Theme::Paper => "#37474F",
+        }
+    }
+
+    /// Trail colors for a room of `players`, see `palette`
+    pub fn palette(&self, players: usize) -> Vec<palette::Color> {
+        let (saturation, lightness) = match self {
+            Theme::Slate => (0.75, 0.5),
+            Theme::Midnight => (0.9, 0.72),
+            Theme::Paper => (0.8, 0.32),
+        };
+        palette::generate(players, saturation, lightness)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RoomConfig {
+    pub width: usize,
+    pub height: usize,
+    pub line_width: u32,
+    pub preset: SpeedPreset,
+    pub round_time_limit: Option<u32>, // seconds until sudden death starts
+    pub max_players: usize,
+    pub target_score: Option<usize>,
+    pub best_of: Option<u32>, // rounds of a match, won by winning more than half of them
+    pub tick_rate: u32,       // simulation ticks per second
+    pub ghost: Option<GhostConfig>, // `None` disables the ghost ability
+    pub gaps: GapConfig,
+    pub trail_lifetime: Option<u32>, // ticks until a trail fades, `None` keeps all trails
+    pub items: bool,                 // powerups appear on the field
+    pub item_inventory: bool,        // collected powerups are kept until used instead of instant
+    pub auto_start: Option<usize>,   // players in the lobby that start a round on their own
+    pub equalize_latency: bool,      // inputs are delayed to the latency of the slowest player
+    pub theme: Theme,
+    pub hazards: bool,        // moving bars that kill on contact
+    pub growing_trails: bool, // trails get wider as the round goes on
+    pub scoring: ScoringMode,
+    pub map: MapPreset,
+    #[serde(default)]
+    pub hitbox: Hitbox,
+    #[serde(default)]
+    pub title: Option<String>, // shown instead of the room code, which is still needed to join
+}
+
+/// Ability to turn invisible at will
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct GhostConfig {
+    pub duration_ms: u32,
+    pub cooldown_ms: u32, // from activation to the next possible activation
+}
+
+/// When players open gaps in their trails, distances are in line widths
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GapConfig {
+    pub interval: u32,  // from the start of one gap to the start of the next
+    pub length: u32,    // of a gap on average
+    pub variation: u32, // percent a gap may be shorter or longer than `length`
+}
+
+impl Default for GapConfig {
+    fn default() -> Self {
+        Self {
+            interval: 100,
+            length: 3,
+            variation: 0,
+        }
+    }
+}
+
+pub const GRID_SIZE_LIMITS: (usize, usize) = (200, 2000);
+pub const TICK_RATE_LIMITS: (u32, u32) = (10, 120);
+pub const MAX_PLAYERS: usize = 7; // one per trail pattern
+pub const GHOST_DURATION_LIMITS: (u32, u32) = (100, 5000);
+pub const TRAIL_LIFETIME_LIMITS: (u32, u32) = (10, 10000);
+pub const GAP_INTERVAL_LIMITS: (u32, u32) = (20, 500);
+pub const GAP_LENGTH_LIMITS: (u32, u32) = (1, 20);
+pub const MAX_GAP_VARIATION: u32 = 90;
+pub const HITBOX_SIZE_LIMITS: (u32, u32) = (0, 10);
+pub const MAX_ROOM_TITLE: usize = 30; // characters
+
+impl RoomConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = GRID_SIZE_LIMITS;
+        if !(min..=max).contains(&self.width) || !(min..=max).contains(&self.height) {
+            return Err(format!("Grid size must be between {} and {}", min, max));
+        }
+        if !(1..=MAX_PLAYERS).contains(&self.max_players) {
+            return Err(format!("Max players must be between 1 and {}", MAX_PLAYERS));
+        }
+        if self.target_score == Some(0) {
+            return Err("Target score must be positive".into());
+        }
+        if self.best_of == Some(0) {
+            return Err("Best of must be at least one round".into());
+        }
+        let (min, max) = TICK_RATE_LIMITS;
+        if !(min..=max).contains(&self.tick_rate) {
+            return Err(format!("Tick rate must be between {} and {}", min, max));
+        }
+        if let Some(ghost) = self.ghost {
+            let (min, max) = GHOST_DURATION_LIMITS;
+            if !(min..=max).contains(&ghost.duration_ms) {
+                return Err(format!(
+                    "Ghost duration must be between {} and {} ms",
+                    min, max
+                ));
+            }
+            if ghost.cooldown_ms < ghost.duration_ms {
+                return Err("Ghost cooldown must not be shorter than its duration".into());
+            }
+        }
+        let (min, max) = GAP_INTERVAL_LIMITS;
+        if !(min..=max).contains(&self.gaps.interval) {
+            return Err(format!("Gap interval must be between {} and {}", min, max));
+        }
+        let (min, max) = GAP_LENGTH_LIMITS;
+        if !(min..=max).contains(&self.gaps.length) {
+            return Err(format!("Gap length must be between {} and {}", min, max));
+        }
+        if self.gaps.length >= self.gaps.interval {
+            return Err("Gaps must be shorter than their interval".into());
+        }
+        if self.gaps.variation > MAX_GAP_VARIATION {
+            return Err(format!(
+                "Gap variation must be at most {}%",
+                MAX_GAP_VARIATION
+            ));
+        }
+        if let Hitbox::Square(size) | Hitbox::Circle(size) = self.hitbox {
+            let (min, max) = HITBOX_SIZE_LIMITS;
+            if !(min..=max).contains(&size) {
+                return Err(format!("Hitbox size must be between {} and {}", min, max));
+            }
+        }
+        if self
+            .auto_start
+            .is_some_and(|players| !(1..=self.max_players).contains(&players))
+        {
+            return Err("Auto start needs between 1 and max players".into());
+        }
+        if let Some(title) = &self.title {
+            if title.trim().is_empty() || title.chars().count() > MAX_ROOM_TITLE {
+                return Err(format!(
+                    "Room names must have between 1 and {} characters",
+                    MAX_ROOM_TITLE
+                ));
+            }
+            if title.contains('<') || title.contains('>') {
+                return Err("Room names must not contain `<` or `>`".into());
+            }
+        }
+        if let Some(lifetime) = self.trail_lifetime {
+            let (min, max) = TRAIL_LIFETIME_LIMITS;
+            if !(min..=max).contains(&lifetime) {
+                return Err(format!(
+                    "Trail lifetime must be between {} and {} ticks",
+                    min, max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        Self {
+            width: 1000,
+            height: 800,
+            line_width: 6,
+            preset: SpeedPreset::Normal,
+            round_time_limit: Some(90),
+            max_players: MAX_PLAYERS,
+            target_score: None,
+            best_of: None,
+            tick_rate: 40,
+            ghost: Some(GhostConfig {
+                duration_ms: 1000,
+                cooldown_ms: 8000,
+            }),
+            gaps: GapConfig::default(),
+            trail_lifetime: None,
+            items: true,
+            item_inventory: false,
+            auto_start: None,
+            equalize_latency: false,
+            theme: Theme::Slate,
+            hazards: false,
+            growing_trails: false,
+            scoring: ScoringMode::Survival,
+            map: MapPreset::Open,
+            hitbox: Hitbox::Band,
+            title: None,
+        }
+    }
+}
+
+/// Longer chat messages are cut off
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatMessage {
+    pub sender: Uuid,
+    pub name: String, // of the sender, who might have left the room since
+    pub text: String,
+}
+
+/// Something that happened in a room, for the event log next to the chat.
+///
+/// Players are named instead of referred to by uuid, so the log of a late joiner still reads
+/// right when some of them have left since.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum RoomEvent {
+    PlayerJoined(String),
+    PlayerLeft(String),
+    HostChanged(String),                // the new host
+    RoundStarted(u32),                  // number of the round in the match
+    PlayerDied(String, Option<String>), // player, owner of the trail it crashed into
+    RoundEnded(String),                 // winner
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum EmoteId {
+    ThumbsUp,
+    Laugh,
+    Wow,
+    Angry,
+    GoodGame,
+}
+
+impl EmoteId {
+    pub const ALL: [EmoteId; 5] = [
+        EmoteId::ThumbsUp,
+        EmoteId::Laugh,
+        EmoteId::Wow,
+        EmoteId::Angry,
+        EmoteId::GoodGame,
+    ];
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            EmoteId::ThumbsUp => "\u{1F44D}",
+            EmoteId::Laugh => "\u{1F602}",
+            EmoteId::Wow => "\u{1F62E}",
+            EmoteId::Angry => "\u{1F620}",
+            EmoteId::GoodGame => "GG",
+        }
+    }
+}
+
+/// A registered player, kept by the server across sessions
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Account {
+    pub name: String,
+    pub color: Option<ArrayString<7>>, // preferred color, taken when a room still has it
+    pub stats: AccountStats,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AccountStats {
+    pub rounds_played: u32,
+    pub rounds_won: u32,
+    pub rating: u32, // Elo rating, updated after every match
+}
+
+impl Default for AccountStats {
+    fn default() -> Self {
+        Self {
+            rounds_played: 0,
+            rounds_won: 0,
+            rating: 1200,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -416,9 +2393,27 @@ pub struct GridInfo {
 pub enum ClientMessage {
     CreateRoom(String),
     JoinRoom(String, String),
+    ResumeSession(String, Uuid), // room name, session token
     StartGame,
+    PauseGame,                // only accepted from the host during a round
+    ResumeGame,               // only accepted from the host during a round
+    UpdateConfig(RoomConfig), // only accepted from the host between rounds
+    CancelAutoStart,          // only accepted from the host
+    Pong(u32),                // answers `Ping` with its sequence number
     Disconnected,
+    LeaveRoom, // back to the lobby, the connection stays open for another room
     Move(Direction),
+    Speed(SpeedChange),
+    UseGhost,
+    UseItem,           // activates the item in the inventory
+    Input(u32, Input), // numbered by the client from 1, acknowledged in `PlayerState::input_ack`
+    Emote(EmoteId),
+    Chat(String),
+    RequestFullSync,   // sent after missing `GameState`s
+    Register(String),  // account name, answered by `AuthResult`
+    Login(Uuid),       // account token, answered by `AuthResult`
+    Spectate(String),  // room name, joins without playing
+    QuickPlay(String), // player name, joins the open room rated closest to the player
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -428,11 +2423,220 @@ pub enum ServerMessage {
         room_name: String,
         grid_info: GridInfo,
         players: Vec<Player>,
-        uuid: Uuid,
+        uuid: Uuid,  // nil for spectators
+        token: Uuid, // secret to resume the session after a lost connection
+        config: RoomConfig,
     },
+    SpectatorsChanged(u32), // number of spectators in the room
+    ItemSpawned(Item),
+    ItemCollected(u32, Uuid),            // item id, player
+    AreaCleared((f64, f64), f64),        // center, radius
+    MatchSummary(Vec<Vec<RoundResult>>), // results of every round, points start over afterwards
+    GameOver(Uuid),                      // winner of the match, sent right before `MatchSummary`
+    SessionStats(Vec<SessionStats>),     // after every match, the most successful player first
     NewPlayer(Player),
+    PlayerColors(Vec<(Uuid, ArrayString<7>, TrailPattern)>), // after the palette changed with the config
+    ConfigChanged(RoomConfig),
+    ConfigRejected(String),
+    GridChanged(GridInfo),
+    ServerShutdown(u32, bool), // seconds until the connection is closed, whether sessions survive
+    Announcement(String),
+    Emote(Uuid, EmoteId), // sender, emote
+    Chat(ChatMessage),
+    ChatHistory(Vec<ChatMessage>), // sent on joining, the latest messages of the room
+    RoomEvent(RoomEvent),          // on joining the latest events of the room are sent as well
+    PlayerDied(Death),
     PlayerDisconnected(Uuid, Uuid),
-    RoundStarted,
-    RoundEnded((Uuid, Vec<(Uuid, usize)>)),
-    GameState(Vec<PlayerState>),
+    Countdown(u8),
+    RoundStarted(u64), // seed of the round, the same inputs with it give the same round
+    MatchRound(u32),   // number of the current or next round in the match, starting at 1
+    PlayerTeleported(Teleport),
+    RoundPaused,
+    RoundResumed,
+    SuddenDeath,
+    ArenaShrunk(u32),
+    TrailsGrew(u32),           // new line width of all players, with growing trails
+    HazardsMoved(Vec<Hazard>), // all hazards of the round in their current position
+    PlayerQueued(Uuid),        // joined during a round, plays from the next one on
+    PlayerAfk(Uuid, bool),     // player became inactive or is back
+    AfkWarning(u32),           // seconds until the inactive receiver is removed from the room
+    RoundEnding(Uuid), // last player standing, `RoundEnded` follows after the slow motion replay
+    RoundEnded((Uuid, Vec<(Uuid, usize, usize)>)), // winner, points and kills of every player
+    RoomSnapshot(RoomSnapshot), // sent right after `JoinSuccess`
+    GameState(u32, Vec<PlayerState>), // sequence number, increasing by one per state
+    FullTrailSync(u32, TrailSync), // sequence number of the latest state
+    AuthResult(Result<(Uuid, Account), String>), // account token and account, or the reason
+    RatingsChanged(Vec<(Uuid, u32)>), // new ratings after a match
+    ReplaySaved(String), // short id of the replay of the finished round
+    GhostRecorded(GhostRun), // to the winner of the round, who may drive against it later
+    AutoStartCountdown(Option<u32>), // seconds until the round starts by itself, `None` if cancelled
+    Ping(u32),                       // sequence number, answered by `Pong` to measure the latency
+    TournamentState(TournamentState),
+    TrafficStats(TrafficStats), // to the host only, so it sees when the room strains the server
+}
+
+/// What a room sent to its clients, told to the host every few seconds
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TrafficStats {
+    pub bytes_per_second: u64,
+    pub messages_per_second: u64,
+    pub total_bytes: u64, // since the room was opened
+    pub total_messages: u64,
+    pub connections: u32, // players and spectators
+}
+
+/// Everything a client entering a room needs to catch up with it, the same whether it joins,
+/// resumes a session or watches.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RoomSnapshot {
+    pub players: Vec<Player>, // along with their points
+    pub config: RoomConfig,
+    pub round: u32, // number of the current or next round in the match, starting at 1
+    pub spectators: u32,
+    pub afk: Vec<Uuid>,
+    pub tournament: Option<TournamentState>,
+    pub session: Vec<SessionStats>, // of the matches finished in the room
+    pub trails: Option<(u32, TrailSync)>, // during a round, with the number of the latest state
+}
+
+/// Everything spectators got to see of a round, played back by the client
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Replay {
+    pub grid_info: GridInfo,
+    pub players: Vec<Player>,
+    pub config: RoomConfig,
+    pub messages: Vec<(u32, ServerMessage)>, // with the tick they were sent in
+    pub seed: u64,
+    pub afk: Vec<Uuid>,
+    pub countdown: u32, // ticks before the round, players may turn but not move
+    pub inputs: Vec<(u32, Uuid, Input)>, // applied right before the tick they are listed with
+    pub ticks: u32,     // played after the countdown until the round was decided
+}
+
+impl Replay {
+    /// Positions of all players after every tick of the round, ordered by id, played again from
+    /// the seed and the inputs.
+    pub fn simulate(&self) -> Vec<Vec<(Uuid, f64, f64)>> {
+        let mut positions = vec![];
+        play_round(
+            round_game(&self.config, &self.players, &self.afk),
+            &self.config,
+            self.seed,
+            self.countdown,
+            &self.inputs,
+            self.ticks,
+            |game, tick| {
+                if tick > self.countdown {
+                    let mut after: Vec<_> = game
+                        .players
+                        .iter()
+                        .map(|(id, player)| (*id, player.x, player.y))
+                        .collect();
+                    after.sort_by_key(|(id, _, _)| *id);
+                    positions.push(after);
+                }
+            },
+        );
+        positions
+    }
+}
+
+/// Game of a round before it was initialized, with everybody who was in the room
+fn round_game(config: &RoomConfig, players: &[Player], afk: &[Uuid]) -> Game {
+    let mut game = Game::from_config(config);
+    for player in players {
+        game.add_player(*player);
+    }
+    for id in afk {
+        game.set_afk(id, true);
+    }
+    game
+}
+
+/// Plays a round again like the server did, the countdown included. `on_tick` sees the game
+/// when the countdown ended and after every tick of the round, along with the ticks played.
+fn play_round(
+    mut game: Game,
+    config: &RoomConfig,
+    seed: u64,
+    countdown: u32,
+    inputs: &[(u32, Uuid, Input)],
+    ticks: u32,
+    mut on_tick: impl FnMut(&Game, u32),
+) {
+    game.seed(seed);
+    game.initialize();
+    let dt = 1. / config.tick_rate as f64;
+    let sudden_death = config
+        .round_time_limit
+        .map(|seconds| countdown + seconds * config.tick_rate);
+    for tick in 0..countdown + ticks {
+        for (_, id, input) in inputs.iter().filter(|(t, _, _)| *t == tick) {
+            // inputs of dead players are ignored like on the server
+            let _ = game.on_input(id, *input);
+        }
+        if tick < countdown {
+            game.tick_frozen(dt);
+            continue;
+        }
+        if tick == countdown {
+            on_tick(&game, tick);
+        }
+        // the server counts the round ticks from one
+        if sudden_death == Some(tick + 1) {
+            game.start_sudden_death();
+        }
+        game.tick(dt);
+        on_tick(&game, tick + 1);
+    }
+}
+
+/// The winner of a round, kept to drive against in later rounds. Everybody's inputs are part of
+/// it, the trails of the others decided where the winner could go.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GhostRun {
+    pub runner: Uuid, // the winner
+    pub config: RoomConfig,
+    pub players: Vec<Player>, // all players of the room when the round started
+    pub afk: Vec<Uuid>,
+    pub seed: u64,
+    pub countdown: u32, // ticks before the round, players may turn but not move
+    pub inputs: Vec<(u32, Uuid, Input)>, // applied right before the tick they are listed with
+    pub ticks: u32,     // played after the countdown until the round was decided
+}
+
+impl GhostRun {
+    /// Positions of the runner when the countdown ended and after every tick of the round,
+    /// played again from the seed and the inputs.
+    pub fn trace(&self) -> Vec<(f64, f64)> {
+        let mut trace = vec![];
+        play_round(
+            round_game(&self.config, &self.players, &self.afk),
+            &self.config,
+            self.seed,
+            self.countdown,
+            &self.inputs,
+            self.ticks,
+            |game, _| {
+                let player = &game.players[&self.runner];
+                trace.push((player.x, player.y));
+            },
+        );
+        trace
+    }
+}
+
+/// Bracket of a single elimination tournament over several rooms
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TournamentState {
+    pub name: String,
+    pub group: usize,                   // players per match after the first round
+    pub rounds: Vec<Vec<BracketMatch>>, // the last one is being played
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BracketMatch {
+    pub room: String,
+    pub players: Vec<String>,
+    pub winner: Option<String>,
 }
@@ -0,0 +1,57 @@
+//! Trail colors, spread evenly around the color wheel for the size of the room.
+//!
+//! A palette only depends on the theme and `RoomConfig::max_players`, so the server and every
+//! client get the same one. Players take the first free color in the order they join, the server
+//! sends `ServerMessage::PlayerColors` when a config change moves them to another palette.
+
+use arrayvec::ArrayString;
+
+pub type Color = ArrayString<7>;
+
+/// Hue of the first color in degrees, an orange that reads well on all backgrounds
+const FIRST_HUE: f64 = 25.;
+/// Difference in lightness between neighbours once hues get close to each other
+const LIGHTNESS_STEP: f64 = 0.1;
+/// From this many colors on neighbours alternate in lightness
+const ALTERNATE_FROM: usize = 5;
+
+/// `players` colors with evenly spaced hues, `saturation` and `lightness` are between 0 and 1.
+pub fn generate(players: usize, saturation: f64, lightness: f64) -> Vec<Color> {
+    let players = players.max(1);
+    (0..players)
+        .map(|i| {
+            let hue = FIRST_HUE + 360. * i as f64 / players as f64;
+            let lightness = match players >= ALTERNATE_FROM && i % 2 == 1 {
+                true => lightness + LIGHTNESS_STEP * if lightness < 0.5 { 1. } else { -1. },
+                false => lightness,
+            };
+            hex(hsl_to_rgb(hue, saturation, lightness))
+        })
+        .collect()
+}
+
+/// The place of a color in a palette
+pub fn position(palette: &[Color], color: &Color) -> Option<usize> {
+    palette.iter().position(|c| c == color)
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> [u8; 3] {
+    let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+    let sector = hue.rem_euclid(360.) / 60.;
+    let x = chroma * (1. - (sector % 2. - 1.).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = lightness - chroma / 2.;
+    let channel = |c: f64| ((c + m) * 255.).round().clamp(0., 255.) as u8;
+    [channel(r), channel(g), channel(b)]
+}
+
+fn hex([r, g, b]: [u8; 3]) -> Color {
+    Color::from(&format!("#{:02X}{:02X}{:02X}", r, g, b)).unwrap()
+}
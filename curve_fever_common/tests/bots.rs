@@ -0,0 +1,75 @@
+//! Steering of the bots, see `Game::bot_direction`.
+
+use arrayvec::ArrayString;
+use uuid::Uuid;
+
+use curve_fever_common::{Direction, Game, Player, RoomConfig};
+
+const SEED: u64 = 3;
+
+/// A round of one bot, heading down from the middle of the arena
+fn game() -> (Game, Uuid) {
+    let config = RoomConfig::default();
+    let mut game = Game::from_config(&config);
+    let id = Uuid::from_u128(1);
+    let mut player = Player::new(
+        id,
+        "bot",
+        ArrayString::<7>::from("#FFFFFF").unwrap(),
+        config.width as u32,
+        config.height as u32,
+        config.line_width,
+        config.preset.turn_rate(),
+    );
+    player.set_handling(config.preset.speed(), config.preset.turn_rate());
+    game.add_player(player);
+    game.seed(SEED);
+    game.initialize();
+    let player = game.players.get_mut(&id).unwrap();
+    player.x = config.width as f64 / 2.;
+    player.y = config.height as f64 / 2.;
+    player.rotation = 0.;
+    (game, id)
+}
+
+/// Ticks played until the bot is out, steered by `steer`
+fn survived(steer: bool) -> u32 {
+    let (mut game, id) = game();
+    let dt = 1. / RoomConfig::default().tick_rate as f64;
+    let mut ticks = 0;
+    while game.is_alive(&id) && ticks < 10_000 {
+        if steer {
+            game.on_move(&id, game.bot_direction(&id)).unwrap();
+        }
+        game.tick(dt);
+        ticks += 1;
+    }
+    ticks
+}
+
+#[test]
+fn open_field_is_crossed_straight_on() {
+    let (game, id) = game();
+    assert_eq!(game.bot_direction(&id), Direction::Unchanged);
+}
+
+#[test]
+fn wall_ahead_is_avoided() {
+    let (mut game, id) = game();
+    let height = game.height as f64;
+    game.players.get_mut(&id).unwrap().y = height - 30.;
+    assert_ne!(game.bot_direction(&id), Direction::Unchanged);
+}
+
+#[test]
+fn walls_are_no_free_points() {
+    let (game, _) = game();
+    assert!(game.is_free((10., 10.)));
+    assert!(!game.is_free((-1., 10.)));
+    assert!(!game.is_free((10., game.height as f64)));
+}
+
+#[test]
+fn steering_bot_outlives_going_straight() {
+    assert!(survived(true) > 2 * survived(false));
+}
@@ -0,0 +1,51 @@
+//! Transitions of the gap cycle, see `GapState`.
+
+use curve_fever_common::GapState;
+
+const INTERVAL: f64 = 600.;
+const LENGTH: f64 = 18.;
+
+fn advance(state: GapState, distance: f64) -> GapState {
+    state.advance(distance, INTERVAL, LENGTH, true)
+}
+
+#[test]
+fn drawing_until_the_interval_is_travelled() {
+    let state = advance(GapState::start(0.), INTERVAL - 1.);
+    assert_eq!(state, GapState::Drawing(INTERVAL - 1.));
+    assert!(!state.is_gap());
+}
+
+#[test]
+fn gap_opens_after_the_interval() {
+    let state = advance(GapState::start(INTERVAL - 1.), 1.);
+    assert_eq!(state, GapState::Gap(0.));
+    assert!(state.is_gap());
+}
+
+#[test]
+fn gap_closes_after_its_length() {
+    let state = advance(GapState::Gap(0.), LENGTH - 1.);
+    assert_eq!(state, GapState::Gap(LENGTH - 1.));
+    let state = advance(state, 1.);
+    assert_eq!(state, GapState::Drawing(LENGTH));
+}
+
+#[test]
+fn disabled_gaps_start_a_new_cycle_without_gap() {
+    let state = GapState::start(INTERVAL - 1.).advance(1., INTERVAL, LENGTH, false);
+    assert_eq!(state, GapState::Drawing(0.));
+}
+
+#[test]
+fn open_gap_closes_after_gaps_are_disabled() {
+    let state = GapState::Gap(0.).advance(LENGTH, INTERVAL, LENGTH, false);
+    assert_eq!(state, GapState::Drawing(LENGTH));
+}
+
+#[test]
+fn progress_counts_towards_the_next_gap() {
+    assert_eq!(GapState::start(0.).progress(INTERVAL), 0);
+    assert_eq!(GapState::start(INTERVAL / 2.).progress(INTERVAL), 50);
+    assert_eq!(GapState::start(INTERVAL).progress(INTERVAL), 100);
+}
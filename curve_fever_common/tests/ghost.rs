@@ -0,0 +1,129 @@
+//! A `GhostRun` must drive its runner exactly where the round took it, see `GhostRun::trace`.
+
+use arrayvec::ArrayString;
+use uuid::Uuid;
+
+use curve_fever_common::{Direction, Game, GhostRun, Input, Player, RoomConfig, SpeedChange};
+
+const SEED: u64 = 7;
+const COUNTDOWN: u32 = 30;
+
+fn config() -> RoomConfig {
+    RoomConfig {
+        items: true,
+        hazards: true,
+        round_time_limit: Some(2),
+        ..RoomConfig::default()
+    }
+}
+
+fn players(n: u128) -> Vec<Player> {
+    let config = config();
+    (1..=n)
+        .map(|i| {
+            let mut player = Player::new(
+                Uuid::from_u128(i),
+                "test",
+                ArrayString::<7>::from("#FFFFFF").unwrap(),
+                config.width as u32,
+                config.height as u32,
+                config.line_width,
+                config.preset.turn_rate(),
+            );
+            player.set_handling(config.preset.speed(), config.preset.turn_rate());
+            player.set_gaps(config.gaps);
+            player
+        })
+        .collect()
+}
+
+/// Turning during the countdown, then steering and boosting until the runner is out
+fn inputs() -> Vec<(u32, Uuid, Input)> {
+    let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    vec![
+        (5, a, Input::Move(Direction::Left)),
+        (20, a, Input::Move(Direction::Unchanged)),
+        (40, b, Input::Speed(SpeedChange::Boost)),
+        (60, a, Input::Move(Direction::Right)),
+        (90, a, Input::Move(Direction::Unchanged)),
+        (120, b, Input::Move(Direction::Left)),
+    ]
+}
+
+/// Plays a round the way a room does and records the ghost of `runner` along with the positions
+/// it took, from the end of the countdown on.
+fn play(players: Vec<Player>, afk: Vec<Uuid>, runner: Uuid) -> (GhostRun, Vec<(f64, f64)>) {
+    let config = config();
+    let mut game = Game::from_config(&config);
+    players.iter().for_each(|player| game.add_player(*player));
+    afk.iter().for_each(|id| game.set_afk(id, true));
+    game.seed(SEED);
+    game.initialize();
+    let dt = 1. / config.tick_rate as f64;
+    let inputs = inputs();
+    let pos = |game: &Game| (game.players[&runner].x, game.players[&runner].y);
+    let mut positions = vec![];
+    let mut tick = 0;
+    loop {
+        for (_, id, input) in inputs.iter().filter(|(t, _, _)| *t == tick) {
+            let _ = game.on_input(id, *input);
+        }
+        if tick < COUNTDOWN {
+            game.tick_frozen(dt);
+        } else {
+            if tick == COUNTDOWN {
+                positions.push(pos(&game));
+            }
+            if tick + 1 == COUNTDOWN + config.round_time_limit.unwrap() * config.tick_rate {
+                game.start_sudden_death();
+            }
+            game.tick(dt);
+            positions.push(pos(&game));
+            if !game.is_alive(&runner) || !game.running() {
+                break;
+            }
+        }
+        tick += 1;
+    }
+    let ghost = GhostRun {
+        runner,
+        config,
+        players,
+        afk,
+        seed: SEED,
+        countdown: COUNTDOWN,
+        inputs,
+        ticks: tick + 1 - COUNTDOWN,
+    };
+    (ghost, positions)
+}
+
+#[test]
+fn trace_follows_the_runner() {
+    let (ghost, positions) = play(players(1), vec![], Uuid::from_u128(1));
+    assert!(ghost.ticks > 0);
+    assert_eq!(ghost.trace(), positions);
+}
+
+#[test]
+fn trace_includes_the_other_players() {
+    let (ghost, positions) = play(players(2), vec![], Uuid::from_u128(2));
+    assert_eq!(ghost.trace(), positions);
+}
+
+#[test]
+fn afk_players_keep_out_of_the_trace() {
+    let (ghost, positions) = play(players(3), vec![Uuid::from_u128(3)], Uuid::from_u128(1));
+    assert_eq!(ghost.trace(), positions);
+}
+
+#[test]
+fn turning_in_the_countdown_changes_the_trace() {
+    let (ghost, _) = play(players(1), vec![], Uuid::from_u128(1));
+    let still = GhostRun {
+        inputs: vec![],
+        ..ghost.clone()
+    };
+    assert_eq!(ghost.trace()[0], still.trace()[0], "nobody moves before");
+    assert_ne!(ghost.trace()[1], still.trace()[1]);
+}
@@ -0,0 +1,121 @@
+//! Collisions with the full line width and with the classic head hitboxes, see `Hitbox`.
+
+use arrayvec::ArrayString;
+use uuid::Uuid;
+
+use curve_fever_common::{
+    Death, DeathCause, Game, GapConfig, Hitbox, Player, RoomConfig, SpeedPreset, HITBOX_SIZE_LIMITS,
+};
+
+const WIDTH: usize = 1000;
+const HEIGHT: usize = 800;
+const SEED: u64 = 42;
+const TICKS: u32 = 40;
+
+/// Two players at their start positions with `hitbox`, the first one moves first.
+fn game(hitbox: Hitbox, first: (f64, f64, f64), second: (f64, f64, f64)) -> Game {
+    let config = RoomConfig::default();
+    let preset = SpeedPreset::Normal;
+    let mut game = Game::new(WIDTH, HEIGHT, config.line_width, preset.turn_rate());
+    for i in 1..=2 {
+        game.add_player(Player::new(
+            Uuid::from_u128(i),
+            "test",
+            ArrayString::<7>::from("#FFFFFF").unwrap(),
+            WIDTH as u32,
+            HEIGHT as u32,
+            config.line_width,
+            preset.turn_rate(),
+        ));
+    }
+    game.set_handling(preset.speed(), preset.turn_rate());
+    // no gap opens within the few ticks of a test
+    game.set_gaps(GapConfig {
+        interval: 500,
+        ..GapConfig::default()
+    });
+    game.hitbox = hitbox;
+    game.seed(SEED);
+    game.initialize();
+    for (i, (x, y, rotation)) in [first, second].iter().enumerate() {
+        let player = game
+            .players
+            .get_mut(&Uuid::from_u128(i as u128 + 1))
+            .unwrap();
+        player.x = *x;
+        player.y = *y;
+        player.rotation = *rotation;
+    }
+    game
+}
+
+fn deaths(mut game: Game) -> Vec<Death> {
+    let dt = 1. / RoomConfig::default().tick_rate as f64;
+    (0..TICKS).flat_map(|_| game.tick(dt)).collect()
+}
+
+/// The second player drives right next to the trail of the first, their lines overlapping by a
+/// pixel while their centers are three pixels apart.
+fn side_by_side(hitbox: Hitbox) -> Vec<Death> {
+    deaths(game(hitbox, (100.5, 400.5, 90.), (100.5, 403.5, 90.)))
+}
+
+/// Whether the second player died in the trail of the first
+fn hit_the_first(death: &Death) -> bool {
+    death.id == Uuid::from_u128(2)
+        && matches!(death.cause, DeathCause::Player(id) if id == Uuid::from_u128(1))
+}
+
+#[test]
+fn overlapping_lines_collide_with_the_band() {
+    let deaths = side_by_side(Hitbox::Band);
+    assert_eq!(deaths.len(), 1);
+    assert!(hit_the_first(&deaths[0]));
+}
+
+#[test]
+fn overlapping_lines_pass_with_a_small_head() {
+    assert!(side_by_side(Hitbox::Square(1)).is_empty());
+    assert!(side_by_side(Hitbox::Circle(1)).is_empty());
+    assert!(side_by_side(Hitbox::Square(0)).is_empty());
+}
+
+#[test]
+fn head_reaching_the_trail_collides() {
+    for hitbox in [Hitbox::Square(2), Hitbox::Circle(2)].iter() {
+        let deaths = side_by_side(*hitbox);
+        assert_eq!(deaths.len(), 1, "{:?}", hitbox);
+        assert!(hit_the_first(&deaths[0]));
+    }
+}
+
+#[test]
+fn crossing_a_trail_collides_with_every_hitbox() {
+    // the second player crosses the trail the first left a few ticks before
+    for hitbox in [Hitbox::Band, Hitbox::Square(0), Hitbox::Circle(1)].iter() {
+        let deaths = deaths(game(*hitbox, (100.5, 400.5, 90.), (130.5, 340.5, 0.)));
+        assert_eq!(deaths.len(), 1, "{:?}", hitbox);
+        assert!(hit_the_first(&deaths[0]));
+    }
+}
+
+#[test]
+fn square_reaches_further_into_the_corners() {
+    assert!(Hitbox::Square(2).covers(2, -2));
+    assert!(!Hitbox::Circle(2).covers(2, -2));
+    assert!(Hitbox::Circle(2).covers(0, -2));
+    assert!(!Hitbox::Circle(2).covers(0, 3));
+    assert!(Hitbox::Band.covers(3, 3));
+}
+
+#[test]
+fn hitbox_size_is_validated() {
+    let (_, max) = HITBOX_SIZE_LIMITS;
+    let config = |hitbox| RoomConfig {
+        hitbox,
+        ..RoomConfig::default()
+    };
+    assert!(config(Hitbox::Band).validate().is_ok());
+    assert!(config(Hitbox::Square(max)).validate().is_ok());
+    assert!(config(Hitbox::Circle(max + 1)).validate().is_err());
+}
@@ -0,0 +1,107 @@
+//! The simulation must give the same positions on the server and in the browser.
+//!
+//! Run natively with `cargo test` and in wasm with `wasm-pack test --node`. Both compare against
+//! the same recorded checksum.
+
+use arrayvec::ArrayString;
+use uuid::Uuid;
+
+use curve_fever_common::{Direction, Game, Input, Player, RoomConfig, SpeedChange, SpeedPreset};
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+const WIDTH: usize = 1000;
+const HEIGHT: usize = 800;
+const SEED: u64 = 42;
+const TICKS: u32 = 400;
+/// Checksum of the positions of `log()` from `SEED`, recorded natively. It only changes along with
+/// the simulation or the random number generator.
+const EXPECTED_CHECKSUM: u64 = 2550552877689866915;
+
+fn game(n_players: u128) -> Game {
+    let config = RoomConfig::default();
+    let preset = SpeedPreset::Normal;
+    let mut game = Game::new(WIDTH, HEIGHT, config.line_width, preset.turn_rate());
+    for i in 1..=n_players {
+        game.add_player(Player::new(
+            Uuid::from_u128(i),
+            "test",
+            ArrayString::<7>::from("#FFFFFF").unwrap(),
+            WIDTH as u32,
+            HEIGHT as u32,
+            config.line_width,
+            preset.turn_rate(),
+        ));
+    }
+    game.set_handling(preset.speed(), preset.turn_rate());
+    game
+}
+
+fn dt() -> f64 {
+    1. / RoomConfig::default().tick_rate as f64
+}
+
+/// Some turning, boosting and braking of three players
+fn log() -> Vec<(u32, Uuid, Input)> {
+    let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+    vec![
+        (10, a, Input::Move(Direction::Left)),
+        (12, b, Input::Speed(SpeedChange::Boost)),
+        (30, a, Input::Move(Direction::Unchanged)),
+        (40, c, Input::Move(Direction::Right)),
+        (45, b, Input::Speed(SpeedChange::Normal)),
+        (60, c, Input::UseGhost),
+        (90, c, Input::Move(Direction::Unchanged)),
+        (120, a, Input::Speed(SpeedChange::Brake)),
+        (150, a, Input::Speed(SpeedChange::Normal)),
+        (200, b, Input::Move(Direction::Left)),
+        (260, b, Input::Move(Direction::Right)),
+    ]
+}
+
+/// FNV-1a over the exact bits of all positions
+fn checksum(trace: &[Vec<(Uuid, f64, f64)>]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (id, x, y) in trace.iter().flatten() {
+        for bytes in &[
+            id.as_u128().to_le_bytes().to_vec(),
+            x.to_bits().to_le_bytes().to_vec(),
+            y.to_bits().to_le_bytes().to_vec(),
+        ] {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+    }
+    hash
+}
+
+#[test]
+fn same_seed_and_inputs_give_same_positions() {
+    let first = game(3).simulate(SEED, &log(), TICKS, dt());
+    let second = game(3).simulate(SEED, &log(), TICKS, dt());
+    assert_eq!(first, second);
+}
+
+#[test]
+fn seed_decides_the_start_positions() {
+    let first = game(3).simulate(SEED, &[], 1, dt());
+    let second = game(3).simulate(SEED + 1, &[], 1, dt());
+    assert_ne!(first, second);
+}
+
+#[test]
+fn inputs_change_the_path() {
+    let steered = game(3).simulate(SEED, &log(), TICKS, dt());
+    let straight = game(3).simulate(SEED, &[], TICKS, dt());
+    assert_eq!(steered[9], straight[9], "no input before tick 10");
+    assert_ne!(steered[10], straight[10]);
+}
+
+#[test]
+fn positions_match_the_recorded_trace() {
+    let trace = game(3).simulate(SEED, &log(), TICKS, dt());
+    assert_eq!(checksum(&trace), EXPECTED_CHECKSUM);
+}
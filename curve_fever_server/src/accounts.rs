@@ -0,0 +1,201 @@
+//! Optional player accounts, kept in a JSON file across server restarts.
+//!
+//! Registering hands out a random token which the client stores and sends with `Login`. The
+//! server only keeps the SHA-256 hash of each token, so the file does not contain any secrets.
+//!
+//! Changes are written every few seconds by `run` and once more when the server shuts down, the
+//! rooms only change the accounts in memory.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use smol::{Task, Timer};
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use curve_fever_common::Account;
+
+pub type AccountList = Arc<Mutex<Accounts>>;
+
+const MAX_NAME_LENGTH: usize = 20;
+/// Most rating points a player can win or lose in a single match
+const RATING_K: f64 = 32.;
+/// Changed accounts are written at most this often
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Identifies an account without revealing its token
+pub fn key(token: &Uuid) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub struct Accounts {
+    path: PathBuf,
+    accounts: HashMap<String, Account>, // by hashed token
+    changed: bool,                      // since the file was last written
+}
+
+impl Accounts {
+    /// Reads the accounts from `path`, a missing file is treated as empty.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let accounts = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        info!("Loaded {} accounts from {}", accounts.len(), path.display());
+        Ok(Self {
+            path,
+            accounts,
+            changed: false,
+        })
+    }
+
+    /// The file along with the accounts as JSON if they changed since the last call
+    fn changes(&mut self) -> Option<(PathBuf, String)> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        match serde_json::to_string(&self.accounts) {
+            Ok(text) => Some((self.path.clone(), text)),
+            Err(e) => {
+                error!("Failed to serialize the accounts: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn register(&mut self, name: &str) -> Result<(Uuid, Account), String> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "The name must have between 1 and {} characters",
+                MAX_NAME_LENGTH
+            ));
+        }
+        if self
+            .accounts
+            .values()
+            .any(|account| account.name.eq_ignore_ascii_case(name))
+        {
+            return Err(format!("The name `{}` is already taken", name));
+        }
+
+        let token = Uuid::new_v4();
+        let account = Account {
+            name: name.to_string(),
+            color: None,
+            stats: Default::default(),
+        };
+        info!("Registered account `{}`", name);
+        self.accounts.insert(key(&token), account.clone());
+        self.changed = true;
+        Ok((token, account))
+    }
+
+    pub fn login(&self, token: &Uuid) -> Option<Account> {
+        self.accounts.get(&key(token)).cloned()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    /// Changes the account with the hashed token `key`.
+    pub fn update(&mut self, key: &str, f: impl FnOnce(&mut Account)) {
+        if let Some(account) = self.accounts.get_mut(key) {
+            f(account);
+            self.changed = true;
+        }
+    }
+
+    /// Counts a finished round for the hashed tokens in `standings`, the winner first.
+    pub fn record_round(&mut self, standings: &[&str]) {
+        for (i, key) in standings.iter().enumerate() {
+            if let Some(account) = self.accounts.get_mut(*key) {
+                account.stats.rounds_played += 1;
+                if i == 0 {
+                    account.stats.rounds_won += 1;
+                }
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Updates the ratings of the hashed tokens in `standings` after a finished match.
+    ///
+    /// The standings are ordered from the winner of the match to the last placed player. Every
+    /// pair of players is rated like a single Elo game won by the one placed higher. Returns the
+    /// new ratings in the order of the standings.
+    pub fn record_match(&mut self, standings: &[&str]) -> Vec<u32> {
+        let old: Vec<f64> = standings
+            .iter()
+            .map(|key| {
+                self.accounts
+                    .get(*key)
+                    .map_or(0., |a| a.stats.rating as f64)
+            })
+            .collect();
+        let k = RATING_K / standings.len().saturating_sub(1).max(1) as f64;
+        let new: Vec<u32> = old
+            .iter()
+            .enumerate()
+            .map(|(i, rating)| {
+                let delta: f64 = old
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(j, other)| {
+                        let expected = 1. / (1. + 10f64.powf((other - rating) / 400.));
+                        let score = if i < j { 1. } else { 0. };
+                        k * (score - expected)
+                    })
+                    .sum();
+                (rating + delta).round().max(0.) as u32
+            })
+            .collect();
+
+        for (key, rating) in standings.iter().zip(&new) {
+            if let Some(account) = self.accounts.get_mut(*key) {
+                account.stats.rating = *rating;
+            }
+        }
+        self.changed = true;
+        new
+    }
+}
+
+fn write(path: &Path, text: &str) {
+    // write to a temporary file first, a crash must not leave a truncated file behind
+    let tmp = path.with_extension("tmp");
+    let result = fs::write(&tmp, text).and_then(|()| fs::rename(&tmp, path));
+    if let Err(e) = result {
+        error!("Failed to save accounts to {}: {}", path.display(), e);
+    }
+}
+
+/// Writes the accounts if they changed, without holding the lock while the file is written.
+pub fn flush(accounts: &AccountList) {
+    let changes = accounts.lock().unwrap().changes();
+    if let Some((path, text)) = changes {
+        write(&path, &text);
+    }
+}
+
+/// Saves the changed accounts periodically.
+pub async fn run(accounts: AccountList) {
+    loop {
+        Timer::after(SAVE_INTERVAL).await;
+        let accounts = accounts.clone();
+        Task::blocking(async move { flush(&accounts) }).await;
+    }
+}
@@ -0,0 +1,255 @@
+//! Admin interface on a separate port.
+//!
+//! Admins connect with a WebSocket, authenticate with an `Authorization: Bearer <token>` header
+//! and send `AdminRequest`s as JSON text messages, each answered by an `AdminResponse`.
+
+use anyhow::Result;
+use async_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+use futures::{sink::SinkExt, stream::StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use smol::{Async, Task};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use tracing::{error, info, info_span, warn, Instrument};
+use uuid::Uuid;
+
+use curve_fever_common::RoomConfig;
+
+use crate::{
+    bans::{BanList, Subnet},
+    tournament,
+    tournament::TournamentList,
+    DefaultConfig, RoomList,
+};
+
+#[derive(Debug, Deserialize)]
+pub enum AdminRequest {
+    ListRooms,
+    CloseRoom(String),
+    KickPlayer(String, Uuid), // room name, player uuid
+    Announce(String),         // shown to the players of all rooms
+    SetDefaultConfig(RoomConfig),
+    CreateTournament(String, Vec<String>, usize), // name, rooms of the first round, players per match
+    ListBans,
+    Ban(String),   // an address or a subnet like `203.0.113.0/24`, for new connections
+    Unban(String), // as banned
+}
+
+#[derive(Debug, Serialize)]
+pub enum AdminResponse {
+    Rooms(Vec<RoomInfo>),
+    Bans(Vec<String>),
+    Ok,
+    Error(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: Vec<PlayerInfo>,
+    pub config: RoomConfig,
+    pub round_in_progress: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayerInfo {
+    pub uuid: Uuid,
+    pub name: String,
+    pub host: bool,
+    pub points: usize,
+    pub dropped_moves: u32, // a high count hints at a modified client
+}
+
+fn on_request(
+    request: AdminRequest,
+    rooms: &RoomList,
+    default_config: &DefaultConfig,
+    tournaments: &TournamentList,
+    bans: &BanList,
+) -> AdminResponse {
+    info!("Admin request: {:?}", request);
+    match request {
+        AdminRequest::ListRooms => AdminResponse::Rooms(
+            rooms
+                .lock()
+                .unwrap()
+                .values()
+                .map(|handle| handle.room.lock().unwrap().info())
+                .collect(),
+        ),
+        AdminRequest::CloseRoom(name) => match rooms.lock().unwrap().remove(&name) {
+            Some(handle) => {
+                handle.with_room(|room| room.close());
+                AdminResponse::Ok
+            }
+            None => AdminResponse::Error(format!("Room `{}` does not exist", name)),
+        },
+        AdminRequest::KickPlayer(name, id) => match rooms.lock().unwrap().get(&name) {
+            Some(handle)
+                if handle.with_room(|room| room.kick(&id, "You were kicked from the room")) =>
+            {
+                AdminResponse::Ok
+            }
+            Some(_) => AdminResponse::Error(format!("There is no player with uuid: {}", id)),
+            None => AdminResponse::Error(format!("Room `{}` does not exist", name)),
+        },
+        AdminRequest::Announce(text) => {
+            rooms
+                .lock()
+                .unwrap()
+                .values()
+                .for_each(|handle| handle.with_room(|room| room.announce(&text)));
+            AdminResponse::Ok
+        }
+        AdminRequest::SetDefaultConfig(config) => match config.validate() {
+            Ok(()) => {
+                *default_config.lock().unwrap() = config;
+                AdminResponse::Ok
+            }
+            Err(e) => AdminResponse::Error(e),
+        },
+        AdminRequest::CreateTournament(name, room_names, group) => {
+            let mut tournaments = tournaments.lock().unwrap();
+            if tournaments.contains_key(&name) {
+                return AdminResponse::Error(format!("Tournament `{}` exists already", name));
+            }
+            if let Some(missing) = room_names
+                .iter()
+                .find(|room| !rooms.lock().unwrap().contains_key(*room))
+            {
+                return AdminResponse::Error(format!("Room `{}` does not exist", missing));
+            }
+            match tournament::create(name.clone(), room_names, group) {
+                Ok(state) => {
+                    tournament::publish(&state, rooms);
+                    tournaments.insert(name, state);
+                    AdminResponse::Ok
+                }
+                Err(e) => AdminResponse::Error(e),
+            }
+        }
+        AdminRequest::ListBans => AdminResponse::Bans(
+            bans.lock()
+                .unwrap()
+                .list()
+                .iter()
+                .map(Subnet::to_string)
+                .collect(),
+        ),
+        AdminRequest::Ban(subnet) => change_bans(bans, &subnet, true),
+        AdminRequest::Unban(subnet) => change_bans(bans, &subnet, false),
+    }
+}
+
+/// Bans `subnet` or lifts its ban.
+fn change_bans(bans: &BanList, subnet: &str, ban: bool) -> AdminResponse {
+    let subnet = match subnet.parse::<Subnet>() {
+        Ok(subnet) => subnet,
+        Err(e) => return AdminResponse::Error(e),
+    };
+    let mut bans = bans.lock().unwrap();
+    let changed = match ban {
+        true => bans.ban(subnet),
+        false => bans.unban(&subnet),
+    };
+    match changed {
+        Ok(true) => AdminResponse::Ok,
+        Ok(false) => AdminResponse::Error(format!("Nothing to change for {}", subnet)),
+        Err(e) => AdminResponse::Error(format!("Could not save the bans: {}", e)),
+    }
+}
+
+/// Compares the digests of both byte by byte without stopping at the first difference, so the
+/// time taken tells nothing about the token.
+fn same_token(given: &[u8], expected: &[u8]) -> bool {
+    let (given, expected) = (Sha256::digest(given), Sha256::digest(expected));
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+async fn serve(
+    stream: Async<TcpStream>,
+    token: String,
+    rooms: RoomList,
+    default_config: DefaultConfig,
+    tournaments: TournamentList,
+    bans: BanList,
+) -> Result<()> {
+    let expected = format!("Bearer {}", token);
+    // the error response type is given by tungstenite
+    #[allow(clippy::result_large_err)]
+    let authorize =
+        move |request: &Request, response: Response| match request.headers().get("Authorization") {
+            Some(value) if same_token(value.as_bytes(), expected.as_bytes()) => Ok(response),
+            _ => {
+                let mut denied = ErrorResponse::new(Some("Unauthorized".into()));
+                *denied.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(denied)
+            }
+        };
+    let mut ws = async_tungstenite::accept_hdr_async(stream, authorize).await?;
+    info!("Admin connected");
+
+    while let Some(msg) = ws.next().await {
+        let response = match msg? {
+            Message::Text(text) => match serde_json::from_str::<AdminRequest>(&text) {
+                Ok(request) => on_request(request, &rooms, &default_config, &tournaments, &bans),
+                Err(e) => AdminResponse::Error(format!("Invalid request: {}", e)),
+            },
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        ws.send(Message::Text(serde_json::to_string(&response)?))
+            .await?;
+    }
+    info!("Admin disconnected");
+    Ok(())
+}
+
+pub async fn run(
+    addr: SocketAddr,
+    token: String,
+    rooms: RoomList,
+    default_config: DefaultConfig,
+    tournaments: TournamentList,
+    bans: BanList,
+) {
+    // anybody could send an empty token
+    if token.trim().is_empty() {
+        error!("ADMIN_TOKEN is empty, the admin interface stays off");
+        return;
+    }
+    let listener = match Async::<TcpListener>::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not create admin listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Admin interface listening on: {}", addr);
+
+    while let Ok((stream, addr)) = listener.accept().await {
+        let token = token.clone();
+        let rooms = rooms.clone();
+        let default_config = default_config.clone();
+        let tournaments = tournaments.clone();
+        let bans = bans.clone();
+        Task::spawn(
+            async move {
+                if let Err(e) = serve(stream, token, rooms, default_config, tournaments, bans).await
+                {
+                    warn!("Admin connection failed: {}", e);
+                }
+            }
+            .instrument(info_span!("admin", %addr)),
+        )
+        .detach();
+    }
+}
@@ -0,0 +1,63 @@
+//! The files of the client served in single-port mode, see `http`.
+//!
+//! They are read from `CLIENT_DIR` or, with the `embed-client` feature, compiled into the server
+//! so it can be deployed as a single executable. Build the client before the server then:
+//!
+//! ```text
+//! (cd curve_fever_client && wasm-pack build --target web)
+//! (cd curve_fever_server && cargo build --release --features embed-client)
+//! ```
+//!
+//! `CLIENT_DIR` still wins over the embedded files, e.g. to try changes of the client without
+//! building the server again.
+
+use std::{borrow::Cow, fs, path::PathBuf};
+
+#[cfg(feature = "embed-client")]
+const EMBEDDED: &[(&str, &[u8])] = &[
+    (
+        "index.html",
+        include_bytes!("../../curve_fever_client/index.html"),
+    ),
+    (
+        "style.css",
+        include_bytes!("../../curve_fever_client/style.css"),
+    ),
+    (
+        "pkg/curve_fever_wasm.js",
+        include_bytes!("../../curve_fever_client/pkg/curve_fever_wasm.js"),
+    ),
+    (
+        "pkg/curve_fever_wasm_bg.wasm",
+        include_bytes!("../../curve_fever_client/pkg/curve_fever_wasm_bg.wasm"),
+    ),
+];
+#[cfg(not(feature = "embed-client"))]
+const EMBEDDED: &[(&str, &[u8])] = &[];
+
+pub enum ClientFiles {
+    Dir(PathBuf),
+    Embedded,
+}
+
+impl ClientFiles {
+    /// `None` if there is no client to serve, the server only accepts WebSockets then.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("CLIENT_DIR") {
+            Ok(dir) => Some(ClientFiles::Dir(dir.into())),
+            Err(_) if !EMBEDDED.is_empty() => Some(ClientFiles::Embedded),
+            Err(_) => None,
+        }
+    }
+
+    /// `file` is relative to the client directory and already checked to stay inside of it.
+    pub fn read(&self, file: &str) -> Option<Cow<'static, [u8]>> {
+        match self {
+            ClientFiles::Dir(dir) => fs::read(dir.join(file)).ok().map(Cow::Owned),
+            ClientFiles::Embedded => EMBEDDED
+                .iter()
+                .find(|(name, _)| *name == file)
+                .map(|(_, bytes)| Cow::Borrowed(*bytes)),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Banned addresses and a limit on the connections per address, both checked as soon as a
+//! connection is accepted, before it is upgraded to a WebSocket.
+//!
+//! A ban is a single address or a subnet like `203.0.113.0/24`, kept in a JSON file across server
+//! restarts. A running server is managed through the admin interface, the command line changes
+//! the file of a stopped one:
+//!
+//! ```text
+//! curve-fever-server ban 203.0.113.0/24
+//! curve-fever-server unban 203.0.113.0/24
+//! curve-fever-server bans
+//! ```
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    fmt, fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+pub type BanList = Arc<Mutex<Bans>>;
+pub type ThrottleHandle = Arc<Mutex<Throttle>>;
+
+/// Connection attempts per address within this window are limited
+const WINDOW: Duration = Duration::from_secs(60);
+/// Attempts per address and minute unless `CONNECTIONS_PER_MINUTE` says otherwise, loading the
+/// client from a single-port server takes a few connections as well
+const DEFAULT_CONNECTIONS_PER_MINUTE: u32 = 60;
+
+/// `BANS_FILE`, `bans.json` by default
+pub fn file() -> PathBuf {
+    std::env::var("BANS_FILE")
+        .unwrap_or_else(|_| "bans.json".into())
+        .into()
+}
+
+/// `CONNECTIONS_PER_MINUTE`, `0` turns the limit off
+pub fn connections_per_minute() -> Option<u32> {
+    match std::env::var("CONNECTIONS_PER_MINUTE") {
+        Ok(limit) => Some(
+            limit
+                .parse()
+                .expect("Unable to parse the connections per minute"),
+        )
+        .filter(|&limit| limit > 0),
+        Err(_) => Some(DEFAULT_CONNECTIONS_PER_MINUTE),
+    }
+}
+
+/// IPv4 clients of a dual-stack listener show up with mapped IPv6 addresses
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Bits of an address along with their number
+fn bits(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(v4) => (u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    }
+}
+
+/// Addresses sharing their first `prefix` bits with `addr`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Subnet {
+    addr: IpAddr, // without the bits after the prefix
+    prefix: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, width) = bits(self.addr);
+        let (ip, ip_width) = bits(canonical(ip));
+        let shift = (width - self.prefix) as u32;
+        // a shift by all bits covers every address
+        width == ip_width && net.checked_shr(shift) == ip.checked_shr(shift)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = addr
+            .parse()
+            .map(canonical)
+            .map_err(|_| format!("`{}` is no IP address", addr))?;
+        let (n, width) = bits(addr);
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= width)
+                .ok_or_else(|| format!("`{}` is no prefix length up to {}", prefix, width))?,
+            None => width,
+        };
+        let shift = (width - prefix) as u32;
+        let n = n
+            .checked_shr(shift)
+            .unwrap_or(0)
+            .checked_shl(shift)
+            .unwrap_or(0);
+        let addr = match addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(n as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(n)),
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl From<Subnet> for String {
+    fn from(subnet: Subnet) -> String {
+        subnet.to_string()
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.prefix == bits(self.addr).1 {
+            true => write!(f, "{}", self.addr),
+            false => write!(f, "{}/{}", self.addr, self.prefix),
+        }
+    }
+}
+
+pub struct Bans {
+    path: PathBuf,
+    subnets: Vec<Subnet>,
+}
+
+impl Bans {
+    /// Reads the bans from `path`, a missing file is treated as empty.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let subnets: Vec<Subnet> = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        info!("Loaded {} bans from {}", subnets.len(), path.display());
+        Ok(Self { path, subnets })
+    }
+
+    fn save(&self) -> Result<()> {
+        // write to a temporary file first, a crash must not leave a truncated file behind
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.subnets)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> &[Subnet] {
+        &self.subnets
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.subnets.iter().any(|subnet| subnet.contains(ip))
+    }
+
+    /// Bans `subnet`, false if it is banned already.
+    pub fn ban(&mut self, subnet: Subnet) -> Result<bool> {
+        if self.subnets.contains(&subnet) {
+            return Ok(false);
+        }
+        info!("Banning {}", subnet);
+        self.subnets.push(subnet);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Lifts the ban of `subnet`, false if it isn't banned.
+    pub fn unban(&mut self, subnet: &Subnet) -> Result<bool> {
+        let before = self.subnets.len();
+        self.subnets.retain(|banned| banned != subnet);
+        if self.subnets.len() == before {
+            return Ok(false);
+        }
+        info!("Lifting the ban of {}", subnet);
+        self.save()?;
+        Ok(true)
+    }
+}
+
+/// Runs `ban <subnet>...`, `unban <subnet>...` or `bans` on the ban list in `path`, `None` for
+/// other arguments.
+pub fn command(args: &[String], path: PathBuf) -> Option<Result<()>> {
+    let (command, subnets) = args.split_first()?;
+    match command.as_str() {
+        "ban" | "unban" => Some(change(command == "ban", subnets, path)),
+        "bans" => Some(Bans::load(path).map(|bans| {
+            for subnet in bans.list() {
+                println!("{}", subnet);
+            }
+        })),
+        _ => None,
+    }
+}
+
+fn change(ban: bool, subnets: &[String], path: PathBuf) -> Result<()> {
+    let mut bans = Bans::load(path)?;
+    for subnet in subnets {
+        let subnet: Subnet = subnet.parse().map_err(anyhow::Error::msg)?;
+        let changed = match ban {
+            true => bans.ban(subnet)?,
+            false => bans.unban(&subnet)?,
+        };
+        if !changed {
+            println!("Nothing to change for {}", subnet);
+        }
+    }
+    Ok(())
+}
+
+/// Connection attempts of every address within the last minute
+pub struct Throttle {
+    per_minute: Option<u32>, // `None` lets every attempt through
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+    pruned: Instant, // addresses without recent attempts are forgotten once a minute
+}
+
+impl Throttle {
+    pub fn new(per_minute: Option<u32>) -> Self {
+        Self {
+            per_minute,
+            attempts: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+
+    /// Counts an attempt of `ip`, false if it tried too often within the last minute. Refused
+    /// attempts don't count, a client backing off gets through again.
+    pub fn allow(&mut self, ip: IpAddr) -> bool {
+        let limit = match self.per_minute {
+            Some(limit) => limit as usize,
+            None => return true,
+        };
+        let now = Instant::now();
+        let recent = |at: &Instant| now.duration_since(*at) < WINDOW;
+        if !recent(&self.pruned) {
+            self.attempts
+                .retain(|_, attempts| attempts.back().is_some_and(recent));
+            self.pruned = now;
+        }
+        let attempts = self.attempts.entry(canonical(ip)).or_default();
+        while attempts.front().is_some_and(|at| !recent(at)) {
+            attempts.pop_front();
+        }
+        if attempts.len() >= limit {
+            return false;
+        }
+        attempts.push_back(now);
+        true
+    }
+}
@@ -0,0 +1,229 @@
+//! Capacity harness: simulated players in several rooms of an in-process server.
+//!
+//! Every player steers left and right and sends an emote every few seconds. The time until its
+//! own emote comes back from the room is the latency of a broadcast, which must stay within
+//! `MAX_BROADCAST_LATENCY`. Scale it up with `CAPACITY_ROOMS` and `CAPACITY_PLAYERS`, e.g.
+//!
+//! ```text
+//! CAPACITY_ROOMS=50 CAPACITY_PLAYERS=7 cargo test --release capacity -- --nocapture
+//! ```
+
+use async_tungstenite::{tungstenite::Message, WebSocketStream};
+use futures::{
+    channel::mpsc::unbounded,
+    future::{self, join, join_all, Either},
+    sink::SinkExt,
+    stream::{SplitSink, StreamExt},
+};
+use smol::{Async, Task, Timer};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use curve_fever_common::{ClientMessage, Direction, EmoteId, RoomConfig, ServerMessage};
+
+use crate::{
+    accounts::Accounts,
+    bans::{Bans, Throttle},
+    close_rooms, listen,
+    metrics::TickMetrics,
+    replays::Replays,
+    Shared,
+};
+
+type Client = WebSocketStream<Async<TcpStream>>;
+
+const MAX_BROADCAST_LATENCY: Duration = Duration::from_millis(100);
+const PLAY_TIME: Duration = Duration::from_secs(8);
+const MOVE_INTERVAL: Duration = Duration::from_millis(150);
+/// A bit longer than the emote limit of the server
+const EMOTE_INTERVAL: Duration = Duration::from_millis(2100);
+
+fn env_or(key: &str, default: usize) -> usize {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Starts the server on a free port, with its files in a fresh temporary directory.
+fn start_server() -> SocketAddr {
+    for _ in 0..num_cpus::get().max(2) {
+        std::thread::spawn(|| smol::run(future::pending::<()>()));
+    }
+    let dir = std::env::temp_dir().join(format!("curve_fever_capacity_{}", std::process::id()));
+    let accounts = Accounts::load(dir.join("accounts.json")).unwrap();
+    let rooms = Arc::new(Mutex::new(HashMap::new()));
+    let shared = Shared {
+        rooms: rooms.clone(),
+        default_config: Arc::new(Mutex::new(RoomConfig::default())),
+        accounts: Arc::new(Mutex::new(accounts)),
+        replays: Arc::new(Replays::new(dir.join("replays")).unwrap()),
+        match_results: unbounded().0,
+        close_room: close_rooms(rooms),
+        client: None,
+        metrics: Arc::new(TickMetrics::default()),
+        bans: Arc::new(Mutex::new(Bans::load(dir.join("bans.json")).unwrap())),
+        // all players connect from the same address
+        throttle: Arc::new(Mutex::new(Throttle::new(None))),
+    };
+
+    let listener = Async::<TcpListener>::bind("127.0.0.1:0").unwrap();
+    let addr = listener.get_ref().local_addr().unwrap();
+    Task::spawn(listen(listener, shared)).detach();
+    addr
+}
+
+async fn send(sink: &mut SplitSink<Client, Message>, msg: ClientMessage) {
+    let bytes = bincode::serialize(&msg).unwrap();
+    sink.send(Message::Binary(bytes)).await.unwrap();
+}
+
+async fn receive(client: &mut Client) -> Option<ServerMessage> {
+    loop {
+        match client.next().await? {
+            Ok(Message::Binary(bytes)) => return Some(bincode::deserialize(&bytes).unwrap()),
+            Ok(_) => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Connects and enters a room, returns the room name and the player id.
+async fn join_room(addr: SocketAddr, msg: ClientMessage) -> (Client, String, Uuid) {
+    let stream = Async::<TcpStream>::connect(addr).await.unwrap();
+    let (mut client, _) = async_tungstenite::client_async(format!("ws://{}", addr), stream)
+        .await
+        .unwrap();
+    let bytes = bincode::serialize(&msg).unwrap();
+    client.send(Message::Binary(bytes)).await.unwrap();
+    loop {
+        match receive(&mut client).await {
+            Some(ServerMessage::JoinSuccess {
+                room_name, uuid, ..
+            }) => return (client, room_name, uuid),
+            Some(ServerMessage::JoinFailed(reason)) => panic!("Could not join: {}", reason),
+            Some(_) => (),
+            None => panic!("Connection closed before joining"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    states: usize,
+    latencies: Vec<Duration>,
+}
+
+/// Plays with scripted inputs until `deadline`, the host also keeps starting rounds.
+async fn play(client: Client, id: Uuid, host: bool, deadline: Instant) -> Stats {
+    let (mut sink, mut stream) = client.split();
+    let emotes_sent = Arc::new(Mutex::new(VecDeque::new()));
+
+    let sent = emotes_sent.clone();
+    let inputs = async move {
+        let mut last_emote = Instant::now() - EMOTE_INTERVAL;
+        let mut left = true;
+        while Instant::now() < deadline {
+            if last_emote.elapsed() >= EMOTE_INTERVAL {
+                if host {
+                    // refused while a round is running
+                    send(&mut sink, ClientMessage::StartGame).await;
+                }
+                sent.lock().unwrap().push_back(Instant::now());
+                send(&mut sink, ClientMessage::Emote(EmoteId::ALL[0])).await;
+                last_emote = Instant::now();
+            }
+            let direction = if left {
+                Direction::Left
+            } else {
+                Direction::Right
+            };
+            send(&mut sink, ClientMessage::Move(direction)).await;
+            left = !left;
+            Timer::after(MOVE_INTERVAL).await;
+        }
+        let _ = sink.close().await;
+    };
+
+    let messages = async move {
+        let mut stats = Stats::default();
+        loop {
+            let next = Box::pin(stream.next());
+            let msg = match future::select(next, Timer::at(deadline)).await {
+                Either::Left((Some(Ok(Message::Binary(bytes))), _)) => {
+                    bincode::deserialize::<ServerMessage>(&bytes).unwrap()
+                }
+                Either::Left((Some(Ok(_)), _)) => continue,
+                _ => return stats,
+            };
+            match msg {
+                ServerMessage::GameState(..) => stats.states += 1,
+                ServerMessage::Emote(sender, _) if sender == id => {
+                    if let Some(sent) = emotes_sent.lock().unwrap().pop_front() {
+                        stats.latencies.push(sent.elapsed());
+                    }
+                }
+                _ => (),
+            }
+        }
+    };
+
+    join(inputs, messages).await.1
+}
+
+#[test]
+fn broadcast_latency_stays_bounded() {
+    let rooms = env_or("CAPACITY_ROOMS", 4);
+    let players = env_or("CAPACITY_PLAYERS", 4);
+    let addr = start_server();
+
+    let stats = smol::block_on(async {
+        let mut clients = vec![];
+        for i in 0..rooms {
+            let create = ClientMessage::CreateRoom(format!("host{}", i));
+            let (client, room, id) = join_room(addr, create).await;
+            clients.push((client, id, true));
+            let guests = (1..players).map(|j| {
+                let join = ClientMessage::JoinRoom(format!("p{}-{}", i, j), room.clone());
+                join_room(addr, join)
+            });
+            for (client, _, id) in join_all(guests).await {
+                clients.push((client, id, false));
+            }
+        }
+
+        let deadline = Instant::now() + PLAY_TIME;
+        let games = clients
+            .into_iter()
+            .map(|(client, id, host)| play(client, id, host, deadline));
+        join_all(games).await
+    });
+
+    let mut latencies: Vec<Duration> = stats.iter().flat_map(|s| s.latencies.clone()).collect();
+    latencies.sort();
+    assert!(!latencies.is_empty(), "no emote came back");
+    let max = *latencies.last().unwrap();
+    println!(
+        "{} rooms x {} players: {} broadcasts, median {:?}, max {:?}",
+        rooms,
+        players,
+        latencies.len(),
+        latencies[latencies.len() / 2],
+        max
+    );
+    assert!(
+        stats.iter().all(|s| s.states > 0),
+        "a player never received a game state"
+    );
+    assert!(
+        max <= MAX_BROADCAST_LATENCY,
+        "broadcast took {:?}, more than {:?}",
+        max,
+        MAX_BROADCAST_LATENCY
+    );
+}
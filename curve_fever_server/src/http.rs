@@ -0,0 +1,205 @@
+//! Single-port mode: plain HTTP and the WebSocket on the same port.
+//!
+//! With a client to serve, see `assets`, every connection starts as an HTTP request. `GET /ws`
+//! is upgraded to the game's WebSocket, `GET /health` answers for load balancers, `GET /metrics`
+//! serves the tick timings and the traffic of the rooms, `GET /replays/<id>` serves replays and
+//! everything else is a file of the client. The served `index.html` tells the client to connect
+//! to `/ws` of the same origin, so the game works behind any proxy that passes upgrades.
+
+use anyhow::Result;
+use async_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role},
+    WebSocketStream,
+};
+use futures::{
+    future::{self, Either},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use smol::{Async, Timer};
+use std::{net::TcpStream, time::Duration};
+use tracing::info;
+
+use crate::{assets::ClientFiles, metrics, Shared};
+
+/// Requests are tiny, anything larger is not meant for us
+const MAX_REQUEST_SIZE: usize = 8192;
+/// Time a client gets to send its request, an idle connection is closed after it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBSOCKET_PATH: &str = "/ws";
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.lines();
+        let mut words = lines.next()?.split_whitespace();
+        let method = words.next()?.into();
+        let path = words.next()?;
+        // the query is of no interest to any of the routes
+        let path = path.split('?').next().unwrap_or_default().into();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().into()))
+            .collect();
+        Some(Self {
+            method,
+            path,
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn is_upgrade(&self) -> bool {
+        matches!(self.header("upgrade"), Some(value) if value.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+async fn read_head(stream: &mut Async<TcpStream>) -> Result<String> {
+    // read byte by byte, whatever follows the head belongs to the WebSocket
+    let mut head = vec![];
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 || head.len() >= MAX_REQUEST_SIZE {
+            anyhow::bail!("Incomplete request");
+        }
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Reads the head of the request on `stream`, a client that doesn't send one in time is given up.
+pub async fn read_request(stream: &mut Async<TcpStream>) -> Result<Request> {
+    let read = Box::pin(read_head(stream));
+    let head = match future::select(read, Timer::after(REQUEST_TIMEOUT)).await {
+        Either::Left((head, _)) => head?,
+        Either::Right(_) => anyhow::bail!("No request within {:?}", REQUEST_TIMEOUT),
+    };
+    Request::parse(&head).ok_or_else(|| anyhow::anyhow!("Malformed request"))
+}
+
+/// Answers the HTTP request on `stream`, returns the WebSocket if it asked for an upgrade.
+pub async fn serve(
+    mut stream: Async<TcpStream>,
+    shared: &Shared,
+    client: &ClientFiles,
+) -> Result<Option<WebSocketStream<Async<TcpStream>>>> {
+    let request = read_request(&mut stream).await?;
+
+    if request.method == "GET" && request.path == WEBSOCKET_PATH && request.is_upgrade() {
+        let key = request
+            .header("sec-websocket-key")
+            .ok_or_else(|| anyhow::anyhow!("Upgrade without a key"))?;
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            derive_accept_key(key.as_bytes())
+        );
+        stream.write_all(response.as_bytes()).await?;
+        let ws_stream = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+        return Ok(Some(ws_stream));
+    }
+
+    let (status, content_type, body) = match request.method.as_str() {
+        "GET" => route(&request.path, shared, client),
+        _ => ("405 Method Not Allowed", "text/plain", vec![]),
+    };
+    info!("{} {} {}", request.method, request.path, status);
+
+    let head = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    Ok(None)
+}
+
+fn route(
+    path: &str,
+    shared: &Shared,
+    client: &ClientFiles,
+) -> (&'static str, &'static str, Vec<u8>) {
+    const NOT_FOUND: (&str, &str, Vec<u8>) = ("404 Not Found", "text/plain", vec![]);
+    if path == "/health" {
+        return ("200 OK", "text/plain", b"ok".to_vec());
+    }
+    if path == "/metrics" {
+        let rooms = shared.rooms.lock().unwrap();
+        let traffic = rooms
+            .iter()
+            .map(|(name, room)| (name.as_str(), &*room.traffic));
+        let body = (shared.metrics.render() + &metrics::render_traffic(traffic)).into_bytes();
+        return ("200 OK", "text/plain; version=0.0.4", body);
+    }
+    if let Some(id) = path.strip_prefix("/replays/") {
+        return match shared.replays.load(id) {
+            Some(body) => ("200 OK", "application/octet-stream", body),
+            None => NOT_FOUND,
+        };
+    }
+
+    let file = match path.trim_start_matches('/') {
+        "" => "index.html",
+        file => file,
+    };
+    // hidden files and anything outside of the client directory stay private
+    if file
+        .split('/')
+        .any(|part| part.is_empty() || part.starts_with('.'))
+    {
+        return NOT_FOUND;
+    }
+    let body = match client.read(file) {
+        Some(body) => body.into_owned(),
+        None => return NOT_FOUND,
+    };
+    if file == "index.html" {
+        return (
+            "200 OK",
+            "text/html; charset=utf-8",
+            with_websocket_path(body),
+        );
+    }
+    ("200 OK", content_type(file), body)
+}
+
+/// Tells the client where to find the WebSocket, see `connect` of the client.
+fn with_websocket_path(page: Vec<u8>) -> Vec<u8> {
+    let page = String::from_utf8_lossy(&page);
+    let meta = format!(
+        "<meta name=\"websocket-path\" content=\"{}\">\n</head>",
+        WEBSOCKET_PATH
+    );
+    page.replacen("</head>", &meta, 1).into_bytes()
+}
+
+fn content_type(file: &str) -> &'static str {
+    match file.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("wasm") => "application/wasm",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
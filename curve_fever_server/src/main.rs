@@ -1,40 +1,220 @@
 use anyhow::Result;
 use arrayvec::ArrayString;
 use async_tungstenite::{tungstenite::Message, WebSocketStream};
-use env_logger::Env;
+use bytes::Bytes;
 use futures::{
-    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
-    future::{self, join},
+    channel::mpsc::{unbounded, SendError, UnboundedReceiver, UnboundedSender},
+    future::{self, join, Abortable, Aborted},
     sink::SinkExt,
     stream::StreamExt,
 };
-use log::{debug, error, info, warn};
-use rand::{distributions::Alphanumeric, seq::SliceRandom, Rng};
+use rand::seq::SliceRandom;
 use smol::{Async, Task, Timer};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     convert::TryInto,
     net::{SocketAddr, TcpListener, TcpStream},
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
+use tracing::{error, field::display, info, info_span, instrument, warn, Instrument, Span};
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
-use curve_fever_common::{ClientMessage, Game, GridInfo, Player, ServerMessage};
+use curve_fever_common::{
+    normalize_room_code, palette, AccountStats, ChatMessage, ClientMessage, Death, DeathCause,
+    Direction, EmoteId, Game, GhostRun, Input, ItemEvent, Player, Replay, RoomConfig, RoomEvent,
+    RoomSnapshot, RoundResult, ServerMessage, TournamentState, TrafficStats, TrailPattern,
+    MAX_CHAT_LENGTH, ROOM_CODE_ALPHABET, ROOM_CODE_LENGTH, SLOW_MOTION_FACTOR, SLOW_MOTION_SECONDS,
+};
+
+mod accounts;
+mod admin;
+mod assets;
+mod bans;
+#[cfg(test)]
+mod capacity;
+mod http;
+mod matches;
+mod metrics;
+mod outbox;
+mod persistence;
+mod replays;
+#[cfg(test)]
+mod scenarios;
+mod tournament;
+
+use accounts::{AccountList, Accounts};
+use assets::ClientFiles;
+use bans::{BanList, Bans, Throttle, ThrottleHandle};
+use matches::{Match, Session};
+use metrics::{Metrics, TickMetrics, TrafficCounter};
+use outbox::{outbox, Frame, Outbox};
+use persistence::{RoomStore, SavedPlayer, SavedRoom};
+use replays::{Recorder, ReplayList, Replays};
+use tournament::{MatchResult, TournamentList};
 
 type RoomList = Arc<Mutex<HashMap<String, RoomHandle>>>;
+type DefaultConfig = Arc<Mutex<RoomConfig>>; // config of newly created rooms
+
+const COUNTDOWN_SECONDS: u8 = 3;
+/// Time the players in the lobby get before a round starts by itself
+const AUTO_START_SECONDS: usize = 10;
+/// How long the slot of a disconnected player is kept for resuming the session
+const RESUME_GRACE: Duration = Duration::from_secs(60);
+/// Direction changes of a player per tick, more are dropped as no human can press keys that fast
+const MAX_MOVES_PER_TICK: u8 = 2;
+/// Ticks between two full trail syncs of a player, later requests wait for their turn
+const FULL_SYNC_TICKS: usize = 30;
+/// Minimum time between two emotes of a player
+const EMOTE_INTERVAL: Duration = Duration::from_secs(2);
+/// Minimum time between two chat messages of a player
+const CHAT_INTERVAL: Duration = Duration::from_millis(500);
+/// Minimum time between two accounts registered over the same connection
+const REGISTER_INTERVAL: Duration = Duration::from_secs(10);
+/// Chat messages kept for players joining later
+const CHAT_HISTORY: usize = 50;
+/// Room events kept for players joining later
+const EVENT_HISTORY: usize = 30;
+/// Time without any input after which a player between rounds is marked as AFK
+const AFK_TIMEOUT: Duration = Duration::from_secs(180);
+/// How long a player may stay AFK before being removed from the room
+const AFK_REMOVAL: Duration = Duration::from_secs(300);
+/// How long before the removal an AFK player is warned
+const AFK_WARNING: Duration = Duration::from_secs(30);
+/// Ticks taking longer than this are logged, with the size of the room
+const SLOW_TICK: Duration = Duration::from_millis(40);
+/// Time between two latency measurements
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// Time between two reports of the traffic of a room to its host
+const TRAFFIC_INTERVAL: Duration = Duration::from_secs(5);
+/// Round-trip times above this are not equalized, one bad connection must not slow down everybody
+const MAX_EQUALIZED_RTT: Duration = Duration::from_millis(300);
+/// How long a running round may continue once the server is shutting down
+const SHUTDOWN_GRACE_SECONDS: u32 = 15;
+/// Listen address without any on the command line
+const DEFAULT_ADDR: &str = "0.0.0.0:8095";
+
+/// How a connection wants to enter a room
+enum JoinRequest {
+    New(String, Option<String>), // player name, hashed token of the logged in account
+    Spectate,
+    Resume(Uuid), // session token of a player that lost the connection
+}
+
+/// Something that happens to a room, see `Room::apply`
+#[derive(Debug)]
+enum Event {
+    Message(SocketAddr, ClientMessage),
+    Tick,
+}
+
+/// What a room wants done with the connections of its clients, carried out in order by its
+/// `RoomHandle`
+#[derive(Debug)]
+enum Effect {
+    Send(SocketAddr, ServerMessage),
+    Broadcast(ServerMessage), // to every client in the room
+    Disconnect(SocketAddr),   // after the messages sent to the client so far
+}
 
+/// A room along with the queues of its clients, the only part of it that touches connections
 #[derive(Clone)]
 struct RoomHandle {
     play: bool,
     write: UnboundedSender<(SocketAddr, ClientMessage)>,
     room: Arc<Mutex<Room>>,
+    clients: Arc<Mutex<HashMap<SocketAddr, Outbox>>>,
+    traffic: TrafficCounter, // of the room, readable without locking it
 }
 
 impl RoomHandle {
+    fn new(room: Room, write: UnboundedSender<(SocketAddr, ClientMessage)>) -> Self {
+        Self {
+            play: false,
+            write,
+            traffic: room.traffic.clone(),
+            room: Arc::new(Mutex::new(room)),
+            clients: Arc::default(),
+        }
+    }
+
+    /// Feeds an event to the room and carries out its effects, returns whether the room is still
+    /// running.
+    fn apply(&self, event: Event) -> bool {
+        let mut room = self.room.lock().unwrap();
+        let (running, effects) = room.apply(event);
+        self.execute(effects);
+        running
+    }
+
+    /// Runs `f` on the room and carries out the effects it leaves behind. The room stays locked
+    /// until then, so the clients get the messages in the order the room produced them.
+    fn with_room<T>(&self, f: impl FnOnce(&mut Room) -> T) -> T {
+        let mut room = self.room.lock().unwrap();
+        let result = f(&mut room);
+        self.execute(room.take_effects());
+        result
+    }
+
+    /// Adds a client to the room, its queue gets nothing before the room accepted it.
+    fn join(
+        &self,
+        addr: SocketAddr,
+        request: JoinRequest,
+        ws_tx: Outbox,
+    ) -> Result<(String, Uuid)> {
+        self.with_room(|room| {
+            self.clients.lock().unwrap().insert(addr, ws_tx);
+            let joined = match request {
+                JoinRequest::Spectate => Ok(room.add_spectator(addr)),
+                request => room.add_player(addr, request),
+            };
+            if joined.is_err() {
+                self.clients.lock().unwrap().remove(&addr);
+            }
+            joined
+        })
+    }
+
+    fn execute(&self, effects: Vec<Effect>) {
+        let mut clients = self.clients.lock().unwrap();
+        for effect in effects {
+            match effect {
+                Effect::Send(addr, msg) => {
+                    if let Some(ws) = clients.get(&addr) {
+                        let frame = Frame::new(&msg);
+                        self.traffic.record(frame.len(), 1);
+                        if let Err(e) = ws.send(frame) {
+                            error!("Failed to send to {}: {}", addr, e);
+                        }
+                    }
+                }
+                Effect::Broadcast(msg) => {
+                    let frame = Frame::new(&msg);
+                    self.traffic.record(frame.len(), clients.len());
+                    clients.iter().for_each(|(addr, ws)| {
+                        if let Err(e) = ws.send(frame.clone()) {
+                            error!("Failed to send broadcast to {}: {}", addr, e);
+                        }
+                    });
+                }
+                Effect::Disconnect(addr) => {
+                    // closing the queue lets `run_player` close the WebSocket
+                    if let Some(ws) = clients.remove(&addr) {
+                        ws.close();
+                    }
+                }
+            }
+        }
+    }
+
     async fn run_room(&mut self, mut read: UnboundedReceiver<(SocketAddr, ClientMessage)>) {
         while let Some((addr, msg)) = read.next().await {
-            if !self.room.lock().unwrap().on_message(addr, msg) {
+            if !self.apply(Event::Message(addr, msg)) {
                 break;
             }
         }
@@ -42,14 +222,11 @@ impl RoomHandle {
 
     async fn tick(&mut self) {
         loop {
-            Timer::after(Duration::from_millis(25)).await;
-            if !self.room.lock().unwrap().tick_once() {
+            let tick_rate = self.room.lock().unwrap().config.tick_rate;
+            Timer::after(Duration::from_secs(1) / tick_rate).await;
+            if !self.apply(Event::Tick) {
                 break;
             }
-            if self.room.lock().unwrap().initialized {
-                Timer::after(Duration::from_secs(2)).await; // room cannot be mutably blocked at this state
-                self.room.lock().unwrap().initialized = false;
-            }
         }
     }
 }
@@ -57,178 +234,1192 @@ impl RoomHandle {
 struct Room {
     name: String,
     connections: HashMap<SocketAddr, Uuid>,
+    spectators: HashSet<SocketAddr>,
     players: HashMap<Uuid, PlayerServer>,
-    colors: Vec<ArrayString<7>>,
+    departed: HashMap<Uuid, (Player, Option<String>, Instant)>, // disconnected players by session token
+    kicked: HashSet<Uuid>, // players that may not resume their session
+    config: RoomConfig,
     game: Game,
-    initialized: bool,
+    freeze_ticks: usize, // remaining ticks of the countdown before a round
+    auto_start_ticks: Option<usize>, // remaining ticks until the round starts by itself
+    auto_start_cancelled: bool, // by the host, until the next round
+    ping: (u32, Instant), // sequence number and time of the last `Ping`
+    ending: Option<(Uuid, usize)>, // winner and remaining ticks of the slow motion replay
+    round_ticks: usize,  // ticks played in the current round
+    paused: bool,
+    paused_ticks: usize,
+    shutdown_ticks: Option<usize>, // remaining ticks until the connections are closed
+    seq: u32,                      // sequence number of the last `GameState`
+    seed: u64,                     // of the current round
+    deaths: Vec<(Death, usize)>,   // deaths of the current round in order, with the round tick
+    current_match: Match,
+    session: Session,                    // results of the finished matches
+    recording: Option<Recorder>,         // replay of the current round
+    ghost: Option<GhostRun>,             // of the winner of the current round
+    tournament: Option<TournamentState>, // bracket of the tournament this room is part of
+    chat: VecDeque<ChatMessage>,         // the latest `CHAT_HISTORY` messages
+    events: VecDeque<RoomEvent>,         // the latest `EVENT_HISTORY` events
+    waiting_until: Option<Instant>,      // restored rooms stay open for their players until then
+    accounts: AccountList,
+    replays: ReplayList,
+    match_results: UnboundedSender<MatchResult>, // for the tournaments
+    metrics: Metrics,
+    traffic: TrafficCounter, // counted by the handle as it sends
+    traffic_reported: (u64, u64, Instant), // totals at the last report to the host
+    effects: Vec<Effect>,    // not carried out yet, see `apply`
 }
 
 impl Room {
     fn new(
         name: String,
-        width: usize,
-        height: usize,
-        line_width: u32,
-        rotation_delta: f64,
+        config: RoomConfig,
+        accounts: AccountList,
+        replays: ReplayList,
+        match_results: UnboundedSender<MatchResult>,
+        metrics: Metrics,
     ) -> Self {
-        let colors = {
-            let mut vec = vec![];
-            for color in &[
-                "#E65100", "#388E3C", "#0277BD", "#D32F2F", "#9C27B0", "#FFC107", "#9E9E9E",
-            ] {
-                vec.push(ArrayString::<7>::from(color).unwrap());
-            }
-            vec.shuffle(&mut rand::thread_rng());
-            vec
-        };
+        let game = Game::from_config(&config);
 
         Self {
             name,
             connections: HashMap::new(),
+            spectators: HashSet::new(),
             players: HashMap::new(),
-            colors,
-            game: Game::new(width, height, line_width, rotation_delta),
-            initialized: false,
+            departed: HashMap::new(),
+            kicked: HashSet::new(),
+            game,
+            config,
+            freeze_ticks: 0,
+            ending: None,
+            round_ticks: 0,
+            paused: false,
+            paused_ticks: 0,
+            shutdown_ticks: None,
+            seq: 0,
+            seed: 0,
+            deaths: vec![],
+            current_match: Match::default(),
+            session: Session::default(),
+            recording: None,
+            ghost: None,
+            auto_start_ticks: None,
+            auto_start_cancelled: false,
+            ping: (0, Instant::now()),
+            tournament: None,
+            chat: VecDeque::new(),
+            events: VecDeque::new(),
+            waiting_until: None,
+            accounts,
+            replays,
+            match_results,
+            metrics,
+            traffic: Arc::default(),
+            traffic_reported: (0, 0, Instant::now()),
+            effects: vec![],
+        }
+    }
+
+    /// Brings back a room of a previous run, its players can resume their sessions for a while.
+    fn restore(
+        snapshot: SavedRoom,
+        accounts: AccountList,
+        replays: ReplayList,
+        match_results: UnboundedSender<MatchResult>,
+        metrics: Metrics,
+    ) -> Self {
+        let mut room = Room::new(
+            snapshot.name,
+            snapshot.config,
+            accounts,
+            replays,
+            match_results,
+            metrics,
+        );
+        let now = Instant::now();
+        for saved in snapshot.players {
+            room.departed
+                .insert(saved.token, (saved.player, saved.account, now));
+        }
+        room.current_match.rounds = snapshot.rounds;
+        room.session = Session::new(snapshot.session);
+        room.tournament = snapshot.tournament;
+        room.chat = snapshot.chat.into();
+        room.waiting_until = Some(now + RESUME_GRACE);
+        room
+    }
+
+    /// What a restarted server needs to bring the room back, `None` if nobody could return.
+    fn saved(&self) -> Option<SavedRoom> {
+        let connected = self.players.iter().filter_map(|(id, player)| {
+            Some(SavedPlayer {
+                token: player.token,
+                player: *self.game.players.get(id)?,
+                account: player.account.clone(),
+            })
+        });
+        let departed = self
+            .departed
+            .iter()
+            .filter(|(_, (_, _, left))| left.elapsed() < RESUME_GRACE)
+            .map(|(token, (player, account, _))| SavedPlayer {
+                token: *token,
+                player: *player,
+                account: account.clone(),
+            });
+        let players: Vec<SavedPlayer> = connected.chain(departed).collect();
+        if players.is_empty() {
+            return None;
+        }
+        Some(SavedRoom {
+            name: self.name.clone(),
+            config: self.config.clone(),
+            players,
+            rounds: self.current_match.rounds.clone(),
+            session: self.session.stats(),
+            tournament: self.tournament.clone(),
+            chat: self.chat.iter().cloned().collect(),
+        })
+    }
+
+    /// Catches up a client entering the room, trails are only included during a round.
+    fn snapshot(&self) -> RoomSnapshot {
+        RoomSnapshot {
+            players: self.game.players.values().copied().collect(),
+            config: self.config.clone(),
+            round: self.current_match.round(),
+            spectators: self.spectators.len() as u32,
+            afk: self
+                .players
+                .iter()
+                .filter(|(_, player)| player.afk_since.is_some())
+                .map(|(id, _)| *id)
+                .collect(),
+            tournament: self.tournament.clone(),
+            session: self.session.stats(),
+            trails: Some((self.seq, self.game.trail_sync())).filter(|_| self.round_in_progress()),
         }
     }
 
     fn running(&self) -> bool {
         !self.connections.is_empty()
+            || self
+                .waiting_until
+                .is_some_and(|until| Instant::now() < until)
     }
 
-    fn add_player(
-        &mut self,
-        addr: SocketAddr,
-        player_name: String,
-        ws_tx: UnboundedSender<ServerMessage>,
-    ) -> Result<()> {
-        // generate UUID
-        let id = Uuid::new_v4();
+    fn round_in_progress(&self) -> bool {
+        self.freeze_ticks > 0 || self.game.running() || self.ending.is_some()
+    }
 
-        // get color
-        let color = self.colors.pop().expect("no more colors left");
+    fn is_full(&self) -> bool {
+        self.players.len() >= self.config.max_players
+    }
 
-        // create player for game
-        let player = Arc::new(Mutex::new(Player::new(
-            id,
-            &player_name,
-            color,
-            self.game.width.try_into().unwrap(),
-            self.game.height.try_into().unwrap(),
-            self.game.line_width,
-            self.game.rotation_delta,
-        )));
+    /// Average rating of the players if quick play may send more players here, guests count
+    /// with the rating of a new account.
+    fn matchmaking_rating(&self) -> Option<u32> {
+        let open = !self.is_full()
+            && !self.game.players.is_empty()
+            && self.tournament.is_none()
+            && self.shutdown_ticks.is_none();
+        if !open {
+            return None;
+        }
+        let ratings = self.game.players.values().map(|player| {
+            player
+                .rating
+                .unwrap_or_else(|| AccountStats::default().rating)
+        });
+        Some(ratings.sum::<u32>() / self.game.players.len() as u32)
+    }
+
+    fn palette(&self) -> Vec<palette::Color> {
+        self.config.theme.palette(self.config.max_players)
+    }
+
+    /// The place in the palette for a joining player: the preferred color if it is free, else the
+    /// first free one. Places kept for departed players are only given away if the room is full
+    /// otherwise.
+    fn free_slot(&self, palette: &[palette::Color], preferred: Option<ArrayString<7>>) -> usize {
+        let connected: Vec<_> = self.game.players.values().map(|p| p.color).collect();
+        let departed: Vec<_> = self.departed.values().map(|(p, _, _)| p.color).collect();
+        let free = |color: &palette::Color| !connected.contains(color) && !departed.contains(color);
+        preferred
+            .and_then(|color| palette::position(palette, &color))
+            .filter(|&i| free(&palette[i]))
+            .or_else(|| palette.iter().position(free))
+            .or_else(|| palette.iter().position(|color| !connected.contains(color)))
+            .expect("no more colors left")
+    }
+
+    /// Moves all players from the `from` palette to the one of the current config. Players keep
+    /// their place if it still exists, the others get the first free places.
+    fn recolor(&mut self, from: &[palette::Color]) {
+        let to = self.palette();
+        let mut taken = vec![false; to.len()];
+        let mut players: Vec<&mut Player> = self
+            .game
+            .players
+            .values_mut()
+            .chain(self.departed.values_mut().map(|(player, _, _)| player))
+            .collect();
+        // the pattern goes along with the place in the palette, like for joining players
+        let place = |player: &mut Player, slot: usize| {
+            player.color = to[slot];
+            player.pattern = TrailPattern::ALL[slot % TrailPattern::ALL.len()];
+        };
+        let mut moved = vec![];
+        for (i, player) in players.iter_mut().enumerate() {
+            match palette::position(from, &player.color).filter(|&slot| slot < to.len()) {
+                Some(slot) if !taken[slot] => {
+                    taken[slot] = true;
+                    place(player, slot);
+                }
+                _ => moved.push(i),
+            }
+        }
+        for i in moved {
+            if let Some(slot) = taken.iter().position(|taken| !taken) {
+                taken[slot] = true;
+                place(players[i], slot);
+            }
+        }
+    }
+
+    fn is_host(&self, id: &Uuid) -> bool {
+        self.game.players.get(id).is_some_and(|player| player.host)
+    }
 
-        // insert player to players
-        self.game.players.insert(id, player.clone());
+    fn can_resume(&self, token: &Uuid) -> bool {
+        self.departed
+            .get(token)
+            .is_some_and(|(_, _, left)| left.elapsed() < RESUME_GRACE)
+    }
+
+    fn add_player(&mut self, addr: SocketAddr, request: JoinRequest) -> Result<(String, Uuid)> {
+        let (mut player, token, account) = match request {
+            JoinRequest::Spectate => anyhow::bail!("Spectators are no players"),
+            JoinRequest::New(player_name, account) => {
+                // generate UUID
+                let id = Uuid::new_v4();
+
+                // accounts play under their own name and keep their color if it is still free
+                let (player_name, preferred, rating) = match account
+                    .as_ref()
+                    .and_then(|key| self.accounts.lock().unwrap().get(key).cloned())
+                {
+                    Some(account) => (account.name, account.color, Some(account.stats.rating)),
+                    None => (player_name, None, None),
+                };
+
+                // get color, every place in the palette has its own pattern for players who can't
+                // tell the colors apart
+                let palette = self.palette();
+                let slot = self.free_slot(&palette, preferred);
+                let (color, pattern) = (
+                    palette[slot],
+                    TrailPattern::ALL[slot % TrailPattern::ALL.len()],
+                );
+                if preferred.is_none() {
+                    if let Some(key) = &account {
+                        self.accounts
+                            .lock()
+                            .unwrap()
+                            .update(key, |account| account.color = Some(color));
+                    }
+                }
+
+                let mut player = Player::new(
+                    id,
+                    &player_name,
+                    color,
+                    self.game.width.try_into().unwrap(),
+                    self.game.height.try_into().unwrap(),
+                    self.game.line_width,
+                    self.game.turn_rate,
+                );
+                player.rating = rating;
+                player.pattern = pattern;
+                (player, Uuid::new_v4(), account)
+            }
+            JoinRequest::Resume(token) => {
+                // keep uuid, color and points of the previous session
+                let (mut player, account, _) = self
+                    .departed
+                    .remove(&token)
+                    .filter(|(_, _, left)| left.elapsed() < RESUME_GRACE)
+                    .ok_or_else(|| anyhow::anyhow!("Session has expired"))?;
+                player.host = false;
+                info!("Resuming session of `{}`", player.name);
+                (player, token, account)
+            }
+        };
+        // the config might have changed since the player was created
+        player.set_handling(self.game.speed, self.game.turn_rate);
+        player.set_ghost(self.game.ghost);
+        player.set_gaps(self.game.gaps);
+        player.x_max = self.game.width.try_into().unwrap();
+        player.y_max = self.game.height.try_into().unwrap();
+        let id = player.uuid;
+        let player_name = player.name.to_string();
 
         // insert player to connection map, first player is the host
         if self.connections.is_empty() {
-            player.lock().unwrap().host = true;
+            player.host = true;
         }
         self.connections.insert(addr, id);
 
         // tell other players that a player has joined
         info!(
-            "[{}] Player `{}` with uuid `{}` connected sucessfully",
-            self.name,
+            "Player `{}` with uuid `{}` connected sucessfully",
             &player_name,
             id.to_string()
         );
-        ws_tx.unbounded_send(ServerMessage::JoinSuccess {
-            room_name: self.name.clone(),
-            grid_info: GridInfo {
-                width: self.game.width.try_into().unwrap(),
-                height: self.game.height.try_into().unwrap(),
-                line_width: self.game.line_width,
+        self.send_to(
+            addr,
+            ServerMessage::JoinSuccess {
+                room_name: self.name.clone(),
+                grid_info: self.game.grid_info(),
+                players: self.game.players.values().copied().collect(),
+                uuid: id,
+                token,
+                config: self.config.clone(),
             },
-            players: {
-                self.players
-                    .values()
-                    .map(|v| v.player.clone())
-                    .map(|v| *v.lock().unwrap())
-                    .collect::<Vec<Player>>()
+        );
+        self.send_to(addr, ServerMessage::RoomSnapshot(self.snapshot()));
+        self.send_histories(addr);
+
+        // create player for server
+        self.players.insert(
+            id,
+            PlayerServer {
+                name: player_name.clone(),
+                token,
+                addr,
+                last_emote: None,
+                last_chat: None,
+                moves: 0,
+                dropped_moves: 0,
+                account,
+                last_input: Instant::now(),
+                input_this_round: false,
+                afk_since: None,
+                afk_warned: false,
+                rtt: None,
+                delayed: VecDeque::new(),
+                input_ack: 0,
+                sync_ticks: 0,
+                sync_requested: false,
+            },
+        );
+
+        // insert player to the game
+        self.game.add_player(player);
+
+        // tell other players that a player has joined
+        self.broadcast(ServerMessage::NewPlayer(player));
+        self.room_event(RoomEvent::PlayerJoined(player_name.clone()));
+        if self.round_in_progress() {
+            // the game only activates the player once the next round is initialized
+            info!("`{}` joins with the next round", player_name);
+            self.broadcast(ServerMessage::PlayerQueued(id));
+        }
+        Ok((player_name, id))
+    }
+
+    fn add_spectator(&mut self, addr: SocketAddr) -> (String, Uuid) {
+        self.send_to(
+            addr,
+            ServerMessage::JoinSuccess {
+                room_name: self.name.clone(),
+                grid_info: self.game.grid_info(),
+                players: self.game.players.values().copied().collect(),
+                uuid: Uuid::nil(),
+                token: Uuid::nil(),
+                config: self.config.clone(),
             },
-            uuid: id,
-        })?;
+        );
+        self.send_to(addr, ServerMessage::RoomSnapshot(self.snapshot()));
+        self.send_histories(addr);
+        self.spectators.insert(addr);
+        info!("Spectator joined, {} watching", self.spectators.len());
+        self.broadcast(ServerMessage::SpectatorsChanged(
+            self.spectators.len() as u32
+        ));
+        ("spectator".into(), Uuid::nil())
+    }
+
+    /// Sends the chat and the room events so far to a client entering the room.
+    fn send_histories(&mut self, addr: SocketAddr) {
+        if !self.chat.is_empty() {
+            let history = self.chat.iter().cloned().collect();
+            self.send_to(addr, ServerMessage::ChatHistory(history));
+        }
+        let events: Vec<RoomEvent> = self.events.iter().cloned().collect();
+        for event in events {
+            self.send_to(addr, ServerMessage::RoomEvent(event));
+        }
+    }
+
+    /// Adds an event to the log of the room and sends it to everyone in it.
+    fn room_event(&mut self, event: RoomEvent) {
+        if self.events.len() == EVENT_HISTORY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event.clone());
+        self.broadcast(ServerMessage::RoomEvent(event));
+    }
+
+    /// Name of a player of the game, also of players that died this round
+    fn player_name(&self, id: &Uuid) -> String {
+        self.game
+            .players
+            .get(id)
+            .map(|player| player.name.to_string())
+            .unwrap_or_default()
+    }
+
+    fn ticks_per_second(&self) -> usize {
+        self.config.tick_rate as usize
+    }
+
+    /// Seconds of simulation per tick
+    fn dt(&self) -> f64 {
+        1. / self.config.tick_rate as f64
+    }
+
+    fn do_tick(&mut self) {
+        self.round_ticks += 1;
+        if let Some(limit) = self.config.round_time_limit {
+            if self.round_ticks == limit as usize * self.ticks_per_second() {
+                info!("Round time limit reached, sudden death");
+                self.game.start_sudden_death();
+                self.broadcast(ServerMessage::SuddenDeath);
+            }
+        }
+
+        let wall_margin = self.game.wall_margin();
+        let trail_width = self.game.trail_width();
+        let dt = self.dt();
+        for death in self.game.tick(dt) {
+            info!(
+                "Player `{}` died: {:?}",
+                self.players
+                    .get(&death.id)
+                    .map(|p| p.name.clone())
+                    .unwrap_or_default(),
+                death.cause
+            );
+            self.deaths.push((death, self.round_ticks));
+            self.broadcast(ServerMessage::PlayerDied(death));
+            let killer = match death.cause {
+                DeathCause::Player(killer) => Some(self.player_name(&killer)),
+                _ => None,
+            };
+            self.room_event(RoomEvent::PlayerDied(self.player_name(&death.id), killer));
+        }
+        for event in self.game.take_item_events() {
+            self.broadcast(match event {
+                ItemEvent::Spawned(item) => ServerMessage::ItemSpawned(item),
+                ItemEvent::Collected(item, id) => ServerMessage::ItemCollected(item, id),
+                ItemEvent::AreaCleared(center, radius) => {
+                    ServerMessage::AreaCleared(center, radius)
+                }
+            });
+        }
+        for teleport in self.game.take_teleports() {
+            self.broadcast(ServerMessage::PlayerTeleported(teleport));
+        }
+        if self.game.wall_margin() != wall_margin {
+            self.broadcast(ServerMessage::ArenaShrunk(self.game.wall_margin()));
+        }
+        if self.game.trail_width() != trail_width {
+            self.broadcast(ServerMessage::TrailsGrew(self.game.trail_width()));
+        }
+        if !self.game.hazards().is_empty() {
+            self.broadcast(ServerMessage::HazardsMoved(self.game.hazards().to_vec()));
+        }
+        self.broadcast_state();
+        if let Some(winner) = self.game.get_winner() {
+            // give the clients time to replay the final moments
+            let seconds = SLOW_MOTION_SECONDS / SLOW_MOTION_FACTOR;
+            let ticks = (seconds * self.ticks_per_second() as f64).round() as usize;
+            self.ending = Some((winner, ticks));
+            if let Some(ghost) = &mut self.ghost {
+                ghost.runner = winner;
+                ghost.ticks = self.round_ticks as u32;
+            }
+            if let Some(recording) = &mut self.recording {
+                recording.decided(self.round_ticks as u32);
+            }
+            self.broadcast(ServerMessage::RoundEnding(winner));
+        }
+    }
+
+    fn do_ending_tick(&mut self) {
+        match &mut self.ending {
+            Some((_, ticks)) if *ticks > 0 => *ticks -= 1,
+            Some((winner, _)) => {
+                let winner = *winner;
+                self.ending = None;
+                self.finish_round(winner);
+            }
+            None => (),
+        }
+    }
+
+    fn finish_round(&mut self, winner: Uuid) {
+        info!("Round has finished");
+        // whoever played the whole round without a single input is not around
+        for id in self.standings(winner) {
+            if self.players.get(&id).is_some_and(|p| !p.input_this_round) {
+                self.set_afk(&id, true);
+            }
+        }
+        self.count_round(winner);
+        self.broadcast(ServerMessage::RoundEnded((winner, self.game.state_ended())));
+        self.room_event(RoomEvent::RoundEnded(self.player_name(&winner)));
+        self.record_round(winner);
+        if let Some(recorder) = self.recording.take() {
+            let id = self.replays.save(recorder.finish());
+            self.broadcast(ServerMessage::ReplaySaved(id));
+        }
+        if let Some(ghost) = self.ghost.take() {
+            self.send(&winner, ServerMessage::GhostRecorded(ghost));
+        }
+    }
+
+    /// Players of the finished round, from the winner to the first one that died
+    fn standings(&self, winner: Uuid) -> Vec<Uuid> {
+        std::iter::once(winner)
+            .chain(
+                self.deaths
+                    .iter()
+                    .rev()
+                    .map(|(death, _)| death.id)
+                    .filter(|id| *id != winner),
+            )
+            .collect()
+    }
+
+    /// Adds the finished round to the match, which ends once a player reaches the target score or
+    /// the best of rounds is decided.
+    fn record_round(&mut self, winner: Uuid) {
+        let results = self
+            .standings(winner)
+            .iter()
+            .enumerate()
+            .filter_map(|(i, id)| {
+                let ticks = self
+                    .deaths
+                    .iter()
+                    .find(|(death, _)| death.id == *id)
+                    .map_or(self.round_ticks, |(_, ticks)| *ticks);
+                let player = self.game.players.get(id)?;
+                let kills = self
+                    .deaths
+                    .iter()
+                    .filter(|(death, _)| {
+                        death.id != *id
+                            && matches!(death.cause, DeathCause::Player(killer) if killer == *id)
+                    })
+                    .count();
+                Some(RoundResult {
+                    id: *id,
+                    name: player.name.to_string(),
+                    placement: i as u32 + 1,
+                    survival_ms: (ticks * 1000 / self.ticks_per_second()) as u32,
+                    kills: kills as u32,
+                    stats: player.stats,
+                })
+            })
+            .collect();
+        self.current_match.rounds.push(results);
+
+        let players: Vec<&Player> = self.game.players.values().collect();
+        if self.current_match.is_decided(&self.config, &players) {
+            self.finish_match();
+        }
+        self.broadcast(ServerMessage::MatchRound(self.current_match.round()));
+    }
+
+    /// Ends the match for everybody still in the room, the next one starts from zero.
+    fn finish_match(&mut self) {
+        let finished = std::mem::take(&mut self.current_match);
+        info!("Match finished after {} rounds", finished.rounds.len());
+        let winner = self
+            .match_standings()
+            .first()
+            .map(|winner| (winner.uuid, winner.name.to_string()));
+        if let Some((id, _)) = winner {
+            self.broadcast(ServerMessage::GameOver(id));
+        }
+        self.session
+            .record(&finished, winner.as_ref().map(|(_, name)| name.as_str()));
+        self.broadcast(ServerMessage::MatchSummary(finished.rounds));
+        self.broadcast(ServerMessage::SessionStats(self.session.stats()));
+        self.update_ratings();
+        self.report_match();
+        self.game.reset_points();
+    }
+
+    /// Players of the finished match, the winner first
+    fn match_standings(&self) -> Vec<&Player> {
+        Match::standings(&self.config, self.game.players.values().collect())
+    }
+
+    /// Lets the tournament know who won the match of this room.
+    fn report_match(&mut self) {
+        if self.tournament.is_none() {
+            return;
+        }
+        let players = self.match_standings();
+        let result = MatchResult {
+            room: self.name.clone(),
+            players: players.iter().map(|p| p.name.to_string()).collect(),
+            winner: players[0].name.to_string(),
+        };
+        if let Err(e) = self.match_results.unbounded_send(result) {
+            error!("Failed to report the match result: {}", e);
+        }
+    }
+
+    fn set_tournament(&mut self, state: TournamentState) {
+        self.tournament = Some(state.clone());
+        self.broadcast(ServerMessage::TournamentState(state));
+    }
+
+    /// Hashed tokens of the logged in players among `standings`, in the same order
+    fn account_keys(&self, standings: impl IntoIterator<Item = Uuid>) -> Vec<(Uuid, String)> {
+        standings
+            .into_iter()
+            .filter_map(|id| Some((id, self.players.get(&id)?.account.clone()?)))
+            .collect()
+    }
+
+    fn count_round(&mut self, winner: Uuid) {
+        let standings = self.account_keys(self.standings(winner));
+        if standings.is_empty() {
+            return;
+        }
+        let keys: Vec<&str> = standings.iter().map(|(_, key)| key.as_str()).collect();
+        self.accounts.lock().unwrap().record_round(&keys);
+    }
+
+    /// Rates the account holders by their places in the finished match.
+    fn update_ratings(&mut self) {
+        let placed: Vec<Uuid> = self.match_standings().iter().map(|p| p.uuid).collect();
+        let standings = self.account_keys(placed);
+        if standings.is_empty() {
+            return;
+        }
+        let keys: Vec<&str> = standings.iter().map(|(_, key)| key.as_str()).collect();
+        let ratings = self.accounts.lock().unwrap().record_match(&keys);
+        let changes: Vec<(Uuid, u32)> = standings.iter().map(|(id, _)| *id).zip(ratings).collect();
+        for (id, rating) in &changes {
+            if let Some(player) = self.game.players.get_mut(id) {
+                player.rating = Some(*rating);
+            }
+        }
+        self.broadcast(ServerMessage::RatingsChanged(changes));
+    }
+
+    fn do_freeze_tick(&mut self) {
+        let dt = self.dt();
+        self.game.tick_frozen(dt);
+        self.broadcast_state();
+        self.freeze_ticks -= 1;
+        if self.freeze_ticks.is_multiple_of(self.ticks_per_second()) {
+            let seconds_left = (self.freeze_ticks / self.ticks_per_second()) as u8;
+            self.broadcast(ServerMessage::Countdown(seconds_left));
+            if seconds_left == 0 {
+                self.broadcast(ServerMessage::RoundStarted(self.seed));
+                self.room_event(RoomEvent::RoundStarted(self.current_match.round()));
+            }
+        }
+    }
+
+    fn do_paused_tick(&mut self) {
+        // keep the clients updated once a second, the game itself stands still
+        self.paused_ticks += 1;
+        if self.paused_ticks.is_multiple_of(self.ticks_per_second()) {
+            self.broadcast_state();
+        }
+    }
+
+    /// Lets a running round finish within `seconds`, no new rounds are started afterwards.
+    /// Closes the room after the running round, `resumable` if the room is saved for a restart.
+    fn shutdown(&mut self, seconds: u32, resumable: bool) {
+        info!("Server is shutting down");
+        self.shutdown_ticks = Some(seconds as usize * self.ticks_per_second());
+        self.broadcast(ServerMessage::ServerShutdown(seconds, resumable));
+    }
+
+    fn close_connections(&mut self) {
+        let clients = self.connections.keys().chain(&self.spectators).copied();
+        self.effects.extend(clients.map(Effect::Disconnect));
+    }
+
+    fn announce(&mut self, text: &str) {
+        info!("Announcement: {}", text);
+        self.broadcast(ServerMessage::Announcement(text.to_string()));
+    }
+
+    /// Disconnects a player without the possibility to resume the session.
+    fn kick(&mut self, id: &Uuid, reason: &str) -> bool {
+        match self.players.get(id) {
+            Some(player) => {
+                info!("Kicking player `{}`: {}", player.name, reason);
+                let addr = player.addr;
+                self.send_to(addr, ServerMessage::Announcement(reason.to_string()));
+                self.effects.push(Effect::Disconnect(addr));
+                self.kicked.insert(*id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Disconnects all players for good.
+    fn close(&mut self) {
+        self.announce("This room was closed by an admin");
+        self.departed.clear();
+        self.kicked.extend(self.players.keys());
+        self.close_connections();
+    }
+
+    fn info(&self) -> admin::RoomInfo {
+        admin::RoomInfo {
+            name: self.name.clone(),
+            players: self
+                .players
+                .iter()
+                .map(|(id, player)| {
+                    let p = &self.game.players[id];
+                    admin::PlayerInfo {
+                        uuid: *id,
+                        name: player.name.clone(),
+                        host: p.host,
+                        points: p.points,
+                        dropped_moves: player.dropped_moves,
+                    }
+                })
+                .collect(),
+            config: self.config.clone(),
+            round_in_progress: self.round_in_progress(),
+        }
+    }
+
+    /// Advances the room by a tick and records how long that took, returns whether the room is
+    /// still running.
+    fn tick_once(&mut self) -> bool {
+        let start = Instant::now();
+        let running = self.step();
+        let elapsed = start.elapsed();
+        let slow = elapsed > SLOW_TICK;
+        self.metrics.record(elapsed, slow);
+        if slow {
+            warn!(
+                elapsed_ms = elapsed.as_millis() as u64,
+                players = self.players.len(),
+                spectators = self.spectators.len(),
+                width = self.config.width,
+                height = self.config.height,
+                "Slow tick"
+            );
+        }
+        running
+    }
+
+    fn step(&mut self) -> bool {
+        self.players.values_mut().for_each(|player| {
+            player.moves = 0;
+            player.sync_ticks = player.sync_ticks.saturating_sub(1);
+        });
+        self.send_full_syncs();
+        if let Some(recorder) = &mut self.recording {
+            recorder.tick();
+        }
+        self.check_afk();
+        self.check_auto_start();
+        self.check_ping();
+        self.check_traffic();
+        self.release_inputs();
+        if let Some(ticks) = self.shutdown_ticks {
+            if ticks == 0 || !self.round_in_progress() {
+                self.close_connections();
+                return self.running();
+            }
+            self.shutdown_ticks = Some(ticks - 1);
+        }
+        if self.running() {
+            if self.paused {
+                self.do_paused_tick();
+            } else if self.freeze_ticks > 0 {
+                self.do_freeze_tick();
+            } else if self.game.running() {
+                self.do_tick();
+            } else if self.ending.is_some() {
+                self.do_ending_tick();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Counts down to the next round once enough players that are not AFK wait in the lobby.
+    fn check_auto_start(&mut self) {
+        let ready = match self.config.auto_start {
+            Some(players) => {
+                !self.round_in_progress()
+                    && !self.auto_start_cancelled
+                    && self.shutdown_ticks.is_none()
+                    && self
+                        .players
+                        .values()
+                        .filter(|p| p.afk_since.is_none())
+                        .count()
+                        >= players
+            }
+            None => false,
+        };
+        let tps = self.ticks_per_second();
+        match self.auto_start_ticks {
+            None if ready => {
+                self.auto_start_ticks = Some(AUTO_START_SECONDS * tps);
+                self.broadcast(ServerMessage::AutoStartCountdown(Some(
+                    AUTO_START_SECONDS as u32,
+                )));
+            }
+            None => (),
+            Some(_) if !ready => {
+                self.auto_start_ticks = None;
+                self.broadcast(ServerMessage::AutoStartCountdown(None));
+            }
+            Some(0) => {
+                info!("Starting the round automatically");
+                self.on_start_game();
+            }
+            Some(ticks) => {
+                self.auto_start_ticks = Some(ticks - 1);
+                if (ticks - 1) % tps == 0 {
+                    let seconds = ((ticks - 1) / tps) as u32;
+                    self.broadcast(ServerMessage::AutoStartCountdown(Some(seconds)));
+                }
+            }
+        }
+    }
+
+    fn check_ping(&mut self) {
+        if self.ping.1.elapsed() < PING_INTERVAL {
+            return;
+        }
+        self.ping = (self.ping.0.wrapping_add(1), Instant::now());
+        let seq = self.ping.0;
+        let pings = self
+            .connections
+            .keys()
+            .map(|addr| Effect::Send(*addr, ServerMessage::Ping(seq)));
+        self.effects.extend(pings);
+    }
+
+    /// Tells the host what the room sent since the last report.
+    fn check_traffic(&mut self) {
+        let (bytes, messages, reported) = self.traffic_reported;
+        let elapsed = reported.elapsed();
+        if elapsed < TRAFFIC_INTERVAL {
+            return;
+        }
+        let (total_bytes, total_messages) = self.traffic.totals();
+        self.traffic_reported = (total_bytes, total_messages, Instant::now());
+        let host = match self.game.players.values().find(|player| player.host) {
+            Some(host) => host.uuid,
+            None => return,
+        };
+        let per_second = |count: u64| (count as f64 / elapsed.as_secs_f64()).round() as u64;
+        let stats = TrafficStats {
+            bytes_per_second: per_second(total_bytes - bytes),
+            messages_per_second: per_second(total_messages - messages),
+            total_bytes,
+            total_messages,
+            connections: (self.connections.len() + self.spectators.len()) as u32,
+        };
+        self.send(&host, ServerMessage::TrafficStats(stats));
+    }
+
+    fn on_pong(&mut self, id: &Uuid, seq: u32) {
+        if seq != self.ping.0 {
+            // too late, the next ping is out already
+            return;
+        }
+        let sample = self.ping.1.elapsed();
+        if let Some(player) = self.players.get_mut(id) {
+            player.rtt = Some(match player.rtt {
+                Some(rtt) => (rtt * 3 + sample) / 4,
+                None => sample,
+            });
+        }
+    }
+
+    /// How long the inputs of `id` are held back, so that everybody plays with the latency of
+    /// the slowest player. `None` if they are applied right away.
+    fn input_delay(&self, id: &Uuid) -> Option<Duration> {
+        if !self.config.equalize_latency {
+            return None;
+        }
+        let max = self
+            .players
+            .values()
+            .filter_map(|player| player.rtt)
+            .max()?
+            .min(MAX_EQUALIZED_RTT);
+        let rtt = self.players.get(id)?.rtt?;
+        Some(max.saturating_sub(rtt) / 2).filter(|delay| !delay.is_zero())
+    }
+
+    /// Holds an input back for `delay`. No more inputs are held back than could be applied in the
+    /// ticks of the delay, a flood of them is dropped instead of piling up.
+    fn delay_input(&mut self, id: Uuid, delay: Duration, msg: ClientMessage) {
+        let ticks = (delay.as_secs_f64() * self.config.tick_rate as f64).ceil() as usize + 1;
+        let player = self.players.get_mut(&id).unwrap();
+        if player.delayed.len() >= MAX_MOVES_PER_TICK as usize * ticks {
+            player.dropped_moves = player.dropped_moves.saturating_add(1);
+            return;
+        }
+        player.delayed.push_back((Instant::now() + delay, msg));
+    }
+
+    /// Applies the held back inputs that are due. While the round is paused they stay queued,
+    /// a released key must still arrive once it goes on.
+    fn release_inputs(&mut self) {
+        if self.paused {
+            return;
+        }
+        let now = Instant::now();
+        let mut due = vec![];
+        for (id, player) in &mut self.players {
+            while player.delayed.front().is_some_and(|(at, _)| *at <= now) {
+                due.extend(player.delayed.pop_front().map(|(_, msg)| (*id, msg)));
+            }
+        }
+        for (id, msg) in due {
+            self.on_input(id, msg);
+        }
+    }
+
+    fn on_input(&mut self, id: Uuid, msg: ClientMessage) {
+        let msg = match msg {
+            ClientMessage::Input(seq, input) => {
+                self.ack_input(&id, seq);
+                input.into()
+            }
+            msg => msg,
+        };
+        let input = match msg {
+            ClientMessage::Move(direction) => Some(Input::Move(direction)),
+            ClientMessage::Speed(change) => Some(Input::Speed(change)),
+            ClientMessage::UseGhost => Some(Input::UseGhost),
+            ClientMessage::UseItem => Some(Input::UseItem),
+            _ => None,
+        };
+        if let Some(input) = input {
+            // counted from the start of the countdown, which is played again as well
+            let countdown = COUNTDOWN_SECONDS as u32 * self.config.tick_rate;
+            let tick = countdown - self.freeze_ticks as u32 + self.round_ticks as u32;
+            if let Some(recording) = &mut self.recording {
+                recording.record_input(tick, id, input);
+            }
+            if let Some(ghost) = &mut self.ghost {
+                ghost.inputs.push((tick, id, input));
+            }
+        }
+        match msg {
+            ClientMessage::Move(direction) => self.on_move(id, direction),
+            ClientMessage::Speed(change) => {
+                if let Err(e) = self.game.on_speed(&id, change) {
+                    error!("Error occurd during speed change: {}", e);
+                }
+            }
+            ClientMessage::UseGhost => {
+                if let Err(e) = self.game.on_use_ghost(&id) {
+                    error!("Error occurd during ghost ability: {}", e);
+                }
+            }
+            ClientMessage::UseItem => {
+                if let Err(e) = self.game.on_use_item(&id) {
+                    error!("Error occurd while using an item: {}", e);
+                }
+            }
+            msg => warn!("Not an input: {:?}", msg),
+        }
+    }
+
+    fn on_cancel_auto_start(&mut self) {
+        self.auto_start_cancelled = true;
+        if self.auto_start_ticks.take().is_some() {
+            self.broadcast(ServerMessage::AutoStartCountdown(None));
+        }
+    }
+
+    /// Marks players without input as AFK while no round is running, warns AFK players and
+    /// finally removes them.
+    fn check_afk(&mut self) {
+        let lobby = !self.round_in_progress();
+        let mut afk = vec![];
+        let mut warn = vec![];
+        let mut remove = vec![];
+        for (id, player) in &mut self.players {
+            match player.afk_since {
+                None if lobby && player.last_input.elapsed() >= AFK_TIMEOUT => afk.push(*id),
+                None => (),
+                Some(since) if since.elapsed() >= AFK_REMOVAL => remove.push(*id),
+                Some(since)
+                    if since.elapsed() >= AFK_REMOVAL - AFK_WARNING && !player.afk_warned =>
+                {
+                    player.afk_warned = true;
+                    warn.push(*id);
+                }
+                Some(_) => (),
+            }
+        }
+        afk.iter().for_each(|id| self.set_afk(id, true));
+        for id in warn {
+            self.send(&id, ServerMessage::AfkWarning(AFK_WARNING.as_secs() as u32));
+        }
+        for id in remove {
+            self.kick(&id, "You were removed for inactivity");
+        }
+    }
+
+    fn set_afk(&mut self, id: &Uuid, afk: bool) {
+        let player = match self.players.get_mut(id) {
+            Some(player) => player,
+            None => return,
+        };
+        if player.afk_since.is_some() == afk {
+            return;
+        }
+        player.afk_since = if afk { Some(Instant::now()) } else { None };
+        player.afk_warned = false;
+        info!("`{}` is {}", player.name, if afk { "AFK" } else { "back" });
+        self.game.set_afk(id, afk);
+        self.broadcast(ServerMessage::PlayerAfk(*id, afk));
+    }
+
+    /// Any message sent by the player, except the automatic ones, shows that they are around.
+    fn on_activity(&mut self, id: &Uuid) {
+        if let Some(player) = self.players.get_mut(id) {
+            player.last_input = Instant::now();
+            player.input_this_round = true;
+        }
+        self.set_afk(id, false);
+    }
 
-        // create player for server
-        self.players.insert(
-            id,
-            PlayerServer {
-                name: player_name.clone(),
-                ws: Some(ws_tx.clone()),
-                player: player.clone(),
-            },
-        );
+    /// Remembers the latest input of `id` that was consumed, applied or ignored, so that its
+    /// client can drop it from the inputs it still expects to take effect.
+    fn ack_input(&mut self, id: &Uuid, seq: u32) {
+        if let Some(player) = self.players.get_mut(id) {
+            player.input_ack = player.input_ack.max(seq);
+        }
+    }
 
-        // tell other players that a player has joined
-        self.broadcast(ServerMessage::NewPlayer(*player.clone().lock().unwrap()));
-        Ok(())
+    fn broadcast_state(&mut self) {
+        self.seq = self.seq.wrapping_add(1);
+        let mut state = self.game.state();
+        for s in &mut state {
+            s.input_ack = self.players.get(&s.id).map_or(0, |player| player.input_ack);
+        }
+        self.broadcast(ServerMessage::GameState(self.seq, state));
     }
 
-    fn do_tick(&mut self) {
-        self.game.tick();
-        self.broadcast(ServerMessage::GameState(self.game.state()));
-        if let Some(winner) = self.game.get_winner() {
-            info!("[{}] Round has finished", self.name);
-            self.broadcast(ServerMessage::RoundEnded((winner, self.game.state_ended())));
+    fn send(&mut self, id: &Uuid, msg: ServerMessage) {
+        if let Some(player) = self.players.get(id) {
+            let addr = player.addr;
+            self.send_to(addr, msg);
         }
     }
 
-    fn tick_once(&mut self) -> bool {
-        if self.running() {
-            if self.game.running() {
-                self.do_tick();
-            }
-            true
-        } else {
-            false
+    /// Answers the requests for a full trail sync, at most one per player every
+    /// `FULL_SYNC_TICKS`. Encoding all trails is expensive, a client can't make the room do it for
+    /// every message.
+    fn send_full_syncs(&mut self) {
+        let due: Vec<SocketAddr> = self
+            .players
+            .values_mut()
+            .filter(|player| player.sync_requested && player.sync_ticks == 0)
+            .map(|player| {
+                player.sync_requested = false;
+                player.sync_ticks = FULL_SYNC_TICKS;
+                player.addr
+            })
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+        let sync = self.game.trail_sync();
+        for addr in due {
+            self.send_to(addr, ServerMessage::FullTrailSync(self.seq, sync.clone()));
         }
     }
 
-    fn broadcast(&self, msg: ServerMessage) {
-        self.connections.values().for_each(|id| {
-            if let Some(ws) = &self.players.get(id).unwrap().ws {
-                if let Err(e) = ws.unbounded_send(msg.clone()) {
-                    error!(
-                        "[{}] Failed to send broadast to {}: {}",
-                        self.name,
-                        self.players.get(id).unwrap().name,
-                        e
-                    );
-                }
-            } else {
-                error!(
-                    "[{}] Failed to send broadast to player uuid {}",
-                    self.name, id
-                )
-            }
-        });
+    fn send_to(&mut self, addr: SocketAddr, msg: ServerMessage) {
+        self.effects.push(Effect::Send(addr, msg));
+    }
+
+    fn broadcast(&mut self, msg: ServerMessage) {
+        if let Some(recorder) = &mut self.recording {
+            recorder.record(&msg);
+        }
+        self.effects.push(Effect::Broadcast(msg));
+    }
+
+    /// Advances the room by `event` without touching any connection. Returns whether the room is
+    /// still running, along with what has to happen to the connections of its clients.
+    fn apply(&mut self, event: Event) -> (bool, Vec<Effect>) {
+        let running = match event {
+            Event::Message(addr, msg) => self.on_message(addr, msg),
+            Event::Tick => self.tick_once(),
+        };
+        (running, self.take_effects())
+    }
+
+    /// The effects of everything since the last call, for changes made outside of `apply`
+    fn take_effects(&mut self) -> Vec<Effect> {
+        std::mem::take(&mut self.effects)
     }
 
     fn on_client_disconnected(&mut self, addr: SocketAddr) {
         if let Some(id) = self.connections.remove(&addr) {
-            let player = self.players.get(&id).unwrap();
-            let host = { player.player.lock().unwrap().host };
-            info!(
-                "[{}] Removed disconnected player `{}`",
-                self.name,
-                player.name.clone()
-            );
-            self.game.remove_player(&id);
-            self.players.remove(&id).unwrap();
-            if self.game.running() {
+            self.effects.push(Effect::Disconnect(addr));
+            let player = self.players.remove(&id).unwrap();
+            info!("Removed disconnected player `{}`", player.name.clone());
+            let snapshot = self.game.remove_player(&id).unwrap();
+            let host = snapshot.host;
+
+            // keep the slot around in case the player reconnects
+            self.departed
+                .retain(|_, (_, _, left)| left.elapsed() < RESUME_GRACE);
+            if !self.kicked.remove(&id) {
+                self.departed
+                    .insert(player.token, (snapshot, player.account, Instant::now()));
+            }
+            if self.freeze_ticks == 0 && !self.paused && self.game.running() {
                 self.do_tick();
             }
+            if self.paused && !self.round_in_progress() {
+                // nothing left to resume
+                self.paused = false;
+                self.broadcast(ServerMessage::RoundResumed);
+            }
 
             let id_host = if host {
-                info!("[{}] Assinging a new host...", self.name);
+                info!("Assinging a new host...");
                 // we need a new host
-                match self.players.iter_mut().next() {
-                    Some((id, player)) => {
-                        player.player.lock().unwrap().host = true;
-                        *id
+                match self.game.players.values_mut().next() {
+                    Some(player) => {
+                        player.host = true;
+                        player.uuid
                     }
                     None => id.clone(),
                 }
@@ -236,51 +1427,365 @@ impl Room {
                 id.clone()
             };
 
-            self.broadcast(ServerMessage::PlayerDisconnected(id, id_host))
+            self.broadcast(ServerMessage::PlayerDisconnected(id, id_host));
+            self.room_event(RoomEvent::PlayerLeft(player.name.clone()));
+            if id_host != id {
+                self.room_event(RoomEvent::HostChanged(self.player_name(&id_host)));
+            }
+            if self.connections.is_empty() {
+                // nothing left to watch, the room closes
+                self.close_connections();
+            }
+        } else if self.spectators.remove(&addr) {
+            self.effects.push(Effect::Disconnect(addr));
+            info!("Spectator left, {} watching", self.spectators.len());
+            self.broadcast(ServerMessage::SpectatorsChanged(
+                self.spectators.len() as u32
+            ));
+        }
+    }
+
+    /// A client going back to the lobby, unlike after a lost connection there is no session to
+    /// resume.
+    fn on_leave(&mut self, addr: SocketAddr) {
+        if let Some(id) = self.connections.get(&addr) {
+            self.kicked.insert(*id);
         }
+        self.on_client_disconnected(addr);
     }
 
     fn on_start_game(&mut self) {
         // initialize game
+        self.seed = rand::random();
+        let afk: Vec<Uuid> = self
+            .players
+            .iter()
+            .filter(|(_, player)| player.afk_since.is_some())
+            .map(|(id, _)| *id)
+            .collect();
+        let countdown = COUNTDOWN_SECONDS as u32 * self.config.tick_rate;
+        self.ghost = Some(GhostRun {
+            runner: Uuid::nil(), // known once the round is decided
+            config: self.config.clone(),
+            players: self.game.players.values().copied().collect(),
+            afk: afk.clone(),
+            seed: self.seed,
+            countdown,
+            inputs: vec![],
+            ticks: 0,
+        });
+        self.game.seed(self.seed);
         self.game.initialize();
+        self.ending = None;
+        self.auto_start_ticks = None;
+        self.auto_start_cancelled = false;
+        self.round_ticks = 0;
+        self.deaths.clear();
+        self.players
+            .values_mut()
+            .for_each(|player| player.input_this_round = false);
+        self.paused = false;
+        self.recording = Some(Recorder::new(Replay {
+            grid_info: self.game.grid_info(),
+            players: self.game.players.values().copied().collect(),
+            config: self.config.clone(),
+            messages: vec![],
+            seed: self.seed,
+            afk,
+            countdown,
+            inputs: vec![],
+            ticks: 0,
+        }));
+
+        self.broadcast_state();
+        // replaces the hazards of the last round, also if there are none
+        self.broadcast(ServerMessage::HazardsMoved(self.game.hazards().to_vec()));
+
+        // freeze positions during the countdown
+        self.freeze_ticks = COUNTDOWN_SECONDS as usize * self.ticks_per_second();
+        self.broadcast(ServerMessage::Countdown(COUNTDOWN_SECONDS));
+    }
+
+    fn on_move(&mut self, id: Uuid, direction: Direction) {
+        if !self.game.is_alive(&id) {
+            // dead players have nothing left to steer
+            return;
+        }
+        let player = self.players.get_mut(&id).unwrap();
+        // a flood of moves must neither overflow nor wrap around to zero
+        player.moves = player.moves.saturating_add(1);
+        if player.moves > MAX_MOVES_PER_TICK {
+            player.dropped_moves = player.dropped_moves.saturating_add(1);
+            // flag the player without flooding the log
+            if player.dropped_moves.is_power_of_two() {
+                warn!(
+                    "Dropping moves of `{}`, {} moves were too fast so far",
+                    player.name, player.dropped_moves
+                );
+            }
+            return;
+        }
+        if let Err(e) = self.game.on_move(&id, direction) {
+            error!("Error occurd during move: {}", e);
+        }
+    }
+
+    fn on_emote(&mut self, id: Uuid, emote: EmoteId) {
+        let player = self.players.get_mut(&id).unwrap();
+        if player
+            .last_emote
+            .is_some_and(|last| last.elapsed() < EMOTE_INTERVAL)
+        {
+            warn!("Dropping emote of `{}`, too many emotes", player.name);
+            return;
+        }
+        player.last_emote = Some(Instant::now());
+        self.broadcast(ServerMessage::Emote(id, emote));
+    }
+
+    fn on_chat(&mut self, id: Uuid, text: String) {
+        let player = self.players.get_mut(&id).unwrap();
+        if player
+            .last_chat
+            .is_some_and(|last| last.elapsed() < CHAT_INTERVAL)
+        {
+            warn!(
+                "Dropping chat message of `{}`, too many messages",
+                player.name
+            );
+            return;
+        }
+        let text: String = text.trim().chars().take(MAX_CHAT_LENGTH).collect();
+        if text.is_empty() {
+            return;
+        }
+        player.last_chat = Some(Instant::now());
+        let msg = ChatMessage {
+            sender: id,
+            name: player.name.clone(),
+            text,
+        };
+        if self.chat.len() == CHAT_HISTORY {
+            self.chat.pop_front();
+        }
+        self.chat.push_back(msg.clone());
+        self.broadcast(ServerMessage::Chat(msg));
+    }
+
+    fn on_pause(&mut self, pause: bool) {
+        if !self.round_in_progress() {
+            warn!("There is no round to pause or resume");
+            return;
+        }
+        if self.paused == pause {
+            return;
+        }
+        self.paused = pause;
+        if pause {
+            info!("Round paused");
+            self.paused_ticks = 0;
+            self.broadcast(ServerMessage::RoundPaused);
+        } else {
+            info!("Round resumed");
+            self.broadcast(ServerMessage::RoundResumed);
+        }
+    }
+
+    fn check_config(&self, config: &RoomConfig) -> Result<(), String> {
+        if self.round_in_progress() {
+            return Err("The config can't be changed during a round".into());
+        }
+        config.validate()?;
+        if config.max_players < self.players.len() {
+            return Err(format!(
+                "There are already {} players in the room",
+                self.players.len()
+            ));
+        }
+        Ok(())
+    }
 
-        self.broadcast(ServerMessage::GameState(self.game.state()));
-        self.broadcast(ServerMessage::RoundStarted);
-        self.initialized = true;
+    fn on_update_config(&mut self, id: Uuid, config: RoomConfig) {
+        if let Err(reason) = self.check_config(&config) {
+            warn!("Rejected config change: {}", reason);
+            self.send(&id, ServerMessage::ConfigRejected(reason));
+            return;
+        }
+        info!("Changing config to {:?}", config);
+        if (config.width, config.height) != (self.config.width, self.config.height) {
+            self.game.resize(config.width, config.height);
+            self.broadcast(ServerMessage::GridChanged(self.game.grid_info()));
+        }
+        self.game
+            .set_handling(config.preset.speed(), config.preset.turn_rate());
+        self.config.width = config.width;
+        self.config.height = config.height;
+        self.config.preset = config.preset;
+        let palette = self.palette();
+        self.config.max_players = config.max_players;
+        self.config.target_score = config.target_score;
+        self.config.best_of = config.best_of;
+        self.config.trail_lifetime = config.trail_lifetime;
+        self.game.trail_lifetime = config.trail_lifetime;
+        self.config.auto_start = config.auto_start;
+        self.config.equalize_latency = config.equalize_latency;
+        self.config.hazards = config.hazards;
+        self.game.hazards_enabled = config.hazards;
+        self.config.growing_trails = config.growing_trails;
+        self.config.gaps = config.gaps;
+        self.game.set_gaps(config.gaps);
+        self.game.growing_trails = config.growing_trails;
+        self.config.hitbox = config.hitbox;
+        self.game.hitbox = config.hitbox;
+        self.config.ghost = config.ghost;
+        self.game.set_ghost(config.ghost);
+        if config.tick_rate != self.config.tick_rate {
+            // between rounds only the countdowns to the auto start and the shutdown can run, they
+            // keep their time in seconds, the tick of the room picks the new rate up by itself
+            let (from, to) = (self.ticks_per_second(), config.tick_rate as usize);
+            let rescale = |ticks: usize| ticks * to / from;
+            self.auto_start_ticks = self.auto_start_ticks.map(rescale);
+            self.shutdown_ticks = self.shutdown_ticks.map(rescale);
+            self.config.tick_rate = config.tick_rate;
+        }
+        self.config.items = config.items;
+        self.game.items_enabled = config.items;
+        self.config.item_inventory = config.item_inventory;
+        self.game.item_inventory = config.item_inventory;
+        self.config.scoring = config.scoring;
+        self.game.scoring = config.scoring;
+        self.config.map = config.map;
+        self.game.set_map(config.map);
+        self.config.theme = config.theme;
+        self.config.title = config.title;
+        let recolored = self.palette() != palette;
+        if recolored {
+            self.recolor(&palette);
+        }
+        self.broadcast(ServerMessage::ConfigChanged(self.config.clone()));
+        if recolored {
+            let colors = self
+                .game
+                .players
+                .iter()
+                .map(|(id, player)| (*id, player.color, player.pattern))
+                .collect();
+            self.broadcast(ServerMessage::PlayerColors(colors));
+        }
     }
 
     fn on_message(&mut self, addr: SocketAddr, msg: ClientMessage) -> bool {
         info!(
-            "[{}] Got message from `{}`: {:?}",
-            self.name,
+            "Got message from `{}`: {:?}",
             self.connections
                 .get(&addr)
                 .map(|id| self.players.get(id).unwrap().name.clone())
                 .unwrap_or_else(|| format!("unknown player at {}", addr)),
             msg
         );
+        if !matches!(
+            msg,
+            ClientMessage::Disconnected | ClientMessage::RequestFullSync | ClientMessage::Pong(_)
+        ) {
+            if let Some(id) = self.connections.get(&addr).copied() {
+                self.on_activity(&id);
+            }
+        }
         match msg {
-            ClientMessage::Move(direction) => {
-                if let Some(id) = self.connections.get(&addr) {
-                    let player = &self.players.get(id).unwrap();
-                    let uuid = { player.player.lock().unwrap().uuid };
-                    if let Err(e) = self.game.on_move(&uuid, direction) {
-                        error!("[{}] Error occurd during move: {}", self.name, e);
+            ClientMessage::Move(_)
+            | ClientMessage::Speed(_)
+            | ClientMessage::UseGhost
+            | ClientMessage::UseItem
+            | ClientMessage::Input(..)
+                if self.paused =>
+            {
+                // inputs are ignored while the round is paused
+                if let (ClientMessage::Input(seq, _), Some(id)) =
+                    (&msg, self.connections.get(&addr).copied())
+                {
+                    self.ack_input(&id, *seq);
+                }
+            }
+            ClientMessage::Move(_)
+            | ClientMessage::Speed(_)
+            | ClientMessage::UseGhost
+            | ClientMessage::UseItem
+            | ClientMessage::Input(..) => {
+                if let Some(id) = self.connections.get(&addr).copied() {
+                    match self.input_delay(&id) {
+                        Some(delay) => self.delay_input(id, delay, msg),
+                        None => self.on_input(id, msg),
                     }
                 }
             }
-            ClientMessage::CreateRoom(_) | ClientMessage::JoinRoom(_, _) => {
-                warn!("[{}] Invalid message", self.name);
+            ClientMessage::Pong(seq) => {
+                if let Some(id) = self.connections.get(&addr).copied() {
+                    self.on_pong(&id, seq);
+                }
+            }
+            ClientMessage::CreateRoom(_)
+            | ClientMessage::JoinRoom(_, _)
+            | ClientMessage::ResumeSession(_, _)
+            | ClientMessage::Register(_)
+            | ClientMessage::Login(_)
+            | ClientMessage::Spectate(_)
+            | ClientMessage::QuickPlay(_) => {
+                warn!("Invalid message");
             }
             ClientMessage::Disconnected => self.on_client_disconnected(addr),
+            ClientMessage::LeaveRoom => self.on_leave(addr),
+            ClientMessage::Emote(emote) => {
+                if let Some(id) = self.connections.get(&addr).copied() {
+                    self.on_emote(id, emote);
+                }
+            }
+            ClientMessage::Chat(text) => {
+                if let Some(id) = self.connections.get(&addr).copied() {
+                    self.on_chat(id, text);
+                }
+            }
+            ClientMessage::RequestFullSync => {
+                if let Some(id) = self.connections.get(&addr) {
+                    self.players.get_mut(id).unwrap().sync_requested = true;
+                    self.send_full_syncs();
+                }
+            }
             ClientMessage::StartGame => {
                 if let Some(id) = self.connections.get(&addr) {
-                    let player = &self.players.get(id).unwrap();
-                    if player.player.lock().unwrap().host {
+                    if self.shutdown_ticks.is_some() {
+                        warn!("Not starting a round during shutdown");
+                    } else if self.is_host(id) {
                         // valid
                         self.on_start_game();
                     } else {
-                        warn!("[{}] Only the host can start a game", self.name);
+                        warn!("Only the host can start a game");
+                    }
+                }
+            }
+            ClientMessage::CancelAutoStart => {
+                if let Some(id) = self.connections.get(&addr) {
+                    if self.is_host(id) {
+                        self.on_cancel_auto_start();
+                    } else {
+                        warn!("Only the host can cancel the auto start");
+                    }
+                }
+            }
+            ClientMessage::PauseGame | ClientMessage::ResumeGame => {
+                if let Some(id) = self.connections.get(&addr) {
+                    if self.is_host(id) {
+                        self.on_pause(matches!(msg, ClientMessage::PauseGame));
+                    } else {
+                        warn!("Only the host can pause a round");
+                    }
+                }
+            }
+            ClientMessage::UpdateConfig(config) => {
+                if let Some(id) = self.connections.get(&addr).copied() {
+                    if self.is_host(&id) {
+                        self.on_update_config(id, config);
+                    } else {
+                        warn!("Only the host can change the config");
                     }
                 }
             }
@@ -291,16 +1796,47 @@ impl Room {
 
 struct PlayerServer {
     name: String,
-    ws: Option<UnboundedSender<ServerMessage>>,
-    player: Arc<Mutex<Player>>,
+    token: Uuid,      // secret for resuming the session after a lost connection
+    addr: SocketAddr, // of the connection, messages to the player go there
+    last_emote: Option<Instant>,
+    last_chat: Option<Instant>,
+    moves: u8,               // direction changes since the last tick
+    dropped_moves: u32,      // direction changes dropped for being too fast
+    account: Option<String>, // hashed token of the logged in account
+    last_input: Instant,
+    input_this_round: bool,
+    afk_since: Option<Instant>,
+    afk_warned: bool,      // whether the player was told about the upcoming removal
+    rtt: Option<Duration>, // smoothed round-trip time
+    delayed: VecDeque<(Instant, ClientMessage)>, // inputs held back to equalize the latency
+    input_ack: u32,        // number of the last numbered input applied, sent back in `GameState`
+    sync_ticks: usize,     // until another full trail sync may be sent
+    sync_requested: bool,  // answered once `sync_ticks` ran out
+}
+
+/// The room a player typed the code of, codes from before they were upper case only are still
+/// found by their exact name.
+fn find_room(rooms: &HashMap<String, RoomHandle>, name: &str) -> Option<RoomHandle> {
+    rooms
+        .get(name)
+        .or_else(|| rooms.get(&normalize_room_code(name)))
+        .cloned()
+}
+
+/// The open room whose players are rated closest to `rating`, for quick play.
+fn closest_room(rooms: &HashMap<String, RoomHandle>, rating: u32) -> Option<RoomHandle> {
+    rooms
+        .values()
+        .filter_map(|handle| Some((handle, handle.room.lock().unwrap().matchmaking_rating()?)))
+        .min_by_key(|(_, average)| average.abs_diff(rating))
+        .map(|(handle, _)| handle.clone())
 }
 
 fn next_room_name(rooms: &mut HashMap<String, RoomHandle>, handle: RoomHandle) -> String {
+    let mut rng = rand::thread_rng();
     loop {
-        let candidate: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(7)
-            .map(char::from)
+        let candidate: String = (0..ROOM_CODE_LENGTH)
+            .map(|_| *ROOM_CODE_ALPHABET.choose(&mut rng).unwrap() as char)
             .collect();
         use std::collections::hash_map::Entry;
         if let Entry::Vacant(v) = rooms.entry(candidate.clone()) {
@@ -310,65 +1846,236 @@ fn next_room_name(rooms: &mut HashMap<String, RoomHandle>, handle: RoomHandle) -
     }
 }
 
+#[instrument(
+    name = "room",
+    skip_all,
+    fields(name = %handle.room.lock().unwrap().name, player)
+)]
 async fn run_player(
-    player_name: String,
+    request: JoinRequest,
     addr: SocketAddr,
     handle: RoomHandle,
     ws_stream: WebSocketStream<Async<TcpStream>>,
-) {
-    let (incoming, outgoing) = ws_stream.split();
+) -> Option<WebSocketStream<Async<TcpStream>>> {
+    let (mut incoming, mut outgoing) = ws_stream.split();
 
-    let (ws_tx, ws_rx) = unbounded();
+    let (ws_tx, ws_rx, stalled) = outbox();
 
-    {
-        // lock the room to add the player
-        let room = &mut handle.room.lock().unwrap();
-        if let Err(e) = room.add_player(addr, player_name.clone(), ws_tx) {
-            error!("[{}] Failed to add player: {:?}", room.name, e);
-            return;
+    let player_name = {
+        match handle.join(addr, request, ws_tx) {
+            Ok((player_name, id)) => {
+                Span::current().record("player", display(id));
+                player_name
+            }
+            Err(e) => {
+                error!("Failed to add player: {:?}", e);
+                return None;
+            }
         }
-    }
+    };
 
     let write = handle.write.clone();
-    let ra = ws_rx
-        .map(|c| bincode::serialize(&c).unwrap_or_else(|_| panic!("Could not encode {:?}", c)))
-        .map(Message::Binary)
-        .map(Ok)
-        .forward(incoming);
-    let rb = outgoing
-        .map(|m| match m {
-            Ok(Message::Binary(t)) => bincode::deserialize::<ClientMessage>(&t).ok(),
-            _ => None,
-        })
-        .take_while(|m| future::ready(m.is_some()))
-        .map(|m| m.unwrap())
-        .chain(futures::stream::once(async { ClientMessage::Disconnected }))
-        .map(move |m| Ok((addr, m)))
-        .forward(write);
-    let (ra, rb) = join(ra, rb).await;
-
-    if let Err(e) = ra {
-        error!(
-            "[{}] Got error {} from player {}'s rx queue",
-            addr, e, player_name
-        );
+    let disconnected = handle.write.clone();
+    // the client went back to the lobby, its WebSocket stays open for the next room
+    let left = &AtomicBool::new(false);
+    let mut frames = ws_rx
+        // tungstenite wants an owned buffer, copying is still cheaper than serializing
+        .map(|bytes: Bytes| Message::Binary(bytes.to_vec()))
+        .map(Ok);
+    let ra = async {
+        incoming.send_all(&mut frames).await?;
+        if !left.load(Ordering::SeqCst) {
+            incoming.close().await?;
+        }
+        Ok::<_, async_tungstenite::tungstenite::Error>(())
+    };
+    // ends with the message that takes the client out of the room, without reading past it
+    let rb = async {
+        let mut write = write;
+        loop {
+            let msg = match outgoing.next().await {
+                Some(Ok(Message::Binary(t))) => bincode::deserialize::<ClientMessage>(&t).ok(),
+                _ => None,
+            }
+            .unwrap_or(ClientMessage::Disconnected);
+            let ended = matches!(msg, ClientMessage::Disconnected | ClientMessage::LeaveRoom);
+            if matches!(msg, ClientMessage::LeaveRoom) {
+                left.store(true, Ordering::SeqCst);
+            }
+            write.send((addr, msg)).await?;
+            if ended {
+                return Ok::<_, SendError>(());
+            }
+        }
+    };
+    match Abortable::new(join(ra, rb), stalled).await {
+        Ok((ra, rb)) => {
+            if let Err(e) = ra {
+                error!("Got error {} from player {}'s rx queue", e, player_name);
+            }
+            if let Err(e) = rb {
+                error!("Got error {} from player {}'s tx queue", e, player_name);
+            }
+        }
+        Err(Aborted) => {
+            // the messages of the player were never forwarded to the end
+            warn!("Dropping the stalled connection of {}", player_name);
+            if let Err(e) = disconnected.unbounded_send((addr, ClientMessage::Disconnected)) {
+                error!("Could not remove stalled player {}: {}", player_name, e);
+            }
+        }
     }
-    if let Err(e) = rb {
-        error!(
-            "[{}] Got error {} from player {}'s tx queue",
-            addr, e, player_name
-        );
+    info!("Finished session with {}", player_name);
+    if !left.load(Ordering::SeqCst) {
+        return None;
+    }
+    incoming.reunite(outgoing).ok()
+}
+
+/// State shared by all connections
+#[derive(Clone)]
+struct Shared {
+    rooms: RoomList,
+    default_config: DefaultConfig,
+    accounts: AccountList,
+    replays: ReplayList,
+    match_results: UnboundedSender<MatchResult>,
+    close_room: UnboundedSender<String>,
+    client: Option<Arc<ClientFiles>>, // single-port mode, see `http`
+    metrics: Metrics,
+    bans: BanList,
+    throttle: ThrottleHandle, // of the connection attempts
+}
+
+/// Removes the rooms whose names are sent to the returned channel.
+fn close_rooms(rooms: RoomList) -> UnboundedSender<String> {
+    let (tx, mut rx) = unbounded();
+    Task::spawn(async move {
+        while let Some(room) = rx.next().await {
+            info!(%room, "Room closed");
+            rooms.lock().unwrap().remove(&room);
+        }
+    })
+    .detach();
+    tx
+}
+
+/// Runs a room in its own task until it closes, independent of the connection that created it.
+fn spawn_room(
+    handle: RoomHandle,
+    read: UnboundedReceiver<(SocketAddr, ClientMessage)>,
+    mut close_room: UnboundedSender<String>,
+) {
+    let name = handle.room.lock().unwrap().name.clone();
+    let span = info_span!("room", name = %name);
+    Task::spawn(
+        async move {
+            // nobody might come back to send a message, so the ticks decide when it closes
+            future::select(
+                Box::pin(handle.clone().tick()),
+                Box::pin(handle.clone().run_room(read)),
+            )
+            .await;
+            info!("All players left, closing room");
+            if let Err(e) = close_room.send(name).await {
+                error!("Failed to close room: `{}`", e);
+            }
+        }
+        .instrument(span),
+    )
+    .detach();
+}
+
+/// Accepts connections on one address, all listeners share the rooms. Connections of banned
+/// addresses or beyond the limit per address are closed right away.
+async fn listen(listener: Async<TcpListener>, shared: Shared) {
+    loop {
+        match listener.accept().await {
+            Ok((_, addr)) if shared.bans.lock().unwrap().is_banned(addr.ip()) => {
+                warn!("Refused connection from banned {}", addr);
+            }
+            Ok((_, addr)) if !shared.throttle.lock().unwrap().allow(addr.ip()) => {
+                warn!("Refused connection from {}, too many attempts", addr);
+            }
+            Ok((stream, addr)) => accept(stream, addr, shared.clone()),
+            Err(e) => error!("Failed to accept a connection: {}", e),
+        }
     }
-    info!("[{}] Finished session with {}", addr, player_name);
+}
+
+/// Upgrades a new connection to a WebSocket and handles it in its own task.
+fn accept(stream: Async<TcpStream>, addr: SocketAddr, shared: Shared) {
+    info!("Got connection from {}", addr);
+    let span = info_span!("connection", %addr);
+    Task::spawn(
+        async move {
+            let ws_stream = match &shared.client {
+                Some(client) => http::serve(stream, &shared, client).await,
+                None => async_tungstenite::accept_async(stream)
+                    .await
+                    .map(Some)
+                    .map_err(anyhow::Error::from),
+            };
+            match ws_stream {
+                Err(e) => {
+                    error!("Could not get stream: {}", e);
+                }
+                Ok(None) => (), // a plain HTTP request
+                Ok(Some(ws_stream)) => {
+                    info!("Reading incoming stream...");
+                    if let Err(e) = read_stream(ws_stream, addr, shared).await {
+                        error!("Failed to read stream: {}", e);
+                    }
+                }
+            };
+        }
+        .instrument(span),
+    )
+    .detach();
+}
+
+/// Opens a new room for `player_name` to join.
+fn create_room(shared: &Shared, player_name: &str) -> RoomHandle {
+    let (write, read) = unbounded();
+    let room = Room::new(
+        "Testing Room".into(),
+        shared.default_config.lock().unwrap().clone(),
+        shared.accounts.clone(),
+        shared.replays.clone(),
+        shared.match_results.clone(),
+        shared.metrics.clone(),
+    );
+    let handle = RoomHandle::new(room, write);
+
+    let room_name = next_room_name(&mut shared.rooms.lock().unwrap(), handle.clone());
+    info!("Creating room `{}` for player {}", room_name, player_name);
+    handle.room.lock().unwrap().name = room_name;
+
+    // the room outlives its creator, who joins before its first tick
+    spawn_room(handle.clone(), read, shared.close_room.clone());
+    handle
 }
 
 async fn read_stream(
     mut stream: WebSocketStream<Async<TcpStream>>,
     addr: SocketAddr,
-    rooms: RoomList,
-    mut close_room: UnboundedSender<String>,
+    shared: Shared,
 ) -> Result<()> {
-    // do something when connected
+    let Shared {
+        rooms, accounts, ..
+    } = &shared;
+    // hashed token of the account this connection logged into
+    let mut account = None;
+    let mut registered: Option<Instant> = None;
+    // runs the client in a room, which hands the stream back if it left for another one
+    macro_rules! enter {
+        ($request:expr, $handle:expr) => {
+            match run_player($request, addr, $handle, stream).await {
+                Some(back) => stream = back,
+                None => return Ok(()),
+            }
+        };
+    }
 
     // read client messages
     while let Some(Ok(Message::Binary(t))) = stream.next().await {
@@ -376,62 +2083,65 @@ async fn read_stream(
         info!("Received and deserialized msg");
         match msg {
             ClientMessage::CreateRoom(player_name) => {
-                // create room
-                let (write, read) = unbounded();
-                let room = Arc::new(Mutex::new(Room::new(
-                    "Testing Room".into(),
-                    1000, // width
-                    800,  // height
-                    6,    // line width in px
-                    8.,   // rotation delta in deg
-                )));
-                let handle = RoomHandle {
-                    play: false,
-                    write,
-                    room,
-                };
-
-                let room_name = next_room_name(&mut rooms.lock().unwrap(), handle.clone());
-                info!(
-                    "[{}] Creating room `{}` for player {}",
-                    addr, room_name, player_name
-                );
-                handle.room.lock().unwrap().name = room_name.clone();
-
-                //let mut h = handle.clone();
-
-                join(
-                    handle.clone().tick(),
-                    join(
-                        handle.clone().run_room(read),
-                        run_player(player_name, addr, handle, stream),
-                    ),
-                )
-                .await;
-
-                info!("[{}] All players left, closing room", room_name);
-                if let Err(e) = close_room.send(room_name.clone()).await {
-                    error!("[{}] Failed to close room: `{}`", room_name, e);
-                }
-
-                return Ok(());
+                let handle = create_room(&shared, &player_name);
+                enter!(JoinRequest::New(player_name, account.clone()), handle);
             }
             ClientMessage::JoinRoom(player_name, room_name) => {
                 info!(
-                    "[{}] Player `{}` tries to join room `{}`",
-                    addr, player_name, room_name
+                    "Player `{}` tries to join room `{}`",
+                    player_name, room_name
                 );
 
-                let handle = rooms.lock().unwrap().get_mut(&room_name).cloned();
+                let handle = find_room(&rooms.lock().unwrap(), &room_name);
 
                 if let Some(h) = handle {
                     // room exists
-                    // TODO: check for maximum amount of clients?
-                    run_player(player_name, addr, h, stream).await;
-                    return Ok(());
+                    if h.room.lock().unwrap().is_full() {
+                        warn!("Room `{}` is full!", room_name);
+                        let msg =
+                            ServerMessage::JoinFailed(format!("Room `{}` is full", room_name));
+                        stream
+                            .send(Message::Binary(bincode::serialize(&msg)?))
+                            .await?;
+                        continue;
+                    }
+                    enter!(JoinRequest::New(player_name, account.clone()), h);
                 } else {
                     // room doesn't exist
-                    warn!("[{}] Room `{}` does not exist!", addr, room_name);
+                    warn!("Room `{}` does not exist!", room_name);
+                    let msg =
+                        ServerMessage::JoinFailed(format!("Room `{}` does not exist", room_name));
+                    stream
+                        .send(Message::Binary(bincode::serialize(&msg)?))
+                        .await?;
+                }
+            }
+            ClientMessage::QuickPlay(player_name) => {
+                let rating = account
+                    .as_ref()
+                    .and_then(|key| Some(accounts.lock().unwrap().get(key)?.stats.rating))
+                    .unwrap_or_else(|| AccountStats::default().rating);
+                let found = closest_room(&rooms.lock().unwrap(), rating);
+                let handle = match found {
+                    Some(handle) => {
+                        info!(
+                            "Quick play sends `{}` to room `{}`",
+                            player_name,
+                            handle.room.lock().unwrap().name
+                        );
+                        handle
+                    }
+                    None => create_room(&shared, &player_name),
+                };
+                enter!(JoinRequest::New(player_name, account.clone()), handle);
+            }
+            ClientMessage::Spectate(room_name) => {
+                info!("Spectating room `{}`", room_name);
+                let handle = find_room(&rooms.lock().unwrap(), &room_name);
+                if let Some(h) = handle {
+                    enter!(JoinRequest::Spectate, h);
+                } else {
+                    warn!("Room `{}` does not exist!", room_name);
                     let msg =
                         ServerMessage::JoinFailed(format!("Room `{}` does not exist", room_name));
                     stream
@@ -439,63 +2149,230 @@ async fn read_stream(
                         .await?;
                 }
             }
+            ClientMessage::ResumeSession(room_name, token) => {
+                info!("Resuming session in room `{}`", room_name);
+
+                let handle = find_room(&rooms.lock().unwrap(), &room_name)
+                    .filter(|h| h.room.lock().unwrap().can_resume(&token));
+
+                if let Some(h) = handle {
+                    enter!(JoinRequest::Resume(token), h);
+                } else {
+                    warn!("Session in room `{}` has expired", room_name);
+                    let msg = ServerMessage::JoinFailed("Your session has expired".into());
+                    stream
+                        .send(Message::Binary(bincode::serialize(&msg)?))
+                        .await?;
+                }
+            }
+            ClientMessage::Register(name) => {
+                let result = match registered {
+                    Some(at) if at.elapsed() < REGISTER_INTERVAL => {
+                        warn!("Refused to register another account so soon");
+                        Err("Wait a moment before registering again".to_string())
+                    }
+                    _ => accounts.lock().unwrap().register(&name),
+                };
+                if let Ok((token, _)) = &result {
+                    account = Some(accounts::key(token));
+                    registered = Some(Instant::now());
+                }
+                let msg = ServerMessage::AuthResult(result);
+                stream
+                    .send(Message::Binary(bincode::serialize(&msg)?))
+                    .await?;
+            }
+            ClientMessage::Login(token) => {
+                let result = match accounts.lock().unwrap().login(&token) {
+                    Some(found) => {
+                        info!("Logged into account `{}`", found.name);
+                        account = Some(accounts::key(&token));
+                        Ok((token, found))
+                    }
+                    None => Err("Unknown account".to_string()),
+                };
+                let msg = ServerMessage::AuthResult(result);
+                stream
+                    .send(Message::Binary(bincode::serialize(&msg)?))
+                    .await?;
+            }
             msg => {
-                warn!("[{}] Got unexpected message {:?}", addr, msg);
+                warn!("Got unexpected message {:?}", msg);
                 //break;
             }
         }
     }
-    info!("[{}] Dropping connection", addr);
+    info!("Dropping connection");
     Ok(())
 }
 
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("curve_fever_server=info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    // `LOG_FORMAT=json` for log aggregation
+    if std::env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Listen addresses are given on the command line, e.g. `0.0.0.0:8095 [::]:8098`, see `bans` for
+/// the commands managing the ban list
+fn listen_addrs() -> Vec<SocketAddr> {
+    let addrs: Vec<SocketAddr> = std::env::args()
+        .skip(1)
+        .map(|arg| {
+            arg.parse()
+                .unwrap_or_else(|_| panic!("Unable to parse socket address `{}`", arg))
+        })
+        .collect();
+    if addrs.is_empty() {
+        vec![DEFAULT_ADDR.parse().unwrap()]
+    } else {
+        addrs
+    }
+}
+
 pub fn main() {
-    env_logger::from_env(Env::default().default_filter_or("curve_fever_server=INFO")).init();
-    let addr = "0.0.0.0:8095";
+    init_logging();
+    // the ban list can be changed without starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = bans::command(&args, bans::file()) {
+        if let Err(e) = result {
+            error!("Could not change the bans: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let addrs = listen_addrs();
 
     let rooms = Arc::new(Mutex::new(HashMap::new()));
+    let tournaments: TournamentList = Arc::new(Mutex::new(HashMap::new()));
+    let default_config = Arc::new(Mutex::new(RoomConfig::default()));
+    let accounts_file = std::env::var("ACCOUNTS_FILE").unwrap_or_else(|_| "accounts.json".into());
+    let accounts = Accounts::load(accounts_file.into()).expect("Could not load the accounts");
+    let accounts = Arc::new(Mutex::new(accounts));
+    Task::spawn(accounts::run(accounts.clone())).detach();
+    let bans = Bans::load(bans::file()).expect("Could not load the bans");
+    let bans = Arc::new(Mutex::new(bans));
+    let throttle = Arc::new(Mutex::new(Throttle::new(bans::connections_per_minute())));
+    let replay_dir = std::env::var("REPLAY_DIR").unwrap_or_else(|_| "replays".into());
+    let replays = Arc::new(Replays::new(replay_dir.into()).expect("Could not open the replays"));
+    let replay_addr = std::env::var("REPLAY_ADDR").unwrap_or_else(|_| "0.0.0.0:8097".into());
+    let replay_addr: SocketAddr = replay_addr
+        .parse()
+        .expect("Unable to parse replay socket address");
+    Task::spawn(replays::run(replay_addr, replays.clone())).detach();
+    let (match_results, results) = unbounded();
+    Task::spawn(tournament::run(results, rooms.clone(), tournaments.clone())).detach();
+    let close_room = close_rooms(rooms.clone());
+    let metrics = Arc::new(TickMetrics::default());
+
+    // rooms survive restarts if they are kept in a file
+    let room_store = std::env::var("ROOMS_FILE")
+        .ok()
+        .map(|path| Arc::new(RoomStore::new(path.into())));
+    if let Some(store) = &room_store {
+        for snapshot in store.load().expect("Could not load the rooms") {
+            let room = Room::restore(
+                snapshot,
+                accounts.clone(),
+                replays.clone(),
+                match_results.clone(),
+                metrics.clone(),
+            );
+            info!("Restored room `{}`", room.name);
+            let (write, read) = unbounded();
+            let name = room.name.clone();
+            let handle = RoomHandle::new(room, write);
+            rooms.lock().unwrap().insert(name, handle.clone());
+            spawn_room(handle, read, close_room.clone());
+        }
+        Task::spawn(persistence::run(store.clone(), rooms.clone())).detach();
+    }
+
+    // the admin interface is only available with a token
+    if let Ok(token) = std::env::var("ADMIN_TOKEN") {
+        let admin_addr = std::env::var("ADMIN_ADDR").unwrap_or_else(|_| "127.0.0.1:8096".into());
+        let admin_addr: SocketAddr = admin_addr
+            .parse()
+            .expect("Unable to parse admin socket address");
+        Task::spawn(admin::run(
+            admin_addr,
+            token,
+            rooms.clone(),
+            default_config.clone(),
+            tournaments.clone(),
+            bans.clone(),
+        ))
+        .detach();
+    }
+
+    // serve the client and the WebSocket on the same port
+    let client = ClientFiles::from_env().map(Arc::new);
 
     for _ in 0..20 {
         std::thread::spawn(|| smol::run(future::pending::<()>()));
     }
 
-    let close_room = {
-        let (tx, mut rx) = unbounded();
-        let rooms = rooms.clone();
-        Task::spawn(async move {
-            while let Some(room) = rx.next().await {
-                info!("[{}] Room closed", room);
-                rooms.lock().unwrap().remove(&room);
-            }
-        })
-        .detach();
-        tx
+    let shared = Shared {
+        rooms: rooms.clone(),
+        default_config,
+        accounts,
+        replays,
+        match_results,
+        close_room,
+        client,
+        metrics,
+        bans,
+        throttle,
     };
 
     smol::block_on(async {
-        info!("Listening on: {}", addr);
-
-        let socket_addr: SocketAddr = addr.parse().expect("Unable to parse socket address");
-        let listener = Async::<TcpListener>::bind(socket_addr).expect("Could not create listener");
-
-        while let Ok((stream, addr)) = listener.accept().await {
-            info!("Got connection from {}", addr);
-            let close_room = close_room.clone();
-            let rooms = rooms.clone();
-            Task::spawn(async move {
-                match async_tungstenite::accept_async(stream).await {
-                    Err(e) => {
-                        error!("Could not get stream: {}", e);
-                    }
-                    Ok(ws_stream) => {
-                        info!("Reading incoming stream...");
-                        if let Err(e) = read_stream(ws_stream, addr, rooms, close_room).await {
-                            error!("Failed to read stream from {}: {}", addr, e);
-                        }
-                    }
-                };
+        // dropping the tasks stops accepting connections
+        let listeners: Vec<Task<()>> = addrs
+            .iter()
+            .map(|addr| {
+                let listener = Async::<TcpListener>::bind(*addr)
+                    .unwrap_or_else(|e| panic!("Could not listen on {}: {}", addr, e));
+                // the system picks the port for port 0
+                let addr = listener.get_ref().local_addr().unwrap_or(*addr);
+                info!("Listening on: {}", addr);
+                Task::spawn(listen(listener, shared.clone()))
             })
-            .detach();
+            .collect();
+
+        let mut shutdown = {
+            let (tx, rx) = unbounded();
+            ctrlc::set_handler(move || {
+                let _ = tx.unbounded_send(());
+            })
+            .expect("Could not set signal handler");
+            rx
+        };
+
+        // accept connections until a signal arrives
+        shutdown.next().await;
+        drop(listeners);
+
+        info!("Shutting down {} rooms", rooms.lock().unwrap().len());
+        if let Some(store) = &room_store {
+            store.close(&persistence::snapshot(&rooms));
+        }
+        for handle in rooms.lock().unwrap().values() {
+            handle.with_room(|room| {
+                let _span = info_span!("room", name = %room.name).entered();
+                room.shutdown(SHUTDOWN_GRACE_SECONDS, room_store.is_some());
+            });
+        }
+        let deadline = Instant::now() + Duration::from_secs(SHUTDOWN_GRACE_SECONDS as u64 + 5);
+        while !rooms.lock().unwrap().is_empty() && Instant::now() < deadline {
+            Timer::after(Duration::from_millis(100)).await;
         }
+        // the rooms are gone, so are any later changes to the accounts
+        accounts::flush(&shared.accounts);
+        info!("Shutdown complete");
     });
 }
@@ -0,0 +1,98 @@
+//! Matches of a room, played back to back while players come and go.
+//!
+//! A match lasts until a player reaches the target score or the best of rounds is decided, then
+//! the points start over for the next one. Who is in the room is not part of a match: players may
+//! join and leave at any time, and what they achieved in the finished matches is kept by name in
+//! the `Session` of the room for as long as it exists, also for the players that left.
+
+use curve_fever_common::{Player, RoomConfig, RoundResult, SessionStats};
+
+/// The rounds played towards one winner
+#[derive(Default)]
+pub struct Match {
+    pub rounds: Vec<Vec<RoundResult>>,
+}
+
+impl Match {
+    /// Number of the current or next round, starting at 1
+    pub fn round(&self) -> u32 {
+        self.rounds.len() as u32 + 1
+    }
+
+    /// Whether a player reached the target score or won most of the best of rounds.
+    pub fn is_decided(&self, config: &RoomConfig, players: &[&Player]) -> bool {
+        let target_reached = config
+            .target_score
+            .is_some_and(|target| players.iter().any(|p| p.points >= target));
+        let best_of_decided = config.best_of.is_some_and(|rounds| {
+            self.rounds.len() >= rounds as usize
+                || players.iter().any(|p| p.round_wins > rounds as usize / 2)
+        });
+        target_reached || best_of_decided
+    }
+
+    /// `players` sorted by how they did in the match, the winner first
+    pub fn standings<'a>(config: &RoomConfig, mut players: Vec<&'a Player>) -> Vec<&'a Player> {
+        // round wins decide a best of match, points break ties
+        let best_of = config.best_of.is_some();
+        players.sort_by_key(|player| {
+            let wins = if best_of { player.round_wins } else { 0 };
+            std::cmp::Reverse((wins, player.points))
+        });
+        players
+    }
+}
+
+/// What everybody achieved over the finished matches of a room
+#[derive(Default)]
+pub struct Session {
+    stats: Vec<SessionStats>,
+}
+
+impl Session {
+    pub fn new(stats: Vec<SessionStats>) -> Self {
+        Self { stats }
+    }
+
+    /// Adds the rounds of a finished match, players of the same name count as one.
+    pub fn record(&mut self, finished: &Match, winner: Option<&str>) {
+        let mut played: Vec<&str> = vec![];
+        for result in finished.rounds.iter().flatten() {
+            let stats = self.entry(&result.name);
+            stats.kills += result.kills;
+            if result.placement == 1 {
+                stats.round_wins += 1;
+            }
+            if !played.contains(&result.name.as_str()) {
+                played.push(&result.name);
+            }
+        }
+        for name in played {
+            self.entry(name).matches += 1;
+        }
+        if let Some(winner) = winner {
+            self.entry(winner).match_wins += 1;
+        }
+    }
+
+    /// The stats of everybody, the most successful player first
+    pub fn stats(&self) -> Vec<SessionStats> {
+        let mut stats = self.stats.clone();
+        stats.sort_by_key(|s| std::cmp::Reverse((s.match_wins, s.round_wins, s.kills)));
+        stats
+    }
+
+    fn entry(&mut self, name: &str) -> &mut SessionStats {
+        let i = match self.stats.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                self.stats.push(SessionStats {
+                    name: name.to_string(),
+                    ..SessionStats::default()
+                });
+                self.stats.len() - 1
+            }
+        };
+        &mut self.stats[i]
+    }
+}
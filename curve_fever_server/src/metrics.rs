@@ -0,0 +1,126 @@
+//! Timing of the room ticks and the traffic of every room, served as `GET /metrics` in the
+//! Prometheus text format.
+//!
+//! All rooms share one histogram. Slow ticks are also logged by the room, with the size of the
+//! room, see `Room::tick_once`. The traffic is counted per room and also told to its host, see
+//! `Room::check_traffic`.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub type Metrics = Arc<TickMetrics>;
+pub type TrafficCounter = Arc<Traffic>;
+
+/// Upper bounds of the histogram buckets in milliseconds, the last bucket takes the rest
+const BUCKETS_MS: [u64; 9] = [1, 2, 5, 10, 20, 40, 80, 160, 320];
+
+#[derive(Default)]
+pub struct TickMetrics {
+    buckets: [AtomicU64; 9], // ticks of at most `BUCKETS_MS[i]`, not cumulative
+    count: AtomicU64,
+    sum_us: AtomicU64,
+    slow: AtomicU64, // ticks over the budget of the server
+}
+
+impl TickMetrics {
+    pub fn record(&self, duration: Duration, slow: bool) {
+        let ms = duration.as_secs_f64() * 1000.;
+        if let Some(i) = BUCKETS_MS.iter().position(|&bound| ms <= bound as f64) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        if slow {
+            self.slow.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out += "# HELP curve_fever_tick_seconds Time spent in a tick of a room\n";
+        out += "# TYPE curve_fever_tick_seconds histogram\n";
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS_MS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "curve_fever_tick_seconds_bucket{{le=\"{}\"}} {}",
+                *bound as f64 / 1000.,
+                cumulative
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "curve_fever_tick_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.;
+        let _ = writeln!(out, "curve_fever_tick_seconds_sum {}", sum);
+        let _ = writeln!(out, "curve_fever_tick_seconds_count {}", count);
+        out += "# HELP curve_fever_slow_ticks_total Ticks that took longer than the budget\n";
+        out += "# TYPE curve_fever_slow_ticks_total counter\n";
+        let _ = writeln!(
+            out,
+            "curve_fever_slow_ticks_total {}",
+            self.slow.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+/// Messages and bytes a room queued for its clients, a broadcast counts once per recipient
+#[derive(Default)]
+pub struct Traffic {
+    bytes: AtomicU64,
+    messages: AtomicU64,
+}
+
+impl Traffic {
+    pub fn record(&self, bytes: usize, recipients: usize) {
+        self.bytes
+            .fetch_add((bytes * recipients) as u64, Ordering::Relaxed);
+        self.messages
+            .fetch_add(recipients as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes and messages since the room was opened
+    pub fn totals(&self) -> (u64, u64) {
+        (
+            self.bytes.load(Ordering::Relaxed),
+            self.messages.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The traffic of the open rooms, labeled by room name
+pub fn render_traffic<'a>(rooms: impl Iterator<Item = (&'a str, &'a Traffic)>) -> String {
+    let rooms: Vec<(&str, (u64, u64))> = rooms.map(|(name, t)| (name, t.totals())).collect();
+    let mut out = String::new();
+    out += "# HELP curve_fever_room_sent_bytes_total Bytes a room sent to its clients\n";
+    out += "# TYPE curve_fever_room_sent_bytes_total counter\n";
+    for (name, (bytes, _)) in &rooms {
+        let _ = writeln!(
+            out,
+            "curve_fever_room_sent_bytes_total{{room=\"{}\"}} {}",
+            name, bytes
+        );
+    }
+    out += "# HELP curve_fever_room_sent_messages_total Messages a room sent to its clients\n";
+    out += "# TYPE curve_fever_room_sent_messages_total counter\n";
+    for (name, (_, messages)) in &rooms {
+        let _ = writeln!(
+            out,
+            "curve_fever_room_sent_messages_total{{room=\"{}\"}} {}",
+            name, messages
+        );
+    }
+    out
+}
@@ -0,0 +1,187 @@
+//! Bounded queues of the messages waiting to be written to a client's WebSocket.
+//!
+//! A stalled client must not make the server buffer without limit. Once a queue is full, the
+//! oldest `GameState` in it is dropped for a new message, a newer state replaces it anyway and the
+//! client asks for a `FullTrailSync` when it notices the gap. All other messages, like
+//! `RoundStarted`, are never dropped. A client whose queue stays full for `STALL_TIMEOUT` is
+//! disconnected, even while a write to its WebSocket is blocked.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{
+    future::{AbortHandle, AbortRegistration},
+    stream::Stream,
+    task::{Context, Poll, Waker},
+};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use curve_fever_common::ServerMessage;
+
+/// Messages a queue holds before it starts dropping states, about a second of a fast room
+const CAPACITY: usize = 128;
+/// How long a queue may stay full before the client is disconnected
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A serialized `ServerMessage`, shared by all recipients
+#[derive(Clone)]
+pub struct Frame {
+    bytes: Bytes,
+    droppable: bool, // superseded by any later frame of its kind
+}
+
+impl Frame {
+    pub fn new(msg: &ServerMessage) -> Self {
+        let bytes = bincode::serialize(msg)
+            .unwrap_or_else(|_| panic!("Could not encode {:?}", msg))
+            .into();
+        Self {
+            bytes,
+            droppable: matches!(msg, ServerMessage::GameState(..)),
+        }
+    }
+
+    /// Encoded size in bytes
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    frames: VecDeque<Frame>,
+    full_since: Option<Instant>,
+    dropped: u64, // states dropped for this client, for the log
+    closed: bool,
+    senders: usize,
+    waker: Option<Waker>,       // of the task writing to the WebSocket
+    stall: Option<AbortHandle>, // aborts the connection
+}
+
+impl Queue {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Sending half, the room keeps one per client. The queue closes once all of them are dropped.
+pub struct Outbox {
+    queue: Arc<Mutex<Queue>>,
+}
+
+/// Receiving half, written to the WebSocket by `run_player`
+pub struct OutboxReceiver {
+    queue: Arc<Mutex<Queue>>,
+}
+
+/// A new queue, the connection registered with the `AbortRegistration` is aborted once the client
+/// stalls.
+pub fn outbox() -> (Outbox, OutboxReceiver, AbortRegistration) {
+    let (stall, registration) = AbortHandle::new_pair();
+    let queue = Arc::new(Mutex::new(Queue {
+        senders: 1,
+        stall: Some(stall),
+        ..Queue::default()
+    }));
+    (
+        Outbox {
+            queue: queue.clone(),
+        },
+        OutboxReceiver { queue },
+        registration,
+    )
+}
+
+impl Outbox {
+    /// Queues a frame, fails if the queue is closed or the client stalled for too long.
+    pub fn send(&self, frame: Frame) -> Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            anyhow::bail!("The connection is closed");
+        }
+        if queue.frames.len() >= CAPACITY {
+            let full_since = *queue.full_since.get_or_insert_with(Instant::now);
+            if full_since.elapsed() > STALL_TIMEOUT {
+                queue.frames.clear();
+                queue.close();
+                if let Some(stall) = queue.stall.take() {
+                    stall.abort();
+                }
+                anyhow::bail!(
+                    "The client stalled, {} states were dropped before",
+                    queue.dropped
+                );
+            }
+            match queue.frames.iter().position(|frame| frame.droppable) {
+                Some(i) => {
+                    queue.frames.remove(i);
+                    queue.dropped += 1;
+                }
+                // nothing to make room for a state, it is dropped right away
+                None if frame.droppable => {
+                    queue.dropped += 1;
+                    return Ok(());
+                }
+                // everything else goes in anyway
+                None => (),
+            }
+        }
+        queue.frames.push_back(frame);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Closes the queue after the frames already in it.
+    pub fn close(&self) {
+        self.queue.lock().unwrap().close();
+    }
+}
+
+impl Clone for Outbox {
+    fn clone(&self) -> Self {
+        self.queue.lock().unwrap().senders += 1;
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.senders -= 1;
+        if queue.senders == 0 {
+            queue.close();
+        }
+    }
+}
+
+impl Stream for OutboxReceiver {
+    type Item = Bytes;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.frames.pop_front() {
+            Some(frame) => {
+                if queue.frames.len() < CAPACITY {
+                    queue.full_since = None;
+                }
+                Poll::Ready(Some(frame.bytes))
+            }
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
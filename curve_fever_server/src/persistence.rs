@@ -0,0 +1,118 @@
+//! Optional snapshots of the rooms, so a restarted server can bring them back.
+//!
+//! The rooms are written to a JSON file every few seconds and once more when the server shuts
+//! down. A snapshot keeps the config, the points and the session tokens of the players and the
+//! results of the finished matches, but not a round in progress. After a restart the players
+//! resume their sessions as after a lost connection, a restored room waits `RESUME_GRACE` for them
+//! before it closes.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use smol::Timer;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use curve_fever_common::{
+    ChatMessage, Player, RoomConfig, RoundResult, SessionStats, TournamentState,
+};
+
+use crate::RoomList;
+
+pub type RoomStoreHandle = Arc<RoomStore>;
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SavedRoom {
+    pub name: String,
+    pub config: RoomConfig,
+    pub players: Vec<SavedPlayer>,
+    pub rounds: Vec<Vec<RoundResult>>, // of the current match
+    #[serde(default)]
+    pub session: Vec<SessionStats>, // of the finished matches
+    pub tournament: Option<TournamentState>,
+    pub chat: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SavedPlayer {
+    pub token: Uuid, // session token
+    pub player: Player,
+    pub account: Option<String>, // hashed token of the logged in account
+}
+
+pub struct RoomStore {
+    path: PathBuf,
+    closed: AtomicBool, // the final snapshot was written
+}
+
+impl RoomStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Reads the rooms of the last run, a missing file is treated as empty.
+    pub fn load(&self) -> Result<Vec<SavedRoom>> {
+        let rooms: Vec<SavedRoom> = match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        info!("Loaded {} rooms from {}", rooms.len(), self.path.display());
+        Ok(rooms)
+    }
+
+    pub fn save(&self, rooms: &[SavedRoom]) {
+        if self.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        // write to a temporary file first, a crash must not leave a truncated file behind
+        let tmp = self.path.with_extension("tmp");
+        let result = serde_json::to_string(rooms)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(fs::write(&tmp, text)?))
+            .and_then(|()| Ok(fs::rename(&tmp, &self.path)?));
+        if let Err(e) = result {
+            error!("Failed to save rooms to {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Writes the last snapshot before the server shuts down, later ones would miss the rooms
+    /// that already closed.
+    pub fn close(&self, rooms: &[SavedRoom]) {
+        self.save(rooms);
+        self.closed.store(true, Ordering::SeqCst);
+        info!("Saved {} rooms to {}", rooms.len(), self.path.display());
+    }
+}
+
+/// Snapshots of all rooms that have players
+pub fn snapshot(rooms: &RoomList) -> Vec<SavedRoom> {
+    rooms
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(|handle| handle.room.lock().unwrap().saved())
+        .collect()
+}
+
+/// Saves the rooms periodically.
+pub async fn run(store: RoomStoreHandle, rooms: RoomList) {
+    loop {
+        Timer::after(SNAPSHOT_INTERVAL).await;
+        store.save(&snapshot(&rooms));
+    }
+}
@@ -0,0 +1,192 @@
+//! Recorded rounds, kept as files and served over HTTP so they can be shared with a link.
+//!
+//! A replay holds every message a spectator received during the round, except for the chat,
+//! which stays among the players. The client fetches it with `GET /replays/<id>` and feeds the
+//! messages to its usual handlers. The seed and the inputs of the round are kept as well,
+//! `Replay::simulate` plays the round again from them, its countdown included.
+
+use anyhow::Result;
+use futures::io::AsyncWriteExt;
+use rand::{distributions::Alphanumeric, Rng};
+use smol::{Async, Task};
+use std::{
+    fs,
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use curve_fever_common::{Input, Replay, ServerMessage};
+use uuid::Uuid;
+
+use crate::http;
+
+pub type ReplayList = Arc<Replays>;
+
+const ID_LENGTH: usize = 8;
+/// Oldest replays are deleted beyond this number
+const MAX_REPLAYS: usize = 1000;
+
+/// Collects the messages of a round while it is played.
+pub struct Recorder {
+    ticks: u32,
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(replay: Replay) -> Self {
+        Self { ticks: 0, replay }
+    }
+
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    pub fn record(&mut self, msg: &ServerMessage) {
+        if !matches!(msg, ServerMessage::Chat(_)) {
+            self.replay.messages.push((self.ticks, msg.clone()));
+        }
+    }
+
+    /// Keeps an input along with the tick counted from the start of the countdown, like a ghost.
+    pub fn record_input(&mut self, tick: u32, id: Uuid, input: Input) {
+        self.replay.inputs.push((tick, id, input));
+    }
+
+    /// Ends the replayed part once the round has a winner after `ticks`.
+    pub fn decided(&mut self, ticks: u32) {
+        self.replay.ticks = ticks;
+    }
+
+    pub fn finish(self) -> Replay {
+        self.replay
+    }
+}
+
+pub struct Replays {
+    dir: PathBuf,
+}
+
+impl Replays {
+    /// Keeps the replays in `dir`, which is created if missing.
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id).with_extension("replay")
+    }
+
+    /// Stores the replay under a new short id, which is returned right away. The file is
+    /// written in the background, the room doesn't wait for it.
+    pub fn save(self: &Arc<Self>, replay: Replay) -> String {
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(ID_LENGTH)
+            .map(char::from)
+            .collect();
+        let replays = self.clone();
+        let path = self.path(&id);
+        Task::blocking(async move {
+            let result = bincode::serialize(&replay)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(fs::write(&path, bytes)?));
+            match result {
+                Ok(()) => {
+                    info!("Saved replay {}", path.display());
+                    replays.prune();
+                }
+                Err(e) => error!("Failed to save replay {}: {}", path.display(), e),
+            }
+        })
+        .detach();
+        id
+    }
+
+    /// Encoded replay with the short `id`, if it exists.
+    pub fn load(&self, id: &str) -> Option<Vec<u8>> {
+        // the id ends up in a path, so it must not be able to leave the directory
+        if id.len() != ID_LENGTH || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        fs::read(self.path(id)).ok()
+    }
+
+    fn prune(&self) {
+        let mut files: Vec<_> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+                .collect(),
+            Err(e) => {
+                error!("Could not list replays in {}: {}", self.dir.display(), e);
+                return;
+            }
+        };
+        if files.len() > MAX_REPLAYS {
+            files.sort();
+            for (_, path) in &files[..files.len() - MAX_REPLAYS] {
+                if let Err(e) = fs::remove_file(path) {
+                    error!("Could not delete replay {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Answers a single HTTP request and closes the connection.
+async fn serve(mut stream: Async<TcpStream>, replays: ReplayList) -> Result<()> {
+    let request = http::read_request(&mut stream).await?;
+    let (status, body) = match request.method.as_str() {
+        "GET" => match request
+            .path
+            .strip_prefix("/replays/")
+            .and_then(|id| replays.load(id))
+        {
+            Some(body) => ("200 OK", body),
+            None => ("404 Not Found", vec![]),
+        },
+        _ => ("405 Method Not Allowed", vec![]),
+    };
+    info!("{} {} {}", request.method, request.path, status);
+
+    // the game is served from another origin
+    let head = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/octet-stream\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    Ok(())
+}
+
+pub async fn run(addr: SocketAddr, replays: ReplayList) {
+    let listener = match Async::<TcpListener>::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not create replay listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving replays on: {}", addr);
+
+    while let Ok((stream, addr)) = listener.accept().await {
+        let replays = replays.clone();
+        Task::spawn(
+            async move {
+                if let Err(e) = serve(stream, replays).await {
+                    warn!("Replay request failed: {}", e);
+                }
+            }
+            .instrument(info_span!("replay", %addr)),
+        )
+        .detach();
+    }
+}
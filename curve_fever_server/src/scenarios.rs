@@ -0,0 +1,673 @@
+//! Scenarios of a single room, played through `Room::apply` without any connections.
+//!
+//! The room only leaves `Effect`s behind, so every scenario checks what its clients would have
+//! been sent, and in which order.
+
+use futures::channel::mpsc::unbounded;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use curve_fever_common::{
+    ClientMessage, DeathCause, Direction, RoomConfig, ServerMessage, SessionStats, TrailPattern,
+    MAX_PLAYERS,
+};
+
+use crate::{
+    accounts::{self, Accounts},
+    closest_room,
+    replays::Replays,
+    Effect, Event, JoinRequest, Room, RoomHandle, COUNTDOWN_SECONDS, FULL_SYNC_TICKS,
+    TRAFFIC_INTERVAL,
+};
+
+/// Ticks a round may take to end once decided, far more than the slow motion replay
+const MAX_ENDING_TICKS: usize = 1000;
+
+fn room(config: RoomConfig) -> Room {
+    let dir = std::env::temp_dir().join(format!("curve_fever_scenarios_{}", std::process::id()));
+    let accounts = Accounts::load(dir.join("accounts.json")).unwrap();
+    let (match_results, _) = unbounded();
+    Room::new(
+        "TEST".into(),
+        config,
+        Arc::new(Mutex::new(accounts)),
+        Arc::new(Replays::new(dir.join("replays")).unwrap()),
+        match_results,
+        Arc::default(),
+    )
+}
+
+/// Address of the client with the given port
+fn addr(port: u16) -> SocketAddr {
+    ([127, 0, 0, 1], port).into()
+}
+
+/// Adds a player and forgets about the effects of joining.
+fn join(room: &mut Room, port: u16, name: &str) -> Uuid {
+    let (_, id) = room
+        .add_player(addr(port), JoinRequest::New(name.into(), None))
+        .unwrap();
+    room.take_effects();
+    id
+}
+
+fn message(room: &mut Room, port: u16, msg: ClientMessage) -> (bool, Vec<Effect>) {
+    room.apply(Event::Message(addr(port), msg))
+}
+
+fn broadcasts(effects: &[Effect]) -> Vec<&ServerMessage> {
+    effects
+        .iter()
+        .filter_map(|effect| match effect {
+            Effect::Broadcast(msg) => Some(msg),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Starts a round and ticks through its countdown.
+fn start_round(room: &mut Room, host_port: u16) {
+    message(room, host_port, ClientMessage::StartGame);
+    let countdown = COUNTDOWN_SECONDS as usize * room.config.tick_rate as usize;
+    for _ in 0..countdown {
+        room.apply(Event::Tick);
+    }
+    assert!(room.round_in_progress());
+}
+
+/// Steers `loser` into the left wall and plays the round to its end.
+fn crash_into_wall(room: &mut Room, loser: Uuid) -> Vec<Effect> {
+    let player = room.game.players.get_mut(&loser).unwrap();
+    player.x = 1.;
+    player.rotation = 270.;
+    let mut effects = vec![];
+    for _ in 0..MAX_ENDING_TICKS {
+        if broadcasts(&effects)
+            .iter()
+            .any(|msg| matches!(msg, ServerMessage::RoundEnded(_)))
+        {
+            return effects;
+        }
+        effects.extend(room.apply(Event::Tick).1);
+    }
+    panic!("The round never ended");
+}
+
+/// Stats of the room sent after a match, by name
+fn session_stats(effects: &[Effect]) -> HashMap<String, SessionStats> {
+    broadcasts(effects)
+        .iter()
+        .find_map(|msg| match msg {
+            ServerMessage::SessionStats(stats) => Some(stats),
+            _ => None,
+        })
+        .expect("No match finished")
+        .iter()
+        .map(|stats| (stats.name.clone(), stats.clone()))
+        .collect()
+}
+
+#[test]
+fn joining_client_is_caught_up_before_anyone_hears_of_it() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    let (_, bob) = room
+        .add_player(addr(2), JoinRequest::New("Bob".into(), None))
+        .unwrap();
+    let effects = room.take_effects();
+
+    assert!(matches!(
+        &effects[0],
+        Effect::Send(to, ServerMessage::JoinSuccess { uuid, .. }) if *to == addr(2) && *uuid == bob
+    ));
+    assert!(matches!(
+        &effects[1],
+        Effect::Send(to, ServerMessage::RoomSnapshot(snapshot))
+            if *to == addr(2) && snapshot.players.len() == 1
+    ));
+    // the events so far, Alice joining
+    assert!(matches!(
+        &effects[2],
+        Effect::Send(to, ServerMessage::RoomEvent(_)) if *to == addr(2)
+    ));
+    assert!(matches!(
+        &effects[3],
+        Effect::Broadcast(ServerMessage::NewPlayer(player)) if player.uuid == bob
+    ));
+    assert!(!room.is_host(&bob));
+}
+
+#[test]
+fn host_leaving_hands_the_room_to_another_player() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    assert!(room.is_host(&alice));
+
+    let (running, effects) = message(&mut room, 1, ClientMessage::Disconnected);
+    assert!(running);
+    // nothing more reaches the client that is gone
+    assert!(matches!(effects[0], Effect::Disconnect(from) if from == addr(1)));
+    assert!(broadcasts(&effects).iter().any(|msg| matches!(
+        msg,
+        ServerMessage::PlayerDisconnected(id, host) if *id == alice && *host == bob
+    )));
+    assert!(room.is_host(&bob));
+}
+
+#[test]
+fn only_the_host_starts_a_round() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    join(&mut room, 2, "Bob");
+
+    let (_, effects) = message(&mut room, 2, ClientMessage::StartGame);
+    assert!(effects.is_empty());
+    let (_, effects) = message(&mut room, 1, ClientMessage::StartGame);
+    assert!(broadcasts(&effects).iter().any(
+        |msg| matches!(msg, ServerMessage::Countdown(seconds) if *seconds == COUNTDOWN_SECONDS)
+    ));
+}
+
+#[test]
+fn lost_connection_can_be_resumed_but_leaving_cannot() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    join(&mut room, 3, "Carol");
+    let alice_token = room.players[&alice].token;
+    let bob_token = room.players[&bob].token;
+
+    message(&mut room, 1, ClientMessage::Disconnected);
+    let (_, effects) = message(&mut room, 2, ClientMessage::LeaveRoom);
+    assert!(matches!(effects[0], Effect::Disconnect(from) if from == addr(2)));
+    assert!(room.can_resume(&alice_token));
+    assert!(!room.can_resume(&bob_token));
+
+    // the session comes back on a new connection
+    let (_, id) = room
+        .add_player(addr(4), JoinRequest::Resume(alice_token))
+        .unwrap();
+    assert_eq!(id, alice);
+    assert!(!room.is_host(&alice), "Carol took over as host");
+    assert!(room
+        .add_player(addr(5), JoinRequest::Resume(bob_token))
+        .is_err());
+}
+
+#[test]
+fn room_stops_running_without_players() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    let (running, _) = message(&mut room, 1, ClientMessage::Disconnected);
+    assert!(!running);
+    let (running, _) = room.apply(Event::Tick);
+    assert!(!running);
+}
+
+#[test]
+fn last_player_standing_wins_the_match() {
+    let mut room = room(RoomConfig {
+        target_score: Some(2),
+        ..RoomConfig::default()
+    });
+    let alice = join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    start_round(&mut room, 1);
+    let effects = crash_into_wall(&mut room, bob);
+
+    let broadcasts = broadcasts(&effects);
+    let ended = broadcasts.iter().find_map(|msg| match msg {
+        ServerMessage::RoundEnded((winner, points)) => Some((*winner, points)),
+        _ => None,
+    });
+    let (winner, points) = ended.expect("The round never ended");
+    assert_eq!(winner, alice);
+    // a point for the first to die, two for outliving one player
+    let mut points = points.clone();
+    points.sort_by_key(|&(_, points, _)| points);
+    assert_eq!(points, vec![(bob, 1, 0), (alice, 2, 0)]);
+    assert!(broadcasts.iter().any(|msg| matches!(
+        msg,
+        ServerMessage::PlayerDied(death) if death.id == bob && matches!(death.cause, DeathCause::Wall)
+    )));
+    assert!(broadcasts
+        .iter()
+        .any(|msg| matches!(msg, ServerMessage::RoundEnding(id) if *id == alice)));
+    assert!(broadcasts
+        .iter()
+        .any(|msg| matches!(msg, ServerMessage::GameOver(id) if *id == alice)));
+}
+
+#[test]
+fn ghost_can_be_turned_off_by_the_host() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    let config = RoomConfig {
+        ghost: None,
+        ..room.config.clone()
+    };
+    let (_, effects) = message(&mut room, 1, ClientMessage::UpdateConfig(config));
+    assert!(broadcasts(&effects)
+        .iter()
+        .any(|msg| matches!(msg, ServerMessage::ConfigChanged(config) if config.ghost.is_none())));
+
+    start_round(&mut room, 1);
+    message(&mut room, 1, ClientMessage::UseGhost);
+    room.apply(Event::Tick);
+    let state = room.game.state();
+    assert!(!state.iter().find(|s| s.id == alice).unwrap().ghost_active);
+}
+
+#[test]
+fn items_can_be_turned_off_by_the_host() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    let config = RoomConfig {
+        items: false,
+        ..room.config.clone()
+    };
+    let (_, effects) = message(&mut room, 1, ClientMessage::UpdateConfig(config));
+    assert!(broadcasts(&effects)
+        .iter()
+        .any(|msg| matches!(msg, ServerMessage::ConfigChanged(config) if !config.items)));
+    assert!(!room.game.items_enabled);
+}
+
+#[test]
+fn room_title_of_the_host_reaches_everybody() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    let config = RoomConfig {
+        title: Some("Friday league".into()),
+        ..room.config.clone()
+    };
+    let (_, effects) = message(&mut room, 1, ClientMessage::UpdateConfig(config));
+    assert!(broadcasts(&effects).iter().any(|msg| matches!(
+        msg,
+        ServerMessage::ConfigChanged(config) if config.title.as_deref() == Some("Friday league")
+    )));
+}
+
+#[test]
+fn tick_rate_of_the_host_keeps_countdowns_in_seconds() {
+    let mut room = room(RoomConfig {
+        auto_start: Some(1),
+        ..RoomConfig::default()
+    });
+    join(&mut room, 1, "Alice");
+    room.apply(Event::Tick);
+    let auto_start = room.auto_start_ticks.expect("No auto start");
+
+    let tick_rate = room.config.tick_rate / 2;
+    let config = RoomConfig {
+        tick_rate,
+        ..room.config.clone()
+    };
+    message(&mut room, 1, ClientMessage::UpdateConfig(config));
+    assert_eq!(room.config.tick_rate, tick_rate);
+    assert_eq!(room.auto_start_ticks, Some(auto_start / 2));
+
+    message(&mut room, 1, ClientMessage::StartGame);
+    assert_eq!(
+        room.freeze_ticks,
+        COUNTDOWN_SECONDS as usize * tick_rate as usize
+    );
+}
+
+#[test]
+fn flood_of_moves_stays_dropped() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    start_round(&mut room, 1);
+    for i in 0..300 {
+        let direction = match i % 2 {
+            0 => Direction::Left,
+            _ => Direction::Right,
+        };
+        message(&mut room, 1, ClientMessage::Move(direction));
+    }
+    assert_eq!(room.players[&alice].dropped_moves, 298);
+}
+
+#[test]
+fn held_back_inputs_do_not_pile_up() {
+    let mut room = room(RoomConfig {
+        equalize_latency: true,
+        ..RoomConfig::default()
+    });
+    let alice = join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    room.players.get_mut(&alice).unwrap().rtt = Some(Duration::from_millis(10));
+    room.players.get_mut(&bob).unwrap().rtt = Some(Duration::from_millis(300));
+    start_round(&mut room, 1);
+    for _ in 0..100 {
+        message(&mut room, 1, ClientMessage::UseGhost);
+    }
+    let player = &room.players[&alice];
+    assert!(!player.delayed.is_empty());
+    assert!(player.delayed.len() < 100);
+    assert_eq!(player.delayed.len() as u32 + player.dropped_moves, 100);
+}
+
+#[test]
+fn held_back_inputs_outlast_a_pause() {
+    let mut room = room(RoomConfig {
+        equalize_latency: true,
+        ..RoomConfig::default()
+    });
+    let alice = join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    room.players.get_mut(&alice).unwrap().rtt = Some(Duration::from_millis(10));
+    room.players.get_mut(&bob).unwrap().rtt = Some(Duration::from_millis(50));
+    start_round(&mut room, 1);
+    message(&mut room, 1, ClientMessage::Move(Direction::Left));
+    message(&mut room, 1, ClientMessage::PauseGame);
+    assert!(room.paused);
+
+    std::thread::sleep(Duration::from_millis(50));
+    room.apply(Event::Tick);
+    assert_eq!(room.players[&alice].delayed.len(), 1);
+
+    message(&mut room, 1, ClientMessage::ResumeGame);
+    room.apply(Event::Tick);
+    assert!(room.players[&alice].delayed.is_empty());
+}
+
+#[test]
+fn kicked_player_is_told_before_the_connection_closes() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    assert!(room.kick(&alice, "Bye"));
+    let effects = room.take_effects();
+    assert!(matches!(
+        &effects[..],
+        [Effect::Send(to, ServerMessage::Announcement(_)), Effect::Disconnect(from)]
+            if *to == addr(1) && *from == addr(1)
+    ));
+    let token = room.players[&alice].token;
+    message(&mut room, 1, ClientMessage::Disconnected);
+    assert!(!room.can_resume(&token));
+}
+
+#[test]
+fn ratings_change_once_the_match_is_decided() {
+    // two round wins take the match
+    let mut room = room(RoomConfig {
+        best_of: Some(3),
+        ..RoomConfig::default()
+    });
+    let mut login = |port, name: &str| {
+        let (token, _) = room.accounts.lock().unwrap().register(name).unwrap();
+        let request = JoinRequest::New(name.into(), Some(accounts::key(&token)));
+        let (_, id) = room.add_player(addr(port), request).unwrap();
+        (id, accounts::key(&token))
+    };
+    let (_, alice_key) = login(1, "Alice");
+    let (bob, bob_key) = login(2, "Bob");
+    room.take_effects();
+    let stats = |room: &Room, key: &str| room.accounts.lock().unwrap().get(key).unwrap().stats;
+    let sent =
+        |effects: &[Effect], f: fn(&ServerMessage) -> bool| broadcasts(effects).into_iter().any(f);
+
+    let mut rounds = 0;
+    loop {
+        start_round(&mut room, 1);
+        // players without a single input are away and left out of the next round
+        for port in [1, 2] {
+            message(&mut room, port, ClientMessage::Move(Direction::Unchanged));
+        }
+        let effects = crash_into_wall(&mut room, bob);
+        rounds += 1;
+        let decided = sent(&effects, |msg| {
+            matches!(msg, ServerMessage::MatchSummary(_))
+        });
+        let rated = sent(&effects, |msg| {
+            matches!(msg, ServerMessage::RatingsChanged(_))
+        });
+        assert_eq!(rated, decided);
+        if decided {
+            break;
+        }
+        assert_eq!(stats(&room, &alice_key).rating, 1200);
+        assert_eq!(stats(&room, &bob_key).rating, 1200);
+    }
+    // a best of three takes more than one round
+    assert!(rounds > 1);
+    assert_eq!(stats(&room, &bob_key).rounds_played, rounds);
+    let (alice, bob) = (stats(&room, &alice_key), stats(&room, &bob_key));
+    assert_ne!(alice.rating, 1200);
+    assert_eq!(alice.rating + bob.rating, 2400);
+}
+
+#[test]
+fn quick_play_prefers_players_of_about_the_same_rating() {
+    let rated = |name: &str, rating| {
+        let mut room = room(RoomConfig::default());
+        room.name = name.into();
+        let id = join(&mut room, 1, "Alice");
+        room.game.players.get_mut(&id).unwrap().rating = Some(rating);
+        let (write, _) = unbounded();
+        (name.to_string(), RoomHandle::new(room, write))
+    };
+    let mut rooms: HashMap<_, _> = [rated("WEAK", 1000), rated("STRONG", 1500)].into();
+    let name = |handle: Option<RoomHandle>| handle.unwrap().room.lock().unwrap().name.clone();
+    assert_eq!(name(closest_room(&rooms, 1100)), "WEAK");
+    assert_eq!(name(closest_room(&rooms, 1400)), "STRONG");
+
+    // full rooms take nobody else
+    rooms
+        .get_mut("STRONG")
+        .unwrap()
+        .room
+        .lock()
+        .unwrap()
+        .config
+        .max_players = 1;
+    assert_eq!(name(closest_room(&rooms, 1400)), "WEAK");
+}
+
+#[test]
+fn stats_outlast_the_match_and_its_players() {
+    // the first round decides each match
+    let mut room = room(RoomConfig {
+        target_score: Some(2),
+        ..RoomConfig::default()
+    });
+    join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    start_round(&mut room, 1);
+    let stats = session_stats(&crash_into_wall(&mut room, bob));
+    assert_eq!(stats["Alice"].match_wins, 1);
+    assert_eq!(stats["Bob"].matches, 1);
+    assert_eq!(stats["Bob"].match_wins, 0);
+
+    // Bob leaves between the matches and Carol takes his place
+    message(&mut room, 2, ClientMessage::LeaveRoom);
+    let carol = join(&mut room, 3, "Carol");
+    assert_eq!(room.snapshot().round, 1, "The next match starts over");
+    start_round(&mut room, 1);
+    let stats = session_stats(&crash_into_wall(&mut room, carol));
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats["Alice"].matches, 2);
+    assert_eq!(stats["Alice"].match_wins, 2);
+    assert_eq!(stats["Alice"].round_wins, 2);
+    assert_eq!(stats["Bob"].matches, 1);
+    assert_eq!(stats["Carol"].matches, 1);
+
+    // whoever comes next sees them as well
+    let snapshot = room.snapshot();
+    assert_eq!(snapshot.session[0].name, "Alice");
+    assert_eq!(snapshot.session.len(), 3);
+}
+
+#[test]
+fn only_the_host_hears_of_the_traffic() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    join(&mut room, 2, "Bob");
+    room.add_spectator(addr(3));
+    room.take_effects();
+
+    // the handle counts what it sends, a broadcast once per recipient
+    room.traffic.record(100, 3);
+    let (bytes, messages, _) = room.traffic_reported;
+    room.traffic_reported = (bytes, messages, Instant::now() - TRAFFIC_INTERVAL);
+    let (_, effects) = room.apply(Event::Tick);
+
+    let reports: Vec<_> = effects
+        .iter()
+        .filter_map(|effect| match effect {
+            Effect::Send(to, ServerMessage::TrafficStats(stats)) => Some((*to, stats)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reports.len(), 1);
+    let (to, stats) = reports[0];
+    assert_eq!(to, addr(1));
+    assert_eq!(stats.total_bytes, 300);
+    assert_eq!(stats.total_messages, 3);
+    assert_eq!(stats.connections, 3);
+    assert!(stats.bytes_per_second > 0);
+}
+
+#[test]
+fn winner_gets_a_ghost_that_drives_the_same_way() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    message(&mut room, 1, ClientMessage::StartGame);
+    // turning starts in the countdown and goes on into the round
+    message(&mut room, 1, ClientMessage::Move(Direction::Left));
+    let countdown = COUNTDOWN_SECONDS as usize * room.config.tick_rate as usize;
+    for _ in 0..countdown + 20 {
+        room.apply(Event::Tick);
+    }
+    message(&mut room, 1, ClientMessage::Move(Direction::Unchanged));
+
+    let mut effects = vec![];
+    while room.round_in_progress() {
+        effects.extend(room.apply(Event::Tick).1);
+    }
+    let ghost = effects
+        .iter()
+        .find_map(|effect| match effect {
+            Effect::Send(to, ServerMessage::GhostRecorded(ghost)) if *to == addr(1) => Some(ghost),
+            _ => None,
+        })
+        .expect("No ghost recorded");
+    assert_eq!(ghost.runner, alice);
+    assert_eq!(ghost.inputs.len(), 2);
+    let player = &room.game.players[&alice];
+    assert_eq!(ghost.trace().last(), Some(&(player.x, player.y)));
+}
+
+#[test]
+fn replay_plays_the_round_again() {
+    let mut room = room(RoomConfig::default());
+    let alice = join(&mut room, 1, "Alice");
+    let bob = join(&mut room, 2, "Bob");
+    message(&mut room, 1, ClientMessage::StartGame);
+    // turning in the countdown counts as much as turning in the round
+    message(&mut room, 1, ClientMessage::Move(Direction::Left));
+    let countdown = COUNTDOWN_SECONDS as usize * room.config.tick_rate as usize;
+    for _ in 0..countdown + 20 {
+        room.apply(Event::Tick);
+    }
+    message(&mut room, 2, ClientMessage::Move(Direction::Right));
+    for _ in 0..MAX_ENDING_TICKS * 10 {
+        if room.ending.is_some() {
+            break;
+        }
+        room.apply(Event::Tick);
+    }
+    assert!(room.ending.is_some(), "The round was never decided");
+
+    let replay = room.recording.take().unwrap().finish();
+    assert_eq!(replay.inputs.len(), 2);
+    let positions = replay.simulate();
+    assert_eq!(positions.len(), replay.ticks as usize);
+    let mut expected = [alice, bob];
+    expected.sort();
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|id| (*id, room.game.players[id].x, room.game.players[id].y))
+        .collect();
+    assert_eq!(positions.last(), Some(&expected));
+}
+
+#[test]
+fn recolored_player_takes_the_pattern_of_the_new_place() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    join(&mut room, 2, "Bob");
+    let carol = join(&mut room, 3, "Carol");
+    // the last place of the palette is not there in a smaller room
+    let last = room.palette()[MAX_PLAYERS - 1];
+    let player = room.game.players.get_mut(&carol).unwrap();
+    player.color = last;
+    player.pattern = TrailPattern::ALL[MAX_PLAYERS - 1];
+
+    let config = RoomConfig {
+        max_players: 3,
+        ..room.config.clone()
+    };
+    let (_, effects) = message(&mut room, 1, ClientMessage::UpdateConfig(config));
+    let player = &room.game.players[&carol];
+    assert_eq!(player.color, room.palette()[2]);
+    assert_eq!(player.pattern, TrailPattern::ALL[2]);
+    let colors = broadcasts(&effects)
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::PlayerColors(colors) => Some(colors.clone()),
+            _ => None,
+        })
+        .expect("No colors sent");
+    assert!(colors.contains(&(carol, player.color, TrailPattern::ALL[2])));
+}
+
+#[test]
+fn chat_stays_out_of_the_replay() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    start_round(&mut room, 1);
+    let (_, effects) = message(&mut room, 1, ClientMessage::Chat("gg".into()));
+    assert!(broadcasts(&effects)
+        .iter()
+        .any(|msg| matches!(msg, ServerMessage::Chat(_))));
+
+    let replay = room.recording.take().unwrap().finish();
+    assert!(!replay.messages.is_empty());
+    assert!(!replay
+        .messages
+        .iter()
+        .any(|(_, msg)| matches!(msg, ServerMessage::Chat(_))));
+}
+
+#[test]
+fn full_syncs_are_spaced_out() {
+    let mut room = room(RoomConfig::default());
+    join(&mut room, 1, "Alice");
+    let syncs = |effects: &[Effect]| {
+        effects
+            .iter()
+            .filter(|effect| matches!(effect, Effect::Send(_, ServerMessage::FullTrailSync(..))))
+            .count()
+    };
+    let mut effects = vec![];
+    for _ in 0..5 {
+        effects.extend(message(&mut room, 1, ClientMessage::RequestFullSync).1);
+    }
+    assert_eq!(syncs(&effects), 1);
+
+    // the requests that had to wait are answered once, when it is their turn
+    let mut effects = vec![];
+    for _ in 0..FULL_SYNC_TICKS * 2 {
+        effects.extend(room.apply(Event::Tick).1);
+    }
+    assert_eq!(syncs(&effects), 1);
+}
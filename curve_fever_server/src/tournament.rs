@@ -0,0 +1,125 @@
+//! Single elimination tournaments over several rooms.
+//!
+//! An admin groups rooms into a tournament and every room plays one match. Once all matches of a
+//! round are decided, their winners are grouped into the matches of the next round, which are
+//! played in the room of the first winner of each group. All rooms of the tournament show the
+//! bracket, it is sent with `ServerMessage::TournamentState` whenever it changes.
+
+use futures::{channel::mpsc::UnboundedReceiver, stream::StreamExt};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+use curve_fever_common::{BracketMatch, TournamentState};
+
+use crate::RoomList;
+
+pub type TournamentList = Arc<Mutex<HashMap<String, TournamentState>>>;
+
+/// A room of a tournament finished its match
+#[derive(Debug)]
+pub struct MatchResult {
+    pub room: String,
+    pub players: Vec<String>,
+    pub winner: String,
+}
+
+pub fn create(name: String, rooms: Vec<String>, group: usize) -> Result<TournamentState, String> {
+    if rooms.len() < 2 {
+        return Err("A tournament needs at least two rooms".into());
+    }
+    if group < 2 {
+        return Err("Matches need at least two players".into());
+    }
+    let first_round = rooms
+        .into_iter()
+        .map(|room| BracketMatch {
+            room,
+            players: vec![],
+            winner: None,
+        })
+        .collect();
+    Ok(TournamentState {
+        name,
+        group,
+        rounds: vec![first_round],
+    })
+}
+
+/// Rooms that show the bracket
+fn room_names(state: &TournamentState) -> Vec<&String> {
+    let mut rooms: Vec<&String> = state.rounds.iter().flatten().map(|m| &m.room).collect();
+    rooms.sort();
+    rooms.dedup();
+    rooms
+}
+
+/// Enters the result into the current round and starts the next round once it is complete.
+/// Returns whether the match was part of the tournament.
+fn on_result(state: &mut TournamentState, result: &MatchResult) -> bool {
+    let round = match state.rounds.last_mut() {
+        Some(round) => round,
+        None => return false,
+    };
+    let bracket_match = round
+        .iter_mut()
+        .find(|m| m.room == result.room && m.winner.is_none());
+    match bracket_match {
+        Some(m) => {
+            m.players = result.players.clone();
+            m.winner = Some(result.winner.clone());
+        }
+        None => return false,
+    }
+    if round.iter().all(|m| m.winner.is_some()) {
+        let winners: Vec<(String, String)> = round
+            .iter()
+            .filter_map(|m| Some((m.room.clone(), m.winner.clone()?)))
+            .collect();
+        if winners.len() > 1 {
+            let next = winners
+                .chunks(state.group)
+                .map(|group| BracketMatch {
+                    room: group[0].0.clone(),
+                    players: group.iter().map(|(_, name)| name.clone()).collect(),
+                    // the last winner might have nobody left to play against
+                    winner: Some(group[0].1.clone()).filter(|_| group.len() == 1),
+                })
+                .collect();
+            state.rounds.push(next);
+        }
+    }
+    true
+}
+
+/// Sends the bracket to all its rooms.
+pub fn publish(state: &TournamentState, rooms: &RoomList) {
+    let rooms = rooms.lock().unwrap();
+    for name in room_names(state) {
+        if let Some(handle) = rooms.get(name) {
+            handle.with_room(|room| room.set_tournament(state.clone()));
+        }
+    }
+}
+
+/// Advances the tournaments with the results of their rooms.
+pub async fn run(
+    mut results: UnboundedReceiver<MatchResult>,
+    rooms: RoomList,
+    tournaments: TournamentList,
+) {
+    while let Some(result) = results.next().await {
+        let mut tournaments = tournaments.lock().unwrap();
+        if let Some(state) = tournaments
+            .values_mut()
+            .find(|state| room_names(state).contains(&&result.room))
+        {
+            if on_result(state, &result) {
+                info!(tournament = %state.name, "`{}` won in room {}", result.winner, result.room);
+                publish(state, &rooms);
+            }
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Plays a round on the real server with two clients speaking the binary protocol.
+//!
+//! Every broadcast has to reach both clients, in the same order. Run it after any change to the
+//! messages or to the order the server sends them in. The connections refused for a ban or too
+//! many attempts are tested here as well, as are accounts registered too quickly.
+
+use async_tungstenite::tungstenite::{self, Message, WebSocket};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use curve_fever_common::{ClientMessage, Direction, RoomConfig, ServerMessage};
+
+/// Longest wait for a single message
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server binary on a port picked by the system, stopped when dropped
+struct Server {
+    process: Child,
+    addr: SocketAddr,
+    dir: PathBuf, // accounts, bans and replays
+}
+
+impl Server {
+    /// Files of the server of the test `name`
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("curve-fever-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn start(name: &str, env: &[(&str, &str)]) -> Self {
+        let dir = Self::dir(name);
+        let mut process = Command::new(env!("CARGO_BIN_EXE_curve-fever-server"))
+            .arg("127.0.0.1:0")
+            .env("RUST_LOG", "curve_fever_server=info")
+            .env("ACCOUNTS_FILE", dir.join("accounts.json"))
+            .env("BANS_FILE", dir.join("bans.json"))
+            .env("REPLAY_DIR", dir.join("replays"))
+            .env("REPLAY_ADDR", "127.0.0.1:0")
+            .env_remove("ROOMS_FILE")
+            .env_remove("ADMIN_TOKEN")
+            .env_remove("CLIENT_DIR")
+            .env_remove("CONNECTIONS_PER_MINUTE")
+            .envs(env.iter().copied())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Could not start the server");
+
+        // the log names the port
+        let mut log = BufReader::new(process.stdout.take().unwrap());
+        let addr = loop {
+            let mut line = String::new();
+            if log.read_line(&mut line).unwrap() == 0 {
+                panic!("The server exited before listening");
+            }
+            if let Some((_, addr)) = line.split_once("Listening on: ") {
+                break addr.trim().parse().unwrap();
+            }
+        };
+        // keep reading, a full pipe would block the server
+        thread::spawn(move || io::copy(&mut log, &mut io::sink()));
+        Self { process, addr, dir }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+struct Client {
+    ws: WebSocket<TcpStream>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        Self::try_connect(addr).expect("The connection was refused")
+    }
+
+    /// `None` if the server closes the connection instead of upgrading it
+    fn try_connect(addr: SocketAddr) -> Option<Self> {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let (ws, _) = tungstenite::client(format!("ws://{}", addr), stream).ok()?;
+        Some(Self { ws })
+    }
+
+    fn send(&mut self, msg: ClientMessage) {
+        let bytes = bincode::serialize(&msg).unwrap();
+        self.ws.write_message(Message::Binary(bytes)).unwrap();
+    }
+
+    /// The next message along with its encoding, pings are answered on the way.
+    fn recv(&mut self) -> (ServerMessage, Vec<u8>) {
+        let bytes = match self.ws.read_message() {
+            Ok(Message::Binary(bytes)) => bytes,
+            other => panic!("Expected a binary message, got {:?}", other),
+        };
+        let msg = bincode::deserialize(&bytes).unwrap();
+        if let ServerMessage::Ping(seq) = msg {
+            self.send(ClientMessage::Pong(seq));
+        }
+        (msg, bytes)
+    }
+
+    /// All messages up to and including the first one `last` matches.
+    fn recv_until(
+        &mut self,
+        last: impl Fn(&ServerMessage) -> bool,
+    ) -> Vec<(ServerMessage, Vec<u8>)> {
+        let mut received = vec![];
+        loop {
+            let (msg, bytes) = self.recv();
+            let done = last(&msg);
+            received.push((msg, bytes));
+            if done {
+                return received;
+            }
+        }
+    }
+}
+
+/// The encoded broadcasts among `received`, without the messages sent to a single client.
+fn broadcasts(received: &[(ServerMessage, Vec<u8>)]) -> Vec<&[u8]> {
+    received
+        .iter()
+        .filter(|(msg, _)| {
+            !matches!(
+                msg,
+                ServerMessage::Ping(_)
+                    | ServerMessage::AfkWarning(_)
+                    | ServerMessage::ConfigRejected(_)
+                    | ServerMessage::FullTrailSync(..)
+                    | ServerMessage::TrafficStats(_)
+                    | ServerMessage::GhostRecorded(_)
+            )
+        })
+        .map(|(_, bytes)| &bytes[..])
+        .collect()
+}
+
+/// Name of the variant, `Countdown` for `Countdown(3)`
+fn kind(msg: &ServerMessage) -> String {
+    format!("{:?}", msg)
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn create_join_and_play_a_round() {
+    let server = Server::start("protocol", &[]);
+
+    let mut alice = Client::connect(server.addr);
+    alice.send(ClientMessage::CreateRoom("Alice".into()));
+    let (room_name, config) = match alice.recv().0 {
+        ServerMessage::JoinSuccess {
+            room_name,
+            players,
+            config,
+            ..
+        } => {
+            assert!(players.is_empty());
+            (room_name, config)
+        }
+        msg => panic!("Expected `JoinSuccess`, got {:?}", msg),
+    };
+
+    let mut bob = Client::connect(server.addr);
+    bob.send(ClientMessage::JoinRoom("Bob".into(), room_name.clone()));
+    let bob_id = match bob.recv().0 {
+        ServerMessage::JoinSuccess {
+            room_name: joined,
+            players,
+            uuid,
+            ..
+        } => {
+            assert_eq!(joined, room_name);
+            assert_eq!(players.len(), 1);
+            assert_eq!(players[0].name.as_str(), "Alice");
+            assert!(players[0].host);
+            uuid
+        }
+        msg => panic!("Expected `JoinSuccess`, got {:?}", msg),
+    };
+    match bob.recv().0 {
+        ServerMessage::RoomSnapshot(snapshot) => {
+            assert_eq!(snapshot.players.len(), 1);
+            assert_eq!(snapshot.round, 1);
+            assert!(snapshot.trails.is_none());
+        }
+        msg => panic!("Expected `RoomSnapshot`, got {:?}", msg),
+    }
+
+    // both get the same broadcasts from the one announcing Bob on
+    let bob_joined =
+        move |msg: &ServerMessage| matches!(msg, ServerMessage::NewPlayer(p) if p.uuid == bob_id);
+    alice.recv_until(bob_joined);
+    bob.recv_until(bob_joined);
+    let round_ended = |msg: &ServerMessage| matches!(msg, ServerMessage::RoundEnded(_));
+    // the server stops writing to a client that doesn't read
+    let bob = thread::spawn(move || bob.recv_until(round_ended));
+
+    // a small board ends the round quickly
+    alice.send(ClientMessage::UpdateConfig(RoomConfig {
+        width: 400,
+        height: 300,
+        ..config
+    }));
+    alice.send(ClientMessage::StartGame);
+    let mut received = alice.recv_until(|msg| matches!(msg, ServerMessage::RoundStarted(_)));
+    // Alice circles into her own trail if Bob doesn't hit a wall first
+    alice.send(ClientMessage::Move(Direction::Left));
+    received.extend(alice.recv_until(round_ended));
+    let received_by_bob = bob.join().unwrap();
+
+    assert_eq!(broadcasts(&received), broadcasts(&received_by_bob));
+
+    // the round went through all its stages in order
+    let kinds: Vec<String> = received.iter().map(|(msg, _)| kind(msg)).collect();
+    let stages = [
+        "GridChanged",
+        "ConfigChanged",
+        "HazardsMoved",
+        "Countdown",
+        "RoundStarted",
+        "PlayerDied",
+        "RoundEnding",
+        "RoundEnded",
+    ];
+    let mut rest = kinds.iter();
+    for stage in stages.iter() {
+        assert!(
+            rest.any(|kind| kind == stage),
+            "`{}` missing or out of order in {:?}",
+            stage,
+            kinds
+        );
+    }
+
+    let countdown: Vec<u8> = received
+        .iter()
+        .filter_map(|(msg, _)| match msg {
+            ServerMessage::Countdown(seconds) => Some(*seconds),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(countdown, [3, 2, 1, 0]);
+
+    // no state got lost on the way
+    let seqs: Vec<u32> = received
+        .iter()
+        .filter_map(|(msg, _)| match msg {
+            ServerMessage::GameState(seq, _) => Some(*seq),
+            _ => None,
+        })
+        .collect();
+    assert!(!seqs.is_empty());
+    assert!(
+        seqs.windows(2).all(|pair| pair[1] == pair[0] + 1),
+        "{:?}",
+        seqs
+    );
+
+    let died: Vec<_> = received
+        .iter()
+        .filter_map(|(msg, _)| match msg {
+            ServerMessage::PlayerDied(death) => Some(death.id),
+            _ => None,
+        })
+        .collect();
+    let ending = received.iter().find_map(|(msg, _)| match msg {
+        ServerMessage::RoundEnding(winner) => Some(*winner),
+        _ => None,
+    });
+    let (winner, points) = match &received.last().unwrap().0 {
+        ServerMessage::RoundEnded((winner, points)) => (*winner, points),
+        msg => panic!("Expected `RoundEnded`, got {:?}", msg),
+    };
+    assert_eq!(ending, Some(winner));
+    assert!(!died.contains(&winner));
+    assert_eq!(points.len(), 2);
+    assert!(points.iter().any(|&(id, ..)| id == winner));
+}
+
+#[test]
+fn banned_subnets_are_refused() {
+    let bans = Server::dir("bans").join("bans.json");
+    let ban = Command::new(env!("CARGO_BIN_EXE_curve-fever-server"))
+        .args(["ban", "127.0.0.0/8"])
+        .env("BANS_FILE", &bans)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(ban.success());
+
+    let server = Server::start("bans", &[]);
+    assert!(Client::try_connect(server.addr).is_none());
+}
+
+#[test]
+fn attempts_beyond_the_limit_are_refused() {
+    let server = Server::start("throttle", &[("CONNECTIONS_PER_MINUTE", "2")]);
+    let _first = Client::connect(server.addr);
+    let _second = Client::connect(server.addr);
+    assert!(Client::try_connect(server.addr).is_none());
+}
+
+#[test]
+fn registering_again_right_away_is_refused() {
+    let server = Server::start("register", &[]);
+    let mut client = Client::connect(server.addr);
+    client.send(ClientMessage::Register("Alice".into()));
+    assert!(matches!(client.recv().0, ServerMessage::AuthResult(Ok(_))));
+    client.send(ClientMessage::Register("Bob".into()));
+    assert!(matches!(client.recv().0, ServerMessage::AuthResult(Err(_))));
+}